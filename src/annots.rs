@@ -0,0 +1,150 @@
+use crate::crypt::StringDecryptor;
+use crate::{decode_pdf_string, resolve_object};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// 他の注釈を指すキー（返信元・ポップアップ・親）
+const ANNOT_LINK_KEYS: [&[u8]; 3] = [b"IRT", b"Popup", b"Parent"];
+
+/// ページ削除後、残ったページの注釈が削除済みページの注釈を参照していないか確認し、
+/// 壊れたリンクを取り除く。修復した箇所の数を返す。
+pub fn repair_annotation_links(doc: &mut Document) -> usize {
+    let pages = doc.get_pages();
+
+    // 残ったページ上の注釈と、その注釈が載っているページ
+    let mut annot_page: HashMap<ObjectId, ObjectId> = HashMap::new();
+    for page_id in pages.values() {
+        for annot_id in page_annotation_ids(doc, *page_id) {
+            annot_page.insert(annot_id, *page_id);
+        }
+    }
+    let kept: HashSet<ObjectId> = annot_page.keys().cloned().collect();
+
+    let mut repaired = 0;
+    let mut orphan_popups: Vec<(ObjectId, ObjectId)> = Vec::new();
+
+    for (&annot_id, &page_id) in &annot_page {
+        let Ok(annot) = doc.get_object_mut(annot_id).and_then(|o| o.as_dict_mut()) else { continue };
+        let is_popup = annot.get(b"Subtype").and_then(|o| o.as_name()).map(|n| n == b"Popup").unwrap_or(false);
+
+        for key in ANNOT_LINK_KEYS {
+            let Ok(target) = annot.get(key).and_then(|o| o.as_reference()) else { continue };
+            if kept.contains(&target) {
+                continue;
+            }
+            // 親を失ったポップアップは単独では意味を持たないのでページから外す
+            if is_popup && key == b"Parent" {
+                orphan_popups.push((page_id, annot_id));
+            }
+            annot.remove(key);
+            if key == b"IRT" {
+                annot.remove(b"RT");
+            }
+            repaired += 1;
+        }
+
+        // /P は注釈が実際に載っているページを指す必要がある
+        if annot.get(b"P").and_then(|o| o.as_reference()).map(|p| p != page_id).unwrap_or(false) {
+            annot.set("P", page_id);
+            repaired += 1;
+        }
+    }
+
+    for (page_id, popup_id) in orphan_popups {
+        remove_from_annots(doc, page_id, popup_id);
+    }
+
+    repaired
+}
+
+/// `--split-on-annot` の値（注釈の /Subtype の名前。先頭の `/` は省略できる）
+pub fn parse_subtype(value: &str) -> Result<String, String> {
+    let name = value.strip_prefix('/').unwrap_or(value);
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("{:?} is not an annotation subtype (e.g. Stamp or FileAttachment)", value));
+    }
+    Ok(name.to_string())
+}
+
+/// `--split-on-annot`: 指定した種類（/Subtype）の注釈があるページと、区切りのタイトル
+///
+/// タイトルは注釈の内容（/Contents の1行目）、スタンプの名前（/Name）、添付ファイルの名前の順に探し、
+/// どれも無ければ空にする。
+pub fn pages_with_subtype(
+    doc: &Document,
+    decryptor: &StringDecryptor,
+    page_numbers: &BTreeMap<u32, ObjectId>,
+    subtype: &str,
+) -> Vec<(u32, String)> {
+    let mut pages = Vec::new();
+    for (&number, &page_id) in page_numbers {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let Ok(annots) = page.get(b"Annots").and_then(|o| resolve_object(doc, o)).and_then(|o| o.as_array()) else {
+            continue;
+        };
+        let title = annots.iter().find_map(|annot| {
+            let annot_id = annot.as_reference().unwrap_or(page_id);
+            let annot = resolve_object(doc, annot).and_then(|o| o.as_dict()).ok()?;
+            if annot.get(b"Subtype").and_then(|o| o.as_name()).ok()? != subtype.as_bytes() {
+                return None;
+            }
+            Some(separator_title(doc, decryptor, annot_id, annot))
+        });
+        if let Some(title) = title {
+            pages.push((number, title));
+        }
+    }
+    pages
+}
+
+fn separator_title(doc: &Document, decryptor: &StringDecryptor, annot_id: ObjectId, annot: &Dictionary) -> String {
+    let text = |dict: &Dictionary, container: ObjectId, key: &[u8]| -> Option<String> {
+        let value = dict.get(key).ok()?;
+        let container = value.as_reference().unwrap_or(container);
+        let bytes = resolve_object(doc, value).and_then(|o| o.as_str()).ok()?;
+        let text = decode_pdf_string(&decryptor.decrypt(container, bytes));
+        let line = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+        Some(line.to_string())
+    };
+    if let Some(contents) = text(annot, annot_id, b"Contents") {
+        return contents;
+    }
+    if let Ok(name) = annot.get(b"Name").and_then(|o| o.as_name_str()) {
+        return name.to_string();
+    }
+    let file_spec = annot.get(b"FS").ok().map(|fs| (fs.as_reference().unwrap_or(annot_id), fs));
+    if let Some((container, fs)) = file_spec
+        && let Ok(fs) = resolve_object(doc, fs).and_then(|o| o.as_dict())
+        && let Some(name) = text(fs, container, b"UF").or_else(|| text(fs, container, b"F"))
+    {
+        return name;
+    }
+    String::new()
+}
+
+fn page_annotation_ids(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let Ok(page) = doc.get_dictionary(page_id) else { return Vec::new() };
+    let Ok(annots) = page.get(b"Annots").and_then(|o| doc.dereference(o)).and_then(|(_, o)| o.as_array()) else {
+        return Vec::new();
+    };
+    annots.iter().filter_map(|o| o.as_reference().ok()).collect()
+}
+
+fn remove_from_annots(doc: &mut Document, page_id: ObjectId, annot_id: ObjectId) {
+    // /Annots は直接の配列または配列オブジェクトへの参照
+    let annots_ref = doc
+        .get_dictionary(page_id)
+        .and_then(|p| p.get(b"Annots"))
+        .and_then(|o| o.as_reference())
+        .ok();
+    let annots = match annots_ref {
+        Some(id) => doc.get_object_mut(id).and_then(|o| o.as_array_mut()),
+        None => doc
+            .get_dictionary_mut(page_id)
+            .and_then(|p| p.get_mut(b"Annots"))
+            .and_then(|o| o.as_array_mut()),
+    };
+    if let Ok(annots) = annots {
+        annots.retain(|o| !matches!(o, Object::Reference(id) if *id == annot_id));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::progress::{InfoLines, Progress};
+use crate::provenance::pdf_date;
+use crate::{embedded_files, load_document, save_document};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::time::SystemTime;
+
+// 拡張子から推測する添付ファイルの MIME タイプ（/Subtype）。ここに無いものは application/octet-stream
+const MIME_TYPES: [(&str, &str); 14] = [
+    ("csv", "text/csv"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+];
+
+/// `attach` で加える添付ファイル
+pub struct AttachOptions<'a> {
+    pub file: &'a Path,
+    /// 文書の中での名前（省略時は `file` のファイル名）
+    pub name: Option<&'a str>,
+    pub description: Option<&'a str>,
+    /// 省略時は拡張子から推測する
+    pub mime_type: Option<&'a str>,
+}
+
+/// `attach` サブコマンド: ファイルを添付ファイル（/Names /EmbeddedFiles の名前ツリーの項目）として加えて保存する
+///
+/// 由来情報（`--provenance`）の添付と同じく `embedded_files::add` で登録するため、同じ名前の添付ファイルは置き換える。
+/// 更新日時には添付するファイルの更新日時を使う。
+pub fn run(input_path: &Path, output_path: &Path, options: &AttachOptions) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut doc = load_document(input_path, false)?;
+    // 加える文字列とストリームを暗号化できないため、暗号文の中に平文が混ざらないよう断る
+    if doc.is_encrypted() {
+        bail!("{:?} is encrypted; decrypt it first with `pdf_splitter decrypt`", input_path);
+    }
+    let data = std::fs::read(options.file).with_context(|| format!("Failed to read {:?}", options.file))?;
+    let name = match options.name {
+        Some(name) => name.to_string(),
+        None => options.file.file_name().context("--file has no file name")?.to_string_lossy().into_owned(),
+    };
+    let mime_type = options.mime_type.unwrap_or_else(|| guess_mime_type(&name));
+    let modified = std::fs::metadata(options.file).and_then(|m| m.modified()).unwrap_or_else(|_| SystemTime::now());
+    let size = data.len() as u64;
+
+    embedded_files::add(&mut doc, &name, data, mime_type, options.description.unwrap_or(""), &pdf_date(modified))
+        .with_context(|| format!("Failed to attach {:?}", options.file))?;
+    save_document(&doc, output_path)?;
+    progress.println(format!("Attached {:?} ({} bytes, {}); wrote {:?}.", name, size, mime_type, output_path));
+    Ok(())
+}
+
+fn guess_mime_type(name: &str) -> &'static str {
+    let extension = Path::new(name).extension().map(|e| e.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    MIME_TYPES.iter().find(|(ext, _)| *ext == extension).map_or("application/octet-stream", |(_, mime)| mime)
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::path::Path;
+
+// バーコードの読み取りには zbar のコマンドラインツールを使う
+#[cfg(feature = "barcode")]
+const ZBARIMG: &str = "zbarimg";
+
+// 区切りページを探すときの描画幅（ピクセル）。A4・Letter でおよそ 100dpi
+#[cfg(feature = "barcode")]
+const RENDER_WIDTH: i32 = 850;
+
+/// `--pattern` の `*`（任意の文字列）と `?`（任意の1文字）によるワイルドカード
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "barcode"), allow(dead_code))]
+pub struct Pattern(Vec<char>);
+
+pub fn parse_pattern(value: &str) -> Result<Pattern, String> {
+    if value.is_empty() {
+        return Err("empty barcode pattern".to_string());
+    }
+    Ok(Pattern(value.chars().collect()))
+}
+
+#[cfg_attr(not(feature = "barcode"), allow(dead_code))]
+impl Pattern {
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        // 最後に見た `*` の位置と、そこから読み進めた文字の位置（バックトラック用）
+        let (mut p, mut t) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while t < text.len() {
+            match self.0.get(p) {
+                Some('*') => {
+                    star = Some((p, t));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                _ => match star {
+                    Some((star_p, star_t)) => {
+                        p = star_p + 1;
+                        t = star_t + 1;
+                        star = Some((star_p, star_t + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.0[p..].iter().all(|c| *c == '*')
+    }
+}
+
+/// 区切りページ（ページ番号と読み取ったコード）を探す（`--split-on-barcode`）
+///
+/// 各ページを低い解像度で描画して zbarimg でバーコード・QR コードを読み取り、`pattern` に一致する
+/// （指定が無ければ何らかの）コードのあるページを区切りとする。描画には PDFium を使うため、
+/// `barcode` フィーチャー付きでビルドし、実行時に PDFium の共有ライブラリと zbarimg が必要になる。
+#[cfg(feature = "barcode")]
+pub fn find_separators(
+    input_path: &Path,
+    password: Option<&str>,
+    pattern: Option<&Pattern>,
+) -> Result<Vec<(u32, String)>> {
+    use anyhow::{Context, bail};
+    use pdfium_render::prelude::PdfRenderConfig;
+    use std::process::Command;
+
+    let pdfium = crate::thumbnails::bind_pdfium("--split-on-barcode")?;
+    let document = pdfium.load_pdf_from_file(input_path, password)?;
+    let config = PdfRenderConfig::new().set_target_width(RENDER_WIDTH);
+    let png_path = std::env::temp_dir().join(format!("pdf_splitter-barcode-{}.png", std::process::id()));
+
+    // 途中で失敗しても一時ファイルを残さない
+    let separators = (|| {
+        let mut separators = Vec::new();
+        for (index, page) in document.pages().iter().enumerate() {
+            let page_number = index as u32 + 1;
+            page.render_with_config(&config)?
+                .as_image()
+                .save_with_format(&png_path, image::ImageFormat::Png)
+                .with_context(|| format!("Failed to write {:?}", png_path))?;
+            let output = Command::new(ZBARIMG)
+                .args(["--quiet", "--raw"])
+                .arg(&png_path)
+                .output()
+                .with_context(|| format!("Failed to run {}", ZBARIMG))?;
+            // 終了コード 4 はコードが見つからなかったことを表す
+            match output.status.code() {
+                Some(0) => {
+                    let codes = String::from_utf8_lossy(&output.stdout);
+                    if let Some(code) = codes.lines().map(str::trim).find(|code| pattern.is_none_or(|p| p.matches(code))) {
+                        separators.push((page_number, code.to_string()));
+                    }
+                }
+                Some(4) => {}
+                _ => bail!(
+                    "{} failed on page {}: {}",
+                    ZBARIMG,
+                    page_number,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            }
+        }
+        Ok(separators)
+    })();
+    let _ = std::fs::remove_file(&png_path);
+    separators
+}
+
+/// zbarimg が使えるか（処理を始める前に確かめる）
+#[cfg(feature = "barcode")]
+pub fn check_available() -> Result<()> {
+    use anyhow::Context;
+
+    std::process::Command::new(ZBARIMG)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("--split-on-barcode requires `{}` on PATH", ZBARIMG))?;
+    Ok(())
+}
+
+/// `barcode` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "barcode"))]
+pub fn check_available() -> Result<()> {
+    anyhow::bail!("--split-on-barcode is not available in this build; rebuild with `cargo build --release --features barcode`")
+}
+
+#[cfg(not(feature = "barcode"))]
+pub fn find_separators(_input_path: &Path, _password: Option<&str>, _pattern: Option<&Pattern>) -> Result<Vec<(u32, String)>> {
+    check_available().map(|()| Vec::new())
+}
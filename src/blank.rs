@@ -0,0 +1,37 @@
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+
+// 何かを描く演算子（パスの塗り・線、画像・フォーム、シェーディング、インライン画像）。テキストは別に調べる
+const MARKING_OPERATORS: [&str; 13] = ["S", "s", "f", "F", "f*", "B", "B*", "b", "b*", "Do", "sh", "BI", "EI"];
+
+/// ページが実質的に白紙か（`--drop-blank`）
+///
+/// 内容ストリームが空か、グラフィックス状態の設定やクリップだけで何も描かないページを白紙とみなす。
+/// 空白だけの文字列を描くテキストも白紙扱いにする。注釈のあるページや、内容を読めないページは白紙としない。
+/// スキャンした白紙の画像を貼ったページは画像を描くため対象外。
+pub fn is_blank(doc: &Document, page_id: ObjectId) -> bool {
+    let Ok(page) = doc.get_dictionary(page_id) else { return false };
+    let has_annotations = page
+        .get(b"Annots")
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_array())
+        .is_ok_and(|annots| !annots.is_empty());
+    if has_annotations {
+        return false;
+    }
+    let Ok(content) = doc.get_page_content(page_id) else { return false };
+    let Ok(content) = Content::decode(&content) else { return false };
+    !content.operations.iter().any(|operation| match operation.operator.as_str() {
+        "Tj" | "TJ" | "'" | "\"" => draws_text(&operation.operands),
+        operator => MARKING_OPERATORS.contains(&operator),
+    })
+}
+
+/// 演算子の引数に空白以外の文字を含む文字列があるか
+fn draws_text(operands: &[Object]) -> bool {
+    operands.iter().any(|operand| match operand {
+        Object::String(bytes, _) => bytes.iter().any(|b| !b.is_ascii_whitespace() && *b != 0),
+        Object::Array(items) => draws_text(items),
+        _ => false,
+    })
+}
@@ -0,0 +1,89 @@
+use crate::manifest::SkippedBookmark;
+use anyhow::{Context, Result};
+use lopdf::ObjectId;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::path::{Path, PathBuf};
+
+/// 入力と同じディレクトリに置くキャッシュのディレクトリ名
+const CACHE_DIR: &str = ".pdfsplit-cache";
+
+// 保存形式を変えたら上げる（古いキャッシュは読み捨てて作り直す）
+const CACHE_FORMAT: u32 = 3;
+
+// ハッシュ計算で一度に読む大きさ
+const HASH_CHUNK: usize = 8 * 1024 * 1024;
+
+/// ページツリーと目次を走査した結果（入力と走査の設定が同じなら毎回同じになる）
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Analysis {
+    /// ページ番号とページオブジェクトの対応表
+    pub page_numbers: BTreeMap<u32, ObjectId>,
+    /// 章の開始ページとタイトル（ソート・重複除去前）
+    pub chapter_starts: Vec<(u32, String)>,
+    /// ページツリーの走査で出た警告（キャッシュから読んだときも表示し直す）
+    pub page_tree_warnings: Vec<String>,
+    /// 宛先を解決できなかった目次項目
+    pub skipped_bookmarks: Vec<SkippedBookmark>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<A> {
+    format: u32,
+    key: String,
+    analysis: A,
+}
+
+/// `--cache`: 解析結果を入力ファイルの内容のハッシュをキーにサイドカーへ保存し、次回の実行で再利用する
+///
+/// 同じ巨大なファイルに対して命名や出力のオプションだけを変えて何度も実行する場合に、
+/// ページツリーと目次の走査（名前付き宛先の解決を含む）を省く。文書の読み込み自体は省けない。
+pub struct AnalysisCache {
+    path: PathBuf,
+    key: String,
+}
+
+impl AnalysisCache {
+    /// 入力の内容と、解析結果を左右する設定からキーを作る
+    pub fn open(input_path: &Path, settings: &str) -> Result<Self> {
+        let file = File::open(input_path).with_context(|| format!("Failed to open file: {:?}", input_path))?;
+        // SAFETY: 読み込み中に他のプロセスが入力ファイルを書き換えないことを前提とする
+        let map = unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to map file: {:?}", input_path))?;
+        // DefaultHasher::new() の鍵は固定なので実行をまたいで同じ値になる
+        // （Rust のバージョンで変わりうるが、その場合はキャッシュが外れるだけ）
+        let mut hasher = DefaultHasher::new();
+        for chunk in map.chunks(HASH_CHUNK) {
+            hasher.write(chunk);
+        }
+        let key = format!("{:016x}-{}-{}", hasher.finish(), map.len(), settings);
+
+        let file_name = input_path.file_name().context("Invalid file name")?;
+        let mut cache_name = file_name.to_os_string();
+        cache_name.push(".json");
+        let path = input_path.parent().unwrap_or_else(|| Path::new(".")).join(CACHE_DIR).join(cache_name);
+        Ok(Self { path, key })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// キーが一致するキャッシュがあれば返す（無い・壊れている・古い場合は `None`）
+    pub fn load(&self) -> Option<Analysis> {
+        let data = fs::read(&self.path).ok()?;
+        let cached: CacheFile<Analysis> = serde_json::from_slice(&data).ok()?;
+        (cached.format == CACHE_FORMAT && cached.key == self.key).then_some(cached.analysis)
+    }
+
+    pub fn store(&self, analysis: &Analysis) -> Result<()> {
+        let cached = CacheFile { format: CACHE_FORMAT, key: self.key.clone(), analysis };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let data = serde_json::to_vec(&cached)?;
+        fs::write(&self.path, data).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
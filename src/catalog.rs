@@ -0,0 +1,76 @@
+use crate::plan::PlannedChapter;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+// 読み手のアプリが頼りにする形式なので、項目を変えたり消したりしたら上げる（追加だけなら据え置く）
+const CATALOG_FORMAT: u32 = 1;
+
+/// `--catalog`: 分割した一式の目録（章選択の画面を作るためのもの）
+#[derive(Serialize)]
+struct CatalogFile {
+    format: u32,
+    source: SourceEntry,
+    chapters: Vec<ChapterEntry>,
+}
+
+/// 元の文書の情報
+#[derive(Serialize)]
+pub struct SourceEntry {
+    pub file_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub page_count: u32,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+struct ChapterEntry {
+    /// 章の番号（1始まり）
+    number: usize,
+    title: String,
+    /// 目録ファイルのあるディレクトリからの相対パス（その外にある場合は絶対パス）
+    path: String,
+    start_page: u32,
+    end_page: u32,
+    page_count: u32,
+    size: u64,
+}
+
+/// 目録を書き出す。`outputs` は保存した章のファイル（`plan` と同じ順）
+pub fn write(path: &Path, source: SourceEntry, outputs: &[(&PlannedChapter, &Path)]) -> Result<()> {
+    let base = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let base = fs::canonicalize(base).with_context(|| format!("Failed to resolve the catalog directory {:?}", base))?;
+    let chapters = outputs
+        .iter()
+        .map(|&(chapter, output)| {
+            let size = fs::metadata(output).with_context(|| format!("Failed to read {:?}", output))?.len();
+            Ok(ChapterEntry {
+                number: chapter.index + 1,
+                title: chapter.title.clone(),
+                path: relative_path(&base, output),
+                start_page: chapter.start_page,
+                end_page: chapter.end_page,
+                page_count: chapter.page_count(),
+                size,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let file = CatalogFile { format: CATALOG_FORMAT, source, chapters };
+    let json = serde_json::to_string_pretty(&file)?;
+    fs::write(path, json + "\n").with_context(|| format!("Failed to write catalog {:?}", path))
+}
+
+/// 区切り文字はプラットフォームによらず `/` にする
+fn relative_path(base: &Path, output: &Path) -> String {
+    let output = fs::canonicalize(output).unwrap_or_else(|_| output.to_path_buf());
+    match output.strip_prefix(base) {
+        Ok(relative) => relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+        Err(_) => output.display().to_string(),
+    }
+}
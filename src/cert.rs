@@ -0,0 +1,236 @@
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object};
+use std::path::Path;
+
+/// 証明書で暗号化された文書を復号できなかったときの終了コード
+pub const EXIT_CODE: i32 = 3;
+
+/// 証明書で暗号化された文書（公開鍵セキュリティハンドラ）を開けない理由
+///
+/// 鍵ファイルの指定漏れや受取人の違いなど、利用者が指定を直せば済む失敗をまとめ、終了コード `EXIT_CODE` で区別する。
+#[derive(Debug)]
+pub struct CertificateError(String);
+
+impl std::fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// 暗号化辞書が公開鍵セキュリティハンドラ（/Filter /Adobe.PubSec）のものか
+pub fn is_public_key(encrypt: &Dictionary) -> bool {
+    encrypt.get(b"Filter").and_then(Object::as_name).is_ok_and(|filter| filter == b"Adobe.PubSec")
+}
+
+/// 証明書で暗号化された文書の、文字列とストリームを復号する鍵を求める
+///
+/// 文書の鍵は、受取人ごとに CMS（PKCS#7）の EnvelopedData で包まれた 20 バイトのシードと、受取人のリスト全体から作られる。
+/// RC4（/V 1・2、または /V 4 で /CFM /V2）と AES（/V 4 で /AESV2、/V 5 で /AESV3）の暗号フィルターに対応する。
+pub fn file_key(doc: &Document, key_file: Option<&KeyFile>) -> Result<Vec<u8>> {
+    let encrypt = doc.get_encrypted()?;
+    let version = encrypt.get(b"V").and_then(Object::as_i64).unwrap_or(0);
+    let (params, default_bits) = match version {
+        1 | 2 => (encrypt, 40),
+        4 | 5 => {
+            // 目次タイトルなどの文字列を復号するため、文字列の暗号フィルター（/StrF）を見る
+            let filter = encrypt.get(b"StrF").and_then(Object::as_name).unwrap_or(b"Identity");
+            let params = encrypt
+                .get(b"CF")
+                .and_then(|o| doc.dereference(o))
+                .and_then(|(_, o)| o.as_dict())
+                .and_then(|filters| filters.get(filter))
+                .and_then(|o| doc.dereference(o))
+                .and_then(|(_, o)| o.as_dict())
+                .map_err(|_| unsupported(format!("the crypt filter /{} is missing", String::from_utf8_lossy(filter))))?;
+            let method = params.get(b"CFM").and_then(Object::as_name).unwrap_or(b"None");
+            match (version, method) {
+                (4, b"V2" | b"AESV2") => (params, 128),
+                (5, b"AESV3") => (params, 256),
+                _ => {
+                    return Err(unsupported(format!(
+                        "it uses the /{} crypt filter method with /V {}; only RC4 (/V2), AES-128 (/AESV2) and AES-256 (/AESV3) are supported",
+                        String::from_utf8_lossy(method),
+                        version
+                    )));
+                }
+            }
+        }
+        _ => return Err(unsupported(format!("it uses /V {}; only /V 1, 2, 4 and 5 are supported", version))),
+    };
+    // 暗号フィルターの /Length はビット数だが、バイト数で書くソフトもある。AES-256 の鍵は常に32バイト
+    let key_len = match params.get(b"Length").and_then(Object::as_i64).unwrap_or(default_bits) {
+        _ if version == 5 => 32,
+        bytes @ 5..=16 => bytes as usize,
+        bits => (bits / 8).clamp(5, 16) as usize,
+    };
+    let recipients: Vec<&[u8]> = match params.get(b"Recipients").and_then(|o| doc.dereference(o)).map(|(_, o)| o) {
+        Ok(Object::Array(items)) => items.iter().filter_map(|item| item.as_str().ok()).collect(),
+        Ok(Object::String(bytes, _)) => vec![bytes.as_slice()],
+        _ => Vec::new(),
+    };
+    if recipients.is_empty() {
+        return Err(unsupported("the encryption dictionary has no /Recipients".to_string()));
+    }
+    let encrypt_metadata = version < 4 || params.get(b"EncryptMetadata").and_then(Object::as_bool).unwrap_or(true);
+
+    let Some(key_file) = key_file else {
+        return Err(CertificateError(
+            "Document is encrypted with a certificate (Adobe.PubSec); supply the recipient's key with --cert KEY.p12 --cert-pass PASSWORD"
+                .to_string(),
+        )
+        .into());
+    };
+    key_file.file_key(&recipients, encrypt_metadata, key_len)
+}
+
+fn unsupported(reason: String) -> anyhow::Error {
+    CertificateError(format!("Cannot decrypt the certificate-encrypted document: {}", reason)).into()
+}
+
+/// `--cert` で指定した受取人の証明書と秘密鍵（PKCS#12）
+///
+/// 文書の受取人のうち、証明書の発行者とシリアル番号が一致するものの鍵を RSA の秘密鍵で取り出す。
+#[cfg(feature = "cert")]
+pub struct KeyFile {
+    name: String,
+    key: rsa::RsaPrivateKey,
+    certificates: Vec<cms::cert::IssuerAndSerialNumber>,
+}
+
+#[cfg(feature = "cert")]
+impl KeyFile {
+    pub fn open(path: &Path, password: &str) -> Result<Self> {
+        use anyhow::Context;
+        use rsa::pkcs8::DecodePrivateKey;
+        use x509_cert::der::Decode;
+
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let store = p12_keystore::KeyStore::from_pkcs12(&data, password)
+            .map_err(|e| CertificateError(format!("cannot open {:?} ({}); check --cert-pass", path, e)))?;
+        let Some((_, chain)) = store.private_key_chain() else {
+            return Err(CertificateError(format!("{:?} contains no private key", path)).into());
+        };
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(chain.key())
+            .map_err(|e| CertificateError(format!("the private key in {:?} is not an RSA key ({})", path, e)))?;
+        let certificates = chain
+            .chain()
+            .iter()
+            .filter_map(|certificate| x509_cert::Certificate::from_der(certificate.as_der()).ok())
+            .map(|certificate| cms::cert::IssuerAndSerialNumber {
+                issuer: certificate.tbs_certificate.issuer,
+                serial_number: certificate.tbs_certificate.serial_number,
+            })
+            .collect();
+        let name = chain.chain().first().map(|certificate| certificate.subject().to_string()).unwrap_or_default();
+        Ok(Self { name, key, certificates })
+    }
+
+    /// 鍵はシードと受取人のリストの SHA-1 の先頭 `key_len` バイト（AES-256 の32バイトの鍵では SHA-256）
+    fn file_key(&self, recipients: &[&[u8]], encrypt_metadata: bool, key_len: usize) -> Result<Vec<u8>> {
+        use sha1::{Digest, Sha1};
+        use sha2::Sha256;
+
+        let seed = recipients.iter().find_map(|recipient| self.seed(recipient).transpose()).transpose()?.ok_or_else(|| {
+            CertificateError(format!(
+                "the certificate {:?} is not one of the document's {} recipients; use the key of a recipient with --cert",
+                self.name,
+                recipients.len()
+            ))
+        })?;
+        fn digest<D: Digest>(seed: &[u8], recipients: &[&[u8]], encrypt_metadata: bool) -> Vec<u8> {
+            let mut hasher = D::new();
+            hasher.update(seed);
+            for recipient in recipients {
+                hasher.update(recipient);
+            }
+            if !encrypt_metadata {
+                hasher.update([0xff; 4]);
+            }
+            hasher.finalize().to_vec()
+        }
+        let hash = match key_len {
+            32 => digest::<Sha256>(&seed, recipients, encrypt_metadata),
+            _ => digest::<Sha1>(&seed, recipients, encrypt_metadata),
+        };
+        Ok(hash[..key_len].to_vec())
+    }
+
+    /// 受取人のエントリ（DER の ContentInfo）が自分宛てならシードを取り出す。自分宛てでなければ `None`
+    fn seed(&self, recipient: &[u8]) -> Result<Option<[u8; 20]>> {
+        use cms::content_info::ContentInfo;
+        use cms::enveloped_data::{EnvelopedData, RecipientIdentifier, RecipientInfo};
+        use x509_cert::der::Decode;
+
+        let envelope = ContentInfo::from_der(recipient)
+            .and_then(|info| info.content.decode_as::<EnvelopedData>())
+            .map_err(|e| unsupported(format!("a recipient entry is not a CMS EnvelopedData ({})", e)))?;
+        let encrypted_key = envelope.recip_infos.0.iter().find_map(|info| match info {
+            RecipientInfo::Ktri(info) => match &info.rid {
+                RecipientIdentifier::IssuerAndSerialNumber(id) if self.certificates.contains(id) => Some(&info.enc_key),
+                _ => None,
+            },
+            _ => None,
+        });
+        let Some(encrypted_key) = encrypted_key else { return Ok(None) };
+        let content_key = self
+            .key
+            .decrypt(rsa::Pkcs1v15Encrypt, encrypted_key.as_bytes())
+            .map_err(|e| CertificateError(format!("failed to unwrap the document key with the private key ({})", e)))?;
+        let content = &envelope.encrypted_content;
+        let data = content.encrypted_content.as_ref().map(|data| data.as_bytes()).unwrap_or_default();
+        let iv = content
+            .content_enc_alg
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.decode_as::<x509_cert::der::asn1::OctetString>().ok())
+            .ok_or_else(|| unsupported("the recipient entry has no IV".to_string()))?;
+        let plain = decrypt_content(&content.content_enc_alg.oid.to_string(), &content_key, iv.as_bytes(), data)?;
+        // 先頭の 20 バイトがシード（続く 4 バイトは受取人ごとの権限）
+        let seed = plain.get(..20).and_then(|seed| seed.try_into().ok());
+        seed.map(Some).ok_or_else(|| unsupported("the decrypted seed is too short".to_string()))
+    }
+}
+
+/// EnvelopedData の内容の暗号（CBC モードの 3DES・AES）を解く
+#[cfg(feature = "cert")]
+fn decrypt_content(oid: &str, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use cbc::cipher::block_padding::Pkcs7;
+    use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+
+    fn cbc<C: cbc::cipher::BlockDecryptMut + cbc::cipher::BlockCipher + cbc::cipher::KeyInit>(
+        key: &[u8],
+        iv: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let decryptor = cbc::Decryptor::<C>::new_from_slices(key, iv)
+            .map_err(|_| unsupported("the content key or IV has the wrong length".to_string()))?;
+        decryptor
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(|_| CertificateError("failed to decrypt the document key; the private key does not match the certificate".to_string()).into())
+    }
+
+    match oid {
+        "1.2.840.113549.3.7" => cbc::<des::TdesEde3>(key, iv, data),
+        "2.16.840.1.101.3.4.1.2" => cbc::<aes::Aes128>(key, iv, data),
+        "2.16.840.1.101.3.4.1.22" => cbc::<aes::Aes192>(key, iv, data),
+        "2.16.840.1.101.3.4.1.42" => cbc::<aes::Aes256>(key, iv, data),
+        oid => Err(unsupported(format!("the recipient entry uses an unsupported cipher (OID {})", oid))),
+    }
+}
+
+/// `cert` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "cert"))]
+pub struct KeyFile;
+
+#[cfg(not(feature = "cert"))]
+impl KeyFile {
+    pub fn open(_path: &Path, _password: &str) -> Result<Self> {
+        anyhow::bail!("--cert is not available in this build; rebuild with `cargo build --release --features cert`")
+    }
+
+    fn file_key(&self, _recipients: &[&[u8]], _encrypt_metadata: bool, _key_len: usize) -> Result<Vec<u8>> {
+        unreachable!("KeyFile cannot be created without the cert feature")
+    }
+}
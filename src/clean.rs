@@ -0,0 +1,123 @@
+use crate::{fetch, plan};
+use crate::interrupt::PARTIAL_EXTENSION;
+use crate::memory::format_size;
+use crate::progress::{InfoLines, Progress};
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// 章のファイルと、その横に書き出す付随ファイル（テキスト・対応表・YAML・サムネイル）の拡張子
+const OUTPUT_EXTENSIONS: [&str; 5] = [".pdf", ".txt", ".pages.json", ".yaml", ".png"];
+
+/// `clean` サブコマンド: 中断や失敗で残ったファイルを出力先のディレクトリから消す
+///
+/// 消すのは、書きかけの章（`.pdf.partial`）、暗号化のために qpdf に渡した引数のファイル（パスワードを含む）と qpdf の一時ファイル。
+/// `manifest`（`jobs` の結果の行・`<入力名>_manifest.json`・`<入力名>_interrupted.json`）を渡すと、その入力の章のファイル
+/// （`<入力名>_chapter_…`・`<入力名>_back_matter`）と付随ファイルのうち、結果に載っていないものも消す。
+/// サブディレクトリはたどらない。`dry_run` では消さずに一覧だけを表示する。
+pub fn run(dir: &Path, manifest: Option<&Path>, dry_run: bool) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    let listed = manifest.map(|manifest| read_manifest(manifest, dir)).transpose()?;
+    let entries = std::fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?;
+
+    let mut stale: Vec<(PathBuf, &str)> = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read directory {:?}", dir))?;
+        if !entry.file_type().is_ok_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let reason = if name.ends_with(&format!(".{}", PARTIAL_EXTENSION)) {
+            Some("partial output")
+        } else if name.ends_with(".qpdf-args") {
+            Some("qpdf argument file")
+        } else if name.contains(".~qpdf-temp") {
+            Some("qpdf temporary file")
+        } else {
+            listed.as_ref().filter(|listed| listed.is_unlisted_output(&name)).map(|_| "not in the manifest")
+        };
+        if let Some(reason) = reason {
+            stale.push((entry.path(), reason));
+        }
+    }
+    stale.sort();
+
+    let mut removed = 0;
+    let mut bytes = 0;
+    for (path, reason) in &stale {
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if dry_run {
+            progress.println(format!("Would remove: {} ({})", path.display(), reason));
+        } else if let Err(e) = std::fs::remove_file(path) {
+            progress.eprintln(format!("Warning: could not remove {}: {}", path.display(), e));
+            continue;
+        } else {
+            progress.println(format!("Removed: {} ({})", path.display(), reason));
+        }
+        removed += 1;
+        bytes += size;
+    }
+    progress.println(format!(
+        "{} {} files ({}) in {:?}.",
+        if dry_run { "Would remove" } else { "Removed" },
+        removed,
+        format_size(bytes),
+        dir
+    ));
+    Ok(())
+}
+
+/// 結果の JSON に載っている、1つの入力の章のファイル
+struct ListedOutputs {
+    /// 章のファイル名の先頭（入力名）
+    stem: String,
+    outputs: HashSet<String>,
+}
+
+impl ListedOutputs {
+    /// 入力の章のファイルか付随ファイルで、対応する章のファイルが結果に載っていないか
+    fn is_unlisted_output(&self, name: &str) -> bool {
+        let is_output = name.starts_with(&format!("{}_chapter_", self.stem)) || name.starts_with(&format!("{}_back_matter.", self.stem));
+        if !is_output {
+            return false;
+        }
+        // 付随ファイルは対応する章のファイル（同じ名前の .pdf）で判断する
+        OUTPUT_EXTENSIONS
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+            .is_some_and(|base| !self.outputs.contains(&format!("{}.pdf", base)))
+    }
+}
+
+/// `outputs`（`jobs` の結果の行・`<入力名>_manifest.json`）か `completed`（`<入力名>_interrupted.json`）を読む
+fn read_manifest(path: &Path, dir: &Path) -> Result<ListedOutputs> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read manifest {:?}", path))?;
+    let manifest: Value = serde_json::from_str(&text).with_context(|| format!("Failed to parse manifest {:?}", path))?;
+    let input = manifest.get("input").and_then(Value::as_str).with_context(|| format!("{:?} has no \"input\"", path))?;
+    // 失敗した分割の結果には書き出した章が載らないため、すべての章を消してしまわないよう断る
+    if manifest.get("ok").and_then(Value::as_bool) == Some(false) {
+        bail!("{:?} records a failed run whose outputs are not listed; rerun the split or clean without --manifest", path);
+    }
+    let outputs = manifest
+        .get("outputs")
+        .or_else(|| manifest.get("completed"))
+        .and_then(Value::as_array)
+        .with_context(|| format!("{:?} has neither \"outputs\" nor \"completed\"", path))?;
+    let outputs = outputs.iter().filter_map(Value::as_str).map(file_name).collect();
+    // URL の入力は、ダウンロードしたファイルの名前で章を書き出す
+    let input_name = if fetch::is_url(Path::new(input)) { fetch::file_name(input) } else { input.to_string() };
+    let stem = Path::new(&input_name).file_stem().context("Invalid input file name in the manifest")?.to_string_lossy().into_owned();
+    // 長すぎる入力名は、分割のときと同じく切り詰めた名前で章のファイルを書き出している
+    let stem = plan::output_stem(&stem, dir).unwrap_or(stem);
+    Ok(ListedOutputs { stem, outputs })
+}
+
+/// ローカルのパスか、アップロードしたオブジェクトの URL の最後の要素
+fn file_name(output: &str) -> String {
+    if output.contains("://") {
+        fetch::percent_decode(output.rsplit('/').next().unwrap_or(output))
+    } else {
+        Path::new(output).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+}
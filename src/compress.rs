@@ -0,0 +1,54 @@
+use clap::ValueEnum;
+use flate2::Compression as Level;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use lopdf::{Object, Stream};
+use std::io::{Read, Write};
+
+/// 保存時のストリーム再圧縮
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// 元のまま書き出す（最速）
+    None,
+    /// 未圧縮のストリームだけを低い圧縮レベルで圧縮する
+    Fast,
+    /// 未圧縮のストリームに加え、Flate 圧縮済みのストリームも最高レベルで圧縮し直す
+    Best,
+}
+
+impl Compression {
+    /// 再圧縮したストリームを返す。対象外か、圧縮しても小さくならない場合は `None`
+    pub fn apply(self, stream: &Stream) -> Option<Stream> {
+        // PDF/A ではXMPメタデータを圧縮せずに置くため触らない
+        if self == Compression::None || matches!(stream.dict.get(b"Type").and_then(|o| o.as_name()), Ok(b"Metadata")) {
+            return None;
+        }
+        let (data, level) = match stream.dict.get(b"Filter") {
+            Err(_) => (stream.content.clone(), if self == Compression::Best { Level::best() } else { Level::fast() }),
+            Ok(filter) if self == Compression::Best && is_plain_flate(filter) && !stream.dict.has(b"DecodeParms") => {
+                let mut data = Vec::new();
+                ZlibDecoder::new(stream.content.as_slice()).read_to_end(&mut data).ok()?;
+                (data, Level::best())
+            }
+            Ok(_) => return None,
+        };
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(&data).ok()?;
+        let compressed = encoder.finish().ok()?;
+        if compressed.len() >= stream.content.len() {
+            return None;
+        }
+        let mut dict = stream.dict.clone();
+        dict.set("Filter", "FlateDecode");
+        Some(Stream::new(dict, compressed))
+    }
+}
+
+fn is_plain_flate(filter: &Object) -> bool {
+    match filter {
+        Object::Name(name) => name == b"FlateDecode",
+        Object::Array(filters) => matches!(filters.as_slice(), [Object::Name(name)] if name == b"FlateDecode"),
+        _ => false,
+    }
+}
@@ -0,0 +1,333 @@
+use crate::cert::{self, KeyFile};
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use anyhow::{Context, Result, anyhow, bail};
+use lopdf::encryption::{DecryptionError, decrypt_object, get_encryption_key};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::borrow::Cow;
+
+// 鍵を作るときにパスワードの後ろを埋めるバイト列（PDF の仕様の Algorithm 2）
+const PAD_BYTES: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08, 0x2E, 0x2E, 0x00, 0xB6,
+    0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// 文字列・ストリームの暗号方式（暗号フィルターの /CFM）
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Cipher {
+    #[default]
+    Rc4,
+    /// /AESV2（AES-128、CBC モード）
+    Aes128,
+    /// /AESV3（AES-256、CBC モード）
+    Aes256,
+    /// 暗号化されていない（/Identity）
+    Identity,
+}
+
+/// 暗号化された文書の文字列を、オブジェクトごとの鍵で復号する
+///
+/// 文書全体は復号しない（出力は元の暗号化を保ったまま保存される）。
+/// 目次タイトルや名前付き宛先のキーなど、ツール自身が読む文字列だけを復号する。
+/// 標準セキュリティハンドラの RC4（/V 1・2・4）と AES（/V 4 の /AESV2、/V 5 の /AESV3）に対応する。
+#[derive(Debug, Default)]
+pub struct StringDecryptor {
+    key: Option<Vec<u8>>,
+    // 文字列（/StrF）とストリーム（/StmF）の暗号方式
+    strings: Cipher,
+    streams: Cipher,
+}
+
+impl StringDecryptor {
+    /// 暗号化されていない文書では何もしない復号器を返す。
+    /// パスワード未指定の場合は空のユーザーパスワードを試す。
+    /// 証明書で暗号化された文書では、パスワードの代わりに `key_file`（`--cert`）の鍵を使う。
+    pub fn for_document(doc: &Document, password: Option<&str>, key_file: Option<&KeyFile>) -> Result<Self> {
+        if !doc.is_encrypted() {
+            return Ok(Self::default());
+        }
+        let encrypt = doc.get_encrypted()?;
+        let version = encrypt.get(b"V").and_then(Object::as_i64).unwrap_or(0);
+        if cert::is_public_key(encrypt) {
+            let key = Some(cert::file_key(doc, key_file)?);
+            return Ok(match version {
+                4 => Self { key, strings: crypt_filter(doc, encrypt, b"StrF")?, streams: crypt_filter(doc, encrypt, b"StmF")? },
+                5 => Self { key, strings: Cipher::Aes256, streams: Cipher::Aes256 },
+                _ => Self { key, ..Self::default() },
+            });
+        }
+        let incorrect = || match password {
+            None => anyhow!("Document is encrypted; supply the password with --password"),
+            Some(_) => anyhow!("Failed to decrypt document: {}", DecryptionError::IncorrectPassword),
+        };
+        let password = password.unwrap_or("").as_bytes();
+        match version {
+            4 => {
+                let strings = crypt_filter(doc, encrypt, b"StrF")?;
+                let streams = crypt_filter(doc, encrypt, b"StmF")?;
+                // AES-128 の鍵は常に16バイト
+                let key_len = if [strings, streams].contains(&Cipher::Aes128) {
+                    16
+                } else {
+                    (encrypt.get(b"Length").and_then(Object::as_i64).unwrap_or(128) / 8).clamp(5, 16) as usize
+                };
+                let key = key_r4(doc, encrypt, password, key_len)?.ok_or_else(incorrect)?;
+                Ok(Self { key: Some(key), strings, streams })
+            }
+            5 => {
+                let key = key_r6(encrypt, password)?.ok_or_else(incorrect)?;
+                Ok(Self { key: Some(key), strings: Cipher::Aes256, streams: Cipher::Aes256 })
+            }
+            _ => {
+                let key = get_encryption_key(doc, password, true).map_err(|e| match e {
+                    DecryptionError::IncorrectPassword => incorrect(),
+                    e => anyhow!("Failed to decrypt document: {}", e),
+                })?;
+                Ok(Self { key: Some(key), ..Self::default() })
+            }
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// `container` は文字列を直接含む間接オブジェクトのID
+    pub fn decrypt<'a>(&self, container: ObjectId, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        match &self.key {
+            Some(key) => decrypt_bytes(key, self.strings, container, bytes).map(Cow::Owned).unwrap_or(Cow::Borrowed(bytes)),
+            None => Cow::Borrowed(bytes),
+        }
+    }
+
+    /// 添付ファイルなど、ツール自身が中身を取り出すストリームを復号する（`id` はストリームのID）
+    pub fn decrypt_stream<'a>(&self, id: ObjectId, stream: &'a Stream) -> Cow<'a, Stream> {
+        let Some(key) = &self.key else { return Cow::Borrowed(stream) };
+        match decrypt_bytes(key, self.streams, id, &stream.content) {
+            Ok(content) => {
+                let mut decrypted = stream.clone();
+                decrypted.set_content(content);
+                Cow::Owned(decrypted)
+            }
+            Err(_) => Cow::Borrowed(stream),
+        }
+    }
+
+    /// 間接オブジェクト `id` に含まれるすべての文字列と、ストリームの内容をその場で復号する（`decrypt` サブコマンド用）
+    ///
+    /// 電子署名の辞書（/ByteRange を持つ辞書）の /Contents は暗号化されないため触らない。
+    pub fn decrypt_in_place(&self, id: ObjectId, object: &mut Object) -> Result<()> {
+        let Some(key) = &self.key else { return Ok(()) };
+        let context = || format!("Failed to decrypt object {} {}", id.0, id.1);
+        let decrypt = |bytes: &mut Vec<u8>| -> Result<()> {
+            *bytes = decrypt_bytes(key, self.strings, id, bytes).with_context(context)?;
+            Ok(())
+        };
+        decrypt_strings(object, &decrypt)?;
+        if let Object::Stream(stream) = object {
+            let content = decrypt_bytes(key, self.streams, id, &stream.content).with_context(context)?;
+            stream.set_content(content);
+        }
+        Ok(())
+    }
+
+    /// 宛先が文字列（名前付き宛先）なら復号した文字列を返す
+    pub fn decrypt_dest<'a>(&self, doc: &'a Document, container: ObjectId, dest: &'a Object) -> Cow<'a, Object> {
+        if !self.is_active() {
+            return Cow::Borrowed(dest);
+        }
+        let (container, target) = match dest {
+            Object::Reference(id) => match doc.get_object(*id) {
+                Ok(obj) => (*id, obj),
+                Err(_) => return Cow::Borrowed(dest),
+            },
+            _ => (container, dest),
+        };
+        match target {
+            Object::String(bytes, format) => {
+                Cow::Owned(Object::String(self.decrypt(container, bytes).into_owned(), *format))
+            }
+            _ => Cow::Borrowed(dest),
+        }
+    }
+}
+
+/// オブジェクト `id` の文字列またはストリームの内容 `bytes` を復号する
+fn decrypt_bytes(key: &[u8], cipher: Cipher, id: ObjectId, bytes: &[u8]) -> Result<Vec<u8>> {
+    let aes_key = match cipher {
+        Cipher::Identity => return Ok(bytes.to_vec()),
+        Cipher::Rc4 => return Ok(decrypt_object(key, id, &Object::String(bytes.to_vec(), StringFormat::Literal))?),
+        Cipher::Aes256 => Cow::Borrowed(key),
+        // オブジェクトごとの鍵: 文書の鍵にオブジェクト番号（下位3バイト）・世代番号（下位2バイト）と "sAlT" を足した MD5
+        Cipher::Aes128 => {
+            let mut material = key.to_vec();
+            material.extend_from_slice(&id.0.to_le_bytes()[..3]);
+            material.extend_from_slice(&id.1.to_le_bytes()[..2]);
+            material.extend_from_slice(b"sAlT");
+            Cow::Owned(md5::compute(material).0[..(key.len() + 5).min(16)].to_vec())
+        }
+    };
+    // 先頭の16バイトが IV、続きが PKCS#5 で埋めた暗号文
+    let Some((iv, data)) = bytes.split_at_checked(16) else {
+        bail!("the AES-encrypted data is shorter than its IV");
+    };
+    let decrypted = match cipher {
+        Cipher::Aes128 => cbc::Decryptor::<aes::Aes128>::new_from_slices(&aes_key, iv)?.decrypt_padded_vec_mut::<Pkcs7>(data),
+        _ => cbc::Decryptor::<aes::Aes256>::new_from_slices(&aes_key, iv)?.decrypt_padded_vec_mut::<Pkcs7>(data),
+    };
+    decrypted.map_err(|_| anyhow!("the AES-encrypted data has bad padding"))
+}
+
+/// /V 4 の暗号フィルター（/StrF・/StmF の名前で /CF から引く）の暗号方式
+fn crypt_filter(doc: &Document, encrypt: &Dictionary, key: &[u8]) -> Result<Cipher> {
+    let name = encrypt.get(key).and_then(Object::as_name).unwrap_or(b"Identity");
+    if name == b"Identity" {
+        return Ok(Cipher::Identity);
+    }
+    let method = encrypt
+        .get(b"CF")
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_dict())
+        .and_then(|filters| filters.get(name))
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_dict())
+        .map(|params| params.get(b"CFM").and_then(Object::as_name).unwrap_or(b"None"))
+        .map_err(|_| anyhow!("Failed to decrypt document: the crypt filter /{} is missing", String::from_utf8_lossy(name)))?;
+    match method {
+        b"V2" => Ok(Cipher::Rc4),
+        b"AESV2" => Ok(Cipher::Aes128),
+        b"None" => Ok(Cipher::Identity),
+        method => bail!("Failed to decrypt document: the crypt filter method /{} is not supported", String::from_utf8_lossy(method)),
+    }
+}
+
+/// /V 4（/R 4）の文書の鍵（Algorithm 2）。ユーザーパスワードが合わなければ `None`
+fn key_r4(doc: &Document, encrypt: &Dictionary, password: &[u8], key_len: usize) -> Result<Option<Vec<u8>>> {
+    let field = |name: &[u8]| encrypt.get(name).and_then(Object::as_str).map_err(|_| anyhow!("the encryption dictionary has no /{}", String::from_utf8_lossy(name)));
+    let owner = field(b"O")?;
+    let user = field(b"U")?;
+    let permissions = encrypt.get(b"P").and_then(Object::as_i64).context("the encryption dictionary has no /P")? as u32;
+    let file_id = doc
+        .trailer
+        .get(b"ID")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|id| id.first())
+        .and_then(|id| id.as_str().ok())
+        .context("the document has no /ID")?;
+
+    let mut material = password[..password.len().min(32)].to_vec();
+    material.extend_from_slice(&PAD_BYTES[..32 - material.len()]);
+    material.extend_from_slice(owner);
+    material.extend_from_slice(&permissions.to_le_bytes());
+    material.extend_from_slice(file_id);
+    if !encrypt.get(b"EncryptMetadata").and_then(Object::as_bool).unwrap_or(true) {
+        material.extend_from_slice(&[0xFF; 4]);
+    }
+    let mut key = md5::compute(material).0[..key_len].to_vec();
+    for _ in 0..50 {
+        key = md5::compute(&key).0[..key_len].to_vec();
+    }
+
+    // Algorithm 5: パディングと /ID の MD5 を、鍵と鍵の各バイトを 1〜19 と XOR したもので20回 RC4 暗号化したものが /U の先頭16バイト
+    let mut check = md5::compute([PAD_BYTES.as_slice(), file_id].concat()).0.to_vec();
+    for round in 0..20u8 {
+        let round_key: Vec<u8> = key.iter().map(|b| b ^ round).collect();
+        check = rc4(&round_key, &check);
+    }
+    Ok((user.get(..16) == Some(check.as_slice())).then_some(key))
+}
+
+/// /V 5（/R 5・/R 6、AES-256）の文書の鍵（Algorithm 2.A）。ユーザーパスワードが合わなければ `None`
+fn key_r6(encrypt: &Dictionary, password: &[u8]) -> Result<Option<Vec<u8>>> {
+    let field = |name: &[u8], len: usize| {
+        encrypt
+            .get(name)
+            .and_then(Object::as_str)
+            .ok()
+            .and_then(|value| value.get(..len))
+            .with_context(|| format!("the encryption dictionary has no valid /{}", String::from_utf8_lossy(name)))
+    };
+    let user = field(b"U", 48)?;
+    let user_key = field(b"UE", 32)?;
+    let revision = encrypt.get(b"R").and_then(Object::as_i64).unwrap_or(6);
+    // パスワードは UTF-8 で最大127バイト
+    let password = &password[..password.len().min(127)];
+
+    // /U は、ハッシュ（32バイト）・検証用のソルト（8バイト）・鍵用のソルト（8バイト）
+    if hash_r6(revision, password, &user[32..40])? != user[..32] {
+        return Ok(None);
+    }
+    let intermediate = hash_r6(revision, password, &user[40..48])?;
+    let key = cbc::Decryptor::<aes::Aes256>::new_from_slices(&intermediate, &[0; 16])?
+        .decrypt_padded_vec_mut::<NoPadding>(user_key)
+        .map_err(|_| anyhow!("the encryption dictionary has a bad /UE"))?;
+    Ok(Some(key))
+}
+
+/// /R 6 のパスワードのハッシュ（Algorithm 2.B）。/R 5 では SHA-256 だけ
+fn hash_r6(revision: i64, password: &[u8], salt: &[u8]) -> Result<Vec<u8>> {
+    let mut hash = Sha256::digest([password, salt].concat()).to_vec();
+    if revision < 6 {
+        return Ok(hash);
+    }
+    let mut round = 0;
+    loop {
+        let block = [password, &hash].concat().repeat(64);
+        let encrypted = cbc::Encryptor::<aes::Aes128>::new_from_slices(&hash[..16], &hash[16..32])?
+            .encrypt_padded_vec_mut::<NoPadding>(&block);
+        // 暗号文の先頭16バイトを1つの数として3で割った余り（各バイトの和の余りと同じ）で次のハッシュを選ぶ
+        hash = match encrypted[..16].iter().map(|b| u32::from(*b)).sum::<u32>() % 3 {
+            0 => Sha256::digest(&encrypted).to_vec(),
+            1 => Sha384::digest(&encrypted).to_vec(),
+            _ => Sha512::digest(&encrypted).to_vec(),
+        };
+        round += 1;
+        if round >= 64 && u32::from(encrypted[encrypted.len() - 1]) + 32 <= round {
+            break;
+        }
+    }
+    hash.truncate(32);
+    Ok(hash)
+}
+
+/// RC4（暗号化と復号は同じ処理）
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// ストリームの内容を除く、オブジェクトの中のすべての文字列に `decrypt` をかける
+fn decrypt_strings(object: &mut Object, decrypt: &impl Fn(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+    match object {
+        Object::String(bytes, _) => decrypt(bytes),
+        Object::Array(items) => items.iter_mut().try_for_each(|item| decrypt_strings(item, decrypt)),
+        Object::Dictionary(dict) => decrypt_dict_strings(dict, decrypt),
+        Object::Stream(stream) => decrypt_dict_strings(&mut stream.dict, decrypt),
+        _ => Ok(()),
+    }
+}
+
+fn decrypt_dict_strings(dict: &mut Dictionary, decrypt: &impl Fn(&mut Vec<u8>) -> Result<()>) -> Result<()> {
+    let signature = dict.has(b"ByteRange");
+    for (key, value) in dict.iter_mut() {
+        if !(signature && key == b"Contents") {
+            decrypt_strings(value, decrypt)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// CSV ファイルを読み込んで行ごとの欄に分ける（Excel で保存した CSV の BOM は取り除く）
+pub fn read(path: &Path) -> io::Result<Vec<Vec<String>>> {
+    let text = fs::read_to_string(path)?;
+    Ok(parse(text.strip_prefix('\u{feff}').unwrap_or(&text)))
+}
+
+/// RFC 4180 の CSV（引用符で囲んだ欄の中のカンマ・改行・`""` に対応する）
+pub fn parse(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
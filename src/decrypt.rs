@@ -0,0 +1,47 @@
+use crate::cert::KeyFile;
+use crate::crypt::StringDecryptor;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, save_document};
+use anyhow::{Result, bail};
+use lopdf::Object;
+use std::path::Path;
+
+/// `decrypt` サブコマンド: 暗号化された文書の文字列とストリームをすべて復号し、暗号化を外した複製を保存する
+///
+/// 鍵は目次タイトルの復号と同じく、パスワード（未指定なら空のユーザーパスワード）か `--cert` の鍵から求める。
+/// 暗号方式も同じく RC4 と AES（128・256 ビット）に対応する。
+pub fn run(input_path: &Path, output_path: &Path, password: Option<&str>, key_file: Option<&KeyFile>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut doc = load_document(input_path, false)?;
+    let Ok(encrypt) = doc.get_encrypted() else {
+        bail!("{:?} is not encrypted", input_path);
+    };
+    // PDF 1.5 以降では、XMP メタデータを暗号化せずに置ける
+    let metadata_encrypted = encrypt.get(b"EncryptMetadata").and_then(Object::as_bool).unwrap_or(true);
+    let encrypt_id = doc.trailer.get(b"Encrypt").and_then(Object::as_reference).ok();
+    let decryptor = StringDecryptor::for_document(&doc, password, key_file)?;
+
+    let mut decrypted = 0;
+    for (id, object) in doc.objects.iter_mut() {
+        // 暗号化辞書と相互参照ストリームは暗号化されない（オブジェクトストリームの中身は読み込んだ時点で展開されている）
+        if Some(*id) == encrypt_id
+            || matches!(object.type_name(), Ok("XRef") | Ok("ObjStm"))
+            || (!metadata_encrypted && matches!(object.type_name(), Ok("Metadata")))
+        {
+            continue;
+        }
+        decryptor.decrypt_in_place(*id, object)?;
+        decrypted += 1;
+    }
+    if let Some(encrypt_id) = encrypt_id {
+        doc.objects.remove(&encrypt_id);
+    }
+    doc.trailer.remove(b"Encrypt");
+
+    save_document(&doc, output_path)?;
+    progress.println(format!("Wrote {:?} ({} objects decrypted).", output_path, decrypted));
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use crate::page_ranges::PageRanges;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, save_pages};
+use anyhow::{Result, bail};
+use lopdf::ObjectId;
+use std::path::Path;
+use std::sync::Arc;
+
+/// `delete` サブコマンド: 分割と同じページのコピー（`save_pages`）で、指定したページを除いた文書を保存する
+///
+/// 消したページからしか使われていなかった画像やフォントなどのオブジェクトも書き出さない。
+pub fn run(input_path: &Path, output_path: &Path, pages: &PageRanges) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let doc = Arc::new(load_document(input_path, false)?);
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let kept: Vec<ObjectId> = page_numbers.iter().filter(|(page, _)| !pages.contains(**page)).map(|(_, id)| *id).collect();
+    let deleted = page_numbers.len() - kept.len();
+    if deleted == 0 {
+        bail!("No pages of {:?} are in --pages (the document has {} pages)", input_path, page_numbers.len());
+    }
+    if kept.is_empty() {
+        bail!("--pages covers every page of {:?}; at least one page must remain", input_path);
+    }
+
+    save_pages(&doc, input_path, &kept, output_path, &progress)?;
+    progress.println(format!("Deleted {} of {} pages; wrote {:?}.", deleted, page_numbers.len(), output_path));
+    Ok(())
+}
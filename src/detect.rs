@@ -0,0 +1,310 @@
+use crate::text::collect_text;
+use anyhow::Result;
+use clap::ValueEnum;
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// OCR には tesseract のコマンドラインツールを使う
+#[cfg(feature = "ocr")]
+const TESSERACT: &str = "tesseract";
+
+// OCR 用の描画幅（ピクセル）。A4・Letter でおよそ 200dpi
+#[cfg(feature = "ocr")]
+const OCR_WIDTH: i32 = 1700;
+
+// 見出しを探すページ上部の割合と、その中で見る行数
+#[cfg(feature = "ocr")]
+const TOP_REGION: f32 = 0.3;
+#[cfg(feature = "ocr")]
+const HEADING_LINES: usize = 3;
+
+// 本文の文字の大きさ（中央値）に対して、この倍率以上の大きさで始まるページを章の始まりとみなす
+const HEADING_SIZE_RATIO: f32 = 1.4;
+// 本文の2倍の大きさで確からしさを 1 とする
+const FULL_CONFIDENCE_RATIO: f32 = 2.0;
+
+// 見出しとして使うタイトルの最大文字数
+const MAX_TITLE_CHARS: usize = 80;
+
+/// 目次の無い文書で章の始まりを推定する方法（`--detect`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Detect {
+    /// 各ページの上部を OCR し、「Chapter 3」「第3章」などの見出しのあるページを章の始まりとする（テキストの無いスキャン向け）
+    Ocr,
+    /// 各ページの最初の行の文字の大きさが本文より十分大きいページを章の始まりとする（目次の無い電子的に作られた文書向け）
+    FontSize,
+}
+
+/// 推定した章の始まり
+#[derive(Debug, Clone)]
+pub struct Boundary {
+    pub page: u32,
+    pub title: String,
+    /// 推定の確からしさ（0〜1。OCR の見出しは文字列で判定するため無い）
+    pub confidence: Option<f32>,
+}
+
+/// `--detect font-size` の結果（本文の文字の大きさと、推定した章の始まり）
+pub struct FontSizeDetection {
+    pub body_size: f32,
+    pub boundaries: Vec<Boundary>,
+}
+
+/// 内容ストリームの1つの文字列描画（Tj/TJ など）
+struct TextRun {
+    /// ベースラインの高さ（ユーザー空間）
+    y: f32,
+    /// 実際の文字の大きさ（Tf の大きさにテキスト行列と CTM の拡大率を掛けたもの）
+    size: f32,
+    text: String,
+}
+
+/// 各ページの最初の行（最も上にある行）の文字の大きさから章の始まりを推定する（`--detect font-size`）
+///
+/// 本文の大きさは文書全体の文字数で重み付けした中央値とし、最初の行がその `HEADING_SIZE_RATIO` 倍以上の
+/// ページを章の始まりとする。最初の行の文字列を章のタイトルにする。内容ストリームを読めないページは飛ばす。
+pub fn font_size_headings(doc: &Document, page_numbers: &BTreeMap<u32, ObjectId>) -> FontSizeDetection {
+    let pages: Vec<(u32, Vec<TextRun>)> = page_numbers
+        .par_iter()
+        .map(|(number, id)| (*number, text_runs(doc, *id).unwrap_or_default()))
+        .collect();
+
+    let mut sizes: Vec<(f32, usize)> = pages
+        .iter()
+        .flat_map(|(_, runs)| runs.iter().map(|run| (run.size, run.text.chars().filter(|c| !c.is_whitespace()).count())))
+        .filter(|(_, chars)| *chars > 0)
+        .collect();
+    sizes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total: usize = sizes.iter().map(|(_, chars)| chars).sum();
+    let mut seen = 0;
+    let body_size = sizes
+        .iter()
+        .find(|(_, chars)| {
+            seen += chars;
+            seen * 2 >= total
+        })
+        .map_or(0.0, |(size, _)| *size);
+
+    let boundaries = pages
+        .iter()
+        .filter_map(|(number, runs)| {
+            let (size, text) = first_line(runs)?;
+            let ratio = size / body_size;
+            (body_size > 0.0 && ratio >= HEADING_SIZE_RATIO).then(|| Boundary {
+                page: *number,
+                title: if text.is_empty() { format!("Page {}", number) } else { text.chars().take(MAX_TITLE_CHARS).collect() },
+                confidence: Some(((ratio - 1.0) / (FULL_CONFIDENCE_RATIO - 1.0)).min(1.0)),
+            })
+        })
+        .collect();
+    FontSizeDetection { body_size, boundaries }
+}
+
+/// 最も上にある行の文字の大きさと文字列（ベースラインの差が文字の大きさの半分以内の描画を同じ行とする）
+///
+/// 上端に置かれたページ番号だけの行は飛ばし、その次の行を使う。
+fn first_line(runs: &[TextRun]) -> Option<(f32, String)> {
+    let mut remaining: Vec<&TextRun> = runs.iter().collect();
+    loop {
+        let top = *remaining.iter().max_by(|a, b| a.y.total_cmp(&b.y))?;
+        let (line, rest): (Vec<&TextRun>, Vec<&TextRun>) =
+            remaining.iter().partition(|run| (run.y - top.y).abs() <= top.size / 2.0);
+        let text = line.iter().map(|run| run.text.as_str()).collect::<String>();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !is_numbering(&text) {
+            return Some((line.iter().map(|run| run.size).fold(0.0, f32::max), text));
+        }
+        remaining = rest;
+    }
+}
+
+/// ページの内容ストリームから、空白でない文字列の描画を位置と大きさ付きで取り出す
+fn text_runs(doc: &Document, page_id: ObjectId) -> Option<Vec<TextRun>> {
+    let encodings: HashMap<Vec<u8>, &str> = doc
+        .get_page_fonts(page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font.get_font_encoding()))
+        .collect();
+    let content = Content::decode(&doc.get_page_content(page_id).ok()?).ok()?;
+
+    let mut runs = Vec::new();
+    let mut ctm = IDENTITY;
+    let mut saved = Vec::new();
+    let mut line_matrix = IDENTITY;
+    let (mut encoding, mut font_size, mut leading) = (None, 0.0, 0.0);
+    for operation in &content.operations {
+        let operands: Vec<f32> = operation.operands.iter().filter_map(|o| o.as_float().ok()).collect();
+        match (operation.operator.as_str(), operands.as_slice()) {
+            ("q", _) => saved.push(ctm),
+            ("Q", _) => ctm = saved.pop().unwrap_or(IDENTITY),
+            ("cm", [a, b, c, d, e, f]) => ctm = multiply([*a, *b, *c, *d, *e, *f], ctm),
+            ("BT", _) => line_matrix = IDENTITY,
+            ("Tm", [a, b, c, d, e, f]) => line_matrix = [*a, *b, *c, *d, *e, *f],
+            ("Td", [tx, ty]) => line_matrix = multiply([1.0, 0.0, 0.0, 1.0, *tx, *ty], line_matrix),
+            ("TD", [tx, ty]) => {
+                leading = -ty;
+                line_matrix = multiply([1.0, 0.0, 0.0, 1.0, *tx, *ty], line_matrix);
+            }
+            ("TL", [tl]) => leading = *tl,
+            ("T*", _) => line_matrix = multiply([1.0, 0.0, 0.0, 1.0, 0.0, -leading], line_matrix),
+            ("Tf", _) => {
+                encoding = operation.operands.first().and_then(|o| o.as_name().ok()).and_then(|name| encodings.get(name).copied());
+                font_size = operation.operands.get(1).and_then(|o| o.as_float().ok()).unwrap_or(0.0);
+            }
+            ("Tj" | "TJ" | "'" | "\"", _) => {
+                if matches!(operation.operator.as_str(), "'" | "\"") {
+                    line_matrix = multiply([1.0, 0.0, 0.0, 1.0, 0.0, -leading], line_matrix);
+                }
+                let mut text = String::new();
+                let strings = operation.operands.iter().filter(|o| matches!(o, Object::String(..) | Object::Array(_)));
+                collect_text(&mut text, encoding, &strings.cloned().collect::<Vec<_>>());
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let matrix = multiply(line_matrix, ctm);
+                runs.push(TextRun {
+                    y: matrix[5],
+                    size: font_size.abs() * (matrix[2] * matrix[2] + matrix[3] * matrix[3]).sqrt(),
+                    text,
+                });
+            }
+            _ => {}
+        }
+    }
+    Some(runs)
+}
+
+const IDENTITY: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// アフィン変換行列の積（`m` のあとに `n` を適用する）
+fn multiply(m: [f32; 6], n: [f32; 6]) -> [f32; 6] {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+/// 行が章の見出しらしければ、章のタイトルにする文字列を返す
+///
+/// 「Chapter 3」「PART II」「Appendix A」のような語と番号、「第3章」「第二部」、
+/// 「3 Introduction」「3. Introduction」のような番号付きの行を見出しとみなす。
+#[cfg_attr(not(feature = "ocr"), allow(dead_code))]
+pub fn heading_title(line: &str) -> Option<String> {
+    let title = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut words = title.split(' ');
+    let first = words.next()?;
+    let second = words.next();
+
+    let is_heading = if ["chapter", "part", "appendix", "book", "section"].contains(&first.to_lowercase().as_str()) {
+        second.is_some_and(is_numbering)
+    } else if let Some(rest) = first.strip_prefix('第') {
+        let end = rest.find(|c: char| !is_japanese_numeral(c)).unwrap_or(rest.len());
+        end > 0 && rest[end..].starts_with(['章', '部', '編'])
+    } else {
+        // 「3」「3.」のあとに大文字か日本語の語が続く（ページ番号だけの行や本文の箇条書きは除く）
+        let number = first.strip_suffix('.').unwrap_or(first);
+        (1..=2).contains(&number.len())
+            && number.chars().all(|c| c.is_ascii_digit())
+            && second.and_then(|word| word.chars().next()).is_some_and(|c| c.is_uppercase() || !c.is_ascii())
+    };
+    is_heading.then(|| title.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+/// 章番号・ページ番号らしい語（アラビア数字・ローマ数字・1文字の英字。末尾の「.」「:」は無視する）
+fn is_numbering(word: &str) -> bool {
+    let word = word.trim_end_matches(['.', ':']);
+    !word.is_empty()
+        && (word.chars().all(|c| c.is_ascii_digit())
+            || word.chars().all(|c| "IVXLCivxlc".contains(c))
+            || (word.len() == 1 && word.chars().all(|c| c.is_ascii_uppercase())))
+}
+
+#[cfg_attr(not(feature = "ocr"), allow(dead_code))]
+fn is_japanese_numeral(c: char) -> bool {
+    c.is_ascii_digit() || ('０'..='９').contains(&c) || "一二三四五六七八九十百".contains(c)
+}
+
+/// 各ページの上部を OCR して章の見出しを探し、見出しのあるページを章の始まりとする（`--detect ocr`）
+///
+/// 描画には PDFium を使うため、`ocr` フィーチャー付きでビルドし、実行時に PDFium の共有ライブラリと
+/// tesseract（`lang` の言語データを含む）が必要になる。
+#[cfg(feature = "ocr")]
+pub fn ocr_headings(input_path: &Path, password: Option<&str>, lang: &str) -> Result<Vec<Boundary>> {
+    use anyhow::{Context, bail};
+    use pdfium_render::prelude::PdfRenderConfig;
+    use std::process::Command;
+
+    let pdfium = crate::thumbnails::bind_pdfium("--detect ocr")?;
+    let document = pdfium.load_pdf_from_file(input_path, password)?;
+    let config = PdfRenderConfig::new().set_target_width(OCR_WIDTH);
+    let png_path = std::env::temp_dir().join(format!("pdf_splitter-ocr-{}.png", std::process::id()));
+
+    // 途中で失敗しても一時ファイルを残さない
+    let headings = (|| {
+        let mut headings = Vec::new();
+        for (index, page) in document.pages().iter().enumerate() {
+            let page_number = index as u32 + 1;
+            let image = page.render_with_config(&config)?.as_image();
+            let top = (image.height() as f32 * TOP_REGION).ceil() as u32;
+            image
+                .crop_imm(0, 0, image.width(), top)
+                .save_with_format(&png_path, image::ImageFormat::Png)
+                .with_context(|| format!("Failed to write {:?}", png_path))?;
+            let output = Command::new(TESSERACT)
+                .arg(&png_path)
+                .args(["stdout", "-l", lang, "--psm", "6"])
+                .output()
+                .with_context(|| format!("Failed to run {}", TESSERACT))?;
+            if !output.status.success() {
+                bail!(
+                    "{} failed on page {}: {}",
+                    TESSERACT,
+                    page_number,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            let text = String::from_utf8_lossy(&output.stdout);
+            let heading = text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .take(HEADING_LINES)
+                .find_map(heading_title);
+            if let Some(title) = heading {
+                headings.push(Boundary { page: page_number, title, confidence: None });
+            }
+        }
+        Ok(headings)
+    })();
+    let _ = std::fs::remove_file(&png_path);
+    headings
+}
+
+/// tesseract が使えるか（処理を始める前に確かめる）
+#[cfg(feature = "ocr")]
+pub fn check_ocr_available() -> Result<()> {
+    use anyhow::Context;
+
+    std::process::Command::new(TESSERACT)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("--detect ocr requires `{}` on PATH", TESSERACT))?;
+    Ok(())
+}
+
+/// `ocr` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "ocr"))]
+pub fn check_ocr_available() -> Result<()> {
+    anyhow::bail!("--detect ocr is not available in this build; rebuild with `cargo build --release --features ocr`")
+}
+
+#[cfg(not(feature = "ocr"))]
+pub fn ocr_headings(_input_path: &Path, _password: Option<&str>, _lang: &str) -> Result<Vec<Boundary>> {
+    check_ocr_available().map(|()| Vec::new())
+}
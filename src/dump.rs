@@ -0,0 +1,156 @@
+use crate::crypt::StringDecryptor;
+use anyhow::{Context, Result};
+use lopdf::{Document, Object, ObjectId, StringFormat};
+use std::fmt::Write;
+
+// ストリームの内容を表示する上限（バイト）
+const MAX_STREAM_PREVIEW: usize = 2048;
+
+/// `--debug-object`: 元の文書の間接オブジェクトを PDF の構文に近い形で字下げして書き出す
+///
+/// 不具合を報告するときに、問題のオブジェクトをそのまま添えられるようにする。
+/// 暗号化文書では文字列を復号して表示する。ストリームは辞書と長さに加え、展開した内容が
+/// テキストとして読めれば先頭の一部を表示する。
+pub fn format_object(doc: &Document, decryptor: &StringDecryptor, id: ObjectId) -> Result<String> {
+    let object = doc
+        .get_object(id)
+        .with_context(|| format!("object {} {} R is not in the document", id.0, id.1))?;
+    let mut out = format!("{} {} obj\n", id.0, id.1);
+    let printer = Printer { decryptor, container: id };
+    match object {
+        Object::Stream(stream) => {
+            printer.write(&mut out, &Object::Dictionary(stream.dict.clone()), 0);
+            out.push('\n');
+            match stream.decompressed_content() {
+                Ok(data) => {
+                    let _ = writeln!(out, "stream ({} bytes, {} bytes decoded)", stream.content.len(), data.len());
+                    out.push_str(&preview(&data));
+                }
+                Err(_) if stream.filters().map(|filters| filters.is_empty()).unwrap_or(true) => {
+                    let _ = writeln!(out, "stream ({} bytes)", stream.content.len());
+                    out.push_str(&preview(&stream.content));
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "stream ({} bytes, cannot be decoded: {})", stream.content.len(), e);
+                }
+            }
+            out.push_str("endstream\n");
+        }
+        object => {
+            printer.write(&mut out, object, 0);
+            out.push('\n');
+        }
+    }
+    out.push_str("endobj");
+    Ok(out)
+}
+
+struct Printer<'a> {
+    decryptor: &'a StringDecryptor,
+    /// 文字列を復号するときの鍵になる間接オブジェクト
+    container: ObjectId,
+}
+
+impl Printer<'_> {
+    fn write(&self, out: &mut String, object: &Object, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match object {
+            Object::Null => out.push_str("null"),
+            Object::Boolean(value) => out.push_str(if *value { "true" } else { "false" }),
+            Object::Integer(value) => out.push_str(&value.to_string()),
+            Object::Real(value) => out.push_str(&value.to_string()),
+            Object::Name(name) => out.push_str(&name_syntax(name)),
+            Object::String(bytes, format) => {
+                let bytes = self.decryptor.decrypt(self.container, bytes);
+                out.push_str(&string_syntax(&bytes, *format));
+            }
+            Object::Reference(id) => {
+                let _ = write!(out, "{} {} R", id.0, id.1);
+            }
+            Object::Array(items) => {
+                // 数値や名前だけの短い配列（MediaBox など）は1行にまとめる
+                if items.iter().all(|item| !matches!(item, Object::Array(_) | Object::Dictionary(_) | Object::Stream(_))) {
+                    out.push('[');
+                    for (index, item) in items.iter().enumerate() {
+                        if index > 0 {
+                            out.push(' ');
+                        }
+                        self.write(out, item, depth);
+                    }
+                    out.push(']');
+                    return;
+                }
+                out.push_str("[\n");
+                for item in items {
+                    out.push_str(&indent);
+                    self.write(out, item, depth + 1);
+                    out.push('\n');
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push(']');
+            }
+            Object::Dictionary(dict) => {
+                out.push_str("<<\n");
+                for (key, value) in dict.iter() {
+                    out.push_str(&indent);
+                    out.push_str(&name_syntax(key));
+                    out.push(' ');
+                    self.write(out, value, depth + 1);
+                    out.push('\n');
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push_str(">>");
+            }
+            // 間接オブジェクトの中にストリームが直接入ることは無いが、念のため辞書だけ表示する
+            Object::Stream(stream) => self.write(out, &Object::Dictionary(stream.dict.clone()), depth),
+        }
+    }
+}
+
+/// 名前を PDF の構文で表す（区切り文字や表示できないバイトは #xx にする）
+fn name_syntax(name: &[u8]) -> String {
+    let mut out = String::from("/");
+    for &byte in name {
+        if byte.is_ascii_graphic() && !b"#/()<>[]{}%".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "#{:02X}", byte);
+        }
+    }
+    out
+}
+
+/// 文字列を PDF の構文で表す。表示できないバイトを含むリテラル文字列は16進文字列にする
+fn string_syntax(bytes: &[u8], format: StringFormat) -> String {
+    let printable = bytes.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ');
+    if format == StringFormat::Hexadecimal || !printable {
+        let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        return format!("<{}>", hex);
+    }
+    let mut out = String::from("(");
+    for &byte in bytes {
+        if matches!(byte, b'(' | b')' | b'\\') {
+            out.push('\\');
+        }
+        out.push(byte as char);
+    }
+    out.push(')');
+    out
+}
+
+/// ストリームの内容の先頭部分（テキストとして読めない内容は長さだけにする）
+fn preview(data: &[u8]) -> String {
+    let head = &data[..data.len().min(MAX_STREAM_PREVIEW)];
+    let is_text = head.iter().all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace());
+    if !is_text {
+        return "(binary data)\n".to_string();
+    }
+    let mut out = String::from_utf8_lossy(head).into_owned();
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if data.len() > head.len() {
+        let _ = writeln!(out, "... ({} more bytes)", data.len() - head.len());
+    }
+    out
+}
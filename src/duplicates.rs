@@ -0,0 +1,49 @@
+use crate::pagetree::inherited;
+use lopdf::{Document, Object, ObjectId};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// 内容が同じページの組を探す（`--detect-duplicates`）。各組はページ番号の昇順で、組は先頭のページ順に並べる
+///
+/// ページの内容ストリームと、リソースの XObject（画像・フォーム）のストリームのハッシュで比べる。
+/// 同じ内容ストリームでも別の画像を貼ったスキャンのページは別のページになる。内容が空のページは数えない。
+pub fn find(doc: &Document, page_numbers: &BTreeMap<u32, ObjectId>) -> Vec<Vec<u32>> {
+    let fingerprints: Vec<(u32, [u8; 32])> = page_numbers
+        .par_iter()
+        .filter_map(|(number, id)| Some((*number, fingerprint(doc, *id)?)))
+        .collect();
+    let mut groups: HashMap<[u8; 32], Vec<u32>> = HashMap::new();
+    for (number, fingerprint) in fingerprints {
+        groups.entry(fingerprint).or_default().push(number);
+    }
+    let mut groups: Vec<Vec<u32>> = groups.into_values().filter(|pages| pages.len() > 1).collect();
+    for pages in &mut groups {
+        pages.sort_unstable();
+    }
+    groups.sort_unstable();
+    groups
+}
+
+fn fingerprint(doc: &Document, page_id: ObjectId) -> Option<[u8; 32]> {
+    let content = doc.get_page_content(page_id).ok()?;
+    if content.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(&content);
+    let xobjects = inherited(doc, page_id, b"Resources")
+        .and_then(|resources| doc.dereference(&resources).ok()?.1.as_dict().ok()?.get(b"XObject").ok().cloned())
+        .and_then(|xobjects| doc.dereference(&xobjects).ok()?.1.as_dict().ok().cloned())
+        .unwrap_or_default();
+    // 辞書の順序に左右されないよう名前順に加える
+    let mut entries: Vec<(&Vec<u8>, &Object)> = xobjects.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    for (name, xobject) in entries {
+        hasher.update(name);
+        if let Ok(stream) = doc.dereference(xobject).and_then(|(_, o)| o.as_stream()) {
+            hasher.update(&stream.content);
+        }
+    }
+    Some(hasher.finalize().into())
+}
@@ -0,0 +1,204 @@
+use crate::crypt::StringDecryptor;
+use crate::provenance::parse_pdf_date;
+use crate::{decode_pdf_string, encode_pdf_string, resolve_object};
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+// 壊れた（循環した）名前ツリーで無限に潜らないための上限
+const MAX_TREE_DEPTH: usize = 64;
+
+/// 文書の添付ファイル（/Names /EmbeddedFiles の名前ツリーの1項目）
+#[derive(Debug)]
+pub struct Attachment {
+    /// ファイル名（ファイル指定の /UF、無ければ /F、どちらも無ければ名前ツリーのキー）
+    pub name: String,
+    /// フィルターを解いた中身
+    pub data: Vec<u8>,
+    /// 埋め込まれたファイルの更新日時（/Params の /ModDate、無ければ /CreationDate）
+    pub modified: Option<SystemTime>,
+}
+
+/// 文書の添付ファイルを名前ツリーの順にすべて取り出す（中身の無い項目は飛ばす）
+///
+/// 暗号化文書では、ファイル名の文字列と中身のストリームを復号する。
+pub fn list(doc: &Document, decryptor: &StringDecryptor) -> Vec<Attachment> {
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else { return Vec::new() };
+    let Ok(names_obj) = doc.catalog().and_then(|catalog| catalog.get(b"Names")) else { return Vec::new() };
+    let Ok(Object::Dictionary(names)) = resolve_object(doc, names_obj) else { return Vec::new() };
+    let Ok(root_obj) = names.get(b"EmbeddedFiles") else { return Vec::new() };
+    let root_id = root_obj.as_reference().or_else(|_| names_obj.as_reference()).unwrap_or(catalog_id);
+    let mut attachments = Vec::new();
+    if let Ok(root) = resolve_object(doc, root_obj) {
+        collect(doc, decryptor, root_id, root, &mut attachments, 0);
+    }
+    attachments
+}
+
+fn collect(doc: &Document, decryptor: &StringDecryptor, node_id: ObjectId, node: &Object, out: &mut Vec<Attachment>, depth: usize) {
+    if depth > MAX_TREE_DEPTH {
+        return;
+    }
+    let Ok(node) = node.as_dict() else { return };
+    if let Ok(names_obj) = node.get(b"Names")
+        && let Ok(names) = resolve_object(doc, names_obj).and_then(Object::as_array)
+    {
+        let container = names_obj.as_reference().unwrap_or(node_id);
+        for pair in names.chunks_exact(2) {
+            let key = pair[0].as_str().map(|key| decode_pdf_string(&decryptor.decrypt(container, key))).unwrap_or_default();
+            if let Some(attachment) = read_filespec(doc, decryptor, pair[1].as_reference().unwrap_or(container), &pair[1], key) {
+                out.push(attachment);
+            }
+        }
+    }
+    if let Ok(kids) = node.get(b"Kids").and_then(|o| resolve_object(doc, o)).and_then(Object::as_array) {
+        for kid in kids {
+            let Ok(kid_id) = kid.as_reference() else { continue };
+            if let Ok(kid_node) = doc.get_object(kid_id) {
+                collect(doc, decryptor, kid_id, kid_node, out, depth + 1);
+            }
+        }
+    }
+}
+
+/// ファイル指定（/Filespec）から名前と埋め込まれた中身を読む。`container` は辞書を含む間接オブジェクトのID
+fn read_filespec(doc: &Document, decryptor: &StringDecryptor, container: ObjectId, spec: &Object, key: String) -> Option<Attachment> {
+    let spec = resolve_object(doc, spec).ok()?.as_dict().ok()?;
+    let name = [&b"UF"[..], b"F"]
+        .iter()
+        .find_map(|field| spec.get(field).and_then(Object::as_str).ok())
+        .map(|name| decode_pdf_string(&decryptor.decrypt(container, name)))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(key);
+    let ef = resolve_object(doc, spec.get(b"EF").ok()?).ok()?.as_dict().ok()?;
+    let stream_id = [&b"UF"[..], b"F"].iter().find_map(|field| ef.get(field).and_then(Object::as_reference).ok())?;
+    let stream = doc.get_object(stream_id).and_then(Object::as_stream).ok()?;
+    let stream = decryptor.decrypt_stream(stream_id, stream);
+    let data = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+    let params = stream.dict.get(b"Params").and_then(|o| doc.dereference(o)).and_then(|(id, o)| Ok((id, o.as_dict()?)));
+    let modified = params.ok().and_then(|(params_id, params)| {
+        [&b"ModDate"[..], b"CreationDate"].iter().find_map(|field| {
+            let date = params.get(field).and_then(Object::as_str).ok()?;
+            parse_pdf_date(&String::from_utf8_lossy(&decryptor.decrypt(params_id.unwrap_or(stream_id), date)))
+        })
+    });
+    Some(Attachment { name, data, modified })
+}
+
+/// ページのファイル添付注釈（/Subtype /FileAttachment）に埋め込まれたファイルを、ページの順にすべて取り出す
+///
+/// 戻り値はページ番号と添付ファイルの組。名前は注釈のファイル指定の /UF か /F（無ければ注釈の /Contents）。
+pub fn list_annotations(doc: &Document, decryptor: &StringDecryptor, page_numbers: &BTreeMap<u32, ObjectId>) -> Vec<(u32, Attachment)> {
+    let mut attachments = Vec::new();
+    for (page, page_id) in page_numbers {
+        let Ok(annots) = doc.get_dictionary(*page_id).and_then(|page| page.get(b"Annots")).and_then(|o| resolve_object(doc, o)) else {
+            continue;
+        };
+        let Ok(annots) = annots.as_array() else { continue };
+        for annot_obj in annots {
+            let Ok(annot_id) = annot_obj.as_reference() else { continue };
+            let Ok(annot) = doc.get_dictionary(annot_id) else { continue };
+            if annot.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"FileAttachment".as_slice()) {
+                continue;
+            }
+            let Ok(spec) = annot.get(b"FS") else { continue };
+            let fallback = annot
+                .get(b"Contents")
+                .and_then(Object::as_str)
+                .map(|contents| decode_pdf_string(&decryptor.decrypt(annot_id, contents)))
+                .unwrap_or_else(|_| format!("attachment_p{}", page));
+            if let Some(attachment) = read_filespec(doc, decryptor, spec.as_reference().unwrap_or(annot_id), spec, fallback) {
+                attachments.push((*page, attachment));
+            }
+        }
+    }
+    attachments
+}
+
+/// 文書に添付ファイルを加える（カタログの /Names /EmbeddedFiles の名前ツリーに登録する）
+///
+/// 同じ名前の添付ファイルがあれば置き換える。`description` が空なら説明（/Desc）を付けない。名前ツリーが中間ノード（/Kids）を持つ場合は
+/// キーの範囲（/Limits）を保ったまま挿入できないため、エラーにする。
+pub fn add(doc: &mut Document, name: &str, data: Vec<u8>, mime_type: &str, description: &str, mod_date: &str) -> Result<()> {
+    let names_ref = doc.catalog()?.get(b"Names").ok().cloned();
+    let (names_id, mut names) = resolve_dict(doc, names_ref.as_ref());
+    let (tree_id, mut tree) = resolve_dict(doc, names.get(b"EmbeddedFiles").ok());
+    if tree.has(b"Kids") {
+        bail!("the attachment name tree has intermediate nodes");
+    }
+    let mut entries = tree
+        .get(b"Names")
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut params = Dictionary::new();
+    params.set("Size", data.len() as i64);
+    params.set("ModDate", Object::string_literal(mod_date));
+    let mut stream_dict = Dictionary::new();
+    stream_dict.set("Type", "EmbeddedFile");
+    stream_dict.set("Subtype", Object::Name(mime_type.as_bytes().to_vec()));
+    stream_dict.set("Params", params);
+    let file_id = doc.add_object(Stream::new(stream_dict, data));
+
+    let mut ef = Dictionary::new();
+    ef.set("F", file_id);
+    let mut spec = Dictionary::new();
+    spec.set("Type", "Filespec");
+    spec.set("F", Object::string_literal(name));
+    spec.set("UF", encode_pdf_string(name));
+    if !description.is_empty() {
+        spec.set("Desc", encode_pdf_string(description));
+    }
+    spec.set("EF", ef);
+    let spec_id = doc.add_object(spec);
+
+    // 名前ツリーのキーはバイト列の順に並べる
+    let key = name.as_bytes();
+    let mut position = entries.len();
+    let mut replace = false;
+    for (i, pair) in entries.chunks(2).enumerate() {
+        let Ok(existing) = pair[0].as_str() else { continue };
+        if existing >= key {
+            position = i * 2;
+            replace = existing == key;
+            break;
+        }
+    }
+    if replace {
+        entries[position + 1] = spec_id.into();
+    } else {
+        entries.splice(position..position, [Object::string_literal(name), spec_id.into()]);
+    }
+    tree.set("Names", entries);
+
+    store_dict(doc, tree_id, tree, |tree| names.set("EmbeddedFiles", tree));
+    match names_id {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(names));
+        }
+        None => doc.catalog_mut()?.set("Names", names),
+    }
+    Ok(())
+}
+
+/// 間接参照なら参照先のIDと辞書の複製を、直接の辞書ならその複製を返す（無ければ空の辞書）
+fn resolve_dict(doc: &Document, object: Option<&Object>) -> (Option<ObjectId>, Dictionary) {
+    match object {
+        Some(Object::Reference(id)) => (Some(*id), doc.get_dictionary(*id).cloned().unwrap_or_default()),
+        Some(Object::Dictionary(dict)) => (None, dict.clone()),
+        _ => (None, Dictionary::new()),
+    }
+}
+
+/// 間接オブジェクトだった辞書は元のIDに書き戻し、そうでなければ `inline` で親に直接置く
+fn store_dict(doc: &mut Document, id: Option<ObjectId>, dict: Dictionary, inline: impl FnOnce(Dictionary)) {
+    match id {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(dict));
+        }
+        None => inline(dict),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::interrupt;
+use crate::linearize::{self, QPDF};
+use crate::passwords::Password;
+use crate::progress::{InfoLines, Progress};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// 暗号化の方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Cipher {
+    /// AES-256（PDF 2.0 / Acrobat X 以降）
+    Aes256,
+    /// AES-128（PDF 1.6 以降。古いビューアー向け）
+    Aes128,
+}
+
+impl Cipher {
+    /// qpdf の `--encrypt` に渡す鍵の長さと、方式のオプション
+    fn qpdf_args(self) -> &'static [&'static str] {
+        match self {
+            Cipher::Aes256 => &["256"],
+            Cipher::Aes128 => &["128", "--use-aes=y"],
+        }
+    }
+}
+
+/// `encrypt` サブコマンド: 分割せずに、任意のPDFを章の暗号化と同じ方法（qpdf）で暗号化して保存する
+///
+/// `source_password` は暗号化された入力を開くためのパスワード（暗号化し直す）。
+pub fn run(input_path: &Path, output_path: &Path, password: &Password, cipher: Cipher, source_password: Option<&str>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    linearize::check_available("encrypt")?;
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    // 完成するまでは別名で書き、暗号化に失敗しても暗号化されていない複製を出力の名前で残さない
+    let partial_path = interrupt::partial_path(output_path);
+    let encrypted = fs::copy(input_path, &partial_path)
+        .with_context(|| format!("Failed to copy {:?} to {:?}", input_path, partial_path))
+        .and_then(|_| encrypt_in_place(&partial_path, password, cipher, source_password, false));
+    if let Err(e) = encrypted {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+    fs::rename(&partial_path, output_path).with_context(|| format!("Failed to rename {:?} to {:?}", partial_path, output_path))?;
+    progress.println(format!("Wrote {:?} (encrypted with {}).", output_path, cipher.to_possible_value().expect("no skipped variants").get_name()));
+    Ok(())
+}
+
+/// 保存済みのPDFをその場で `cipher` の方式で暗号化する（lopdf には暗号化のライタが無いため qpdf で後処理する）
+///
+/// パスワードがプロセスの一覧に出ないよう、qpdf の引数は本人だけが読める一時ファイル（`@file`）で渡す。
+/// `source_password` は元の暗号化を保ったまま保存した章を開くためのパスワード。
+/// `linearize` なら同じ qpdf の呼び出しでリニアライズも行う（暗号化し直すとリニアライズが外れるため）。
+pub fn encrypt_in_place(path: &Path, password: &Password, cipher: Cipher, source_password: Option<&str>, linearize: bool) -> Result<()> {
+    let mut arguments = vec![path.display().to_string(), "--replace-input".to_string()];
+    if let Some(source_password) = source_password {
+        arguments.push(format!("--password={}", source_password));
+    }
+    if linearize {
+        arguments.push("--linearize".to_string());
+    }
+    arguments.extend(["--encrypt".to_string(), password.user.clone(), password.owner.clone()]);
+    arguments.extend(cipher.qpdf_args().iter().map(|argument| argument.to_string()));
+    arguments.push("--".to_string());
+    if arguments.iter().any(|argument| argument.contains(['\n', '\r'])) {
+        bail!("passwords and paths must not contain line breaks");
+    }
+
+    let args_path = path.with_extension("qpdf-args");
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut args_file = options.open(&args_path).with_context(|| format!("Failed to create {:?}", args_path))?;
+    let written = args_file.write_all((arguments.join("\n") + "\n").as_bytes());
+    drop(args_file);
+    let output = written.map_err(anyhow::Error::from).and_then(|()| {
+        Command::new(QPDF)
+            .arg(format!("@{}", args_path.display()))
+            .output()
+            .with_context(|| format!("Failed to run {} on {:?}", QPDF, path))
+    });
+    let _ = fs::remove_file(&args_path);
+    let output = output?;
+
+    // 終了コード 3 は警告のみ（出力は有効）
+    match output.status.code() {
+        Some(0) | Some(3) => Ok(()),
+        _ => bail!(
+            "{} --encrypt failed for {:?}: {}",
+            QPDF,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{DestFallback, OutlineContext, lookup_page, outline_title, resolve_object};
+use crate::toc::first_outline_item;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+
+// 壊れた（循環した）目次で無限に潜らないための上限
+const MAX_OUTLINE_DEPTH: usize = 64;
+
+/// 1つの目次項目の宛先をたどった結果
+struct Explanation {
+    /// 解決できたページ（できなければ `None`）
+    page: Option<u32>,
+    /// 宛先をたどった手順（/Dest か GoTo アクションか、名前付き宛先をどこで見つけたか、ページ参照など）
+    steps: Vec<String>,
+}
+
+/// `--explain`: 目次の全項目について、宛先をどうたどってページにしたか（できなかった理由）を1行ずつ返す
+///
+/// 行は階層に応じて字下げし、最後に解決できた項目とできなかった項目の数をまとめる。
+/// 章が飛ばされたときに、原因が文書の宛先の誤りか、ツールの解決の仕方かを見分けられるようにする。
+pub fn explain_outline(ctx: &OutlineContext) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut resolved = 0;
+    let mut unresolved = 0;
+    if let Some(first) = first_outline_item(ctx.doc) {
+        let mut visited = HashSet::new();
+        walk(ctx, first, 1, &mut visited, &mut |level, title, explanation| {
+            let indent = "  ".repeat(level - 1);
+            let steps = explanation.steps.join(" -> ");
+            match explanation.page {
+                Some(page) => {
+                    resolved += 1;
+                    lines.push(format!("{}[level {}] \"{}\": p.{} ({})", indent, level, title, page, steps));
+                }
+                None => {
+                    unresolved += 1;
+                    lines.push(format!("{}[level {}] \"{}\": not resolved ({})", indent, level, title, steps));
+                }
+            }
+        });
+    }
+    if resolved + unresolved == 0 {
+        lines.push("The document has no outline entries.".to_string());
+    } else {
+        lines.push(format!("Explained {} outline entries: {} resolved, {} not resolved.", resolved + unresolved, resolved, unresolved));
+    }
+    lines
+}
+
+fn walk(
+    ctx: &OutlineContext,
+    first: ObjectId,
+    level: usize,
+    visited: &mut HashSet<ObjectId>,
+    emit: &mut impl FnMut(usize, &str, Explanation),
+) {
+    if level > MAX_OUTLINE_DEPTH {
+        return;
+    }
+    let mut current = Some(first);
+    while let Some(id) = current {
+        // 循環した /Next や /First を二度たどらない
+        if !visited.insert(id) {
+            break;
+        }
+        let Ok(item) = ctx.doc.get_object(id).and_then(|o| o.as_dict()) else { break };
+        let title = outline_title(ctx.doc, ctx.decryptor, id, item);
+        emit(level, &title, explain_item(ctx, id, item));
+        if let Ok(child) = item.get(b"First").and_then(|o| o.as_reference()) {
+            walk(ctx, child, level + 1, visited, emit);
+        }
+        current = item.get(b"Next").and_then(|o| o.as_reference()).ok();
+    }
+}
+
+/// `resolve_outline_item` と同じ順（/Dest、GoTo アクションの /D）に宛先をたどる
+fn explain_item(ctx: &OutlineContext, id: ObjectId, item: &Dictionary) -> Explanation {
+    let OutlineContext { doc, decryptor, .. } = *ctx;
+    let mut explanation = Explanation { page: None, steps: vec![format!("outline item {} {} R", id.0, id.1)] };
+    if let Ok(dest) = item.get(b"Dest") {
+        explanation.steps.push("/Dest".to_string());
+        explain_dest(ctx, &decryptor.decrypt_dest(doc, id, dest), &mut explanation);
+        return explanation;
+    }
+    let Ok(action_obj) = item.get(b"A") else {
+        explanation.steps.push("it has neither /Dest nor /A".to_string());
+        return explanation;
+    };
+    let Ok(action) = resolve_object(doc, action_obj).and_then(|o| o.as_dict()) else {
+        explanation.steps.push("its /A action is not a dictionary".to_string());
+        return explanation;
+    };
+    match action.get(b"S").and_then(|o| o.as_name_str()) {
+        Ok("GoTo") => explanation.steps.push("GoTo action".to_string()),
+        Ok("GoToR") => {
+            explanation.steps.push("GoToR action: the destination is in another file".to_string());
+            return explanation;
+        }
+        Ok(kind) => {
+            explanation.steps.push(format!("/{} action: not a destination in this document", kind));
+            return explanation;
+        }
+        Err(_) => {
+            explanation.steps.push("the action has no /S type".to_string());
+            return explanation;
+        }
+    }
+    let Ok(d) = action.get(b"D") else {
+        explanation.steps.push("the GoTo action has no /D".to_string());
+        return explanation;
+    };
+    let container = action_obj.as_reference().unwrap_or(id);
+    explain_dest(ctx, &decryptor.decrypt_dest(doc, container, d), &mut explanation);
+    explanation
+}
+
+/// 宛先（配列か、名前付き宛先の名前）をたどる
+fn explain_dest(ctx: &OutlineContext, dest: &Object, explanation: &mut Explanation) {
+    let OutlineContext { doc, named_dests, .. } = *ctx;
+    if let Object::Reference(id) = dest {
+        explanation.steps.push(format!("destination object {} {} R", id.0, id.1));
+    }
+    let Ok(dest) = resolve_object(doc, dest) else {
+        explanation.steps.push("the destination object is missing".to_string());
+        return;
+    };
+    let name = match dest {
+        Object::Array(array) => {
+            explanation.steps.push("direct destination array".to_string());
+            explain_array(ctx, array, explanation);
+            return;
+        }
+        Object::String(name, _) | Object::Name(name) => name,
+        _ => {
+            explanation.steps.push("the destination is neither an array nor a name".to_string());
+            return;
+        }
+    };
+    explanation.steps.push(format!("named destination {:?}", String::from_utf8_lossy(name)));
+    let Some(target) = named_dests.get(name) else {
+        explanation.steps.push("not defined in the catalog /Dests or the /Names /Dests tree".to_string());
+        return;
+    };
+    // 名前付き宛先はカタログの /Dests を名前ツリーより優先して引く
+    explanation.steps.push(if in_catalog_dests(doc, name) { "found in the catalog /Dests" } else { "found in the /Names /Dests tree" }.to_string());
+    match resolve_object(doc, &target) {
+        Ok(Object::Array(array)) => explain_array(ctx, array, explanation),
+        Ok(Object::Dictionary(dict)) => match dict.get(b"D").and_then(|d| resolve_object(doc, d)).and_then(|d| d.as_array()) {
+            Ok(array) => {
+                explanation.steps.push("destination dictionary /D".to_string());
+                explain_array(ctx, array, explanation);
+            }
+            Err(_) => explanation.steps.push("the destination dictionary has no /D array".to_string()),
+        },
+        Ok(_) => explanation.steps.push("the named destination is neither an array nor a dictionary".to_string()),
+        Err(_) => explanation.steps.push("the named destination's object is missing".to_string()),
+    }
+}
+
+/// 宛先の配列の先頭のページ参照をページ番号にする
+fn explain_array(ctx: &OutlineContext, array: &[Object], explanation: &mut Explanation) {
+    let OutlineContext { object_id_to_page, fallback, .. } = *ctx;
+    match array.first() {
+        Some(Object::Reference(page_id)) => {
+            explanation.steps.push(format!("page object {} {} R", page_id.0, page_id.1));
+            if let Some(page) = object_id_to_page.get(page_id) {
+                explanation.page = Some(*page);
+                return;
+            }
+            explanation.steps.push("not in the page tree".to_string());
+            if fallback == DestFallback::Nearest
+                && let Some(page) = lookup_page(object_id_to_page, *page_id, DestFallback::Nearest)
+            {
+                explanation.steps.push("snapped to the nearest page by object number (--dest-fallback nearest)".to_string());
+                explanation.page = Some(page);
+            }
+        }
+        Some(Object::Integer(index)) => {
+            explanation.steps.push(format!("page index {}: the form used for destinations in other documents", index));
+        }
+        Some(_) => explanation.steps.push("the array does not start with a page reference".to_string()),
+        None => explanation.steps.push("the destination array is empty".to_string()),
+    }
+}
+
+fn in_catalog_dests(doc: &Document, name: &[u8]) -> bool {
+    doc.catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Dests").ok())
+        .and_then(|dests| resolve_object(doc, dests).ok())
+        .and_then(|dests| dests.as_dict().ok())
+        .is_some_and(|dests| dests.has(name))
+}
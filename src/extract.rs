@@ -0,0 +1,360 @@
+use crate::compress::Compression;
+use crate::fonts;
+use crate::images::{Downsampled, Downsampler};
+use crate::raw_copy::RawSource;
+use crate::stream_writer::StreamingWriter;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+// ページツリーの中間ノードを捨てるため、葉のページへ引き継ぐ継承可能属性
+pub const INHERITABLE_PAGE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// トレーラーから引き継ぐキー（Prev や XRefStm は元ファイルのオフセットなので捨てる）
+const TRAILER_KEYS: [&[u8]; 4] = [b"Root", b"Info", b"ID", b"Encrypt"];
+
+/// 元の文書から指定ページと、そこから到達できるオブジェクトだけをコピーして新しい文書を作る
+///
+/// 文書全体を clone してからページを削除する方式と違い、出力に必要な分しかメモリを使わない。
+/// オブジェクト番号は元の文書のまま保つ（暗号化文書ではオブジェクト番号が復号鍵の一部になるため）。
+/// 選択外のページやページツリーの中間ノードへの参照は、`Document::delete_pages` と同じく
+/// 辞書のキーごと・配列の要素ごと取り除く。
+///
+/// 元の文書は `Arc` で全ワーカーから共有し、書き換えが必要なオブジェクト（ページ、カタログ、
+/// 除外対象への参照を含むもの）だけを複製する。それ以外のストリームは元の文書から直接書き出す。
+pub struct PageExtractor {
+    source: Arc<Document>,
+    tree_nodes: HashSet<ObjectId>,
+    all_pages: HashSet<ObjectId>,
+    raw: Option<RawSource>,
+    compression: Compression,
+    downsampler: Option<Downsampler>,
+    strip_fonts: bool,
+    skip_contents: bool,
+}
+
+/// 1つの章の抽出で縮小した画像と、取り除いたフォント
+#[derive(Debug, Default)]
+pub struct ExtractStats {
+    pub downsampled: Downsampled,
+    pub stripped_fonts: usize,
+    pub font_bytes_saved: u64,
+}
+
+impl PageExtractor {
+    /// `pages` は元の文書の全ページ（ページツリー走査の結果）
+    pub fn new(source: Arc<Document>, pages: impl IntoIterator<Item = ObjectId>) -> Self {
+        Self {
+            tree_nodes: page_tree_nodes(&source),
+            source,
+            all_pages: pages.into_iter().collect(),
+            raw: None,
+            compression: Compression::None,
+            downsampler: None,
+            strip_fonts: false,
+            skip_contents: false,
+        }
+    }
+
+    /// 書き出すストリームの再圧縮方法
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 解像度の高すぎる画像を縮小して書き出す
+    pub fn with_downsampler(mut self, downsampler: Downsampler) -> Self {
+        self.downsampler = Some(downsampler);
+        self
+    }
+
+    /// 章のページが使わないフォントをリソースから外し、埋め込みプログラムごと書き出さない
+    pub fn with_font_stripping(mut self) -> Self {
+        self.strip_fonts = true;
+        self
+    }
+
+    /// ページの内容ストリームを書き出さない（`--nup` で内容をフォームとして作り直すため）
+    pub fn without_page_contents(mut self) -> Self {
+        self.skip_contents = true;
+        self
+    }
+
+    /// 無変更のストリームを元ファイルからバイト列のままコピーする
+    pub fn with_raw_source(mut self, raw: RawSource) -> Self {
+        self.raw = Some(raw);
+        self
+    }
+
+    /// ストリームオブジェクトはコピーしたそばから `writer` に書き出し、残りの骨格だけを返す
+    ///
+    /// 返された文書はストリームを含まないため、後処理（注釈の修復など）は辞書だけを触ること。
+    /// 最後に `StreamingWriter::finish` に渡して書き出しを完了する。縮小した画像や取り除いたフォントの数も返す。
+    pub fn extract_streaming<W: Write>(
+        &self,
+        page_ids: &[ObjectId],
+        writer: &mut StreamingWriter<W>,
+    ) -> io::Result<(Document, ExtractStats)> {
+        let placements = self.downsampler.map(|downsampler| (downsampler, downsampler.placements(&self.source, page_ids)));
+        let mut downsampled = Downsampled::default();
+        let spill = |id: ObjectId, object: Cow<'_, Object>| -> io::Result<Option<Object>> {
+            let Object::Stream(stream) = &*object else {
+                return Ok(Some(object.into_owned()));
+            };
+            if let Some((downsampler, placements)) = &placements
+                && let Some(page_size) = placements.page_size(id)
+                && let Some(smaller) = downsampler.downsample(&self.source, stream, page_size)
+            {
+                downsampled.images += 1;
+                downsampled.bytes_saved += (stream.content.len() - smaller.content.len()) as u64;
+                writer.write_stream(id, &smaller, None)?;
+                return Ok(None);
+            }
+            if let Some(recompressed) = self.compression.apply(stream) {
+                writer.write_stream(id, &recompressed, None)?;
+            } else {
+                // 書き換えていないストリームは元ファイルのバイト列をそのまま使う
+                let raw = match &object {
+                    Cow::Borrowed(_) => self.raw.as_ref().and_then(|r| r.stream_bytes(&self.source, id, stream)),
+                    Cow::Owned(_) => None,
+                };
+                writer.write_stream(id, stream, raw)?;
+            }
+            Ok(None)
+        };
+        let mut excluded = self.excluded_for(page_ids);
+        let mut stats = ExtractStats::default();
+        if self.strip_fonts {
+            let unused = fonts::find_unused(&self.source, page_ids);
+            stats.stripped_fonts = unused.ids.len();
+            stats.font_bytes_saved = unused.program_bytes;
+            excluded.extend(unused.ids);
+        }
+        if self.skip_contents {
+            excluded.extend(page_ids.iter().flat_map(|page_id| self.source.get_page_contents(*page_id)));
+        }
+        let doc = extract_pages(&self.source, &excluded, page_ids, spill)?;
+        stats.downsampled = downsampled;
+        Ok((doc, stats))
+    }
+
+    /// 抽出でコピーされるオブジェクトのおおよそのバイト数（実際にはコピーしない）
+    pub fn estimate_size(&self, page_ids: &[ObjectId]) -> u64 {
+        let excluded = self.excluded_for(page_ids);
+        let mut queue: VecDeque<ObjectId> = page_ids.iter().cloned().collect();
+        for key in TRAILER_KEYS {
+            if let Ok(value) = self.source.trailer.get(key) {
+                enqueue_refs(value, &excluded, &mut queue);
+            }
+        }
+        let mut visited: HashSet<ObjectId> = HashSet::new();
+        let mut total = 0;
+        while let Some(id) = queue.pop_front() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let Ok(object) = self.source.get_object(id) else { continue };
+            total += object_size(object);
+            enqueue_refs(object, &excluded, &mut queue);
+        }
+        total
+    }
+
+    fn excluded_for(&self, page_ids: &[ObjectId]) -> HashSet<ObjectId> {
+        let selected: HashSet<ObjectId> = page_ids.iter().cloned().collect();
+        let mut excluded: HashSet<ObjectId> = self.tree_nodes.clone();
+        excluded.extend(self.all_pages.iter().filter(|id| !selected.contains(id)));
+        excluded
+    }
+}
+
+/// `excluded` は選択外のページと元のページツリーのノード（これらへの参照は取り除く）。
+/// オブジェクトは書き換えが必要なときだけ複製して `sink` に渡し、`Some` で返されたものだけを文書に残す
+fn extract_pages<F>(source: &Document, excluded: &HashSet<ObjectId>, page_ids: &[ObjectId], mut sink: F) -> io::Result<Document>
+where
+    F: FnMut(ObjectId, Cow<'_, Object>) -> io::Result<Option<Object>>,
+{
+    let mut doc = Document::new();
+    doc.version = source.version.clone();
+    doc.reference_table.cross_reference_type = source.reference_table.cross_reference_type;
+    doc.max_id = source.max_id;
+
+    let pages_id = doc.new_object_id();
+    let mut queue: VecDeque<ObjectId> = VecDeque::new();
+
+    for key in TRAILER_KEYS {
+        if let Ok(value) = source.trailer.get(key) {
+            doc.trailer.set(key.to_vec(), value.clone());
+            enqueue_refs(value, excluded, &mut queue);
+        }
+    }
+
+    // ページは親を新しいページツリーに付け替え、継承属性を自身に持たせてからコピーする
+    for page_id in page_ids {
+        let Ok(page) = source.get_dictionary(*page_id) else { continue };
+        let mut page = page.clone();
+        for key in INHERITABLE_PAGE_KEYS {
+            if !page.has(key)
+                && let Some(value) = inherited_attribute(source, &page, key)
+            {
+                page.set(key.to_vec(), value);
+            }
+        }
+        page.set("Parent", pages_id);
+        let mut page = Object::Dictionary(page);
+        prune_refs(&mut page, excluded);
+        enqueue_refs(&page, excluded, &mut queue);
+        doc.objects.insert(*page_id, page);
+    }
+
+    let kids: Vec<Object> = page_ids.iter().filter(|id| doc.objects.contains_key(id)).map(|id| (*id).into()).collect();
+    let mut pages = Dictionary::new();
+    pages.set("Type", "Pages");
+    pages.set("Count", kids.len() as i64);
+    pages.set("Kids", kids);
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let catalog_id = source.trailer.get(b"Root").and_then(|o| o.as_reference()).ok();
+
+    // 推移閉包のコピー
+    let mut visited: HashSet<ObjectId> = HashSet::new();
+    while let Some(id) = queue.pop_front() {
+        if !visited.insert(id) || doc.objects.contains_key(&id) {
+            continue;
+        }
+        let Ok(object) = source.get_object(id) else { continue };
+        enqueue_refs(object, excluded, &mut queue);
+        let object = if Some(id) == catalog_id || has_excluded_ref(object, excluded) {
+            let mut object = object.clone();
+            if Some(id) == catalog_id
+                && let Ok(catalog) = object.as_dict_mut()
+            {
+                catalog.set("Pages", pages_id);
+            }
+            prune_refs(&mut object, excluded);
+            Cow::Owned(object)
+        } else {
+            Cow::Borrowed(object)
+        };
+        if let Some(object) = sink(id, object)? {
+            doc.objects.insert(id, object);
+        }
+    }
+
+    Ok(doc)
+}
+
+/// 元の文書のページツリーの中間ノード（/Type /Pages）を列挙する
+pub fn page_tree_nodes(source: &Document) -> HashSet<ObjectId> {
+    let mut nodes = HashSet::new();
+    let mut stack: Vec<ObjectId> = source
+        .catalog()
+        .and_then(|c| c.get(b"Pages"))
+        .and_then(|o| o.as_reference())
+        .into_iter()
+        .collect();
+    while let Some(id) = stack.pop() {
+        if !nodes.insert(id) {
+            continue;
+        }
+        if let Ok(kids) = source
+            .get_dictionary(id)
+            .and_then(|d| d.get(b"Kids"))
+            .and_then(|o| source.dereference(o))
+            .and_then(|(_, o)| o.as_array())
+        {
+            for kid in kids {
+                if let Ok(kid_id) = kid.as_reference()
+                    && source.get_dictionary(kid_id).map(|d| d.has(b"Kids")).unwrap_or(false)
+                {
+                    stack.push(kid_id);
+                }
+            }
+        }
+    }
+    nodes
+}
+
+/// ページ自身に無い継承可能属性を、ページツリーの親ノードから探す
+pub fn inherited_attribute(source: &Document, page: &Dictionary, key: &[u8]) -> Option<Object> {
+    let mut parent = page.get(b"Parent").and_then(|o| o.as_reference()).ok();
+    let mut depth = 0;
+    while let Some(parent_id) = parent {
+        let node = source.get_dictionary(parent_id).ok()?;
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        parent = node.get(b"Parent").and_then(|o| o.as_reference()).ok();
+        depth += 1;
+        if depth > 256 {
+            return None;
+        }
+    }
+    None
+}
+
+/// 除外対象への参照を取り除く
+pub fn prune_refs(object: &mut Object, excluded: &HashSet<ObjectId>) {
+    match object {
+        Object::Array(array) => {
+            array.retain(|o| !is_excluded_ref(o, excluded));
+            for item in array.iter_mut() {
+                prune_refs(item, excluded);
+            }
+        }
+        Object::Dictionary(dict) => prune_dict(dict, excluded),
+        Object::Stream(stream) => prune_dict(&mut stream.dict, excluded),
+        _ => {}
+    }
+}
+
+fn prune_dict(dict: &mut Dictionary, excluded: &HashSet<ObjectId>) {
+    let dropped: Vec<Vec<u8>> = dict
+        .iter()
+        .filter(|(_, v)| is_excluded_ref(v, excluded))
+        .map(|(k, _)| k.clone())
+        .collect();
+    for key in dropped {
+        dict.remove(&key);
+    }
+    for (_, value) in dict.iter_mut() {
+        prune_refs(value, excluded);
+    }
+}
+
+fn has_excluded_ref(object: &Object, excluded: &HashSet<ObjectId>) -> bool {
+    match object {
+        Object::Reference(id) => excluded.contains(id),
+        Object::Array(array) => array.iter().any(|o| has_excluded_ref(o, excluded)),
+        Object::Dictionary(dict) => dict.iter().any(|(_, o)| has_excluded_ref(o, excluded)),
+        Object::Stream(stream) => stream.dict.iter().any(|(_, o)| has_excluded_ref(o, excluded)),
+        _ => false,
+    }
+}
+
+fn is_excluded_ref(object: &Object, excluded: &HashSet<ObjectId>) -> bool {
+    matches!(object, Object::Reference(id) if excluded.contains(id))
+}
+
+/// オブジェクトのおおよそのメモリ上のサイズ
+pub fn object_size(object: &Object) -> u64 {
+    match object {
+        Object::Stream(stream) => stream.content.len() as u64 + stream.dict.iter().map(|(k, v)| k.len() as u64 + object_size(v)).sum::<u64>(),
+        Object::Dictionary(dict) => dict.iter().map(|(k, v)| k.len() as u64 + object_size(v)).sum::<u64>() + 32,
+        Object::Array(array) => array.iter().map(object_size).sum::<u64>() + 24,
+        Object::String(bytes, _) | Object::Name(bytes) => bytes.len() as u64 + 24,
+        _ => 16,
+    }
+}
+
+fn enqueue_refs(object: &Object, excluded: &HashSet<ObjectId>, queue: &mut VecDeque<ObjectId>) {
+    match object {
+        Object::Reference(id) if !excluded.contains(id) => queue.push_back(*id),
+        Object::Array(array) => array.iter().for_each(|o| enqueue_refs(o, excluded, queue)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, o)| enqueue_refs(o, excluded, queue)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, o)| enqueue_refs(o, excluded, queue)),
+        _ => {}
+    }
+}
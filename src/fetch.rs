@@ -0,0 +1,173 @@
+use crate::progress::Progress;
+use crate::provenance::hex;
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// 応答が途絶えたとみなすまでの時間（ダウンロード全体ではなく1回の読み書きの上限）
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+// 接続が切れたときに続きから取り直す回数
+const MAX_ATTEMPTS: u32 = 5;
+
+/// 入力が http:// か https:// の URL か
+pub fn is_url(input: &Path) -> bool {
+    input.to_str().is_some_and(|input| input.starts_with("http://") || input.starts_with("https://"))
+}
+
+/// `--sha256` の値（64桁の16進数）
+pub fn parse_sha256(value: &str) -> Result<String, String> {
+    if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(value.to_ascii_lowercase())
+    } else {
+        Err(format!("{:?} is not a SHA-256 digest (64 hex digits)", value))
+    }
+}
+
+/// 入力の URL を一時ディレクトリへダウンロードし、そのパスを返す
+///
+/// ダウンロード中は `<名前>.part` に書き、途中で接続が切れたら Range 要求で続きから取り直す
+/// （前回の実行で残った `.part` も続きから取る）。`expected_sha256` があれば、書き終えた内容の
+/// ハッシュと照合してから本来の名前に変える。ファイル名は URL の最後のパス要素から作るため、
+/// 出力名も元のファイル名から付く。
+pub fn download(url: &str, expected_sha256: Option<&str>, progress: &Progress) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("pdf_splitter-download-{}", &hex(&Sha256::digest(url.as_bytes()))[..16]));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let path = dir.join(file_name(url));
+    let part_path = path.with_extension("pdf.part");
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match fetch_into(url, &part_path, progress) {
+            Ok(()) => break,
+            Err(e) if attempt < MAX_ATTEMPTS && e.downcast_ref::<Retryable>().is_some() => {
+                progress.eprintln(format!("Warning: {:#}; resuming the download ({}/{})", e, attempt + 1, MAX_ATTEMPTS));
+                std::thread::sleep(Duration::from_secs(u64::from(attempt)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(expected) = expected_sha256 {
+        let actual = hex(&hash_file(&part_path)?);
+        if actual != expected {
+            // 壊れた内容から続きを取らないよう消しておく
+            let _ = fs::remove_file(&part_path);
+            bail!("downloaded {} has SHA-256 {}, expected {}", url, actual, expected);
+        }
+        progress.println("Checksum verified.");
+    }
+    fs::rename(&part_path, &path).with_context(|| format!("Failed to rename {:?}", part_path))?;
+    Ok(path)
+}
+
+/// 一時的な失敗（続きから取り直せば済むもの）
+#[derive(Debug)]
+struct Retryable(String);
+
+impl std::fmt::Display for Retryable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Retryable {}
+
+/// `part_path` の続き（無ければ最初）から取り、最後まで書き終えたら `Ok`
+fn fetch_into(url: &str, part_path: &Path, progress: &Progress) -> Result<()> {
+    let offset = fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
+    let agent = ureq::AgentBuilder::new().timeout_read(READ_TIMEOUT).build();
+    let mut request = agent.get(url);
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        // 続きが無い: 手元の長さがサーバーの示す全体の長さ（`Content-Range: bytes */<長さ>`）と同じなら取り終えている
+        Err(ureq::Error::Status(416, response)) if offset > 0 => {
+            let total = response
+                .header("Content-Range")
+                .and_then(|range| range.strip_prefix("bytes */"))
+                .and_then(|total| total.trim().parse::<u64>().ok());
+            if total == Some(offset) {
+                return Ok(());
+            }
+            // 前回の `.part` は古いか途中で壊れているので、消して最初から取り直す
+            progress.eprintln(format!(
+                "Warning: the partial download {:?} ({} bytes) does not match {}; starting over",
+                part_path, offset, url
+            ));
+            fs::remove_file(part_path).with_context(|| format!("Failed to remove {:?}", part_path))?;
+            return fetch_into(url, part_path, progress);
+        }
+        Err(ureq::Error::Status(code, response)) => bail!("{} returned HTTP {} {}", url, code, response.status_text()),
+        Err(ureq::Error::Transport(e)) => return Err(Retryable(format!("failed to connect to {}: {}", url, e)).into()),
+    };
+    // 206 以外はサーバーが Range に応じなかったので最初から書き直す
+    let resumed = response.status() == 206;
+    let expected_len = response.header("Content-Length").and_then(|len| len.parse::<u64>().ok());
+    if resumed {
+        progress.println(format!("Resuming download of {} at byte {}.", url, offset));
+    } else {
+        progress.println(format!("Downloading {}...", url));
+    }
+    let file = if resumed {
+        OpenOptions::new().append(true).open(part_path)
+    } else {
+        File::create(part_path)
+    }
+    .with_context(|| format!("Failed to open {:?}", part_path))?;
+    let mut writer = BufWriter::new(file);
+    let copied = io::copy(&mut response.into_reader(), &mut writer);
+    writer.flush().with_context(|| format!("Failed to write {:?}", part_path))?;
+    let copied = copied.map_err(|e| Retryable(format!("download of {} was cut off: {}", url, e)))?;
+    if let Some(expected_len) = expected_len
+        && copied < expected_len
+    {
+        return Err(Retryable(format!("download of {} ended after {} of {} bytes", url, copied, expected_len)).into());
+    }
+    Ok(())
+}
+
+/// URL の最後のパス要素（クエリとフラグメントを除き、%xx を戻したもの）から、保存するファイル名を作る
+pub fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let last = path.rsplit('/').next().unwrap_or_default();
+    let decoded = percent_decode(last);
+    let stem = decoded.strip_suffix(".pdf").or_else(|| decoded.strip_suffix(".PDF")).unwrap_or(&decoded);
+    let stem = crate::sanitize_filename(stem);
+    if stem.is_empty() {
+        "download.pdf".to_string()
+    } else {
+        format!("{}.pdf", stem)
+    }
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(hasher.finalize().into())
+}
@@ -0,0 +1,217 @@
+use crate::crypt::StringDecryptor;
+use crate::{decode_pdf_string, resolve_object};
+use clap::ValueEnum;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::BTreeMap;
+
+// 壊れた（循環した）フィールドの親子関係で無限にたどらないための上限
+const MAX_FIELD_DEPTH: usize = 32;
+
+/// `--split-on-field-type` で探すフォームフィールドの種類（/FT）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FieldType {
+    /// 署名
+    Sig,
+    /// テキスト
+    Tx,
+    /// ボタン（チェックボックス・ラジオボタンを含む）
+    Btn,
+    /// 選択（リスト・コンボボックス）
+    Ch,
+}
+
+impl FieldType {
+    fn pdf_name(self) -> &'static [u8] {
+        match self {
+            FieldType::Sig => b"Sig",
+            FieldType::Tx => b"Tx",
+            FieldType::Btn => b"Btn",
+            FieldType::Ch => b"Ch",
+        }
+    }
+}
+
+/// フィールドのあるページを章の終わりにするか始まりにするか（`--field-boundary`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FieldBoundary {
+    /// フィールドのあるページで章を終える（署名ページで終わる契約書など）
+    End,
+    /// フィールドのあるページから新しい章を始める
+    Start,
+}
+
+/// 指定した種類のフィールドのウィジェット注釈があるページと、そのページの最初のフィールドの名前
+pub fn pages_with_field(
+    doc: &Document,
+    decryptor: &StringDecryptor,
+    page_numbers: &BTreeMap<u32, ObjectId>,
+    field_type: FieldType,
+) -> Vec<(u32, String)> {
+    let field = Field { doc, decryptor };
+    let mut pages = Vec::new();
+    for (&number, &page_id) in page_numbers {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let Ok(annots) = page.get(b"Annots").and_then(|o| resolve_object(doc, o)).and_then(|o| o.as_array()) else {
+            continue;
+        };
+        let name = annots.iter().find_map(|annot| {
+            // 配列に直接置かれた注釈の文字列はページの鍵で暗号化されている
+            let widget_id = annot.as_reference().unwrap_or(page_id);
+            let widget = resolve_object(doc, annot).and_then(|o| o.as_dict()).ok()?;
+            if widget.get(b"Subtype").and_then(|o| o.as_name()).ok()? != b"Widget" {
+                return None;
+            }
+            (field.inherited_type(widget).as_deref() == Some(field_type.pdf_name())).then(|| field.full_name(widget_id, widget))
+        });
+        if let Some(name) = name {
+            pages.push((number, name));
+        }
+    }
+    pages
+}
+
+/// 完全な名前（またはその最後の部分名）が `name` のフィールドに値があるページと、そのページの最初の値（`--split-by-field`）
+///
+/// 一括生成した請求書などを結合すると、各ページのフィールドは `1.InvoiceNumber` のように親の下に入ることが多いため、
+/// 最後の部分名が一致するフィールドも対象にする。値（/V）は親のフィールドから継承される。
+pub fn pages_with_value(doc: &Document, decryptor: &StringDecryptor, page_numbers: &BTreeMap<u32, ObjectId>, name: &str) -> Vec<(u32, String)> {
+    let field = Field { doc, decryptor };
+    let suffix = format!(".{}", name);
+    let mut pages = Vec::new();
+    for (&number, &page_id) in page_numbers {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let Ok(annots) = page.get(b"Annots").and_then(|o| resolve_object(doc, o)).and_then(|o| o.as_array()) else {
+            continue;
+        };
+        let value = annots.iter().find_map(|annot| {
+            let widget_id = annot.as_reference().unwrap_or(page_id);
+            let widget = resolve_object(doc, annot).and_then(|o| o.as_dict()).ok()?;
+            if widget.get(b"Subtype").and_then(|o| o.as_name()).ok()? != b"Widget" {
+                return None;
+            }
+            let full_name = field.full_name(widget_id, widget);
+            if full_name != name && !full_name.ends_with(&suffix) {
+                return None;
+            }
+            field.inherited_value(widget_id, widget).filter(|value| !value.is_empty())
+        });
+        if let Some(value) = value {
+            pages.push((number, value));
+        }
+    }
+    pages
+}
+
+/// 値のあるページから章の始まり（ページ番号, タイトル）を作る
+///
+/// 値が前のページと変わったページで新しい章を始め、その値を章のタイトル（ファイル名）にする。
+/// 値の無いページ（複数ページにわたる明細の続きなど）は前の章に含め、最初の値より前のページは入力名の章にする。
+pub fn value_chapter_starts(values: &[(u32, String)], file_stem: &str) -> Vec<(u32, String)> {
+    let mut starts: Vec<(u32, String)> = Vec::new();
+    for (page, value) in values {
+        if starts.last().is_none_or(|(_, current)| current != value) {
+            starts.push((*page, value.clone()));
+        }
+    }
+    if starts.first().is_none_or(|(first, _)| *first > 1) {
+        starts.insert(0, (1, file_stem.to_string()));
+    }
+    starts
+}
+
+/// フィールドのあるページから章の始まり（ページ番号, タイトル）を作る
+///
+/// 続けて並んだフィールドのページ（2ページにわたる署名欄など）は1つの区切りとして扱う。
+/// 章のタイトルは区切りのページのフィールド名にし、区切りの無い部分は入力名にする。
+pub fn chapter_starts(pages: &[(u32, String)], boundary: FieldBoundary, total_pages: u32, file_stem: &str) -> Vec<(u32, String)> {
+    // 続けて並んだページの（最初のページ, 最後のページ, フィールド名）
+    let mut runs: Vec<(u32, u32, &str)> = Vec::new();
+    for (page, name) in pages {
+        let name = if name.is_empty() { file_stem } else { name };
+        match runs.last_mut() {
+            Some((_, last, _)) if *last + 1 == *page => *last = *page,
+            _ => runs.push((*page, *page, name)),
+        }
+    }
+    let mut starts = Vec::new();
+    match boundary {
+        FieldBoundary::Start => {
+            if runs.first().is_none_or(|(first, ..)| *first > 1) {
+                starts.push((1, file_stem.to_string()));
+            }
+            starts.extend(runs.iter().map(|(first, _, name)| (*first, name.to_string())));
+        }
+        FieldBoundary::End => {
+            let mut start = 1;
+            for (_, last, name) in &runs {
+                starts.push((start, name.to_string()));
+                start = last + 1;
+            }
+            if start <= total_pages {
+                starts.push((start, file_stem.to_string()));
+            }
+        }
+    }
+    starts
+}
+
+struct Field<'a> {
+    doc: &'a Document,
+    decryptor: &'a StringDecryptor,
+}
+
+impl Field<'_> {
+    /// /FT は親のフィールドから継承される
+    fn inherited_type(&self, widget: &Dictionary) -> Option<Vec<u8>> {
+        let mut node = widget;
+        for _ in 0..MAX_FIELD_DEPTH {
+            if let Ok(name) = node.get(b"FT").and_then(|o| o.as_name()) {
+                return Some(name.to_vec());
+            }
+            node = node.get(b"Parent").and_then(|o| resolve_object(self.doc, o)).and_then(|o| o.as_dict()).ok()?;
+        }
+        None
+    }
+
+    /// 親から継承した値（/V）。テキストは文字列、チェックボックスなどは状態の名前、複数選択のリストは `, ` でつなぐ
+    fn inherited_value(&self, widget_id: ObjectId, widget: &Dictionary) -> Option<String> {
+        let (mut id, mut node) = (widget_id, widget);
+        for _ in 0..MAX_FIELD_DEPTH {
+            if let Ok(value) = node.get(b"V") {
+                let container = value.as_reference().unwrap_or(id);
+                let text = |object: &Object| match object {
+                    Object::String(bytes, _) => Some(decode_pdf_string(&self.decryptor.decrypt(container, bytes))),
+                    Object::Name(name) => Some(String::from_utf8_lossy(name).into_owned()),
+                    _ => None,
+                };
+                return match resolve_object(self.doc, value).ok()? {
+                    Object::Array(items) => Some(items.iter().filter_map(text).collect::<Vec<_>>().join(", ")),
+                    other => text(other),
+                }
+                .map(|value| value.trim().to_string());
+            }
+            let parent_id = node.get(b"Parent").and_then(|o| o.as_reference()).ok()?;
+            (id, node) = (parent_id, self.doc.get_dictionary(parent_id).ok()?);
+        }
+        None
+    }
+
+    /// 親からたどった部分名（/T）を `.` でつないだ完全な名前
+    fn full_name(&self, widget_id: ObjectId, widget: &Dictionary) -> String {
+        let mut parts = Vec::new();
+        let (mut id, mut node) = (widget_id, widget);
+        for _ in 0..MAX_FIELD_DEPTH {
+            if let Ok(title) = node.get(b"T") {
+                let container = title.as_reference().unwrap_or(id);
+                if let Ok(bytes) = resolve_object(self.doc, title).and_then(|o| o.as_str()) {
+                    parts.push(decode_pdf_string(&self.decryptor.decrypt(container, bytes)));
+                }
+            }
+            let Ok(parent_id) = node.get(b"Parent").and_then(|o| o.as_reference()) else { break };
+            let Ok(parent) = self.doc.get_dictionary(parent_id) else { break };
+            (id, node) = (parent_id, parent);
+        }
+        parts.reverse();
+        parts.join(".")
+    }
+}
@@ -0,0 +1,225 @@
+use crate::progress::{InfoLines, Progress};
+use crate::stamp::{number, overlay};
+use crate::{load_document, pagetree, resolve_object, save_document};
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+use std::path::Path;
+
+// 描き込んだ外観ストリームのページのリソース名（`PdfSplitterField1` など）
+const FIELD_XOBJECT_PREFIX: &str = "PdfSplitterField";
+
+// 注釈のフラグ（/F）の Hidden と NoView。これらのウィジェットは表示されていないため描き込まない
+const HIDDEN_FLAGS: i64 = 2 | 32;
+
+// 壊れた（循環した）フィールドの親子関係で無限にたどらないための上限
+const MAX_FIELD_DEPTH: usize = 32;
+
+/// `flatten-forms` サブコマンド: フォームのウィジェット注釈の外観ストリームをページの内容に描き込み、
+/// 入力できるフィールドを取り除いて保存する
+///
+/// 外観ストリームはそのままフォーム XObject としてページのリソースに加え、注釈の /Rect に合わせて配置する
+/// （PDF の仕様の、ビューアーが注釈の外観を描くときと同じ変換）。チェックボックスなどは /AS の状態の外観を使う。
+/// 外観ストリームが無いウィジェット（値を表示するのをビューアーに任せた /NeedAppearances の文書など）は、
+/// 描き込めないまま取り除き、その数を警告する。カタログの /AcroForm とフィールドのオブジェクトも取り除く。
+pub fn run(input_path: &Path, output_path: &Path) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut doc = load_document(input_path, false)?;
+    // 加える内容ストリームを暗号化できないため、暗号文の中に平文が混ざらないよう断る
+    if doc.is_encrypted() {
+        bail!("{:?} is encrypted; decrypt it first with `pdf_splitter decrypt`", input_path);
+    }
+    // 外観ストリームに /Resources が無いときは、フォームの既定のリソース（/DR）を使う
+    let default_resources = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"AcroForm").ok())
+        .and_then(|form| resolve_object(&doc, form).and_then(Object::as_dict).ok())
+        .and_then(|form| form.get(b"DR").ok())
+        .cloned();
+
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let mut removed = HashSet::new();
+    // 取り除くウィジェットの外観（描き込まなかった状態の外観ストリームなど）
+    let mut unused_appearances = HashSet::new();
+    let mut used_appearances = HashSet::new();
+    let (mut drawn, mut without_appearance, mut pages) = (0, 0, 0);
+    for (page, page_id) in &page_numbers {
+        let annots = page_annotations(&doc, *page_id);
+        let mut content = String::new();
+        let mut resources = Vec::new();
+        let mut kept = Vec::new();
+        for annot in annots {
+            let Some(widget) = resolve_object(&doc, &annot).and_then(Object::as_dict).ok().filter(|a| is_widget(a)) else {
+                kept.push(annot);
+                continue;
+            };
+            if let Ok(id) = annot.as_reference() {
+                removed.insert(id);
+            }
+            if let Ok(appearances) = widget.get(b"AP") {
+                appearance_objects(&doc, appearances, &mut unused_appearances, 0);
+            }
+            if widget.get(b"F").and_then(Object::as_i64).is_ok_and(|flags| flags & HIDDEN_FLAGS != 0) {
+                continue;
+            }
+            let Some((appearance_id, matrix)) = placement(&doc, widget) else {
+                without_appearance += 1;
+                progress.eprintln(format!("Warning: a form field on p.{} has no appearance stream; it is removed without being drawn", page));
+                continue;
+            };
+            let name = format!("{}{}", FIELD_XOBJECT_PREFIX, drawn + 1);
+            content.push_str(&format!("q\n{} cm\n/{} Do\nQ\n", matrix.iter().map(|v| number(*v)).collect::<Vec<_>>().join(" "), name));
+            resources.push((name, appearance_id));
+            used_appearances.insert(appearance_id);
+            drawn += 1;
+        }
+        if !resources.is_empty() {
+            for (_, appearance_id) in &resources {
+                prepare_form_xobject(&mut doc, *appearance_id, default_resources.as_ref());
+            }
+            let resources: Vec<(&str, &str, ObjectId)> = resources.iter().map(|(name, id)| ("XObject", name.as_str(), *id)).collect();
+            overlay(&mut doc, *page_id, content.into_bytes(), &resources)?;
+            pages += 1;
+        }
+        set_page_annotations(&mut doc, *page_id, kept);
+    }
+
+    if let Ok(catalog) = doc.catalog()
+        && let Ok(form) = catalog.get(b"AcroForm")
+    {
+        if let Ok(form_id) = form.as_reference() {
+            removed.insert(form_id);
+        }
+        if let Ok(fields) = resolve_object(&doc, form).and_then(Object::as_dict).and_then(|form| form.get(b"Fields")) {
+            collect_fields(&doc, fields, &mut removed, 0);
+        }
+    }
+    if removed.is_empty() && drawn == 0 {
+        bail!("{:?} has no form fields to flatten", input_path);
+    }
+    if let Ok(catalog) = doc.catalog_mut() {
+        catalog.remove(b"AcroForm");
+    }
+    for id in removed.iter().chain(unused_appearances.difference(&used_appearances)) {
+        doc.objects.remove(id);
+    }
+
+    save_document(&doc, output_path)?;
+    progress.println(format!(
+        "Flattened {} form fields on {} pages{}; wrote {:?}.",
+        drawn,
+        pages,
+        if without_appearance > 0 { format!(" ({} without an appearance were dropped)", without_appearance) } else { String::new() },
+        output_path
+    ));
+    Ok(())
+}
+
+fn is_widget(annot: &Dictionary) -> bool {
+    annot.get(b"Subtype").and_then(Object::as_name).is_ok_and(|subtype| subtype == b"Widget")
+}
+
+/// ウィジェットの通常の外観ストリームと、それを注釈の /Rect に合わせる変換行列
+fn placement(doc: &Document, widget: &Dictionary) -> Option<(ObjectId, [f32; 6])> {
+    let normal = widget.get(b"AP").and_then(|ap| resolve_object(doc, ap)).and_then(Object::as_dict).ok()?.get(b"N").ok()?;
+    // 状態ごとの外観の辞書（チェックボックス・ラジオボタン）なら、/AS の状態の外観
+    let appearance = match resolve_object(doc, normal).ok()? {
+        Object::Dictionary(states) => states.get(widget.get(b"AS").and_then(Object::as_name).ok()?).ok()?,
+        _ => normal,
+    };
+    let appearance_id = appearance.as_reference().ok()?;
+    let stream = doc.get_object(appearance_id).and_then(Object::as_stream).ok()?;
+
+    let rect = rectangle(resolve_object(doc, widget.get(b"Rect").ok()?).ok()?)?;
+    let bbox = rectangle(resolve_object(doc, stream.dict.get(b"BBox").ok()?).ok()?)?;
+    let m = match stream.dict.get(b"Matrix").and_then(|o| resolve_object(doc, o)).and_then(Object::as_array) {
+        Ok(values) if values.len() == 6 => {
+            let values: Vec<f32> = values.iter().filter_map(|v| v.as_float().ok()).collect();
+            <[f32; 6]>::try_from(values).ok()?
+        }
+        _ => [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+    };
+    // /Matrix で変換した /BBox を囲む矩形を、/Rect に拡大縮小・移動する
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])]
+        .map(|(x, y)| (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5]));
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max);
+    if max_x - min_x <= 0.0 || max_y - min_y <= 0.0 {
+        return None;
+    }
+    let scale_x = (rect[2] - rect[0]) / (max_x - min_x);
+    let scale_y = (rect[3] - rect[1]) / (max_y - min_y);
+    Some((appearance_id, [scale_x, 0.0, 0.0, scale_y, rect[0] - min_x * scale_x, rect[1] - min_y * scale_y]))
+}
+
+/// 正規化した矩形（左下x, 左下y, 右上x, 右上y）
+fn rectangle(object: &Object) -> Option<[f32; 4]> {
+    let values: Vec<f32> = object.as_array().ok()?.iter().filter_map(|v| v.as_float().ok()).collect();
+    let [x0, y0, x1, y1] = <[f32; 4]>::try_from(values).ok()?;
+    Some([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+}
+
+/// 外観ストリームを、ページから `Do` で描けるフォーム XObject にする
+fn prepare_form_xobject(doc: &mut Document, id: ObjectId, default_resources: Option<&Object>) {
+    let Ok(stream) = doc.get_object_mut(id).and_then(Object::as_stream_mut) else { return };
+    stream.dict.set("Type", "XObject");
+    stream.dict.set("Subtype", "Form");
+    if !stream.dict.has(b"Resources")
+        && let Some(resources) = default_resources
+    {
+        stream.dict.set("Resources", resources.clone());
+    }
+}
+
+/// ページの /Annots の項目（直接の配列か、配列オブジェクトへの参照）
+fn page_annotations(doc: &Document, page_id: ObjectId) -> Vec<Object> {
+    let Ok(page) = doc.get_dictionary(page_id) else { return Vec::new() };
+    page.get(b"Annots").and_then(|o| resolve_object(doc, o)).and_then(Object::as_array).cloned().unwrap_or_default()
+}
+
+/// ページの /Annots を `annots` に置き換える（空なら /Annots を取り除く）
+fn set_page_annotations(doc: &mut Document, page_id: ObjectId, annots: Vec<Object>) {
+    let Ok(page) = doc.get_object_mut(page_id).and_then(Object::as_dict_mut) else { return };
+    if annots.is_empty() {
+        page.remove(b"Annots");
+    } else {
+        page.set("Annots", annots);
+    }
+}
+
+/// 注釈の /AP から参照されているオブジェクト（状態ごとの外観の辞書と外観ストリーム）
+fn appearance_objects(doc: &Document, object: &Object, ids: &mut HashSet<ObjectId>, depth: usize) {
+    // /AP → /N・/D・/R → 状態の名前 → 外観ストリーム より深くはたどらない（外観のリソースは残す）
+    if depth > 2 {
+        return;
+    }
+    if let Ok(id) = object.as_reference() {
+        ids.insert(id);
+    }
+    if let Ok(dict) = resolve_object(doc, object).and_then(Object::as_dict) {
+        dict.iter().for_each(|(_, value)| appearance_objects(doc, value, ids, depth + 1));
+    }
+}
+
+/// フィールドのツリー（/Fields と /Kids）のオブジェクト
+fn collect_fields(doc: &Document, fields: &Object, ids: &mut HashSet<ObjectId>, depth: usize) {
+    if depth > MAX_FIELD_DEPTH {
+        return;
+    }
+    let Ok(fields) = resolve_object(doc, fields).and_then(Object::as_array) else { return };
+    for field in fields {
+        let Ok(id) = field.as_reference() else { continue };
+        if !ids.insert(id) {
+            continue;
+        }
+        if let Ok(kids) = doc.get_dictionary(id).and_then(|field| field.get(b"Kids")) {
+            collect_fields(doc, kids, ids, depth + 1);
+        }
+    }
+}
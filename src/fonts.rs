@@ -0,0 +1,143 @@
+use crate::pagetree::inherited;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+
+// 埋め込みフォントのプログラムを持つ FontDescriptor のキー
+const FONT_FILE_KEYS: [&[u8]; 3] = [b"FontFile", b"FontFile2", b"FontFile3"];
+
+/// 章のページのリソースに載っていても、どのページの内容からも使われていないフォント（`--strip-unused-fonts`）
+///
+/// ページ間で共有された /Resources に文書中の全フォントが並んでいると、章に不要なフォントの
+/// プログラムまでコピーされる。ここで求めたフォントを抽出時の除外対象に加えると、リソース辞書から
+/// その項目が取り除かれ、フォント辞書と埋め込みプログラムも書き出されなくなる。
+#[derive(Debug, Default)]
+pub struct UnusedFonts {
+    pub ids: HashSet<ObjectId>,
+    /// 取り除かれる埋め込みプログラム（FontFile / FontFile2 / FontFile3）のバイト数
+    pub program_bytes: u64,
+}
+
+/// 章のページの内容ストリームで Tf に使われたフォントと、フォーム・注釈・パターンなど
+/// ページのフォント辞書以外から参照されるフォントを残し、それ以外をページのフォント辞書から外す。
+/// 内容ストリームを読めないページのフォントはすべて使われているものとして扱う。
+pub fn find_unused(doc: &Document, page_ids: &[ObjectId]) -> UnusedFonts {
+    let mut candidates: HashSet<ObjectId> = HashSet::new();
+    let mut used: HashSet<ObjectId> = HashSet::new();
+    let mut reached = Reachability { doc, visited: HashSet::new() };
+
+    for page_id in page_ids {
+        let resources = inherited(doc, *page_id, b"Resources")
+            .and_then(|o| doc.dereference(&o).ok().and_then(|(_, o)| o.as_dict().ok().cloned()))
+            .unwrap_or_default();
+        let fonts = resources
+            .get(b"Font")
+            .and_then(|o| doc.dereference(o))
+            .and_then(|(_, o)| o.as_dict())
+            .cloned()
+            .unwrap_or_default();
+        let page_fonts: Vec<ObjectId> = fonts.iter().filter_map(|(_, o)| o.as_reference().ok()).collect();
+        candidates.extend(&page_fonts);
+
+        match font_names(doc, *page_id) {
+            Some(names) => used.extend(names.iter().filter_map(|name| fonts.get(name).and_then(Object::as_reference).ok())),
+            None => used.extend(&page_fonts),
+        }
+
+        // フォント辞書以外のリソース（フォームの中のフォントなど）と注釈の外観から到達できるものは残す
+        for (key, value) in resources.iter() {
+            if key != b"Font" {
+                reached.walk(value);
+            }
+        }
+        if let Ok(page) = doc.get_dictionary(*page_id)
+            && let Ok(annots) = page.get(b"Annots")
+        {
+            reached.walk(annots);
+        }
+    }
+    // AcroForm の /DR など、カタログから到達できるフォントも残す
+    if let Ok(catalog) = doc.catalog() {
+        for (key, value) in catalog.iter() {
+            if key != b"Pages" {
+                reached.walk(value);
+            }
+        }
+    }
+
+    let ids: HashSet<ObjectId> = candidates
+        .iter()
+        .filter(|id| !used.contains(id) && !reached.visited.contains(id))
+        .copied()
+        .collect();
+    // 残るフォントと共有している埋め込みプログラムは書き出されるため数えない
+    let kept_programs: HashSet<ObjectId> = candidates.difference(&ids).flat_map(|id| font_programs(doc, *id)).collect();
+    let program_bytes = ids
+        .iter()
+        .flat_map(|id| font_programs(doc, *id))
+        .collect::<HashSet<ObjectId>>()
+        .difference(&kept_programs)
+        .filter_map(|id| doc.get_object(*id).and_then(Object::as_stream).ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum();
+    UnusedFonts { ids, program_bytes }
+}
+
+/// ページの内容ストリームで Tf に指定されたフォント名（読めなければ None）
+fn font_names(doc: &Document, page_id: ObjectId) -> Option<HashSet<Vec<u8>>> {
+    let content = doc.get_page_content(page_id).ok()?;
+    let content = Content::decode(&content).ok()?;
+    Some(
+        content
+            .operations
+            .iter()
+            .filter(|operation| operation.operator == "Tf")
+            .filter_map(|operation| operation.operands.first()?.as_name().ok().map(<[u8]>::to_vec))
+            .collect(),
+    )
+}
+
+/// フォント（Type0 なら子孫フォントも）の FontDescriptor が指す埋め込みプログラム
+fn font_programs(doc: &Document, font_id: ObjectId) -> Vec<ObjectId> {
+    let Ok(font) = doc.get_dictionary(font_id) else { return Vec::new() };
+    let mut fonts: Vec<&Dictionary> = vec![font];
+    if let Ok(descendants) = font.get(b"DescendantFonts").and_then(|o| doc.dereference(o)).and_then(|(_, o)| o.as_array()) {
+        fonts.extend(descendants.iter().filter_map(|o| doc.dereference(o).ok()?.1.as_dict().ok()));
+    }
+    fonts
+        .into_iter()
+        .filter_map(|font| doc.dereference(font.get(b"FontDescriptor").ok()?).ok()?.1.as_dict().ok())
+        .flat_map(|descriptor| FONT_FILE_KEYS.iter().filter_map(|key| descriptor.get(key).and_then(Object::as_reference).ok()))
+        .collect()
+}
+
+/// オブジェクトから参照をたどって到達できるオブジェクトを集める（ページとページツリーの先へは進まない）
+struct Reachability<'a> {
+    doc: &'a Document,
+    visited: HashSet<ObjectId>,
+}
+
+impl Reachability<'_> {
+    fn walk(&mut self, object: &Object) {
+        let mut stack = vec![object];
+        while let Some(object) = stack.pop() {
+            match object {
+                Object::Reference(id) => {
+                    let Ok(target) = self.doc.get_object(*id) else { continue };
+                    let is_page = target
+                        .as_dict()
+                        .and_then(|d| d.get(b"Type"))
+                        .and_then(Object::as_name_str)
+                        .is_ok_and(|t| t == "Page" || t == "Pages");
+                    if !is_page && self.visited.insert(*id) {
+                        stack.push(target);
+                    }
+                }
+                Object::Array(items) => stack.extend(items.iter()),
+                Object::Dictionary(dict) => stack.extend(dict.iter().map(|(_, o)| o)),
+                Object::Stream(stream) => stack.extend(stream.dict.iter().map(|(_, o)| o)),
+                _ => {}
+            }
+        }
+    }
+}
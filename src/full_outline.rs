@@ -0,0 +1,144 @@
+use crate::{DestFallback, OutlineContext, resolve_object, resolve_outline_item};
+use clap::ValueEnum;
+use lopdf::{Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// 壊れた（循環した）目次で無限に潜らないための上限
+const MAX_OUTLINE_DEPTH: usize = 64;
+
+// 他の章を指す項目の文字色（灰色）
+const GREY: f32 = 0.6;
+
+// 目次項目の /F の書式フラグ
+const ITALIC: i64 = 1;
+const BOLD: i64 = 2;
+
+/// `--keep-full-outline` で、この章に無いページを指す目次項目の扱い
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MissingEntries {
+    /// 灰色の斜体にし、リンクを外す
+    Grey,
+    /// 章の末尾に加えた説明のページへリンクする
+    Placeholder,
+}
+
+/// 元の文書の目次の全項目と、それぞれの宛先のページ
+///
+/// 章のファイルには元の目次がそのままコピーされるが、他の章のページへのリンクは切れてしまう。
+/// 全体の構成を章のファイルでも見られるよう、項目は残したまま他の章を指す項目を区別して表示し、
+/// この章の項目を太字にして、その項目までの階層だけを開いておく。
+pub struct FullOutline {
+    items: Vec<Item>,
+    missing: MissingEntries,
+}
+
+struct Item {
+    id: ObjectId,
+    /// 宛先の元のページ番号とページ（解決できなければ `None`）
+    target: Option<(u32, ObjectId)>,
+    /// 親の項目（外側から順に）
+    ancestors: Vec<ObjectId>,
+}
+
+impl FullOutline {
+    pub fn collect(ctx: &OutlineContext, page_numbers: &BTreeMap<u32, ObjectId>, missing: MissingEntries) -> Self {
+        let mut items = Vec::new();
+        let first = ctx
+            .doc
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Outlines"))
+            .and_then(|outlines| resolve_object(ctx.doc, outlines))
+            .and_then(|outlines| outlines.as_dict())
+            .and_then(|outlines| outlines.get(b"First"))
+            .and_then(|first| first.as_reference());
+        if let Ok(first) = first {
+            walk(ctx, page_numbers, first, &mut Vec::new(), &mut HashSet::new(), &mut items);
+        }
+        Self { items, missing }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// `kept` の元のページだけを含む章で、説明のページを加える必要があるか
+    pub fn needs_placeholder(&self, kept: &HashSet<u32>) -> bool {
+        self.missing == MissingEntries::Placeholder
+            && self.items.iter().any(|item| !item.target.is_some_and(|(page, _)| kept.contains(&page)))
+    }
+
+    /// 章の文書にコピーされた目次項目を書き換え、他の章を指していた項目の数を返す
+    ///
+    /// `pages` は章に残した元のページ番号から、章の文書のページ（`--nup` では並べた用紙）への対応。
+    /// `placeholder` は `MissingEntries::Placeholder` で加えた説明のページ。
+    pub fn apply(&self, doc: &mut Document, pages: &HashMap<u32, ObjectId>, placeholder: Option<ObjectId>) -> usize {
+        let mut open = HashSet::new();
+        let mut missing = 0;
+        for item in &self.items {
+            let Ok(dict) = doc.get_object_mut(item.id).and_then(Object::as_dict_mut) else { continue };
+            let flags = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            match item.target.and_then(|(page, source_id)| Some((*pages.get(&page)?, source_id))) {
+                Some((page_id, source_id)) => {
+                    // 並べた用紙などページが置き換わった場合は、元の表示位置ではなく用紙全体を宛先にする
+                    if page_id != source_id {
+                        dict.remove(b"A");
+                        dict.set("Dest", vec![page_id.into(), Object::Name(b"Fit".to_vec())]);
+                    }
+                    dict.set("F", flags | BOLD);
+                    open.extend(item.ancestors.iter().copied());
+                }
+                None => {
+                    missing += 1;
+                    dict.remove(b"A");
+                    match placeholder {
+                        Some(placeholder) => dict.set("Dest", vec![placeholder.into(), Object::Name(b"Fit".to_vec())]),
+                        None => {
+                            dict.remove(b"Dest");
+                            dict.set("C", vec![Object::Real(GREY), Object::Real(GREY), Object::Real(GREY)]);
+                            dict.set("F", (flags & !BOLD) | ITALIC);
+                        }
+                    }
+                }
+            }
+        }
+        // この章の項目までの階層だけを開き、他は閉じる（/Count の符号が開閉を表す）
+        for item in &self.items {
+            let Ok(dict) = doc.get_object_mut(item.id).and_then(Object::as_dict_mut) else { continue };
+            if let Ok(count) = dict.get(b"Count").and_then(Object::as_i64) {
+                let count = if open.contains(&item.id) { count.abs() } else { -count.abs() };
+                dict.set("Count", count);
+            }
+        }
+        missing
+    }
+}
+
+fn walk(
+    ctx: &OutlineContext,
+    page_numbers: &BTreeMap<u32, ObjectId>,
+    first: ObjectId,
+    ancestors: &mut Vec<ObjectId>,
+    visited: &mut HashSet<ObjectId>,
+    items: &mut Vec<Item>,
+) {
+    if ancestors.len() >= MAX_OUTLINE_DEPTH {
+        return;
+    }
+    let mut current = Some(first);
+    while let Some(id) = current {
+        // 循環した /Next や /First を二度たどらない
+        if !visited.insert(id) {
+            break;
+        }
+        let Ok(item) = ctx.doc.get_object(id).and_then(|o| o.as_dict()) else { break };
+        let target = resolve_outline_item(ctx, id, item, DestFallback::Skip)
+            .and_then(|page| Some((page, *page_numbers.get(&page)?)));
+        items.push(Item { id, target, ancestors: ancestors.clone() });
+        if let Ok(child) = item.get(b"First").and_then(|o| o.as_reference()) {
+            ancestors.push(id);
+            walk(ctx, page_numbers, child, ancestors, visited, items);
+            ancestors.pop();
+        }
+        current = item.get(b"Next").and_then(|o| o.as_reference()).ok();
+    }
+}
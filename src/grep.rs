@@ -0,0 +1,94 @@
+use crate::cert::KeyFile;
+use crate::crypt::StringDecryptor;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, page_labels, page_ranges, pagetree, text};
+use anyhow::{Context, Result, bail};
+use lopdf::Object;
+use regex::RegexBuilder;
+use std::path::Path;
+
+// 一致した部分の前後に表示する文字数
+const SNIPPET_CONTEXT: usize = 40;
+
+/// `grep` で探す文字列の指定
+pub struct GrepOptions<'a> {
+    pub pattern: &'a str,
+    /// `pattern` を正規表現として扱う（そうでなければそのままの文字列）
+    pub regex: bool,
+    pub ignore_case: bool,
+}
+
+/// `grep` サブコマンド: 各ページの本文テキストから `pattern` を探し、一致したページ番号（とページラベル）と前後の文を表示する
+///
+/// テキストは `--extract-text` と同じ方法で取り出し、行の区切りや連続した空白は1つの空白にまとめてから探す
+/// （見出しが複数の行に分かれていても一致する）。最後に一致したページを `--pages` と同じ形式でまとめて表示する。
+pub fn run(input_path: &Path, options: &GrepOptions, password: Option<&str>, key_file: Option<&KeyFile>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    let pattern = if options.regex { options.pattern.to_string() } else { regex::escape(options.pattern) };
+    let matcher = RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .with_context(|| format!("Invalid regular expression {:?}", options.pattern))?;
+
+    let mut doc = load_document(input_path, false)?;
+    let decryptor = StringDecryptor::for_document(&doc, password, key_file)?;
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let total_pages = page_numbers.len() as u32;
+    let labels = page_labels::collect(&doc, &decryptor, total_pages);
+    // 内容ストリームを読む前に、暗号化された文書ならページの内容ストリームを復号しておく
+    if decryptor.is_active() {
+        for page_id in page_numbers.values() {
+            for id in doc.get_page_contents(*page_id) {
+                let Ok(Object::Stream(stream)) = doc.get_object(id) else { continue };
+                let decrypted = decryptor.decrypt_stream(id, stream).into_owned();
+                doc.objects.insert(id, Object::Stream(decrypted));
+            }
+        }
+    }
+
+    let (mut matches, mut matched_pages, mut unreadable) = (0, Vec::new(), 0);
+    for (page, page_id) in &page_numbers {
+        let (page_text, failed) = text::extract_pages(&doc, &[*page_id]);
+        unreadable += failed;
+        let page_text = page_text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let location = match labels.as_ref().and_then(|labels| labels.get(page)) {
+            Some(label) if !label.is_empty() => format!("p.{} [{}]", page, label),
+            _ => format!("p.{}", page),
+        };
+        let mut found = false;
+        for found_match in matcher.find_iter(&page_text).filter(|m| !m.is_empty()) {
+            progress.println(format!("{}: {}", location, snippet(&page_text, found_match.start(), found_match.end())));
+            matches += 1;
+            found = true;
+        }
+        if found {
+            matched_pages.push(*page);
+        }
+    }
+    if unreadable > 0 {
+        progress.eprintln(format!("Warning: could not read the text of {} pages.", unreadable));
+    }
+    if matched_pages.is_empty() {
+        bail!("No matches for {:?} in {:?}", options.pattern, input_path);
+    }
+    progress.println(format!(
+        "{} matches on {} of {} pages: {}",
+        matches,
+        matched_pages.len(),
+        total_pages,
+        page_ranges::format(&matched_pages)
+    ));
+    Ok(())
+}
+
+/// 一致した部分（`start..end`）と、その前後の `SNIPPET_CONTEXT` 文字
+fn snippet(text: &str, start: usize, end: usize) -> String {
+    let before_start = text[..start].char_indices().rev().nth(SNIPPET_CONTEXT - 1).map_or(0, |(i, _)| i);
+    let after_end = text[end..].char_indices().nth(SNIPPET_CONTEXT).map_or(text.len(), |(i, _)| end + i);
+    format!(
+        "{}{}{}",
+        if before_start > 0 { "…" } else { "" },
+        &text[before_start..after_end],
+        if after_end < text.len() { "…" } else { "" }
+    )
+}
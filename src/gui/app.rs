@@ -0,0 +1,457 @@
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use eframe::egui::{self, Button, Color32, RichText, TextEdit};
+use pdf_splitter::plan::{self, PlannedChapter};
+use pdf_splitter::progress::{InfoLines, Progress, ProgressEvent};
+use pdf_splitter::toc::{self, TocEntry};
+use pdf_splitter::{Args, interrupt, split_inputs};
+use std::ffi::OsString;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// 読み込み中・分割中に画面を描き直す間隔
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 入力を選び、章のツリーで書き出す章を選んで、名前を確かめてから分割する画面
+pub struct SplitterApp {
+    /// 入力のパス（ドロップしたファイルのパスもここに入れる）
+    path_text: String,
+    password: String,
+    /// `--title-path` と同じく、章の名前を上の階層の項目からのつながりにする
+    title_path: bool,
+    path_sep: String,
+    loading: Option<Loading>,
+    outline: Option<Outline>,
+    job: Option<Job>,
+    error: Option<String>,
+}
+
+impl Default for SplitterApp {
+    fn default() -> Self {
+        Self {
+            path_text: String::new(),
+            password: String::new(),
+            title_path: false,
+            path_sep: " - ".to_string(),
+            loading: None,
+            outline: None,
+            job: None,
+            error: None,
+        }
+    }
+}
+
+/// 目次を読んでいるスレッド（大きな文書でも画面を止めないよう、別のスレッドで読む）
+type Loading = JoinHandle<Result<Outline>>;
+
+/// 読み込んだ入力の目次と、章にする項目の選択
+struct Outline {
+    input: PathBuf,
+    total_pages: u32,
+    entries: Vec<TocEntry>,
+    /// `entries` の各項目を章にするか
+    checked: Vec<bool>,
+    /// 目次を読むときに出た警告
+    warnings: Vec<String>,
+}
+
+/// 分割中（または分割を終えた）の進み具合
+struct Job {
+    events: Receiver<ProgressEvent>,
+    handle: Option<JoinHandle<Result<(), String>>>,
+    total_pages: u64,
+    done_pages: u64,
+    /// 書き出し中の章（`[1/12] <ファイル名>`）
+    active: Vec<String>,
+    lines: Vec<String>,
+    cancelled: bool,
+    /// 終わったら結果（失敗ならエラーの文）
+    result: Option<Result<(), String>>,
+}
+
+impl Outline {
+    fn new(input: PathBuf, total_pages: u32, mut entries: Vec<TocEntry>, warnings: Vec<String>) -> Self {
+        // CLI と同じく、目次の無い文書は全体を1つのファイルにする
+        if entries.is_empty() {
+            entries.push(TocEntry { level: 1, title: "FullDocument".to_string(), start_page: Some(1), end_page: Some(total_pages) });
+        }
+        let checked = entries.iter().map(|entry| entry.level == 1 && entry.start_page.is_some()).collect();
+        Self { input, total_pages, entries, checked, warnings }
+    }
+
+    /// 宛先の分かる項目のうち `select` に当てはまるものだけを選ぶ
+    fn select(&mut self, select: impl Fn(&TocEntry) -> bool) {
+        for (checked, entry) in self.checked.iter_mut().zip(&self.entries) {
+            *checked = entry.start_page.is_some() && select(entry);
+        }
+    }
+
+    /// 出力先（CLI と同じく入力と同じディレクトリ）
+    fn output_dir(&self) -> &Path {
+        self.input.parent().unwrap_or_else(|| Path::new("."))
+    }
+
+    /// 選んだ項目の計画（名前のプレビューに表示し、そのまま計画ファイルにして分割する）
+    fn plan(&self, path_sep: Option<&str>) -> Result<Vec<PlannedChapter>> {
+        let file_stem = self.input.file_stem().context("Invalid file name")?.to_string_lossy();
+        let name_stem = plan::output_stem(&file_stem, self.output_dir())?;
+        let sections: Vec<(u32, u32, String)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.checked[*i])
+            .filter_map(|(i, entry)| Some((entry.start_page?, entry.end_page?, self.title(i, path_sep))))
+            .collect();
+        Ok(plan::plan_sections(&sections, self.total_pages, &name_stem, self.output_dir()))
+    }
+
+    /// `i` 番目の項目のタイトル（`path_sep` があれば、上の階層の項目のタイトルからつなぐ）
+    fn title(&self, i: usize, path_sep: Option<&str>) -> String {
+        let Some(path_sep) = path_sep else { return self.entries[i].title.clone() };
+        let mut titles = vec![self.entries[i].title.as_str()];
+        let mut level = self.entries[i].level;
+        for entry in self.entries[..i].iter().rev() {
+            if entry.level < level {
+                titles.push(&entry.title);
+                level = entry.level;
+            }
+        }
+        titles.reverse();
+        titles.join(path_sep)
+    }
+}
+
+impl Job {
+    /// 分割のスレッドから届いた進み具合を取り込み、終わっていれば結果を受け取る
+    fn poll(&mut self) {
+        // 終わったかどうかを先に見て、終わる直前に送られた行も取りこぼさないようにする
+        let finished = self.handle.as_ref().is_some_and(JoinHandle::is_finished);
+        for event in self.events.try_iter() {
+            match event {
+                ProgressEvent::Line(line) => self.lines.push(line),
+                ProgressEvent::Pages(total) => self.total_pages += total,
+                ProgressEvent::ChapterStarted(name) => self.active.push(name),
+                ProgressEvent::ChapterFinished(name, pages) => {
+                    self.active.retain(|active| *active != name);
+                    self.done_pages += pages;
+                }
+            }
+        }
+        if finished && let Some(handle) = self.handle.take() {
+            self.result = Some(handle.join().unwrap_or_else(|_| Err("the split stopped unexpectedly".to_string())));
+            interrupt::clear();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.result.is_none()
+    }
+}
+
+impl SplitterApp {
+    /// 入力の目次を別のスレッドで読み始める
+    fn load(&mut self, input: PathBuf) {
+        self.path_text = input.display().to_string();
+        self.outline = None;
+        self.job = None;
+        self.error = None;
+        let password = (!self.password.is_empty()).then(|| self.password.clone());
+        self.loading = Some(thread::spawn(move || {
+            let (sender, receiver) = mpsc::channel();
+            let progress = Progress::with_observer(InfoLines::Hidden, sender);
+            let result = toc::read_entries(&input, password.as_deref(), &progress);
+            // 送られた警告をすべて受け取れるよう、出力スレッドを終わらせてから集める
+            drop(progress);
+            let warnings = receiver
+                .try_iter()
+                .filter_map(|event| match event {
+                    ProgressEvent::Line(line) => Some(line),
+                    _ => None,
+                })
+                .collect();
+            result.map(|(total_pages, entries)| Outline::new(input, total_pages, entries, warnings))
+        }));
+    }
+
+    /// 計画を一時ファイルに書き出し、CLI の `--plan` と同じ引数で分割を始める
+    fn start(&mut self, chapters: &[PlannedChapter]) -> Result<()> {
+        let Some(outline) = &self.outline else { return Ok(()) };
+        if chapters.is_empty() {
+            bail!("check at least one chapter to split");
+        }
+        let plan_path = std::env::temp_dir().join(format!("pdf_splitter-gui_plan_{}.json", std::process::id()));
+        plan::write_plan(&plan_path, &outline.input, outline.total_pages, chapters)?;
+        let mut argv: Vec<OsString> =
+            vec!["pdf_splitter".into(), outline.input.clone().into(), "--plan".into(), plan_path.clone().into()];
+        if self.title_path {
+            argv.push("--title-path".into());
+        }
+        if !self.password.is_empty() {
+            argv.extend(["--password".into(), self.password.clone().into()]);
+        }
+        let args = Args::try_parse_from(argv)?;
+
+        interrupt::clear();
+        let (sender, events) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let progress = Progress::with_observer(InfoLines::Hidden, sender);
+            let result = split_inputs(&args, &progress).map_err(|e| format!("{:#}", e));
+            let _ = std::fs::remove_file(&plan_path);
+            result
+        });
+        self.job = Some(Job {
+            events,
+            handle: Some(handle),
+            total_pages: 0,
+            done_pages: 0,
+            active: Vec::new(),
+            lines: Vec::new(),
+            cancelled: false,
+            result: None,
+        });
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        if self.loading.as_ref().is_some_and(JoinHandle::is_finished)
+            && let Some(loading) = self.loading.take()
+        {
+            match loading.join() {
+                Ok(Ok(outline)) => self.outline = Some(outline),
+                Ok(Err(e)) => self.error = Some(format!("{:#}", e)),
+                Err(_) => self.error = Some("reading the outline stopped unexpectedly".to_string()),
+            }
+        }
+        if let Some(job) = &mut self.job {
+            job.poll();
+        }
+    }
+
+    /// ウィンドウにドロップされたファイルを読み込む（分割中は受け付けない）
+    fn take_dropped_file(&mut self, ctx: &egui::Context) {
+        if self.job.as_ref().is_some_and(Job::is_running) {
+            return;
+        }
+        if let Some(path) = ctx.input(|input| input.raw.dropped_files.iter().find_map(|file| file.path.clone())) {
+            self.load(path);
+        }
+    }
+
+    fn input_bar(&mut self, ui: &mut egui::Ui) {
+        let running = self.job.as_ref().is_some_and(Job::is_running);
+        ui.add_enabled_ui(!running, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("PDF:");
+                let path = ui.add(TextEdit::singleline(&mut self.path_text).hint_text("Drop a PDF here or type its path").desired_width(420.0));
+                ui.label("Password:");
+                ui.add(TextEdit::singleline(&mut self.password).password(true).desired_width(120.0));
+                let submitted = path.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                if (ui.button("Load").clicked() || submitted) && !self.path_text.trim().is_empty() {
+                    self.load(PathBuf::from(self.path_text.trim()));
+                }
+                if self.loading.is_some() {
+                    ui.spinner();
+                }
+            });
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Error: {}", error));
+        }
+    }
+
+    /// 目次の項目を入れ子のツリーにし、章にする項目をチェックボックスで選ぶ
+    fn chapter_tree(&mut self, ui: &mut egui::Ui) {
+        let Some(outline) = &mut self.outline else { return };
+        ui.heading("Chapters");
+        ui.horizontal(|ui| {
+            if ui.button("Top level").clicked() {
+                outline.select(|entry| entry.level == 1);
+            }
+            if ui.button("All").clicked() {
+                outline.select(|_| true);
+            }
+            if ui.button("None").clicked() {
+                outline.select(|_| false);
+            }
+        });
+        for warning in &outline.warnings {
+            ui.colored_label(ui.visuals().warn_fg_color, warning);
+        }
+        ui.separator();
+        egui::ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+            show_entries(ui, &outline.entries, &mut outline.checked, 0..outline.entries.len());
+        });
+    }
+
+    /// 選んだ章のページ範囲と出力ファイル名の一覧（分割するとこのとおりの名前で書き出す）
+    fn naming_preview(&mut self, ui: &mut egui::Ui) {
+        let Some(outline) = &self.outline else {
+            ui.centered_and_justified(|ui| ui.heading("Drop a PDF onto this window to see its chapters"));
+            return;
+        };
+        ui.heading("Output files");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.title_path, "Name chapters after their parent items too, joined by");
+            ui.add_enabled(self.title_path, TextEdit::singleline(&mut self.path_sep).desired_width(48.0));
+        });
+        ui.label(format!("Saved next to the input in {:?}", outline.output_dir()));
+        let chapters = match outline.plan(self.title_path.then_some(self.path_sep.as_str())) {
+            Ok(chapters) => chapters,
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Error: {:#}", e));
+                return;
+            }
+        };
+        let pages: u32 = chapters.iter().map(PlannedChapter::page_count).sum();
+        let mut start = false;
+        ui.horizontal(|ui| {
+            start = ui.add_enabled(!chapters.is_empty(), Button::new("Split")).clicked();
+            ui.label(format!("{} files, {} of {} pages", chapters.len(), pages, outline.total_pages));
+        });
+        ui.separator();
+        egui::ScrollArea::both().auto_shrink(false).show(ui, |ui| {
+            egui::Grid::new("naming_preview").striped(true).num_columns(3).show(ui, |ui| {
+                ui.strong("#");
+                ui.strong("Pages");
+                ui.strong("File name");
+                ui.end_row();
+                for chapter in &chapters {
+                    ui.label((chapter.index + 1).to_string());
+                    ui.label(format!("{}-{}", chapter.start_page, chapter.end_page));
+                    ui.label(&chapter.filename);
+                    ui.end_row();
+                }
+            });
+        });
+        if start && let Err(e) = self.start(&chapters) {
+            self.error = Some(format!("{:#}", e));
+        }
+    }
+
+    /// 分割の進み具合（処理したページ数・書き出し中の章・ログ）
+    fn progress_view(&mut self, ui: &mut egui::Ui) {
+        let Some(job) = &mut self.job else { return };
+        ui.heading(match &job.result {
+            None if job.cancelled => "Cancelling...",
+            None => "Splitting...",
+            Some(Ok(())) => "Done",
+            Some(Err(_)) => "Failed",
+        });
+        let fraction = if job.total_pages == 0 { 0.0 } else { job.done_pages as f32 / job.total_pages as f32 };
+        ui.add(egui::ProgressBar::new(fraction).text(format!("{}/{} pages", job.done_pages, job.total_pages)));
+        for name in &job.active {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(name);
+            });
+        }
+        if let Some(Err(e)) = &job.result {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Error: {}", e));
+        }
+        let mut back = false;
+        ui.horizontal(|ui| {
+            if job.is_running() {
+                if ui.add_enabled(!job.cancelled, Button::new("Cancel")).clicked() {
+                    interrupt::request();
+                    job.cancelled = true;
+                }
+            } else {
+                back = ui.button("Back to chapters").clicked();
+            }
+        });
+        ui.separator();
+        egui::ScrollArea::vertical().auto_shrink(false).stick_to_bottom(true).show(ui, |ui| {
+            for line in &job.lines {
+                let color = if line.starts_with("Error") {
+                    ui.visuals().error_fg_color
+                } else if line.starts_with("Warning") {
+                    ui.visuals().warn_fg_color
+                } else {
+                    ui.visuals().text_color()
+                };
+                ui.label(RichText::new(line).color(color).monospace());
+            }
+        });
+        if back {
+            self.job = None;
+        }
+    }
+}
+
+impl eframe::App for SplitterApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll();
+        self.take_dropped_file(ctx);
+        if self.loading.is_some() || self.job.as_ref().is_some_and(Job::is_running) {
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+        }
+
+        egui::TopBottomPanel::top("input").show(ctx, |ui| {
+            ui.add_space(4.0);
+            self.input_bar(ui);
+            ui.add_space(4.0);
+        });
+        if self.job.is_some() {
+            egui::CentralPanel::default().show(ctx, |ui| self.progress_view(ui));
+        } else {
+            if self.outline.is_some() {
+                egui::SidePanel::left("chapters").resizable(true).default_width(440.0).show(ctx, |ui| self.chapter_tree(ui));
+            }
+            egui::CentralPanel::default().show(ctx, |ui| self.naming_preview(ui));
+        }
+        drop_hint(ctx);
+    }
+}
+
+/// `range` の項目を、子の項目（続く、より下の階層の項目）を折りたためる入れ子にして表示する
+fn show_entries(ui: &mut egui::Ui, entries: &[TocEntry], checked: &mut [bool], range: Range<usize>) {
+    let mut i = range.start;
+    while i < range.end {
+        let end = (i + 1..range.end).find(|next| entries[*next].level <= entries[i].level).unwrap_or(range.end);
+        if end > i + 1 {
+            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), ui.make_persistent_id(("entry", i)), false)
+                .show_header(ui, |ui| entry_row(ui, &entries[i], &mut checked[i]))
+                .body(|ui| show_entries(ui, entries, checked, i + 1..end));
+        } else {
+            ui.horizontal(|ui| {
+                ui.add_space(ui.spacing().indent);
+                entry_row(ui, &entries[i], &mut checked[i]);
+            });
+        }
+        i = end;
+    }
+}
+
+/// 項目のチェックボックスとページ範囲（宛先の分からない項目は選べない）
+fn entry_row(ui: &mut egui::Ui, entry: &TocEntry, checked: &mut bool) {
+    match (entry.start_page, entry.end_page) {
+        (Some(start_page), Some(end_page)) => {
+            ui.checkbox(checked, &entry.title);
+            ui.label(RichText::new(format!("p.{}-{}", start_page, end_page)).weak());
+        }
+        _ => {
+            ui.add_enabled(false, egui::Checkbox::new(&mut false, &entry.title));
+            ui.label(RichText::new("no destination").weak());
+        }
+    }
+}
+
+/// ファイルをウィンドウの上に持ってきている間、ドロップできることを示す
+fn drop_hint(ctx: &egui::Context) {
+    if ctx.input(|input| input.raw.hovered_files.is_empty()) {
+        return;
+    }
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("drop_hint")));
+    let rect = ctx.screen_rect();
+    painter.rect_filled(rect, 0.0, Color32::from_black_alpha(160));
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        "Drop the PDF to load its chapters",
+        egui::TextStyle::Heading.resolve(&ctx.style()),
+        Color32::WHITE,
+    );
+}
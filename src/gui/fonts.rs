@@ -0,0 +1,25 @@
+use eframe::egui::{self, FontData, FontDefinitions, FontFamily};
+
+// 日本語の文字を含む OS のフォント（見つかった最初のものを使う）
+const CJK_FONTS: [&str; 7] = [
+    "C:\\Windows\\Fonts\\YuGothM.ttc",
+    "C:\\Windows\\Fonts\\meiryo.ttc",
+    "C:\\Windows\\Fonts\\msgothic.ttc",
+    "/System/Library/Fonts/ヒラギノ角ゴシック W3.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+];
+
+/// egui の既定のフォントには日本語の文字が無いため、OS のフォントがあれば代わりの字形として加える
+///
+/// 見つからなければ既定のフォントのまま（日本語の目次タイトルは表示できない文字になる）。
+pub fn install(ctx: &egui::Context) {
+    let Some(data) = CJK_FONTS.iter().find_map(|path| std::fs::read(path).ok()) else { return };
+    let mut fonts = FontDefinitions::default();
+    fonts.font_data.insert("cjk".to_string(), FontData::from_owned(data));
+    for family in [FontFamily::Proportional, FontFamily::Monospace] {
+        fonts.families.entry(family).or_default().push("cjk".to_string());
+    }
+    ctx.set_fonts(fonts);
+}
@@ -0,0 +1,23 @@
+use eframe::egui;
+
+mod app;
+mod fonts;
+
+/// 目次の章を選んで分割するデスクトップ GUI（CLI と同じ計画・分割の処理をライブラリから呼ぶ）
+fn main() -> eframe::Result {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("PDF Splitter")
+            .with_inner_size([960.0, 640.0])
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "pdf_splitter",
+        options,
+        Box::new(|cc| {
+            fonts::install(&cc.egui_ctx);
+            Ok(Box::new(app::SplitterApp::default()))
+        }),
+    )
+}
@@ -0,0 +1,232 @@
+use crate::pagetree::inherited;
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::HashMap;
+
+// フォームの入れ子をたどる深さの上限
+const MAX_FORM_DEPTH: usize = 16;
+
+/// `--downsample` の解像度（"150dpi" または "150"）
+pub fn parse_dpi(value: &str) -> Result<u32, String> {
+    let digits = value.strip_suffix("dpi").unwrap_or(value);
+    match digits.parse::<u32>() {
+        Ok(dpi) if (10..=2400).contains(&dpi) => Ok(dpi),
+        _ => Err(format!("invalid resolution {:?} (expected e.g. 150dpi)", value)),
+    }
+}
+
+/// `--jpeg-quality`（1〜100）
+pub fn parse_quality(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(quality) if (1..=100).contains(&quality) => Ok(quality),
+        _ => Err(format!("invalid JPEG quality {:?} (expected 1-100)", value)),
+    }
+}
+
+/// 指定の解像度を超える画像を縮小し、JPEG で圧縮し直す（`--downsample`）
+///
+/// 解像度は画像がそれを使うページ全体に広がっているとみなして求める（スキャンした本のページ画像を想定）。
+/// ページより小さく配置された画像は実際より低い解像度と見積もるため、縮小しすぎることはない。
+/// 対応するのは 8 ビットのグレー・RGB の画像（Flate 圧縮で予測子なし、または JPEG）だけで、
+/// それ以外の画像や、縮小しても小さくならない画像はそのまま残す。
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "images"), allow(dead_code))]
+pub struct Downsampler {
+    dpi: u32,
+    quality: u8,
+}
+
+/// 1つの章で縮小した画像の数と、減ったバイト数
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Downsampled {
+    pub images: usize,
+    pub bytes_saved: u64,
+}
+
+/// 章のページで使われている画像ごとの、ページの大きさ（ポイント）
+pub struct Placements {
+    pages: HashMap<ObjectId, (f32, f32)>,
+    /// どのページから使われているか分からない画像（パターンの中など）には章で最も大きいページを使う
+    largest: Option<(f32, f32)>,
+}
+
+impl Placements {
+    pub fn page_size(&self, image_id: ObjectId) -> Option<(f32, f32)> {
+        self.pages.get(&image_id).copied().or(self.largest)
+    }
+}
+
+impl Downsampler {
+    /// 章のページのリソースから画像を集め、それぞれを使うページの大きさを調べる
+    pub fn placements(&self, source: &Document, page_ids: &[ObjectId]) -> Placements {
+        let mut placements = Placements { pages: HashMap::new(), largest: None };
+        for page_id in page_ids {
+            let Some(size) = inherited(source, *page_id, b"MediaBox").and_then(|rect| rect_size(source, &rect)) else {
+                continue;
+            };
+            if placements.largest.is_none_or(|(w, h)| size.0 * size.1 > w * h) {
+                placements.largest = Some(size);
+            }
+            if let Some(resources) = inherited(source, *page_id, b"Resources") {
+                collect_images(source, &resources, size, &mut placements.pages, 0);
+            }
+        }
+        placements
+    }
+}
+
+fn rect_size(doc: &Document, rect: &Object) -> Option<(f32, f32)> {
+    let values = doc.dereference(rect).ok()?.1.as_array().ok()?;
+    let numbers: Vec<f32> = values
+        .iter()
+        .filter_map(|v| doc.dereference(v).ok().and_then(|(_, v)| v.as_float().ok()))
+        .collect();
+    let [x0, y0, x1, y1] = numbers.as_slice() else { return None };
+    Some(((x1 - x0).abs(), (y1 - y0).abs()))
+}
+
+fn collect_images(
+    doc: &Document,
+    resources: &Object,
+    size: (f32, f32),
+    images: &mut HashMap<ObjectId, (f32, f32)>,
+    depth: usize,
+) {
+    if depth > MAX_FORM_DEPTH {
+        return;
+    }
+    let Ok(xobjects) = doc
+        .dereference(resources)
+        .and_then(|(_, o)| o.as_dict())
+        .and_then(|r| r.get(b"XObject"))
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_dict())
+    else {
+        return;
+    };
+    for (_, xobject) in xobjects.iter() {
+        let Ok(id) = xobject.as_reference() else { continue };
+        let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else { continue };
+        match stream.dict.get(b"Subtype").and_then(Object::as_name_str) {
+            Ok("Image") => {
+                let entry = images.entry(id).or_insert(size);
+                if size.0 * size.1 > entry.0 * entry.1 {
+                    *entry = size;
+                }
+            }
+            Ok("Form") => {
+                if let Ok(form_resources) = stream.dict.get(b"Resources") {
+                    collect_images(doc, form_resources, size, images, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 画像の色空間の成分数（グレーと RGB だけを扱う）
+#[cfg(feature = "images")]
+fn components(doc: &Document, dict: &lopdf::Dictionary) -> Option<u8> {
+    let color_space = doc.dereference(dict.get(b"ColorSpace").ok()?).ok()?.1;
+    match color_space {
+        Object::Name(name) if name == b"DeviceGray" => Some(1),
+        Object::Name(name) if name == b"DeviceRGB" => Some(3),
+        Object::Array(items) if items.first().and_then(|o| o.as_name_str().ok()) == Some("ICCBased") => {
+            let profile = doc.dereference(items.get(1)?).ok()?.1.as_stream().ok()?;
+            match profile.dict.get(b"N").and_then(Object::as_i64).ok()? {
+                1 => Some(1),
+                3 => Some(3),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "images")]
+impl Downsampler {
+    pub fn new(dpi: u32, quality: u8) -> Result<Self> {
+        Ok(Self { dpi, quality })
+    }
+
+    /// 縮小した画像のストリームを返す（対象外か、小さくならなければ None）
+    pub fn downsample(&self, doc: &Document, stream: &Stream, page_size: (f32, f32)) -> Option<Stream> {
+        use image::imageops::FilterType;
+        use image::{DynamicImage, GrayImage, RgbImage};
+
+        let dict = &stream.dict;
+        if dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false)
+            || dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok()? != 8
+        {
+            return None;
+        }
+        let width = u32::try_from(dict.get(b"Width").and_then(Object::as_i64).ok()?).ok()?;
+        let height = u32::try_from(dict.get(b"Height").and_then(Object::as_i64).ok()?).ok()?;
+        let components = components(doc, dict)?;
+
+        // ページ全体に広げたときの解像度。縦横で低いほうを使う
+        let dpi = (width as f32 * 72.0 / page_size.0).min(height as f32 * 72.0 / page_size.1);
+        // わずかに超える程度なら画質を落とす価値がない
+        if !dpi.is_finite() || dpi <= self.dpi as f32 * 1.1 {
+            return None;
+        }
+        let scale = self.dpi as f32 / dpi;
+        let new_width = ((width as f32 * scale).round() as u32).max(1);
+        let new_height = ((height as f32 * scale).round() as u32).max(1);
+
+        let filters = stream.filters().ok()?;
+        let image = match filters.as_slice() {
+            [filter] if filter == "DCTDecode" => {
+                let decoded = image::load_from_memory_with_format(&stream.content, image::ImageFormat::Jpeg).ok()?;
+                if components == 1 { DynamicImage::ImageLuma8(decoded.to_luma8()) } else { DynamicImage::ImageRgb8(decoded.to_rgb8()) }
+            }
+            [filter] if filter == "FlateDecode" => {
+                let predictor = dict
+                    .get(b"DecodeParms")
+                    .and_then(Object::as_dict)
+                    .and_then(|p| p.get(b"Predictor"))
+                    .and_then(Object::as_i64)
+                    .unwrap_or(1);
+                if predictor != 1 {
+                    return None;
+                }
+                let mut samples = Vec::new();
+                std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(stream.content.as_slice()), &mut samples).ok()?;
+                samples.truncate(width as usize * height as usize * components as usize);
+                if components == 1 {
+                    DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, samples)?)
+                } else {
+                    DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, samples)?)
+                }
+            }
+            _ => return None,
+        };
+
+        let resized = image.resize_exact(new_width, new_height, FilterType::Triangle);
+        let mut encoded = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, self.quality)
+            .encode_image(&resized)
+            .ok()?;
+        if encoded.len() >= stream.content.len() {
+            return None;
+        }
+        let mut new_dict = dict.clone();
+        new_dict.set("Filter", "DCTDecode");
+        new_dict.remove(b"DecodeParms");
+        new_dict.set("Width", i64::from(new_width));
+        new_dict.set("Height", i64::from(new_height));
+        Some(Stream::new(new_dict, encoded))
+    }
+}
+
+/// `images` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "images"))]
+impl Downsampler {
+    pub fn new(_dpi: u32, _quality: u8) -> Result<Self> {
+        anyhow::bail!("--downsample is not available in this build; rebuild with `cargo build --release --features images`")
+    }
+
+    pub fn downsample(&self, _doc: &Document, _stream: &Stream, _page_size: (f32, f32)) -> Option<Stream> {
+        None
+    }
+}
@@ -0,0 +1,156 @@
+use crate::extract::{INHERITABLE_PAGE_KEYS, inherited_attribute, page_tree_nodes, prune_refs};
+use crate::page_ranges::PageRanges;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, save_document};
+use anyhow::{Context, Result, bail};
+use lopdf::{Document, Object, ObjectId};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+// 取り込んだページから外すキー（元の文書の構造ツリー・記事スレッドに属するもの）
+const SOURCE_ONLY_PAGE_KEYS: [&[u8]; 2] = [b"StructParents", b"B"];
+
+// 壊れた（循環した）ページツリーで無限に親をたどらないための上限
+const MAX_TREE_DEPTH: usize = 256;
+
+/// `insert` サブコマンド: 別の文書のページを、`at` ページ目の前（`at` が総ページ数+1 なら最後）に取り込んで保存する
+///
+/// `pages` を省略すると `from` のすべてのページを取り込む。どちらかの文書が暗号化されていれば断る
+/// （文字列とストリームの鍵がオブジェクト番号から作られ、番号を付け直すと復号できなくなるため）。
+pub fn run(base_path: &Path, at: u32, from: &Path, pages: Option<&PageRanges>, output_path: &Path) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| base_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut base = load_document(base_path, false)?;
+    let source = load_document(from, false)?;
+    for (path, doc) in [(base_path, &base), (from, &source)] {
+        if doc.is_encrypted() {
+            bail!("{:?} is encrypted; decrypt it first with `pdf_splitter decrypt`", path);
+        }
+    }
+    let (base_pages, _) = pagetree::collect_pages(&base);
+    let total = base_pages.len() as u32;
+    if total == 0 {
+        bail!("{:?} has no pages to insert into", base_path);
+    }
+    if !(1..=total + 1).contains(&at) {
+        bail!("--at must be between 1 and {} (the page count of {:?} plus one)", total + 1, base_path);
+    }
+    let (source_pages, _) = pagetree::collect_pages(&source);
+    let selected: Vec<ObjectId> =
+        source_pages.iter().filter(|(page, _)| pages.is_none_or(|pages| pages.contains(**page))).map(|(_, id)| *id).collect();
+    if selected.is_empty() {
+        bail!("No pages of {:?} are in --pages (the document has {} pages)", from, source_pages.len());
+    }
+
+    let imported = import_pages(&mut base, &source, &selected);
+    // 挿入位置のページの前か、最後のページの後ろ
+    let (anchor, after) = match base_pages.get(&at) {
+        Some(page_id) => (*page_id, false),
+        None => (base_pages[&total], true),
+    };
+    insert_into_page_tree(&mut base, anchor, after, &imported).context("Failed to insert the pages into the page tree")?;
+
+    save_document(&base, output_path)?;
+    progress.println(format!(
+        "Inserted {} pages from {:?} at page {}; wrote {:?} ({} pages).",
+        imported.len(),
+        from,
+        at,
+        output_path,
+        total as usize + imported.len()
+    ));
+    Ok(())
+}
+
+/// `source` の `page_ids` のページと、そこから到達できるオブジェクトを、新しい番号を振って `target` にコピーする
+///
+/// ページは継承属性を自身に持たせ、`source` のページツリーのノードと選ばなかったページへの参照は
+/// `PageExtractor` と同じく辞書のキーごと・配列の要素ごと取り除く。戻り値は `page_ids` の順のコピーしたページ
+/// （`/Parent` はまだ無い）。
+pub fn import_pages(target: &mut Document, source: &Document, page_ids: &[ObjectId]) -> Vec<ObjectId> {
+    let selected: HashSet<ObjectId> = page_ids.iter().copied().collect();
+    let mut excluded = page_tree_nodes(source);
+    let (all_pages, _) = pagetree::collect_pages(source);
+    excluded.extend(all_pages.values().filter(|id| !selected.contains(id)));
+
+    let mut numbering = Renumbering { target, mapping: HashMap::new(), queue: VecDeque::new() };
+    let imported: Vec<ObjectId> = page_ids.iter().map(|id| numbering.map(*id)).collect();
+    while let Some(id) = numbering.queue.pop_front() {
+        let Ok(object) = source.get_object(id) else { continue };
+        let mut object = object.clone();
+        if selected.contains(&id)
+            && let Ok(page) = object.as_dict_mut()
+        {
+            for key in INHERITABLE_PAGE_KEYS {
+                if !page.has(key)
+                    && let Some(value) = inherited_attribute(source, page, key)
+                {
+                    page.set(key.to_vec(), value);
+                }
+            }
+            for key in SOURCE_ONLY_PAGE_KEYS {
+                page.remove(key);
+            }
+        }
+        prune_refs(&mut object, &excluded);
+        numbering.renumber(&mut object);
+        let new_id = numbering.map(id);
+        numbering.target.objects.insert(new_id, object);
+    }
+    imported
+}
+
+/// コピー元のオブジェクト番号から、コピー先で振った番号への対応
+struct Renumbering<'a> {
+    target: &'a mut Document,
+    mapping: HashMap<ObjectId, ObjectId>,
+    /// 番号を振ったが、まだコピーしていないオブジェクト
+    queue: VecDeque<ObjectId>,
+}
+
+impl Renumbering<'_> {
+    fn map(&mut self, id: ObjectId) -> ObjectId {
+        if let Some(new_id) = self.mapping.get(&id) {
+            return *new_id;
+        }
+        let new_id = self.target.new_object_id();
+        self.mapping.insert(id, new_id);
+        self.queue.push_back(id);
+        new_id
+    }
+
+    fn renumber(&mut self, object: &mut Object) {
+        match object {
+            Object::Reference(id) => *id = self.map(*id),
+            Object::Array(array) => array.iter_mut().for_each(|item| self.renumber(item)),
+            Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| self.renumber(value)),
+            Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| self.renumber(value)),
+            _ => {}
+        }
+    }
+}
+
+/// `anchor` のページの前（`after` なら後ろ）に `new_pages` を並べ、祖先のノードの /Count を増やす
+fn insert_into_page_tree(doc: &mut Document, anchor: ObjectId, after: bool, new_pages: &[ObjectId]) -> Result<()> {
+    let parent_id = doc.get_dictionary(anchor)?.get(b"Parent")?.as_reference()?;
+    let parent = doc.get_dictionary_mut(parent_id)?;
+    let kids = parent.get_mut(b"Kids")?.as_array_mut()?;
+    let index = kids.iter().position(|kid| kid.as_reference().ok() == Some(anchor)).context("the page is not in its parent's /Kids")?;
+    let index = if after { index + 1 } else { index };
+    kids.splice(index..index, new_pages.iter().map(|id| Object::Reference(*id)));
+    for page_id in new_pages {
+        doc.get_dictionary_mut(*page_id)?.set("Parent", parent_id);
+    }
+
+    let mut node = Some(parent_id);
+    for _ in 0..MAX_TREE_DEPTH {
+        let Some(node_id) = node else { break };
+        let node_dict = doc.get_dictionary_mut(node_id)?;
+        let count = node_dict.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+        node_dict.set("Count", count + new_pages.len() as i64);
+        node = node_dict.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 中断したときの終了コード（シェルの慣例に合わせて 128 + SIGINT）
+pub const EXIT_CODE: i32 = 130;
+
+/// 書き出し中の章のファイルに付ける拡張子（完成してから `.pdf` に名前を変える）
+pub const PARTIAL_EXTENSION: &str = "pdf.partial";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// SIGINT・SIGTERM を受けたら中断の印を付け、処理を順に止められるようにする
+///
+/// 印が付いた後は新しい章（と入力・ジョブ）を始めず、書き出し中の章は途中で打ち切って書きかけのファイルを消す。
+/// 2回目の割り込みではすぐに終了する。Unix 系以外では何もしない（既定どおりすぐに終了する）。
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            // シグナルハンドラから安全に呼べるのは _exit だけ
+            unsafe { libc::_exit(EXIT_CODE) };
+        }
+    }
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// シグナルを受けたときと同じ中断の印を付ける（GUI の中止ボタン）
+pub fn request() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// 中断の印を消し、次の分割を始められるようにする（GUI で中止した後）
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// 章を書き出している間の一時的なパス
+pub fn partial_path(out_path: &Path) -> PathBuf {
+    out_path.with_extension(PARTIAL_EXTENSION)
+}
@@ -0,0 +1,159 @@
+use crate::memory::MemoryBudget;
+use crate::metrics::Metrics;
+use crate::progress::{InfoLines, Progress};
+use crate::manifest::RunManifest;
+use crate::{Args, RunContext, build_pool, cert, linearize, split_and_notify, thumbnails, upload};
+use anyhow::{Context, Result, anyhow, bail};
+use clap::Parser;
+use rayon::prelude::*;
+use serde_json::{Map, Value, json};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+// 実行全体で1つだけ指定するオプション（ジョブごとには変えられない）
+const RUN_WIDE_OPTIONS: [&str; 7] = ["threads", "no_parallel", "max_memory", "ordered", "quiet", "log_file", "log_format"];
+
+/// `jobs` サブコマンド: 1行に1件の JSON のジョブを読み、並列に分割して1件につき1行の結果を書き出す
+///
+/// ジョブは `{"id": ..., "input": "a.pdf", "verify": true, "compress": "best"}` のように、
+/// 入力ファイルとコマンドラインのオプション（`--` を除き `-` を `_` にした名前）を並べたもの。
+/// 長時間動かし続けるオーケストレーターから大量のジョブを流し込めるよう、読んだそばから処理する。
+/// 結果は標準出力、ログは標準エラーに出す。ジョブが失敗しても処理は続け、結果の行で知らせる。
+pub fn run(
+    source: &Path,
+    concurrency: Option<usize>,
+    threads: Option<usize>,
+    max_memory: Option<u64>,
+    metrics_addr: Option<&str>,
+) -> Result<()> {
+    let input: Box<dyn Read + Send> = if source == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(source).with_context(|| format!("Failed to open jobs file: {:?}", source))?)
+    };
+    // 同時に処理するジョブの数と、ジョブ内で章を並列に処理するスレッド数は別に絞る
+    let job_pool = build_pool(concurrency.unwrap_or(0))?;
+    let chapter_pool = build_pool(threads.unwrap_or(0))?;
+    let progress = Progress::new(false, InfoLines::Stderr, None);
+    let memory_budget = max_memory.map(MemoryBudget::new);
+    let metrics = match metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(Metrics::default());
+            metrics.serve(addr)?;
+            progress.eprintln(format!("Serving metrics on http://{}/metrics", addr));
+            Some(metrics)
+        }
+        None => None,
+    };
+    let shared = Shared { progress: &progress, memory_budget: memory_budget.as_ref(), metrics: metrics.as_deref() };
+
+    job_pool.install(|| {
+        BufReader::new(input).lines().par_bridge().for_each(|line| {
+            // 中断後は残りのジョブを始めない（処理中のジョブは章の途中で打ち切られる）
+            if crate::interrupt::is_interrupted() {
+                return;
+            }
+            let result = match line {
+                Ok(line) if line.trim().is_empty() => return,
+                Ok(line) => chapter_pool.install(|| run_job(&line, &shared)),
+                Err(e) => json!({ "ok": false, "error": format!("failed to read job: {}", e) }),
+            };
+            progress.emit(result.to_string());
+        })
+    });
+    Ok(())
+}
+
+/// 全ジョブで共有する資源
+struct Shared<'a> {
+    progress: &'a Progress,
+    memory_budget: Option<&'a MemoryBudget>,
+    metrics: Option<&'a Metrics>,
+}
+
+/// 1件のジョブを処理し、結果の行を返す
+fn run_job(line: &str, shared: &Shared) -> Value {
+    let start = Instant::now();
+    let job = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(job)) => job,
+        Ok(_) => return json!({ "ok": false, "error": "job must be a JSON object" }),
+        Err(e) => return json!({ "ok": false, "error": format!("invalid job: {}", e) }),
+    };
+    let id = job.get("id").cloned().unwrap_or(Value::Null);
+    let input = job.get("input").and_then(Value::as_str).unwrap_or_default();
+    if let Some(metrics) = shared.metrics {
+        metrics.job_started();
+    }
+    let result = execute(&job, shared);
+    if let Err(e) = &result {
+        shared.progress.eprintln(format!("Error processing job {}: {:#}", id, e));
+    }
+    if let Some(metrics) = shared.metrics {
+        metrics.job_finished(result.is_ok());
+    }
+    let mut value = serde_json::to_value(RunManifest::new(Path::new(input), &result, start.elapsed()))
+        .expect("manifest is serializable");
+    value["id"] = id;
+    value
+}
+
+fn execute(job: &Map<String, Value>, shared: &Shared) -> Result<crate::manifest::FileOutcome> {
+    let args = Args::try_parse_from(job_argv(job)?)
+        .map_err(|e| {
+            let message = e.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            anyhow!("invalid options: {}", first_line.trim_start_matches("error: "))
+        })?;
+    if args.edit_plan {
+        bail!("--edit-plan opens an editor and cannot be used in jobs");
+    }
+    if args.linearize {
+        linearize::check_available("--linearize")?;
+    }
+    if args.passwords.is_some() {
+        linearize::check_available("--passwords")?;
+    }
+    let renderer = args.thumbnails.map(thumbnails::Renderer::new).transpose()?;
+    let sink = args.output.as_ref().map(upload::Sink::new).transpose()?;
+    let key_file = args.cert.as_deref().map(|path| cert::KeyFile::open(path, args.cert_pass.as_deref().unwrap_or(""))).transpose()?;
+    let run = RunContext {
+        args: &args,
+        progress: shared.progress,
+        memory_budget: shared.memory_budget,
+        renderer: renderer.as_ref(),
+        sink: sink.as_ref(),
+        key_file: key_file.as_ref(),
+        metrics: shared.metrics,
+    };
+    split_and_notify(&args.input_paths[0], &run)
+}
+
+/// ジョブの JSON をコマンドライン引数に直す（検証はコマンドラインと同じ定義に任せる）
+fn job_argv(job: &Map<String, Value>) -> Result<Vec<OsString>> {
+    let input = job.get("input").and_then(Value::as_str).context("job has no \"input\" path")?;
+    let mut argv = vec![OsString::from("pdf_splitter")];
+    for (key, value) in job {
+        if key == "id" || key == "input" {
+            continue;
+        }
+        if RUN_WIDE_OPTIONS.contains(&key.as_str()) {
+            bail!("option {:?} applies to the whole jobs run and cannot be set per job", key);
+        }
+        let flag = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Bool(true) => argv.push(flag.into()),
+            Value::Bool(false) | Value::Null => {}
+            Value::String(s) => argv.push(format!("{}={}", flag, s).into()),
+            Value::Number(n) => argv.push(format!("{}={}", flag, n).into()),
+            _ => bail!("option {:?} must be a string, number or boolean", key),
+        }
+    }
+    // 入力はサブコマンド名やオプションと取り違えられないよう `--` の後ろに置く
+    argv.push("--".into());
+    argv.push(input.into());
+    Ok(argv)
+}
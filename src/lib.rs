@@ -0,0 +1,2681 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use compress::Compression;
+use crypt::StringDecryptor;
+use detect::Detect;
+use manifest::{BatesRange, FileOutcome, SavedOutput, SkippedBookmark};
+use memory::MemoryBudget;
+use named_dests::NamedDests;
+use plan::{BackMatter, PlannedChapter};
+use progress::{InfoLines, Progress};
+use stream_writer::{SaveMode, StreamingWriter};
+use timings::{ChapterTimings, FileTimings, TimingsFormat};
+use toc::TocFormat;
+use page_ranges::PageRanges;
+use version::PdfVersion;
+use lopdf::{Document, Object, ObjectId};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+mod annots;
+mod attach;
+mod barcode;
+mod blank;
+mod cache;
+mod catalog;
+pub mod cert;
+mod clean;
+mod compress;
+mod crypt;
+mod decrypt;
+mod delete;
+mod csv;
+mod detect;
+mod dump;
+mod duplicates;
+mod embedded_files;
+mod encrypt;
+mod explain;
+mod extract;
+mod fetch;
+mod fields;
+mod flatten_forms;
+mod fonts;
+mod full_outline;
+mod grep;
+mod images;
+mod insert;
+pub mod interrupt;
+mod jobs;
+mod limits;
+mod manifest;
+mod memory;
+mod metrics;
+mod named_dests;
+mod notify;
+mod nup;
+mod optimize;
+mod output_names;
+mod page_labels;
+mod page_edit;
+mod page_map;
+mod page_ranges;
+mod passwords;
+mod linearize;
+mod logfile;
+mod pagetree;
+mod pdfa;
+pub mod plan;
+mod portfolio;
+pub mod progress;
+mod provenance;
+mod raw_copy;
+mod reorder;
+mod report;
+mod sidecar;
+mod stats;
+mod retry;
+mod rotate;
+mod set_metadata;
+mod set_page_labels;
+mod roundtrip;
+mod stamp;
+mod stream_writer;
+mod struct_parents;
+mod text;
+mod threads;
+mod thumbnails;
+mod timings;
+pub mod toc;
+mod unpack_attachments;
+mod upload;
+mod verify;
+mod version;
+mod xmp;
+mod xref64;
+
+/// PDFを章（トップレベルのブックマーク）ごとに分割するツール
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// 入力PDFファイルのパスまたは http(s):// の URL（複数指定するとファイルをまたいで並列に一括処理する）
+    #[arg(required = true, help = "分割したいPDFファイルのパスまたは URL を指定してください（複数可）")]
+    input_paths: Vec<PathBuf>,
+
+    /// URL の入力をダウンロードしたら、内容の SHA-256 がこの値と一致するか確かめる
+    #[arg(long, value_name = "HEX", value_parser = fetch::parse_sha256)]
+    sha256: Option<String>,
+
+    /// 章のファイルを書き出すごとに S3（`s3://bucket/prefix/`）か GCS（`gs://bucket/prefix/`）へ送り、最後に結果の JSON も送る（upload フィーチャーが必要）
+    #[arg(long, value_name = "URL", value_parser = upload::parse_destination, conflicts_with = "dry_run")]
+    output: Option<upload::Destination>,
+
+    /// ページツリーに存在しないページを指すブックマークの扱い
+    #[arg(long, value_enum, default_value_t = DestFallback::Skip)]
+    dest_fallback: DestFallback,
+
+    /// 章にする目次項目の階層（2 以上では、その階層までの項目ごとに章にする）
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=64))]
+    depth: u32,
+
+    /// 章のタイトルを上の階層の項目からのつながり（例: Part II – Chapter 5 – Methods）にし、ファイル名と出力の文書情報の /Title に使う
+    #[arg(long)]
+    title_path: bool,
+
+    /// --title-path で階層のタイトルをつなぐ区切り
+    #[arg(long, value_name = "SEP", default_value = " - ", requires = "title_path")]
+    path_sep: String,
+
+    /// 分割せずに、目次の全項目について宛先をどうたどってページにしたか（できなかった理由）を表示する
+    #[arg(long)]
+    explain: bool,
+
+    /// 出力PDFのバージョン（1.4 ではオブジェクトストリーム等の新しい機能を使わない）
+    #[arg(long, value_enum)]
+    pdf_version: Option<PdfVersion>,
+
+    /// 章のファイルの保存方法（incremental では、文書全体を1つのファイルに書き出すときに元のファイルを残して変更分だけを追記し、元の版の電子署名を保つ）
+    #[arg(long, value_enum, default_value_t = SaveMode::Full, conflicts_with_all = ["linearize", "passwords", "pdf_version"])]
+    save_mode: SaveMode,
+
+    /// 並列処理に使うスレッド数（省略時または 0 の場合はCPUコア数）
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+
+    /// 並列処理を行わず、1章ずつ順番に処理する
+    #[arg(long, conflicts_with = "threads")]
+    no_parallel: bool,
+
+    /// 並列処理で同時に使うメモリの上限（例: 8G, 512M）。章ごとの推定サイズから同時実行数を絞る
+    #[arg(long, value_name = "SIZE", value_parser = memory::parse_size)]
+    max_memory: Option<u64>,
+
+    /// 保存時のストリーム再圧縮（none: そのまま, fast: 未圧縮分だけ速く, best: 既存の圧縮もやり直して最小に）
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    compress: Compression,
+
+    /// 入力をメモリマップして読み込む（巨大なファイルでの読み込みを高速化）
+    #[arg(long)]
+    mmap: bool,
+
+    /// 暗号化されたPDFを開くためのパスワード
+    #[arg(long)]
+    password: Option<String>,
+
+    /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12 の .p12・.pfx。`cert` フィーチャーが必要）
+    #[arg(long, value_name = "P12", conflicts_with = "password")]
+    cert: Option<PathBuf>,
+
+    /// --cert の PKCS#12 ファイルのパスワード
+    #[arg(long, value_name = "PASSWORD", requires = "cert")]
+    cert_pass: Option<String>,
+
+    /// 章のファイルの書き出しが一時的な入出力の失敗（EIO・ロックの競合など）で失敗したときに、書き直す回数
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    io_retries: u32,
+
+    /// 最初に書き直すまでの待ち時間（ミリ秒）。書き直すたびに倍にする
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    io_retry_delay: u64,
+
+    /// Web表示用に最適化（リニアライズ）した出力を作成する（qpdf が必要）
+    #[arg(long)]
+    linearize: bool,
+
+    /// 保存後に各出力を読み直し、ページ数と目次・リンクの宛先を検証する
+    #[arg(long)]
+    verify: bool,
+
+    /// 全章の保存後に出力を読み直し、計画した元のページがちょうど1回ずつ同じ内容で書き出されたかを元の文書と突き合わせる
+    #[arg(long, conflicts_with_all = ["nup", "passwords", "output"])]
+    verify_roundtrip: bool,
+
+    /// 元のページを2ページ（または4ページ）ずつ縮小して1枚に並べる（確認用の縮刷版を作る）
+    #[arg(long, value_name = "N", value_parser = nup::parse_nup, conflicts_with = "page_map")]
+    nup: Option<usize>,
+
+    /// 出力するページを時計回りに回転する（90・180・270）
+    #[arg(long, value_name = "DEGREES", value_parser = page_edit::parse_rotation)]
+    rotate: Option<i64>,
+
+    /// --rotate を適用するページ（元の文書のページ番号。例: 12-15,20）。省略時はすべてのページ
+    #[arg(long, value_name = "RANGES", value_parser = page_ranges::parse, requires = "rotate")]
+    pages: Option<PageRanges>,
+
+    /// 出力するすべてのページの余白を削る（ポイント単位。`10` または 上,右,下,左 の `10,20,10,20`）
+    #[arg(long, value_name = "MARGINS", value_parser = page_edit::parse_margins, conflicts_with = "set_cropbox")]
+    crop: Option<page_edit::Crop>,
+
+    /// 出力するすべてのページの CropBox を指定の矩形（左下x,左下y,右上x,右上y）にする
+    #[arg(long, value_name = "BOX", value_parser = page_edit::parse_box)]
+    set_cropbox: Option<page_edit::Crop>,
+
+    /// 各ページの下端中央にフッターを書き込む（{title}・{chapter}・{n}・{total}・{source_page} を置き換える）
+    #[arg(long, value_name = "TEMPLATE")]
+    stamp_footer: Option<String>,
+
+    /// 各ページの中央に対角線の向きで半透明の透かし文字を書き込む（{title}・{chapter}・{recipient} を置き換える）
+    #[arg(long, value_name = "TEMPLATE")]
+    watermark: Option<String>,
+
+    /// 透かしの {recipient} に使う章ごとの配布先を、chapter,recipient の列を持つ CSV から読み込む
+    #[arg(long, value_name = "FILE", requires = "watermark")]
+    watermark_recipients: Option<PathBuf>,
+
+    /// 章ごとのパスワード（chapter,password[,owner_password] の列を持つ CSV）で各章のPDFを暗号化する（qpdf が必要）
+    #[arg(long, value_name = "FILE")]
+    passwords: Option<PathBuf>,
+
+    /// 各章の先頭に、章の番号・タイトル・元の文書のタイトル・日付を並べた表紙を加える
+    #[arg(long)]
+    title_page: bool,
+
+    /// 各章に元の文書の目次をすべて残し、この章の項目を太字にする。他の章を指す項目は灰色にする（--keep-full-outline=placeholder では章の末尾に加えた説明のページへリンクする）
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "grey")]
+    keep_full_outline: Option<full_outline::MissingEntries>,
+
+    /// 解像度が指定（例: 150dpi）を超える画像を縮小し、JPEG で圧縮し直す（images フィーチャーが必要）
+    #[arg(long, value_name = "DPI", value_parser = images::parse_dpi)]
+    downsample: Option<u32>,
+
+    /// --downsample で圧縮し直す JPEG の品質（1〜100）
+    #[arg(long, value_name = "QUALITY", value_parser = images::parse_quality, default_value_t = 75, requires = "downsample")]
+    jpeg_quality: u8,
+
+    /// 章のページが使っていないフォントをリソースから外し、埋め込みフォントのプログラムごと取り除く
+    #[arg(long)]
+    strip_unused_fonts: bool,
+
+    /// 分割の際に取り除く元の文書のページ（例: 33,90-95）。どの章に含まれるページでも除く
+    #[arg(long, value_name = "RANGES", value_parser = page_ranges::parse)]
+    exclude_pages: Option<PageRanges>,
+
+    /// --pages・--exclude-pages・計画ファイル（--plan）のページ番号の数え方（logical では印刷されたページ番号。/PageLabels が必要）
+    #[arg(long, value_enum, value_name = "NUMBERING", default_value = "physical")]
+    page_numbering: page_labels::PageNumbering,
+
+    /// 全章を通した Bates 番号（例: ABC000001 から始める）を各ページの右下に書き込む
+    #[arg(long, value_name = "START", value_parser = stamp::parse_bates_start)]
+    bates_start: Option<stamp::BatesStart>,
+
+    /// 何も描かれていない白紙のページ（内容が空か、描画命令の無いページ）を各章から取り除く
+    #[arg(long)]
+    drop_blank: bool,
+
+    /// 内容が同じページ（各部の前に刷り直された目次など）を探して一覧を表示する
+    #[arg(long)]
+    detect_duplicates: bool,
+
+    /// --detect-duplicates で見つけた同じ内容のページのうち、章に含まれる最初の1ページだけを残し、ほかを出力から除く
+    #[arg(long, requires = "detect_duplicates")]
+    drop_duplicates: bool,
+
+    /// 各章のPDFと同じ名前のテキストファイル（.txt）に、章のページの本文テキストを書き出す
+    #[arg(long)]
+    extract_text: bool,
+
+    /// 各章のPDFと同じ名前の .pages.json に、元の文書のページ番号（とページラベル）と章のページ番号の対応表を書き出す
+    #[arg(long)]
+    page_map: bool,
+
+    /// 各章のPDFと同じ名前のファイルに、章のタイトル・元の文書とページ範囲・ページラベル・語数（--extract-text のとき）を書き出す
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    sidecar: Option<sidecar::SidecarFormat>,
+
+    /// 各章の先頭ページのサムネイル（PNG）を指定の幅（例: 300px）で書き出す（render フィーチャーが必要）
+    #[arg(long, value_name = "WIDTH", value_parser = thumbnails::parse_width)]
+    thumbnails: Option<u32>,
+
+    /// 各章に Calibre のシリーズ情報（XMP の calibre:series と series_index）を書き込む（シリーズ名は元の文書のタイトル、番号は章の番号）
+    #[arg(long)]
+    series_metadata: bool,
+
+    /// 各章に、元のファイル名・SHA-256・ページ範囲・ツールのバージョン・作成日時を記録した JSON を添付する
+    #[arg(long)]
+    embed_provenance: bool,
+
+    /// 元のファイル名・SHA-256・ページ範囲を、各章の XMP メタデータにも書き込む（pdfsplitter:sourceHash など）
+    #[arg(long)]
+    provenance_xmp: bool,
+
+    /// 解決済みの分割計画（タイトル・ページ範囲・出力ファイル名）を JSON で書き出す（--plan で読み込める形式）
+    #[arg(long, value_name = "FILE")]
+    plan_out: Option<PathBuf>,
+
+    /// 分割した一式の目録（元の文書の情報と、各章のタイトル・パス・ページ範囲・サイズ）を JSON で書き出す
+    #[arg(long, value_name = "FILE", conflicts_with = "output")]
+    catalog: Option<PathBuf>,
+
+    /// 宛先を解決できずに章にしなかった目次項目と、その理由をテキストで書き出す（元の文書の目次を直す手がかり）
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// 分割せず、元の文書の間接オブジェクト（番号と世代番号）を字下げして表示する（例: --debug-object 123 0）
+    #[arg(long, num_args = 2, value_names = ["NUM", "GEN"])]
+    debug_object: Option<Vec<u32>>,
+
+    /// 目次から計画を作る代わりに、--plan-out と同じ形式の計画ファイルに従って分割する
+    #[arg(long, value_name = "FILE")]
+    plan: Option<PathBuf>,
+
+    /// 分割の前に計画を $EDITOR で開き、編集して保存した計画（--plan と同じく検証する）に従って分割する
+    #[arg(long, conflicts_with = "dry_run")]
+    edit_plan: bool,
+
+    /// 最後の章の本来の終わりより後ろ（目次に無い索引・奥付など）のページを、最後の章に含める（append）・別のファイルにする（separate）・捨てる（drop）
+    #[arg(long, value_enum, value_name = "POLICY", default_value = "append", conflicts_with_all = ["plan", "detect", "split_on_barcode", "split_on_field_type", "split_on_annot", "split_by_field"])]
+    back_matter: BackMatter,
+
+    /// 目次の代わりに、区切り用のバーコード・QR コードのあるページで分割する（区切りのページは出力しない）
+    #[arg(long, conflicts_with = "plan")]
+    split_on_barcode: bool,
+
+    /// 目次の代わりに、指定した種類（Stamp・FileAttachment など）の注釈のある区切りページで分割する（区切りのページは出力しない）
+    #[arg(long, value_name = "SUBTYPE", value_parser = annots::parse_subtype, conflicts_with_all = ["plan", "split_on_barcode"])]
+    split_on_annot: Option<String>,
+
+    /// --split-on-barcode で区切りとみなすコード（* と ? のワイルドカード、例: JOB-*）。省略時はすべてのコード
+    #[arg(long, value_name = "PATTERN", value_parser = barcode::parse_pattern, requires = "split_on_barcode")]
+    pattern: Option<barcode::Pattern>,
+
+    /// 目次の代わりに、ページの内容から推定した章の始まりで分割する（推定した区切りを表示する）
+    #[arg(long, value_enum, value_name = "METHOD", conflicts_with_all = ["plan", "split_on_barcode", "split_on_annot"])]
+    detect: Option<Detect>,
+
+    /// 目次の代わりに、指定した種類（Sig・Tx・Btn・Ch）のフォームフィールドのあるページで分割する
+    #[arg(long, value_enum, value_name = "TYPE", ignore_case = true, conflicts_with_all = ["plan", "split_on_barcode", "split_on_annot", "detect"])]
+    split_on_field_type: Option<fields::FieldType>,
+
+    /// --split-on-field-type のフィールドのあるページで章を終える（end）か、そのページから章を始める（start）
+    #[arg(long, value_enum, value_name = "BOUNDARY", default_value = "end", requires = "split_on_field_type")]
+    field_boundary: fields::FieldBoundary,
+
+    /// 目次の代わりに、指定した名前のフォームフィールド（無ければ同じ名前のタグで囲まれたテキスト）の値が変わるページで分割し、値をファイル名に使う
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["plan", "split_on_barcode", "split_on_annot", "detect", "split_on_field_type"])]
+    split_by_field: Option<String>,
+
+    /// --detect ocr で使う tesseract の言語（例: jpn+eng）
+    #[arg(long, value_name = "LANG", default_value = "eng", requires = "detect")]
+    ocr_lang: String,
+
+    /// 1つの入力の処理が終わる（または失敗する）たびに、結果の JSON をこの URL へ POST する
+    #[arg(long, value_name = "URL", value_parser = notify::parse_url)]
+    notify_url: Option<String>,
+
+    /// 分割せず、各章のページ範囲・出力ファイル名・推定サイズだけを表示する
+    #[arg(long)]
+    dry_run: bool,
+
+    /// 出力先の空き容量が推定出力サイズに足りなくても中断せず、警告だけ表示して続行する
+    #[arg(long)]
+    force: bool,
+
+    /// 出力ファイル名の文字コード（sjis は Shift_JIS のファイル名を前提にした古い共有フォルダ向け。Unix 系のみ）
+    #[arg(long, value_enum, value_name = "ENCODING", default_value = "utf8")]
+    output_encoding: output_names::OutputEncoding,
+
+    /// ページツリーと目次の走査結果を入力と同じ場所の .pdfsplit-cache に保存し、同じ入力では再利用する
+    #[arg(long)]
+    cache: bool,
+
+    /// 並列処理中のログを章の順番に並べてから出力する
+    #[arg(long)]
+    ordered: bool,
+
+    /// 進捗表示と通常のログを出さず、警告とエラーだけを標準エラーに出す（cron などの定期実行向け。成功時は何も出力しない）
+    #[arg(long, conflicts_with_all = ["dry_run", "debug_object", "timings"])]
+    quiet: bool,
+
+    /// 画面の表示に関わらず、調査用の詳細な行も含めたすべてのログを時刻付きでこのファイルへ書き出す
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    /// --log-file の形式（text または json）
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text", requires = "log_file")]
+    log_format: logfile::LogFormat,
+
+    /// 処理時間の内訳（読み込み・目次走査・章ごとのコピー/保存など）を表示する（--timings=json でJSON出力）
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    timings: Option<TimingsFormat>,
+}
+
+/// 分割以外の操作
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 目次を全階層たどり、各項目の階層・タイトル・ページ範囲・ページラベルを標準出力に書き出す
+    Toc {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 出力形式
+        #[arg(long, value_enum, default_value_t = TocFormat::Csv)]
+        format: TocFormat,
+
+        /// 暗号化されたPDFを開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+
+        /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12）
+        #[arg(long, value_name = "P12", conflicts_with = "password")]
+        cert: Option<PathBuf>,
+
+        /// --cert の PKCS#12 ファイルのパスワード
+        #[arg(long, value_name = "PASSWORD", requires = "cert")]
+        cert_pass: Option<String>,
+    },
+
+    /// 本文のテキストを検索し、一致したページ番号（とページラベル）と前後の文を標準出力に書き出す
+    Grep {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 探す文字列
+        pattern: String,
+
+        /// PATTERN を正規表現として扱う
+        #[arg(long)]
+        regex: bool,
+
+        /// 大文字と小文字を区別しない
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// 暗号化されたPDFを開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+
+        /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12）
+        #[arg(long, value_name = "P12", conflicts_with = "password")]
+        cert: Option<PathBuf>,
+
+        /// --cert の PKCS#12 ファイルのパスワード
+        #[arg(long, value_name = "PASSWORD", requires = "cert")]
+        cert_pass: Option<String>,
+    },
+
+    /// PDF ポートフォリオに添付された PDF を、それぞれ別のファイルとして書き出す
+    Unpack {
+        /// 入力PDFファイル（ポートフォリオ）のパス
+        input_path: PathBuf,
+
+        /// 書き出した PDF をそれぞれ章に分割する（分割のオプションは `--` の後に並べる）
+        #[arg(long)]
+        split: bool,
+
+        /// 暗号化されたPDFを開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+
+        /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12）
+        #[arg(long, value_name = "P12", conflicts_with = "password")]
+        cert: Option<PathBuf>,
+
+        /// --cert の PKCS#12 ファイルのパスワード
+        #[arg(long, value_name = "PASSWORD", requires = "cert")]
+        cert_pass: Option<String>,
+
+        /// --split で使う分割のオプション（例: -- --verify --compress best）
+        #[arg(last = true, value_name = "SPLIT_OPTIONS", requires = "split")]
+        split_options: Vec<std::ffi::OsString>,
+    },
+
+    /// 文書のバイト数がどこにあるか（ページの内容・画像・フォント・添付ファイル・メタデータ・使われていないオブジェクト）を集計する
+    Stats {
+        /// 集計するPDFファイル
+        input_path: PathBuf,
+
+        /// 出力形式（json はすべてのページの大きさを含む）
+        #[arg(long, value_enum, default_value = "text")]
+        format: stats::StatsFormat,
+    },
+
+    /// 分割せずに文書全体を、章の書き出しと同じ処理（不要なオブジェクトと未使用フォントの除去・再圧縮・オブジェクトストリーム）で小さくする
+    Optimize {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// ストリームの再圧縮（none: そのまま, fast: 未圧縮分だけ速く, best: 既存の圧縮もやり直して最小に）
+        #[arg(long, value_enum, default_value_t = Compression::Best)]
+        compress: Compression,
+
+        /// 解像度が指定（例: 150dpi）を超える画像を縮小し、JPEG で圧縮し直す（images フィーチャーが必要）
+        #[arg(long, value_name = "DPI", value_parser = images::parse_dpi)]
+        downsample: Option<u32>,
+
+        /// --downsample で圧縮し直す JPEG の品質（1〜100）
+        #[arg(long, value_name = "QUALITY", value_parser = images::parse_quality, default_value_t = 75, requires = "downsample")]
+        jpeg_quality: u8,
+
+        /// どのページも使っていないフォントを取り除かずに残す
+        #[arg(long)]
+        keep_unused_fonts: bool,
+
+        /// qpdf でオブジェクトストリームにまとめない
+        #[arg(long)]
+        no_object_streams: bool,
+    },
+
+    /// 暗号化された文書の暗号化を外し、復号した複製を保存する
+    Decrypt {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// 暗号化されたPDFを開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+
+        /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12）
+        #[arg(long, value_name = "P12", conflicts_with = "password")]
+        cert: Option<PathBuf>,
+
+        /// --cert の PKCS#12 ファイルのパスワード
+        #[arg(long, value_name = "PASSWORD", requires = "cert")]
+        cert_pass: Option<String>,
+    },
+
+    /// 分割せずに、PDF を閲覧用と権限用のパスワードで暗号化して保存する（qpdf が必要）
+    Encrypt {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// 閲覧用のパスワード（ユーザーパスワード）
+        #[arg(long, value_name = "PASSWORD")]
+        user: String,
+
+        /// 権限の変更に使うパスワード（オーナーパスワード。省略すると閲覧用と同じ）
+        #[arg(long, value_name = "PASSWORD")]
+        owner: Option<String>,
+
+        /// 暗号化の方式
+        #[arg(long, value_enum, default_value_t = encrypt::Cipher::Aes256)]
+        cipher: encrypt::Cipher,
+
+        /// 暗号化された入力を開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// 分割せずに、指定したページを時計回りに回転して保存する
+    Rotate {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// 回転するページ（例: 5-10,12）。省略時はすべてのページ
+        #[arg(long, value_name = "RANGES", value_parser = page_ranges::parse)]
+        pages: Option<PageRanges>,
+
+        /// 時計回りに回転する角度（90・180・270。既存の回転に加える）
+        #[arg(long, value_name = "DEGREES", value_parser = page_edit::parse_rotation)]
+        by: i64,
+    },
+
+    /// 分割せずに、ページを指定した順に並べ替えて保存する（両面スキャンの並べ直しなど）
+    Reorder {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// 出力するページを出力の順に並べた指定（例: 1,3,2,4-）。載っていないページは含めない
+        #[arg(long, value_name = "PAGES", value_parser = page_ranges::parse, required_unless_present = "preset", conflicts_with = "preset")]
+        order: Option<PageRanges>,
+
+        /// よく使う並べ替え（reverse: 逆順, interleave: 前半と後半を交互に, interleave-reverse: 前半と逆順の後半を交互に）
+        #[arg(long, value_enum)]
+        preset: Option<reorder::Preset>,
+    },
+
+    /// 分割せずに、指定したページを削除して保存する（削除したページだけが使っていたオブジェクトも取り除く）
+    #[command(alias = "delete-pages")]
+    Delete {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// 削除するページ（例: 2,7-9）
+        #[arg(long, value_name = "RANGES", value_parser = page_ranges::parse)]
+        pages: PageRanges,
+    },
+
+    /// 分割せずに、別の文書のページを指定した位置に取り込んで保存する
+    Insert {
+        /// ページを取り込む先のPDFファイルのパス
+        input_path: PathBuf,
+
+        /// 取り込んだページを置く位置（このページの前。総ページ数+1 なら最後）
+        #[arg(long, value_name = "PAGE")]
+        at: u32,
+
+        /// 取り込むページのあるPDFファイル
+        #[arg(long, value_name = "FILE")]
+        from: PathBuf,
+
+        /// 取り込むページ（--from の文書のページ番号。例: 1-3）。省略時はすべてのページ
+        #[arg(long, value_name = "RANGES", value_parser = page_ranges::parse)]
+        pages: Option<PageRanges>,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// 分割せずに、ファイルを添付ファイルとして加えて保存する
+    Attach {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 添付するファイル
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+
+        /// 添付ファイルの説明（ビューアーの添付ファイルの一覧に表示される）
+        #[arg(long, value_name = "TEXT")]
+        desc: Option<String>,
+
+        /// 文書の中での添付ファイルの名前（省略時は --file のファイル名）
+        #[arg(long)]
+        name: Option<String>,
+
+        /// 添付ファイルの MIME タイプ（省略時は拡張子から推測する）
+        #[arg(long, value_name = "TYPE")]
+        mime: Option<String>,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// 文書に埋め込まれたすべてのファイル（添付ファイルとファイル添付注釈）を、元の名前と更新日時で書き出す
+    UnpackAttachments {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出す先のディレクトリ（無ければ作る）
+        #[arg(short, long, value_name = "DIR")]
+        output: PathBuf,
+
+        /// 暗号化されたPDFを開くためのパスワード
+        #[arg(long)]
+        password: Option<String>,
+
+        /// 証明書で暗号化されたPDFを開くための、受取人の証明書と秘密鍵（PKCS#12）
+        #[arg(long, value_name = "P12", conflicts_with = "password")]
+        cert: Option<PathBuf>,
+
+        /// --cert の PKCS#12 ファイルのパスワード
+        #[arg(long, value_name = "PASSWORD", requires = "cert")]
+        cert_pass: Option<String>,
+    },
+
+    /// 分割せずに、文書のタイトルや作成者などを、文書情報辞書と XMP メタデータの両方で書き換えて保存する
+    SetMetadata {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 文書のタイトル
+        #[arg(long)]
+        title: Option<String>,
+
+        /// 作成者
+        #[arg(long)]
+        author: Option<String>,
+
+        /// サブタイトル・説明
+        #[arg(long)]
+        subject: Option<String>,
+
+        /// キーワード
+        #[arg(long)]
+        keywords: Option<String>,
+
+        /// 元の XMP メタデータの代わりに使う XMP パケットのファイル（ほかのオプションの値はこの上に書き込む）
+        #[arg(long, value_name = "FILE")]
+        xmp: Option<PathBuf>,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// 分割せずに、ページラベル（/PageLabels）を作り直して保存する
+    SetPagelabels {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// ラベル範囲の `開始ページ:先頭のラベル` の並び（例: 1:r,15:1,200:A-1）
+        #[arg(long, value_name = "SPEC", value_parser = page_labels::parse_spec)]
+        spec: page_labels::LabelSpec,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// 分割せずに、フォームに入力された値（フィールドの外観）をページの内容に描き込み、入力できるフィールドを取り除いて保存する
+    FlattenForms {
+        /// 入力PDFファイルのパス
+        input_path: PathBuf,
+
+        /// 書き出すPDFファイルのパス
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// 出力先のディレクトリから、中断や失敗で残った書きかけのファイルと一時ファイル（と結果に載っていない章のファイル）を消す
+    Clean {
+        /// 章のファイルを書き出したディレクトリ
+        dir: PathBuf,
+
+        /// 結果の JSON（jobs の結果の行、<入力名>_manifest.json、<入力名>_interrupted.json）。載っていないその入力の章のファイルも消す
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+
+        /// 消さずに、消すファイルの一覧だけを表示する
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// 1行に1件のJSONのジョブ（{"input": ..., オプション...}）を読み、並列に分割して1件につき1行の結果を書き出す
+    Jobs {
+        /// ジョブを読むファイル（- で標準入力）
+        source: PathBuf,
+
+        /// 同時に処理するジョブの数（省略時はCPUコア数）
+        #[arg(long, value_name = "N")]
+        concurrency: Option<usize>,
+
+        /// 各ジョブの章の並列処理に使うスレッド数（全ジョブで共有、省略時はCPUコア数）
+        #[arg(long, value_name = "N")]
+        threads: Option<usize>,
+
+        /// 全ジョブで同時に使うメモリの上限（例: 8G, 512M）
+        #[arg(long, value_name = "SIZE", value_parser = memory::parse_size)]
+        max_memory: Option<u64>,
+
+        /// Prometheus 形式のメトリクスを http://ADDR/metrics で公開する（例: 127.0.0.1:9100）
+        #[arg(long, value_name = "ADDR")]
+        metrics_addr: Option<String>,
+    },
+}
+
+/// 宛先のページが見つからない場合の動作
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DestFallback {
+    /// オブジェクト番号が最も近い有効なページに寄せる
+    Nearest,
+    /// その章をスキップする
+    Skip,
+}
+
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    if bytes.len() >= 2 && bytes[0] == 0xFE && bytes[1] == 0xFF {
+        let u16_vec: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+            .collect();
+        String::from_utf16(&u16_vec).unwrap_or_else(|_| String::from_utf8_lossy(bytes).to_string())
+    } else {
+        String::from_utf8_lossy(bytes).to_string()
+    }
+}
+
+/// 文書情報辞書の文字列の項目（/Title など。空なら None）
+fn info_string(doc: &Document, decryptor: &StringDecryptor, key: &[u8]) -> Option<String> {
+    let info_id = doc.trailer.get(b"Info").ok()?.as_reference().ok()?;
+    let info = doc.get_dictionary(info_id).ok()?;
+    let value = doc.dereference(info.get(key).ok()?).ok()?.1.as_str().ok()?;
+    let value = decode_pdf_string(&decryptor.decrypt(info_id, value));
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// テキスト文字列（UTF-16BE、BOM 付き）
+fn encode_pdf_string(text: &str) -> Object {
+    let mut bytes = vec![0xFE, 0xFF];
+    bytes.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+    Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+}
+
+/// 文書情報辞書の /Title を書き換える（辞書が無ければ作る）
+fn set_info_title(doc: &mut Document, title: &str) {
+    info_dict_mut(doc).set("Title", encode_pdf_string(title));
+}
+
+/// 文書情報辞書（無いか壊れていれば新しく作ってトレーラーにつなぐ）
+fn info_dict_mut(doc: &mut Document) -> &mut lopdf::Dictionary {
+    let info_id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) if doc.get_dictionary(id).is_ok() => id,
+        _ => {
+            let id = doc.add_object(lopdf::Dictionary::new());
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    doc.get_object_mut(info_id).and_then(Object::as_dict_mut).expect("the info dictionary was just checked or added")
+}
+
+fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | '?' | '%' | '*' | ':' | '|' | '"' | '<' | '>' | '.' => '_',
+            c if c.is_control() => '_',
+            _ => c,
+        })
+        .collect()
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    if let Some(command) = &args.command {
+        return match command {
+            Command::Toc { input_path, format, password, cert, cert_pass } => {
+                let key_file = cert.as_deref().map(|path| cert::KeyFile::open(path, cert_pass.as_deref().unwrap_or(""))).transpose()?;
+                toc::run(input_path, *format, password.as_deref(), key_file.as_ref())
+            }
+            Command::Grep { input_path, pattern, regex, ignore_case, password, cert, cert_pass } => {
+                let key_file = cert.as_deref().map(|path| cert::KeyFile::open(path, cert_pass.as_deref().unwrap_or(""))).transpose()?;
+                let options = grep::GrepOptions { pattern, regex: *regex, ignore_case: *ignore_case };
+                grep::run(input_path, &options, password.as_deref(), key_file.as_ref())
+            }
+            Command::Unpack { input_path, split, password, cert, cert_pass, split_options } => {
+                let key_file = cert.as_deref().map(|path| cert::KeyFile::open(path, cert_pass.as_deref().unwrap_or(""))).transpose()?;
+                portfolio::run(input_path, password.as_deref(), key_file.as_ref(), *split, split_options)
+            }
+            Command::Clean { dir, manifest, dry_run } => clean::run(dir, manifest.as_deref(), *dry_run),
+            Command::Stats { input_path, format } => stats::run(input_path, *format),
+            Command::Rotate { input_path, output, pages, by } => rotate::run(input_path, output, pages.as_ref(), *by),
+            Command::Reorder { input_path, output, order, preset } => reorder::run(input_path, output, order.as_ref(), *preset),
+            Command::Delete { input_path, output, pages } => delete::run(input_path, output, pages),
+            Command::Insert { input_path, at, from, pages, output } => insert::run(input_path, *at, from, pages.as_ref(), output),
+            Command::Attach { input_path, file, desc, name, mime, output } => {
+                let options = attach::AttachOptions { file, name: name.as_deref(), description: desc.as_deref(), mime_type: mime.as_deref() };
+                attach::run(input_path, output, &options)
+            }
+            Command::UnpackAttachments { input_path, output, password, cert, cert_pass } => {
+                let key_file = cert.as_deref().map(|path| cert::KeyFile::open(path, cert_pass.as_deref().unwrap_or(""))).transpose()?;
+                unpack_attachments::run(input_path, output, password.as_deref(), key_file.as_ref())
+            }
+            Command::SetMetadata { input_path, title, author, subject, keywords, xmp, output } => {
+                let options = set_metadata::MetadataOptions {
+                    title: title.as_deref(),
+                    author: author.as_deref(),
+                    subject: subject.as_deref(),
+                    keywords: keywords.as_deref(),
+                    xmp: xmp.as_deref(),
+                };
+                set_metadata::run(input_path, output, &options)
+            }
+            Command::SetPagelabels { input_path, spec, output } => set_page_labels::run(input_path, output, spec),
+            Command::FlattenForms { input_path, output } => flatten_forms::run(input_path, output),
+            Command::Encrypt { input_path, output, user, owner, cipher, password } => {
+                let new_password = passwords::Password { user: user.clone(), owner: owner.clone().unwrap_or_else(|| user.clone()) };
+                encrypt::run(input_path, output, &new_password, *cipher, password.as_deref())
+            }
+            Command::Decrypt { input_path, output, password, cert, cert_pass } => {
+                let key_file = cert.as_deref().map(|path| cert::KeyFile::open(path, cert_pass.as_deref().unwrap_or(""))).transpose()?;
+                decrypt::run(input_path, output, password.as_deref(), key_file.as_ref())
+            }
+            Command::Optimize { input_path, output, compress, downsample, jpeg_quality, keep_unused_fonts, no_object_streams } => {
+                let options = optimize::OptimizeOptions {
+                    compression: *compress,
+                    downsample: downsample.map(|dpi| (dpi, *jpeg_quality)),
+                    keep_unused_fonts: *keep_unused_fonts,
+                    no_object_streams: *no_object_streams,
+                };
+                optimize::run(input_path, output, &options)
+            }
+            Command::Jobs { source, concurrency, threads, max_memory, metrics_addr } => {
+                jobs::run(source, *concurrency, *threads, *max_memory, metrics_addr.as_deref())
+            }
+        };
+    }
+
+    let log_file = args.log_file.as_deref().map(|path| logfile::LogFile::create(path, args.log_format)).transpose()?;
+    let info = if args.quiet { InfoLines::Hidden } else { InfoLines::Stdout };
+    let progress = Progress::new(args.ordered, info, log_file);
+    progress.debug(format!("pdf_splitter {} started: {:?}", env!("CARGO_PKG_VERSION"), std::env::args_os().collect::<Vec<_>>()));
+    split_inputs(args, &progress)
+}
+
+/// サブコマンド以外の実行: `args` の入力をすべて分割し、最後に全体の要約を表示する
+///
+/// ログと進捗は `progress` へ送る（GUI は `Progress::with_observer` で作ったものを渡して進捗を受け取る）。
+pub fn split_inputs(args: &Args, progress: &Progress) -> Result<()> {
+    args.output_encoding.check_supported()?;
+    if args.linearize {
+        linearize::check_available("--linearize")?;
+    }
+    if args.passwords.is_some() {
+        linearize::check_available("--passwords")?;
+    }
+    if args.split_on_barcode {
+        barcode::check_available()?;
+    }
+    if args.detect == Some(Detect::Ocr) {
+        detect::check_ocr_available()?;
+    }
+    // フィーチャー無しでビルドした場合は、処理を始める前に中断する
+    if let Some(dpi) = args.downsample {
+        images::Downsampler::new(dpi, args.jpeg_quality)?;
+    }
+    // 計画ファイルは1つの入力に対するもの
+    if args.input_paths.len() > 1 && (args.plan.is_some() || args.plan_out.is_some() || args.edit_plan) {
+        anyhow::bail!("--plan, --plan-out and --edit-plan take a single input file");
+    }
+    if args.input_paths.len() > 1 && args.catalog.is_some() {
+        anyhow::bail!("--catalog takes a single input file");
+    }
+    if args.input_paths.len() > 1 && args.report.is_some() {
+        anyhow::bail!("--report takes a single input file");
+    }
+    if args.input_paths.len() > 1 && args.sha256.is_some() {
+        anyhow::bail!("--sha256 takes a single input URL");
+    }
+    // Bates 番号は1つの入力の章を通した連番
+    if args.input_paths.len() > 1 && args.bates_start.is_some() {
+        anyhow::bail!("--bates-start takes a single input file");
+    }
+    let renderer = args.thumbnails.map(thumbnails::Renderer::new).transpose()?;
+    let sink = args.output.as_ref().map(upload::Sink::new).transpose()?;
+    let key_file = args.cert.as_deref().map(|path| cert::KeyFile::open(path, args.cert_pass.as_deref().unwrap_or(""))).transpose()?;
+
+    // 並列処理（グローバルではなく専用のスレッドプールで実行し、スレッド数を制限できるようにする）
+    // 一括処理ではファイルと章の両方をこのプールで並列化し、スレッド数とメモリ上限を全体で共有する
+    let pool = build_pool(if args.no_parallel { 1 } else { args.threads.unwrap_or(0) })?;
+    let memory_budget = args.max_memory.map(MemoryBudget::new);
+    let run = RunContext { args, progress, memory_budget: memory_budget.as_ref(), renderer: renderer.as_ref(), sink: sink.as_ref(), key_file: key_file.as_ref(), metrics: None };
+
+    let summaries: Vec<manifest::Summary> = if let [input_path] = args.input_paths.as_slice() {
+        match pool.install(|| split_and_notify(input_path, &run)) {
+            Ok(outcome) => outcome.summary.into_iter().collect(),
+            Err(e) => {
+                progress.log_error(format!("Error processing {:?}: {:#}", input_path, e));
+                return Err(e);
+            }
+        }
+    } else {
+        // 失敗した入力は None（成功しても --dry-run では要約が無い）
+        let results: Vec<Option<Option<manifest::Summary>>> = pool.install(|| {
+            args.input_paths
+                .par_iter()
+                .map(|input_path| match split_and_notify(input_path, &run) {
+                    Ok(outcome) => Some(outcome.summary),
+                    Err(e) => {
+                        progress.eprintln(format!("Error processing {:?}: {:#}", input_path, e));
+                        None
+                    }
+                })
+                .collect()
+        });
+        let failed_files = results.iter().filter(|result| result.is_none()).count();
+        if failed_files > 0 {
+            progress.log_error(format!("{} of {} files failed", failed_files, args.input_paths.len()));
+            anyhow::bail!("{} of {} files failed", failed_files, args.input_paths.len());
+        }
+        results.into_iter().flatten().flatten().collect()
+    };
+
+    // 複数の入力では足し合わせて1つの要約にする（警告は全体で数え直す）
+    if !summaries.is_empty() {
+        let mut total = manifest::Summary::default();
+        for summary in &summaries {
+            total.add(summary);
+        }
+        total.warnings = progress.warnings();
+        progress.println(total.render());
+    }
+    progress.println("All Done!");
+    Ok(())
+}
+
+/// 0 の場合はCPUコア数
+fn build_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context("Failed to build thread pool")
+}
+
+/// 全ファイルで共有する設定と資源
+struct RunContext<'a> {
+    args: &'a Args,
+    progress: &'a Progress,
+    memory_budget: Option<&'a MemoryBudget>,
+    renderer: Option<&'a thumbnails::Renderer>,
+    /// `--output` の送り先
+    sink: Option<&'a upload::Sink>,
+    /// `--cert` の受取人の鍵
+    key_file: Option<&'a cert::KeyFile>,
+    /// `jobs --metrics-addr` の場合だけ集計する
+    metrics: Option<&'a metrics::Metrics>,
+}
+
+/// 章ごとに書き出すページ（元のページ番号とページ）と、`--exclude-pages` と `--drop-duplicates` で除いた元のページ番号
+struct ChapterPages {
+    kept: Vec<(u32, ObjectId)>,
+    excluded: Vec<u32>,
+    duplicates: Vec<u32>,
+}
+
+/// `split_input` を実行し、`--notify-url` があれば結果を通知する（通知の失敗は警告にとどめる）
+///
+/// `--output` では結果の JSON（出力はオブジェクトの URL にしたもの）も `<入力名>_manifest.json` として送る。
+/// 分割に成功してもこれを送れなければ失敗にする。
+fn split_and_notify(input_path: &Path, run: &RunContext) -> Result<FileOutcome> {
+    let start = Instant::now();
+    let mut result = split_input(input_path, run);
+    if let Some(sink) = run.sink {
+        let mut manifest = manifest::RunManifest::new(input_path, &result, start.elapsed());
+        manifest.map_outputs(|output| {
+            let name = Path::new(output).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            sink.destination().object_url(&name)
+        });
+        let stem = input_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let json = serde_json::to_string_pretty(&manifest)? + "\n";
+        match sink.put(&format!("{}_manifest.json", sanitize_filename(&stem)), json.as_bytes(), "application/json") {
+            Ok(url) => run.progress.println(format!("Uploaded manifest: {}", url)),
+            Err(e) if result.is_ok() => result = Err(e.context("Failed to upload the manifest")),
+            Err(e) => run.progress.eprintln(format!("Warning: failed to upload the manifest: {:#}", e)),
+        }
+    }
+    if let Some(url) = &run.args.notify_url {
+        let manifest = manifest::RunManifest::new(input_path, &result, start.elapsed());
+        if let Err(e) = notify::post(url, &manifest) {
+            run.progress.eprintln(format!("Warning: {:#}", e));
+        }
+    }
+    result
+}
+
+/// 入力が URL ならダウンロードしてから分割する
+///
+/// ファイルの出力は入力と同じディレクトリに、URL の入力の出力は現在のディレクトリに置く。
+/// `--output` では、章のファイルを送るまでの間だけ置く一時ディレクトリに書き出し、分割を終えたらディレクトリごと消す。
+/// ダウンロードしたファイルは分割を終えたら消す。
+fn split_input(input_path: &Path, run: &RunContext) -> Result<FileOutcome> {
+    let staging = run.sink.map(|_| upload::staging_dir()).transpose()?;
+    let result = if !fetch::is_url(input_path) {
+        let parent_dir = input_path.parent().unwrap_or_else(|| Path::new("."));
+        split_file(input_path, staging.as_deref().unwrap_or(parent_dir), run)
+    } else {
+        let url = input_path.to_str().expect("URL inputs are valid UTF-8");
+        fetch::download(url, run.args.sha256.as_deref(), run.progress).and_then(|downloaded| {
+            let result = split_file(&downloaded, staging.as_deref().unwrap_or(Path::new(".")), run);
+            let _ = std::fs::remove_file(&downloaded);
+            if let Some(dir) = downloaded.parent() {
+                let _ = std::fs::remove_dir(dir);
+            }
+            result
+        })
+    };
+    if let Some(dir) = &staging {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    result
+}
+
+/// 1ファイルを章ごとに分割する（呼び出し側のスレッドプール上で章を並列に処理する）
+///
+/// 戻り値は保存した章のファイル（章の順、`--dry-run` では空）と、宛先を解決できなかった目次項目。
+fn split_file(input_path: &Path, parent_dir: &Path, run: &RunContext) -> Result<FileOutcome> {
+    let RunContext { args, progress, memory_budget, renderer, sink, key_file, metrics } = *run;
+    let warnings_at_start = progress.warnings();
+    if interrupt::is_interrupted() {
+        anyhow::bail!("interrupted before {:?} was started", input_path);
+    }
+
+    let raw_stem = input_path.file_stem().context("Invalid file name")?;
+    let file_stem = raw_stem.to_string_lossy().to_string();
+    // UTF-8 として正しくない入力名も、出力名では元のバイト列のまま使う
+    let output_names = output_names::OutputNames::new(raw_stem, &file_stem, args.output_encoding);
+    // 出力ファイル名の先頭に使う入力名（長すぎる入力名は、出力先で OS の上限に収まるように切り詰める）
+    let name_stem = plan::output_stem(&file_stem, parent_dir)?;
+
+    progress.println(format!("Loading PDF: {:?}", input_path));
+    let load_start = Instant::now();
+    let mut file_timings = FileTimings { input: input_path.display().to_string(), ..Default::default() };
+    
+    // 解析済みの文書は全ワーカーで共有し、章ごとに複製しない
+    let spinner = progress.spinner("Loading PDF");
+    let doc = Arc::new(load_document(input_path, args.mmap)?);
+    drop(spinner);
+    
+    file_timings.load_ms = timings::ms(load_start.elapsed());
+    progress.println(format!("PDF loaded in {:.2?}. Analyzing structure...", load_start.elapsed()));
+    let spinner = progress.spinner("Analyzing structure");
+    let analysis_start = Instant::now();
+
+    // 暗号化文書では目次タイトル等の文字列をオブジェクト単位で復号する
+    let decryptor = StringDecryptor::for_document(&doc, args.password.as_deref(), key_file)?;
+    if decryptor.is_active() {
+        progress.println("Document is encrypted; decrypting outline strings.");
+    }
+
+    if let Some(values) = &args.debug_object {
+        drop(spinner);
+        let generation = u16::try_from(values[1]).context("--debug-object: the generation number must be at most 65535")?;
+        progress.println(dump::format_object(&doc, &decryptor, (values[0], generation))?);
+        return Ok(FileOutcome::default());
+    }
+
+    // PDF/A 準拠の検出（OutputIntent が欠けた出力はアーカイブで受理されない）
+    let pdfa_conformance = pdfa::detect(&doc);
+    if let Some(conformance) = &pdfa_conformance {
+        progress.println(format!("{} conformance declared in XMP metadata.", conformance));
+        if !pdfa::has_output_intent(&doc) {
+            progress.eprintln(format!("Warning: {} document has no /OutputIntents; outputs will not be conformant either.", conformance));
+        }
+        if let Some(version) = args.pdf_version
+            && version > conformance.max_pdf_version()
+        {
+            progress.eprintln(format!(
+                "Warning: --pdf-version {} breaks {} conformance (at most PDF {} is allowed).",
+                version.as_str(), conformance, conformance.max_pdf_version().as_str()
+            ));
+        }
+    }
+
+    // 1-3. ページツリーと目次の走査（--cache の場合、同じ入力なら前回の結果を使う）
+    let outline_levels = OutlineLevels { depth: args.depth, path_sep: args.title_path.then_some(args.path_sep.as_str()) };
+    let cache = if args.cache {
+        Some(cache::AnalysisCache::open(input_path, &format!("{:?}{}", args.dest_fallback, outline_levels.cache_key()))?)
+    } else {
+        None
+    };
+    let analysis = if let Some(cache) = &cache
+        && let Some(analysis) = cache.load()
+    {
+        progress.println(format!("Using cached analysis from {:?}.", cache.path()));
+        for warning in &analysis.page_tree_warnings {
+            progress.eprintln(format!("Warning: {}", warning));
+        }
+        file_timings.analysis_ms = timings::ms(analysis_start.elapsed());
+        drop(spinner);
+        analysis
+    } else {
+        let analysis = analyze(&doc, &decryptor, args.dest_fallback, outline_levels, progress, &mut file_timings, analysis_start);
+        drop(spinner);
+        if let Some(cache) = &cache
+            && let Err(e) = cache.store(&analysis)
+        {
+            progress.eprintln(format!("Warning: could not write analysis cache: {:#}", e));
+        }
+        analysis
+    };
+    let cache::Analysis { page_numbers, mut chapter_starts, skipped_bookmarks, .. } = analysis;
+    if args.explain {
+        let object_id_to_page: BTreeMap<_, _> = page_numbers.iter().map(|(num, id)| (*id, *num)).collect();
+        let named_dests = NamedDests::new(&doc, &decryptor);
+        let ctx = OutlineContext {
+            doc: &doc,
+            object_id_to_page: &object_id_to_page,
+            named_dests: &named_dests,
+            decryptor: &decryptor,
+            fallback: args.dest_fallback,
+            progress,
+        };
+        for line in explain::explain_outline(&ctx) {
+            progress.println(line);
+        }
+        return Ok(FileOutcome::default());
+    }
+    for bookmark in &skipped_bookmarks {
+        progress.debug(format!("Skipped bookmark '{}': {}", bookmark.title, bookmark.reason));
+    }
+    if !skipped_bookmarks.is_empty() {
+        progress.eprintln(format!(
+            "Warning: skipped {} bookmarks whose destinations could not be resolved.",
+            skipped_bookmarks.len()
+        ));
+    }
+    if let Some(report_path) = &args.report {
+        report::write_skipped(report_path, input_path, &skipped_bookmarks)?;
+        progress.println(format!("Wrote skipped-bookmark report to {:?}.", report_path));
+    }
+
+    // 区切りのページで分割する場合は、目次の代わりに各区切りのコードを章のタイトルにする
+    let separator_pages: HashSet<u32> = if args.split_on_barcode {
+        let spinner = progress.spinner("Reading barcodes");
+        let separators = barcode::find_separators(input_path, args.password.as_deref(), args.pattern.as_ref())?;
+        drop(spinner);
+        progress.println(format!("Found {} separator pages.", separators.len()));
+        chapter_starts = separators.clone();
+        if let Some((first, _)) = separators.first()
+            && *first > 1
+        {
+            chapter_starts.insert(0, (1, file_stem.clone()));
+        }
+        separators.into_iter().map(|(number, _)| number).collect()
+    } else if let Some(subtype) = &args.split_on_annot {
+        let separators = annots::pages_with_subtype(&doc, &decryptor, &page_numbers, subtype);
+        progress.println(format!("Found {} separator pages with {} annotations.", separators.len(), subtype));
+        chapter_starts = separators
+            .iter()
+            .map(|(number, title)| (*number, if title.is_empty() { format!("{}_p{}", file_stem, number) } else { title.clone() }))
+            .collect();
+        if chapter_starts.first().is_none_or(|(first, _)| *first > 1) {
+            chapter_starts.insert(0, (1, file_stem.clone()));
+        }
+        separators.into_iter().map(|(number, _)| number).collect()
+    } else {
+        HashSet::new()
+    };
+    if let Some(method) = args.detect {
+        let spinner = progress.spinner("Detecting chapter headings");
+        let detected = match method {
+            Detect::Ocr => detect::ocr_headings(input_path, args.password.as_deref(), &args.ocr_lang)?,
+            Detect::FontSize => {
+                // 暗号化文書の内容ストリームは復号しないため、文字の大きさを読めない
+                if decryptor.is_active() {
+                    anyhow::bail!("--detect font-size cannot read the content streams of encrypted documents");
+                }
+                let detection = detect::font_size_headings(&doc, &page_numbers);
+                progress.println(format!("Body text size: {:.1}pt.", detection.body_size));
+                detection.boundaries
+            }
+        };
+        drop(spinner);
+        progress.println(format!("Detected {} chapter starts:", detected.len()));
+        for boundary in &detected {
+            match boundary.confidence {
+                Some(confidence) => progress.println(format!(
+                    "  page {}: {} (confidence {:.2})",
+                    boundary.page, boundary.title, confidence
+                )),
+                None => progress.println(format!("  page {}: {}", boundary.page, boundary.title)),
+            }
+        }
+        chapter_starts = detected.into_iter().map(|boundary| (boundary.page, boundary.title)).collect();
+        if let Some((first, _)) = chapter_starts.first()
+            && *first > 1
+        {
+            chapter_starts.insert(0, (1, file_stem.clone()));
+        }
+    }
+
+    if let Some(field_type) = args.split_on_field_type {
+        let pages = fields::pages_with_field(&doc, &decryptor, &page_numbers, field_type);
+        if pages.is_empty() {
+            progress.eprintln(format!("Warning: no pages have {:?} fields; the whole document becomes one file.", field_type));
+        } else {
+            progress.println(format!("Found {} pages with {:?} fields.", pages.len(), field_type));
+        }
+        for (page, name) in &pages {
+            progress.debug(format!("Field {:?} on page {}", name, page));
+        }
+        chapter_starts = fields::chapter_starts(&pages, args.field_boundary, page_numbers.len() as u32, &file_stem);
+    }
+
+    if let Some(name) = &args.split_by_field {
+        let mut values = fields::pages_with_value(&doc, &decryptor, &page_numbers, name);
+        let mut source = "form field";
+        // フィールドが無ければ、同じ名前のタグで囲まれたテキストを探す（暗号化文書の内容ストリームは読めない）
+        if values.is_empty() && !decryptor.is_active() {
+            values = page_numbers.iter().filter_map(|(page, page_id)| text::tagged_text(&doc, *page_id, name).map(|value| (*page, value))).collect();
+            source = "tagged text";
+        }
+        if values.is_empty() {
+            progress.eprintln(format!("Warning: no pages have a value for {:?}; the whole document becomes one file.", name));
+        } else {
+            progress.println(format!("Found {:?} values ({}) on {} pages.", name, source, values.len()));
+        }
+        for (page, value) in &values {
+            progress.debug(format!("{} {:?} on page {}", name, value, page));
+        }
+        chapter_starts = fields::value_chapter_starts(&values, &file_stem);
+    }
+
+    if chapter_starts.is_empty() {
+        progress.println("警告: 有効な目次が見つかりませんでした。");
+        // ポートフォリオの本体は表紙だけなので、添付された PDF を取り出すよう案内する
+        if portfolio::is_portfolio(&doc) {
+            progress.eprintln(format!(
+                "Warning: {:?} is a PDF portfolio; its documents are attachments. Extract them with `pdf_splitter unpack` (add --split to split each).",
+                input_path
+            ));
+        }
+        chapter_starts.push((1, "FullDocument".to_string()));
+    }
+
+    chapter_starts.sort_by_key(|k| k.0);
+    chapter_starts.dedup_by_key(|k| k.0);
+
+    let total_pages = page_numbers.len() as u32;
+    progress.debug(format!("{:?}: {} pages, chapter starts {:?}", input_path, total_pages, chapter_starts));
+    // 印刷されたページ番号で指定された範囲は、ここで通し番号に直す
+    let logical_pages = match args.page_numbering {
+        page_labels::PageNumbering::Physical => None,
+        page_labels::PageNumbering::Logical => {
+            let labels = page_labels::collect(&doc, &decryptor, total_pages)
+                .context("--page-numbering logical needs page labels (/PageLabels), but the document has none")?;
+            Some(page_labels::LogicalPages::new(&labels))
+        }
+    };
+    let exclude_pages = match (&args.exclude_pages, &logical_pages) {
+        (Some(ranges), Some(logical)) => Some(ranges.to_physical(logical, "--exclude-pages")?),
+        (ranges, _) => ranges.clone(),
+    };
+    let rotate_pages = match (&args.pages, &logical_pages) {
+        (Some(ranges), Some(logical)) => Some(ranges.to_physical(logical, "--pages")?),
+        (ranges, _) => ranges.clone(),
+    };
+    let (mut plan, mut total_chapters) = match &args.plan {
+        // 計画ファイルがあれば目次から作った計画の代わりに使う
+        Some(plan_path) => {
+            let plan = plan::read_plan(plan_path, total_pages, &name_stem, parent_dir, logical_pages.as_ref())?;
+            progress.println(format!("Using plan {:?} ({} chapters).", plan_path, plan.len()));
+            let total_chapters = plan.len();
+            (plan, total_chapters)
+        }
+        None => (plan::plan_chapters(&chapter_starts, total_pages, &name_stem, parent_dir), chapter_starts.len()),
+    };
+    // 最後の章の本来の終わり: 目次（全階層）の最後の宛先より後ろで、最初に大きな見出しで始まるページの直前
+    if args.back_matter != BackMatter::Append {
+        if decryptor.is_active() {
+            progress.eprintln("Warning: --back-matter is ignored for encrypted documents.");
+        } else {
+            let object_id_to_page: BTreeMap<_, _> = page_numbers.iter().map(|(num, id)| (*id, *num)).collect();
+            let named_dests = NamedDests::new(&doc, &decryptor);
+            let ctx = OutlineContext {
+                doc: &doc,
+                object_id_to_page: &object_id_to_page,
+                named_dests: &named_dests,
+                decryptor: &decryptor,
+                fallback: args.dest_fallback,
+                progress,
+            };
+            let last_bookmarked = toc::collect_entries(&ctx, total_pages).iter().filter_map(|entry| entry.start_page).max();
+            let start = last_bookmarked.and_then(|last| {
+                detect::font_size_headings(&doc, &page_numbers)
+                    .boundaries
+                    .into_iter()
+                    .find(|boundary| boundary.page > last)
+            });
+            match start {
+                Some(boundary) => {
+                    progress.println(format!(
+                        "Back matter starts at page {} (\"{}\"); pages {}-{} are {}.",
+                        boundary.page,
+                        boundary.title,
+                        boundary.page,
+                        total_pages,
+                        if args.back_matter == BackMatter::Drop { "dropped" } else { "written separately" }
+                    ));
+                    plan::split_back_matter(&mut plan, boundary.page, args.back_matter, &name_stem);
+                    total_chapters = plan.len();
+                }
+                None => progress.println("No back matter found after the last chapter."),
+            }
+        }
+    }
+    if args.edit_plan {
+        plan = plan::edit_plan(input_path, total_pages, &plan, &name_stem, parent_dir)?;
+        total_chapters = plan.len();
+        progress.println(format!("Using the edited plan ({} chapters).", plan.len()));
+    }
+    for chapter in &plan {
+        progress.debug(format!(
+            "Planned chapter {}: p.{}-p.{} {:?} -> {:?}",
+            chapter.index + 1, chapter.start_page, chapter.end_page, chapter.title, chapter.filename
+        ));
+    }
+    if let Some(plan_out) = &args.plan_out {
+        plan::write_plan(plan_out, input_path, total_pages, &plan)?;
+        progress.println(format!("Wrote plan to {:?}.", plan_out));
+    }
+
+    // 暗号化文書のストリームは暗号文のため、圧縮し直すと復号できなくなる
+    let compression = if decryptor.is_active() && args.compress != Compression::None {
+        progress.eprintln("Warning: --compress is ignored for encrypted documents.");
+        Compression::None
+    } else {
+        args.compress
+    };
+    // 暗号化文書の内容ストリームは復号しないため、本文を取り出せない
+    let extract_text = if decryptor.is_active() && args.extract_text {
+        progress.eprintln("Warning: --extract-text is ignored for encrypted documents.");
+        false
+    } else {
+        args.extract_text
+    };
+    // 文書情報辞書の文字列は暗号化されるため、暗号化文書の /Title は書き換えない
+    let title_metadata = if decryptor.is_active() && args.title_path {
+        progress.eprintln("Warning: --title-path does not set the /Title of encrypted documents; only the file names use the title path.");
+        false
+    } else {
+        args.title_path
+    };
+    // 追加するメタデータストリームは暗号化できないため、暗号化文書には書き込まない
+    let series = if decryptor.is_active() && args.series_metadata {
+        progress.eprintln("Warning: --series-metadata is ignored for encrypted documents.");
+        None
+    } else if args.series_metadata {
+        let title = info_string(&doc, &decryptor, b"Title").unwrap_or_else(|| file_stem.clone());
+        Some(xmp::Series::new(title))
+    } else {
+        None
+    };
+    let provenance_xmp = if decryptor.is_active() && args.provenance_xmp {
+        progress.eprintln("Warning: --provenance-xmp is ignored for encrypted documents.");
+        false
+    } else {
+        args.provenance_xmp
+    };
+    // 追加する内容ストリームは暗号化できないため、暗号化文書には書き込まない
+    let footer = if decryptor.is_active() && args.stamp_footer.is_some() {
+        progress.eprintln("Warning: --stamp-footer is ignored for encrypted documents.");
+        None
+    } else {
+        if args.stamp_footer.is_some() && pdfa_conformance.is_some() {
+            progress.eprintln("Warning: --stamp-footer uses a non-embedded font, which breaks PDF/A conformance.");
+        }
+        args.stamp_footer.clone().map(stamp::Footer::new)
+    };
+    // パスワードの抜けた章があれば、暗号化しないまま書き出さないよう、書き出しを始める前に中断する
+    let passwords = match &args.passwords {
+        Some(path) => {
+            let passwords = passwords::ChapterPasswords::read(path)?;
+            let missing: Vec<String> = plan
+                .iter()
+                .filter(|chapter| passwords.for_chapter(chapter).is_none())
+                .map(|chapter| (chapter.index + 1).to_string())
+                .collect();
+            if !missing.is_empty() {
+                anyhow::bail!("no password for chapter(s) {}", missing.join(", "));
+            }
+            if pdfa_conformance.is_some() {
+                progress.eprintln("Warning: --passwords encrypts the outputs, which breaks PDF/A conformance.");
+            }
+            Some(passwords)
+        }
+        None => None,
+    };
+    let watermark = if decryptor.is_active() && args.watermark.is_some() {
+        progress.eprintln("Warning: --watermark is ignored for encrypted documents.");
+        None
+    } else if let Some(template) = &args.watermark {
+        if pdfa_conformance.is_some() {
+            progress.eprintln("Warning: --watermark uses a non-embedded font and transparency, which breaks PDF/A conformance.");
+        }
+        let recipients = match &args.watermark_recipients {
+            Some(path) => stamp::read_recipients(path)?,
+            None => BTreeMap::new(),
+        };
+        let watermark = stamp::Watermark::new(template.clone(), recipients);
+        // 配布先の抜けた章があれば、書き出しを始める前に中断する
+        let missing = watermark.missing_recipients(plan.iter().map(|chapter| chapter.index + 1));
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(|chapter| chapter.to_string()).collect();
+            anyhow::bail!("no watermark recipient for chapter(s) {}", missing.join(", "));
+        }
+        Some(watermark)
+    } else {
+        None
+    };
+    let title_page = if decryptor.is_active() && args.title_page {
+        progress.eprintln("Warning: --title-page is ignored for encrypted documents.");
+        None
+    } else if args.title_page {
+        if pdfa_conformance.is_some() {
+            progress.eprintln("Warning: --title-page uses a non-embedded font, which breaks PDF/A conformance.");
+        }
+        let source_title = info_string(&doc, &decryptor, b"Title").unwrap_or_else(|| file_stem.clone());
+        Some(stamp::TitlePage::new(source_title, provenance::utc_date(std::time::SystemTime::now())))
+    } else {
+        None
+    };
+    // 表紙の分だけ、章のファイルのページ番号がずれる
+    let inserted_pages = u32::from(title_page.is_some());
+    // 説明のページの内容ストリームは暗号化できないため、暗号化文書では灰色にする
+    let full_outline = args.keep_full_outline.and_then(|missing| {
+        let missing = if decryptor.is_active() && missing == full_outline::MissingEntries::Placeholder {
+            progress.eprintln("Warning: --keep-full-outline=placeholder is not supported for encrypted documents; greying out entries instead.");
+            full_outline::MissingEntries::Grey
+        } else {
+            missing
+        };
+        if missing == full_outline::MissingEntries::Placeholder && pdfa_conformance.is_some() {
+            progress.eprintln("Warning: --keep-full-outline=placeholder uses a non-embedded font, which breaks PDF/A conformance.");
+        }
+        let object_id_to_page: BTreeMap<_, _> = page_numbers.iter().map(|(num, id)| (*id, *num)).collect();
+        let named_dests = NamedDests::new(&doc, &decryptor);
+        let ctx = OutlineContext {
+            doc: &doc,
+            object_id_to_page: &object_id_to_page,
+            named_dests: &named_dests,
+            decryptor: &decryptor,
+            fallback: DestFallback::Skip,
+            progress,
+        };
+        let outline = full_outline::FullOutline::collect(&ctx, &page_numbers, missing);
+        if outline.is_empty() {
+            progress.eprintln("Warning: --keep-full-outline is ignored because the document has no outline.");
+            return None;
+        }
+        Some(outline)
+    });
+    let placeholder_page = stamp::PlaceholderPage::new(
+        &plan
+            .iter()
+            .map(|chapter| (format!("p.{}-p.{}", chapter.start_page, chapter.end_page), chapter.filename.clone()))
+            .collect::<Vec<_>>(),
+    );
+    // 説明のページを章の末尾に加える章では、その分だけページが増える
+    let appended_pages = |kept: &[(u32, ObjectId)]| {
+        let kept: HashSet<u32> = kept.iter().map(|(number, _)| *number).collect();
+        u32::from(full_outline.as_ref().is_some_and(|outline| outline.needs_placeholder(&kept)))
+    };
+    // 元の文書の XMP があれば、その内容を残したまま追記する
+    let source_xmp = if series.is_some() || provenance_xmp {
+        if pdfa_conformance.is_some() {
+            progress.eprintln("Warning: custom XMP properties are added without a PDF/A extension schema.");
+        }
+        xmp::catalog_metadata(&doc)
+    } else {
+        None
+    };
+    let mut extractor = extract::PageExtractor::new(Arc::clone(&doc), page_numbers.values().cloned())
+        .with_compression(compression);
+    // 暗号化文書の画像は暗号文のため、復号せずには縮小できない
+    if let Some(dpi) = args.downsample {
+        if decryptor.is_active() {
+            progress.eprintln("Warning: --downsample is ignored for encrypted documents.");
+        } else {
+            extractor = extractor.with_downsampler(images::Downsampler::new(dpi, args.jpeg_quality)?);
+        }
+    }
+    // 使われているフォントは内容ストリームから調べるため、暗号化文書では判定できない
+    if args.strip_unused_fonts {
+        if decryptor.is_active() {
+            progress.eprintln("Warning: --strip-unused-fonts is ignored for encrypted documents.");
+        } else {
+            extractor = extractor.with_font_stripping();
+        }
+    }
+    // 並べるページの内容は元の文書から読み直してフォームにするため、暗号化文書では扱えない
+    let nup = if decryptor.is_active() && args.nup.is_some() {
+        progress.eprintln("Warning: --nup is ignored for encrypted documents.");
+        None
+    } else {
+        args.nup
+    };
+    if nup.is_some() {
+        extractor = extractor.without_page_contents();
+    }
+    // 章のファイルのページ数（表紙を除く）
+    let output_page_count = |pages: usize| nup.map_or(pages, |n| nup::sheet_count(pages, n));
+    // 無変更のストリームは元ファイルのバイト列をそのままコピーする（開けなければ直列化し直す）
+    match raw_copy::RawSource::open(input_path) {
+        Ok(raw) => extractor = extractor.with_raw_source(raw),
+        Err(e) => progress.eprintln(format!("Warning: raw stream copy disabled: {}", e)),
+    }
+
+    if args.dry_run {
+        let estimated_total = print_dry_run(&plan, total_chapters, &extractor, &page_numbers, progress);
+        if let Some(warning) = limits::check_free_space(parent_dir, estimated_total, true)? {
+            progress.eprintln(format!("Warning: {}", warning));
+        }
+        return Ok(FileOutcome { outputs: Vec::new(), skipped_bookmarks, summary: None });
+    }
+
+    // 空き容量の事前確認（足りなければ書き始める前に中断する）
+    let estimated_total: u64 = plan.iter().map(|chapter| extractor.estimate_size(&chapter_page_ids(&page_numbers, chapter))).sum();
+    if let Some(warning) = limits::check_free_space(parent_dir, estimated_total, args.force)? {
+        progress.eprintln(format!("Warning: {}", warning));
+    }
+
+    // 添付ファイルのストリームは暗号化できないため、暗号化文書には添付しない
+    let embed_provenance = if decryptor.is_active() && args.embed_provenance {
+        progress.eprintln("Warning: --embed-provenance is ignored for encrypted documents.");
+        false
+    } else {
+        if args.embed_provenance
+            && let Some(conformance) = &pdfa_conformance
+            && matches!(conformance.part.as_str(), "1" | "2")
+        {
+            progress.eprintln(format!("Warning: --embed-provenance breaks {} conformance (attachments are not allowed).", conformance));
+        }
+        args.embed_provenance
+    };
+    let provenance = if embed_provenance || provenance_xmp {
+        Some(provenance::Provenance::for_source(input_path)?)
+    } else {
+        None
+    };
+
+    // 白紙の判定には内容ストリームを読むため、暗号化文書では判定できない
+    let drop_blank = if decryptor.is_active() && args.drop_blank {
+        progress.eprintln("Warning: --drop-blank is ignored for encrypted documents.");
+        false
+    } else {
+        args.drop_blank
+    };
+
+    // 重複ページの判定には内容ストリームを読むため、暗号化文書では判定できない
+    let duplicate_pages: HashSet<u32> = if decryptor.is_active() && args.detect_duplicates {
+        progress.eprintln("Warning: --detect-duplicates is ignored for encrypted documents.");
+        HashSet::new()
+    } else if args.detect_duplicates {
+        let groups = duplicates::find(&doc, &page_numbers);
+        if groups.is_empty() {
+            progress.println("No duplicate pages found.");
+        } else {
+            progress.println(format!("Found {} sets of duplicate pages:", groups.len()));
+            for pages in &groups {
+                let pages: Vec<String> = pages.iter().map(u32::to_string).collect();
+                progress.println(format!("  pages {}", pages.join(", ")));
+            }
+        }
+        // どの章にも入らないページに最初の1ページを残しても出力には残らないため、章に含まれるページから選ぶ
+        let in_plan = |page: &u32| plan.iter().any(|chapter| (chapter.start_page..=chapter.end_page).contains(page));
+        if args.drop_duplicates {
+            groups.iter().flat_map(|pages| pages.iter().filter(|page| in_plan(page)).skip(1).copied()).collect()
+        } else {
+            HashSet::new()
+        }
+    } else {
+        HashSet::new()
+    };
+
+    // 章ごとに書き出すページ。章の区切りは元のページで決めたまま、章の中の指定ページ・重複ページ・白紙だけを除く
+    // （すべて白紙なら先頭のページを残す）。Bates 番号の割り当てに全章のページ数が要るため先に求める
+    let chapter_pages: Vec<ChapterPages> = plan
+        .par_iter()
+        .map(|chapter| {
+            let (pages, excluded): (Vec<_>, Vec<_>) = page_numbers
+                .range(chapter.start_page..=chapter.end_page)
+                .filter(|(number, _)| !separator_pages.contains(number))
+                .map(|(number, id)| (*number, *id))
+                .partition(|(number, _)| !exclude_pages.as_ref().is_some_and(|ranges| ranges.contains(*number)));
+            let (pages, duplicates): (Vec<_>, Vec<_>) =
+                pages.into_iter().partition(|(number, _)| !duplicate_pages.contains(number));
+            let excluded: Vec<u32> = excluded.into_iter().map(|(number, _)| number).collect();
+            let duplicates: Vec<u32> = duplicates.into_iter().map(|(number, _)| number).collect();
+            if !drop_blank || pages.is_empty() {
+                return ChapterPages { kept: pages, excluded, duplicates };
+            }
+            let kept: Vec<(u32, ObjectId)> = pages.iter().copied().filter(|(_, id)| !blank::is_blank(&doc, *id)).collect();
+            ChapterPages { kept: if kept.is_empty() { pages[..1].to_vec() } else { kept }, excluded, duplicates }
+        })
+        .collect();
+
+    // 追加する内容ストリームは暗号化できないため、暗号化文書には書き込まない
+    let bates_start = if decryptor.is_active() && args.bates_start.is_some() {
+        progress.eprintln("Warning: --bates-start is ignored for encrypted documents.");
+        None
+    } else {
+        if args.bates_start.is_some() && pdfa_conformance.is_some() {
+            progress.eprintln("Warning: --bates-start uses a non-embedded font, which breaks PDF/A conformance.");
+        }
+        args.bates_start.as_ref()
+    };
+    // 各章の先頭ページの Bates 番号（最初の番号からの差）。番号は章の順に、表紙も含めて続ける
+    let bates_offsets: Vec<u64> = chapter_pages
+        .iter()
+        .scan(0, |next, pages| {
+            let offset = *next;
+            // すべてのページを除いた章は書き出さないため、番号を使わない
+            if !pages.kept.is_empty() {
+                *next += u64::from(inserted_pages + appended_pages(&pages.kept)) + output_page_count(pages.kept.len()) as u64;
+            }
+            Some(offset)
+        })
+        .collect();
+
+    // 対応表に載せるページラベル（文書全体で一度だけ作る）
+    let page_labels = if args.page_map || args.sidecar.is_some() { page_labels::collect(&doc, &decryptor, total_pages) } else { None };
+
+    progress.println(format!("Found {} chapters. Starting parallel processing...", total_chapters));
+    let file_id = progress.begin_file(plan.len());
+
+    let header_version = args.pdf_version.map(|v| v.as_str()).unwrap_or(&doc.version);
+
+    let pages_bar = progress.pages(plan.iter().map(|chapter| u64::from(chapter.page_count())).sum());
+
+    // 保存・検証に失敗した章の数（終了コードに反映）
+    let failed_chapters = AtomicUsize::new(0);
+    // 一時的な入出力の失敗で書き直しても書き出せなかった章（結果の JSON に載せる）
+    let retries_exhausted: Mutex<Vec<(usize, manifest::RetriesExhausted)>> = Mutex::new(Vec::new());
+    let retry = retry::RetryPolicy { retries: args.io_retries, delay: Duration::from_millis(args.io_retry_delay) };
+    let chapter_timings: Mutex<Vec<ChapterTimings>> = Mutex::new(Vec::new());
+    let saved_outputs: Mutex<Vec<(usize, SavedOutput)>> = Mutex::new(Vec::new());
+
+    // 1章分の処理
+    let process_chapter = |(position, chapter): (usize, &PlannedChapter)| {
+        let log = progress.chapter_log(file_id, position);
+        let i = chapter.index;
+        let (start_page, end_page) = (chapter.start_page, chapter.end_page);
+        let out_filename = &chapter.filename;
+        let start_time = Instant::now();
+
+        let ChapterPages { kept: pages, excluded: excluded_pages, duplicates } = &chapter_pages[position];
+        if interrupt::is_interrupted() {
+            pages_bar.inc(u64::from(chapter.page_count()));
+            return;
+        }
+        if pages.is_empty() {
+            log.println(format!("Skipped chapter {}: all of its pages are excluded.", i + 1));
+            pages_bar.inc(u64::from(chapter.page_count()));
+            return;
+        }
+        if !excluded_pages.is_empty() {
+            let excluded: Vec<String> = excluded_pages.iter().map(u32::to_string).collect();
+            log.println(format!("Excluded pages {} from chapter {}.", excluded.join(", "), i + 1));
+        }
+        if !duplicates.is_empty() {
+            let duplicates: Vec<String> = duplicates.iter().map(u32::to_string).collect();
+            log.println(format!("Dropped duplicate pages {} from chapter {}.", duplicates.join(", "), i + 1));
+        }
+        // 文書全体を複製せず、章のページとそこから参照されるオブジェクトだけをコピーする
+        let separators = usize::from(separator_pages.contains(&start_page));
+        let dropped_blank = chapter.page_count() as usize - separators - excluded_pages.len() - duplicates.len() - pages.len();
+        let (source_pages, page_ids): (Vec<u32>, Vec<ObjectId>) = pages.iter().copied().unzip();
+        log.debug(format!("Chapter {}: writing source pages {} ({:?})", i + 1, page_ranges::format(&source_pages), page_ids));
+        let appended_pages = appended_pages(pages);
+        let bates = bates_start.map(|start| BatesRange {
+            first: start.label(bates_offsets[position]),
+            last: start.label(
+                bates_offsets[position] + u64::from(inserted_pages + appended_pages) + output_page_count(page_ids.len()) as u64 - 1,
+            ),
+        });
+
+        // メモリ上限の指定があれば、この章の作業メモリを予約できるまで待つ
+        let _reservation = memory_budget.map(|budget| {
+            budget.acquire(MemoryBudget::working_set(extractor.estimate_size(&page_ids)))
+        });
+        let mut timings = ChapterTimings { chapter: i + 1, wait_ms: timings::ms(start_time.elapsed()), ..Default::default() };
+
+        let out_path = output_names.path(parent_dir, out_filename);
+        // 完成するまでは別名で書き、中断や失敗で残った書きかけのファイルを正しい出力と取り違えないようにする
+        let partial_path = interrupt::partial_path(&out_path);
+        let _chapter_bar = progress.chapter(
+            &pages_bar,
+            format!("[{}/{}] {}", i + 1, total_chapters, out_filename),
+            u64::from(chapter.page_count()),
+        );
+
+        // 増分更新にできるのは、元の文書の全ページをそのままの順に含む章だけ（他の章のページが元の版に残ってしまうため）
+        let incremental = args.save_mode == SaveMode::Incremental && source_pages.iter().copied().eq(1..=total_pages);
+        if args.save_mode == SaveMode::Incremental && !incremental {
+            log.eprintln(format!(
+                "Warning: chapter {} does not contain the whole document; saving it in full instead of as an incremental update.",
+                i + 1
+            ));
+        }
+
+        // 抽出したストリームはそのままファイルへ書き出し、メモリには辞書の骨格だけを残す
+        let mut save = || -> Result<()> {
+            let phase_start = Instant::now();
+            let file = File::create(&partial_path)?;
+            let mut writer = if incremental {
+                let original = File::open(input_path).with_context(|| format!("Failed to open {:?}", input_path))?;
+                StreamingWriter::append_to(BufWriter::new(file), BufReader::new(original), Arc::clone(&doc))?
+            } else {
+                StreamingWriter::new(BufWriter::new(file), header_version)?.with_readback(File::open(&partial_path)?)
+            };
+            let (mut split_doc, stats) = extractor.extract_streaming(&page_ids, &mut writer)?;
+            if stats.downsampled.images > 0 {
+                log.println(format!(
+                    "Downsampled {} images in chapter {} (saved {}).",
+                    stats.downsampled.images, i + 1, memory::format_size(stats.downsampled.bytes_saved)
+                ));
+            }
+            if stats.stripped_fonts > 0 {
+                log.println(format!(
+                    "Stripped {} unused fonts from chapter {} (saved {} of embedded font programs).",
+                    stats.stripped_fonts, i + 1, memory::format_size(stats.font_bytes_saved)
+                ));
+            }
+            if dropped_blank > 0 {
+                log.println(format!("Dropped {} blank pages from chapter {}.", dropped_blank, i + 1));
+            }
+            timings.copy_ms = timings::ms(phase_start.elapsed());
+            let phase_start = Instant::now();
+
+            // 削除したページ上の注釈を指す返信・ポップアップ等のリンクを修復
+            let repaired_annots = annots::repair_annotation_links(&mut split_doc);
+            if repaired_annots > 0 {
+                log.println(format!("Repaired {} annotation links in chapter {}.", repaired_annots, i + 1));
+            }
+            let threads = threads::rebuild_threads(&mut split_doc);
+            if threads.dropped_beads > 0 {
+                log.println(format!(
+                    "Rebuilt {} article threads in chapter {} (dropped {} beads on other pages).",
+                    threads.threads, i + 1, threads.dropped_beads
+                ));
+            }
+            // 構造ツリーの /ParentTree を章のページの番号だけで作り直す（アクセシビリティ検査で不整合とされないよう）
+            if let Some(parent_tree) = struct_parents::rebuild_parent_tree(&mut split_doc)
+                && parent_tree.dropped > 0
+            {
+                log.println(format!(
+                    "Renumbered {} structure parents in chapter {} (dropped {} entries for other pages).",
+                    parent_tree.entries, i + 1, parent_tree.dropped
+                ));
+            }
+            if let Some(degrees) = args.rotate {
+                let rotated = page_edit::rotate(&mut split_doc, &page_ids, &source_pages, degrees, rotate_pages.as_ref());
+                if rotated > 0 {
+                    log.println(format!("Rotated {} pages in chapter {}.", rotated, i + 1));
+                }
+            }
+            if let Some(crop) = args.crop.or(args.set_cropbox) {
+                let (cropped, skipped) = page_edit::crop(&mut split_doc, &page_ids, crop);
+                if skipped > 0 {
+                    log.eprintln(format!(
+                        "Warning: left {} pages uncropped in chapter {} (the margins are larger than the page).",
+                        skipped, i + 1
+                    ));
+                }
+                if cropped > 0 {
+                    log.println(format!("Cropped {} pages in chapter {}.", cropped, i + 1));
+                }
+            }
+            // 以降の書き込みは並べた後の用紙に行う（フッターの {source_page} は各用紙の先頭のページ）
+            let (output_pages, output_source_pages) = match nup {
+                Some(n) => {
+                    let sheets = nup::impose(&doc, &mut split_doc, &page_ids, n).context("Failed to impose pages")?;
+                    log.println(format!("Imposed {} pages onto {} sheets in chapter {}.", page_ids.len(), sheets.len(), i + 1));
+                    (sheets, source_pages.iter().step_by(n).copied().collect())
+                }
+                None => (page_ids.clone(), source_pages.clone()),
+            };
+            if let Some(footer) = &footer {
+                footer
+                    .apply(&mut split_doc, &output_pages, &output_source_pages, i + 1, &chapter.title)
+                    .context("Failed to stamp the footer")?;
+            }
+            if let Some(watermark) = &watermark {
+                watermark.apply(&mut split_doc, &output_pages, i + 1, &chapter.title).context("Failed to stamp the watermark")?;
+            }
+            // 表紙にはフッターや透かしを入れない
+            let title_page_id = match &title_page {
+                Some(title_page) => Some(
+                    title_page
+                        .insert(&mut split_doc, output_pages[0], i + 1, &chapter.title)
+                        .context("Failed to insert the title page")?,
+                ),
+                None => None,
+            };
+            let placeholder_id = match appended_pages {
+                0 => None,
+                _ => Some(
+                    placeholder_page
+                        .append(&mut split_doc, *output_pages.last().expect("chapters have pages"))
+                        .context("Failed to append the placeholder page")?,
+                ),
+            };
+            if let Some(full_outline) = &full_outline {
+                // 並べた用紙では、用紙に載せた元のページすべてをその用紙に対応させる
+                let targets: HashMap<u32, ObjectId> = match nup {
+                    Some(n) => source_pages
+                        .chunks(n)
+                        .zip(&output_pages)
+                        .flat_map(|(pages, sheet)| pages.iter().map(move |page| (*page, *sheet)))
+                        .collect(),
+                    None => source_pages.iter().copied().zip(page_ids.iter().copied()).collect(),
+                };
+                let missing = full_outline.apply(&mut split_doc, &targets, placeholder_id);
+                log.println(format!("Kept the full outline in chapter {} ({} entries point to other chapters).", i + 1, missing));
+            }
+            // Bates 番号は表紙と説明のページも含めたすべてのページに振る
+            if let Some(start) = bates_start {
+                let output_pages: Vec<ObjectId> =
+                    title_page_id.into_iter().chain(output_pages.iter().copied()).chain(placeholder_id).collect();
+                stamp::stamp_bates(&mut split_doc, &output_pages, start, bates_offsets[position])
+                    .context("Failed to stamp Bates numbers")?;
+            }
+            if pdfa_conformance.is_some() {
+                pdfa::preserve(&doc, &mut split_doc);
+            }
+            if let Some(provenance) = &provenance
+                && embed_provenance
+                && let Err(e) = provenance.embed(&mut split_doc, chapter)
+            {
+                log.eprintln(format!("Warning: could not attach provenance to chapter {}: {:#}", i + 1, e));
+            }
+            if title_metadata {
+                set_info_title(&mut split_doc, &chapter.title);
+            }
+            let mut descriptions = Vec::new();
+            if let Some(series) = &series {
+                descriptions.push(series.description(i + 1));
+            }
+            if let Some(provenance) = &provenance
+                && provenance_xmp
+            {
+                descriptions.push(provenance.xmp_description(chapter));
+            }
+            if !descriptions.is_empty() {
+                xmp::set_catalog_metadata(&mut split_doc, xmp::with_descriptions(source_xmp.as_deref(), &descriptions));
+            }
+            if let Some(version) = args.pdf_version {
+                version.apply(&mut split_doc);
+            }
+            if writer.deduplicated() > 0 {
+                log.println(format!("Deduplicated {} identical streams in chapter {}.", writer.deduplicated(), i + 1));
+            }
+            timings.fixup_ms = timings::ms(phase_start.elapsed());
+            if interrupt::is_interrupted() {
+                anyhow::bail!("interrupted");
+            }
+            let phase_start = Instant::now();
+            writer.finish(&split_doc)?;
+            timings.write_ms = timings::ms(phase_start.elapsed());
+            Ok(())
+        };
+        // ネットワーク上の共有への書き出しなどの一時的な失敗は、待ち時間を倍にしながら書き直す
+        let exhausted = |attempts: u32, e: &anyhow::Error| {
+            if attempts > 1 {
+                retries_exhausted.lock().expect("retries lock poisoned").push((
+                    position,
+                    manifest::RetriesExhausted { output: out_filename.clone(), attempts, error: format!("{:#}", e) },
+                ));
+            }
+        };
+        let on_retry = |e: &anyhow::Error, delay: Duration| {
+            log.eprintln(format!("Warning: writing {} failed ({:#}); retrying in {:.1?}.", out_filename, e, delay));
+        };
+        let (saved, attempts) = retry.run(
+            || {
+                let result = save();
+                if result.is_err() {
+                    let _ = std::fs::remove_file(&partial_path);
+                }
+                result
+            },
+            on_retry,
+        );
+        if let Err(e) = saved {
+            if interrupt::is_interrupted() {
+                log.eprintln(format!("Interrupted while writing {}; removed the partial file.", out_filename));
+                return;
+            }
+            log.eprintln(format!(
+                "Error saving {} (chapter {} '{}', source pages {}): {:?}",
+                out_filename, i + 1, chapter.title, page_ranges::format(&source_pages), e
+            ));
+            exhausted(attempts, &e);
+            failed_chapters.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let password = passwords.as_ref().and_then(|passwords| passwords.for_chapter(chapter));
+        // 暗号化する章は、暗号化と同時にリニアライズする
+        if args.linearize && password.is_none() {
+            let phase_start = Instant::now();
+            let linearized = linearize::linearize_in_place(&partial_path);
+            timings.linearize_ms = Some(timings::ms(phase_start.elapsed()));
+            if let Err(e) = linearized {
+                log.eprintln(format!("Error linearizing {}: {:?}", out_filename, e));
+                let _ = std::fs::remove_file(&partial_path);
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        let (renamed, attempts) = retry.run(|| Ok(std::fs::rename(&partial_path, &out_path)?), on_retry);
+        if let Err(e) = renamed {
+            log.eprintln(format!("Error saving {}: failed to rename {:?}: {}", out_filename, partial_path, e));
+            let _ = std::fs::remove_file(&partial_path);
+            exhausted(attempts, &e);
+            failed_chapters.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        log.println(format!(
+            "Saved: [{}/{} p.{}-p.{}] \"{}\" ({:.2?})", 
+            i + 1, total_chapters, start_page, end_page, out_filename, start_time.elapsed()
+        ));
+        if incremental {
+            log.println(format!("Appended the changes to \"{}\" as an incremental update; the original revision is unchanged.", out_filename));
+        }
+        if let Some(bates) = &bates {
+            log.println(format!("Bates numbers for \"{}\": {} - {}", out_filename, bates.first, bates.last));
+        }
+        // 章のファイルの横に書き出した付随ファイル（--output では章のファイルと一緒に送る）
+        let mut side_files: Vec<(PathBuf, &str)> = Vec::new();
+
+        if args.verify {
+            let phase_start = Instant::now();
+            let verification = verify::verify_output(&out_path, inserted_pages + appended_pages + output_page_count(page_ids.len()) as u32);
+            timings.verify_ms = Some(timings::ms(phase_start.elapsed()));
+            if verification.is_ok() {
+                log.println(format!(
+                    "Verified: \"{}\" ({} destinations point outside this chapter)",
+                    out_filename, verification.unresolved_destinations
+                ));
+            } else {
+                for problem in &verification.problems {
+                    log.eprintln(format!("Verification failed for {}: {}", out_filename, problem));
+                }
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if args.page_map {
+            let map_path = page_map::path_for(&out_path);
+            if let Err(e) = page_map::write(&map_path, input_path, out_filename, &source_pages, inserted_pages, page_labels.as_ref()) {
+                log.eprintln(format!("Error writing page map for {}: {:#}", out_filename, e));
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+            }
+            side_files.push((map_path, "application/json"));
+        }
+        if let Some(renderer) = renderer {
+            let phase_start = Instant::now();
+            let png_path = out_path.with_extension(thumbnails::EXTENSION);
+            if let Err(e) = renderer.render_first_page(&out_path, &png_path, args.password.as_deref()) {
+                log.eprintln(format!("Error rendering thumbnail for {}: {:#}", out_filename, e));
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+            }
+            side_files.push((png_path, "image/png"));
+            timings.thumbnail_ms = Some(timings::ms(phase_start.elapsed()));
+        }
+        let mut word_count = None;
+        if extract_text {
+            let phase_start = Instant::now();
+            let (text, unreadable) = text::extract_pages(&doc, &page_ids);
+            word_count = Some(text.split_whitespace().count());
+            let text_path = out_path.with_extension(text::EXTENSION);
+            if unreadable > 0 {
+                log.eprintln(format!("Warning: could not extract text from {} pages of chapter {}.", unreadable, i + 1));
+            }
+            if let Err(e) = std::fs::write(&text_path, text) {
+                log.eprintln(format!("Error writing {:?}: {}", text_path, e));
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+            }
+            side_files.push((text_path, "text/plain; charset=utf-8"));
+            timings.extract_text_ms = Some(timings::ms(phase_start.elapsed()));
+        }
+        if let Some(format) = args.sidecar {
+            let info = sidecar::ChapterInfo {
+                chapter: i + 1,
+                title: &chapter.title,
+                source: input_path,
+                output: out_filename,
+                source_pages: &source_pages,
+                labels: page_labels.as_ref(),
+                word_count,
+            };
+            let sidecar_path = sidecar::path_for(&out_path, format);
+            if let Err(e) = sidecar::write(&sidecar_path, format, &info) {
+                log.eprintln(format!("Error writing sidecar for {}: {:#}", out_filename, e));
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+            }
+            side_files.push((sidecar_path, "application/yaml"));
+        }
+        // 検証やサムネイルは出力を読むため、暗号化は最後に行う。失敗したら暗号化されていない出力を残さない
+        let mut written = true;
+        if let Some(password) = password {
+            let phase_start = Instant::now();
+            let encrypted = encrypt::encrypt_in_place(&out_path, password, encrypt::Cipher::Aes256, args.password.as_deref(), args.linearize);
+            timings.encrypt_ms = Some(timings::ms(phase_start.elapsed()));
+            if let Err(e) = encrypted {
+                log.eprintln(format!("Error encrypting {}: {:?}", out_filename, e));
+                let _ = std::fs::remove_file(&out_path);
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+                written = false;
+            }
+        }
+        // --output では書き出したファイルは一時ディレクトリにあるため、送れたかどうかにかかわらず消す
+        // （送れなかった章は失敗に数え、保存した章にも含めない）
+        let bytes = std::fs::metadata(&out_path).map(|m| m.len()).unwrap_or(0);
+        if let Some(sink) = sink
+            && written
+        {
+            let uploaded = std::iter::once((&out_path, "application/pdf"))
+                .chain(side_files.iter().filter(|(path, _)| path.exists()).map(|(path, content_type)| (path, *content_type)))
+                .try_for_each(|(path, content_type)| {
+                    let url = sink.upload_file(path, content_type)?;
+                    log.println(format!("Uploaded: {:?} to {}", path.file_name().unwrap_or_default(), url));
+                    anyhow::Ok(())
+                });
+            if let Err(e) = uploaded {
+                log.eprintln(format!("Error uploading {}: {:#}", out_filename, e));
+                failed_chapters.fetch_add(1, Ordering::Relaxed);
+                written = false;
+            }
+        }
+        if sink.is_some() {
+            for path in std::iter::once(&out_path).chain(side_files.iter().map(|(path, _)| path)) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        if written {
+            saved_outputs.lock().expect("outputs lock poisoned").push((
+                position,
+                SavedOutput { path: out_path.clone(), bytes, bates: bates.clone(), excluded_pages: excluded_pages.clone() },
+            ));
+        }
+        timings.total_ms = timings::ms(start_time.elapsed());
+        log.debug(format!("Chapter {}: {:?}", i + 1, timings));
+        chapter_timings.lock().expect("timings lock poisoned").push(timings);
+    };
+
+    let chapters_start = Instant::now();
+    plan.par_iter().enumerate().for_each(process_chapter);
+    progress.finish(&pages_bar);
+    let chapters_ms = timings::ms(chapters_start.elapsed());
+
+    file_timings.chapters = chapter_timings.into_inner().expect("timings lock poisoned");
+    file_timings.chapters.sort_by_key(|c| c.chapter);
+    file_timings.total_ms = timings::ms(load_start.elapsed());
+    if let Some(format) = args.timings {
+        progress.println(file_timings.render(format));
+    }
+
+    let failed = failed_chapters.into_inner();
+    let mut saved_outputs = saved_outputs.into_inner().expect("outputs lock poisoned");
+    saved_outputs.sort_by_key(|(position, _)| *position);
+    if interrupt::is_interrupted() {
+        let written: HashSet<usize> = saved_outputs.iter().map(|(position, _)| *position).collect();
+        // --output では、書き出した章は送ったオブジェクトの URL で示す
+        let completed = saved_outputs.iter().map(|(_, output)| match sink {
+            Some(sink) => sink.destination().object_url(&output.path.file_name().unwrap_or_default().to_string_lossy()),
+            None => output.path.display().to_string(),
+        });
+        let interrupted = manifest::InterruptedRun {
+            input: input_path.display().to_string(),
+            completed: completed.collect(),
+            not_written: plan
+                .iter()
+                .enumerate()
+                .filter(|(position, _)| !written.contains(position))
+                .map(|(_, chapter)| chapter.filename.clone())
+                .collect(),
+        };
+        let manifest_name = format!("{}_interrupted.json", name_stem);
+        // --output の一時ディレクトリは消すため、結果の JSON も送る
+        let manifest_location = match sink {
+            Some(sink) => {
+                let json = serde_json::to_string_pretty(&interrupted)? + "\n";
+                sink.put(&manifest_name, json.as_bytes(), "application/json")?
+            }
+            None => {
+                let manifest_path = output_names.path(parent_dir, &manifest_name);
+                interrupted.write(&manifest_path)?;
+                format!("{:?}", manifest_path)
+            }
+        };
+        anyhow::bail!(
+            "interrupted after writing {} of {} chapters; the completed files are listed in {}",
+            saved_outputs.len(), plan.len(), manifest_location
+        );
+    }
+    if let Some(metrics) = metrics {
+        let bytes_out = saved_outputs.iter().map(|(_, output)| output.bytes).sum();
+        metrics.record_file(&file_timings, saved_outputs.len() as u64, failed as u64, bytes_out);
+    }
+    if failed > 0 {
+        let mut retries_exhausted = retries_exhausted.into_inner().expect("retries lock poisoned");
+        retries_exhausted.sort_by_key(|(position, _)| *position);
+        return Err(manifest::ChaptersFailed {
+            failed,
+            total: total_chapters,
+            retries_exhausted: retries_exhausted.into_iter().map(|(_, entry)| entry).collect(),
+        }
+        .into());
+    }
+    if args.verify_roundtrip {
+        let expected: Vec<roundtrip::ExpectedOutput> = saved_outputs
+            .iter()
+            .map(|(position, output)| roundtrip::ExpectedOutput {
+                path: &output.path,
+                inserted_pages,
+                appended_pages: appended_pages(&chapter_pages[*position].kept),
+                source_pages: chapter_pages[*position].kept.iter().map(|(number, _)| *number).collect(),
+            })
+            .collect();
+        let ranges: Vec<(u32, u32)> = plan.iter().map(|chapter| (chapter.start_page, chapter.end_page)).collect();
+        let roundtrip = roundtrip::verify(&doc, &page_numbers, &ranges, &expected);
+        if !roundtrip.uncovered.is_empty() {
+            progress.println(format!(
+                "Roundtrip: pages {} are not part of any chapter.",
+                page_ranges::format(&roundtrip.uncovered)
+            ));
+        }
+        if !roundtrip.is_ok() {
+            for problem in &roundtrip.problems {
+                progress.eprintln(format!("Roundtrip verification failed: {}", problem));
+            }
+            anyhow::bail!("roundtrip verification failed for {:?}", input_path);
+        }
+        progress.println(format!(
+            "Roundtrip verified: {} pages in {} outputs match the source.",
+            roundtrip.matched_pages,
+            saved_outputs.len()
+        ));
+    }
+    if let Some(catalog_path) = &args.catalog {
+        let source = catalog::SourceEntry {
+            file_name: input_path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            title: info_string(&doc, &decryptor, b"Title"),
+            author: info_string(&doc, &decryptor, b"Author"),
+            subject: info_string(&doc, &decryptor, b"Subject"),
+            page_count: total_pages,
+            size: std::fs::metadata(input_path)?.len(),
+        };
+        let chapters: Vec<(&PlannedChapter, &Path)> =
+            saved_outputs.iter().map(|(position, output)| (&plan[*position], output.path.as_path())).collect();
+        catalog::write(catalog_path, source, &chapters)?;
+        progress.println(format!("Wrote catalog to {:?}.", catalog_path));
+    }
+    let summary = manifest::Summary {
+        inputs: 1,
+        chapters: saved_outputs.len(),
+        pages: saved_outputs.iter().map(|(position, _)| chapter_pages[*position].kept.len() as u64).sum(),
+        input_bytes: std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0),
+        output_bytes: saved_outputs.iter().map(|(_, output)| output.bytes).sum(),
+        skipped_bookmarks: skipped_bookmarks.len(),
+        warnings: progress.warnings() - warnings_at_start,
+        load_ms: file_timings.load_ms,
+        analysis_ms: file_timings.analysis_ms + file_timings.outline_scan_ms,
+        chapters_ms,
+        total_ms: timings::ms(load_start.elapsed()),
+    };
+    let outputs: Vec<SavedOutput> = saved_outputs.into_iter().map(|(_, output)| output).collect();
+    Ok(FileOutcome { outputs, skipped_bookmarks, summary: Some(summary) })
+}
+
+/// ページツリーと目次を走査して、ページの対応表と章の開始ページを集める
+fn analyze(
+    doc: &Document,
+    decryptor: &StringDecryptor,
+    fallback: DestFallback,
+    levels: OutlineLevels,
+    progress: &Progress,
+    file_timings: &mut FileTimings,
+    analysis_start: Instant,
+) -> cache::Analysis {
+    // 1. ページIDとページ番号の対応表
+    let (page_numbers, page_tree_warnings) = pagetree::collect_pages(doc);
+    for warning in &page_tree_warnings {
+        progress.eprintln(format!("Warning: {}", warning));
+    }
+    let object_id_to_page: BTreeMap<_, _> = page_numbers
+        .iter()
+        .map(|(num, id)| (*id, *num))
+        .collect();
+
+    // 2. 名前付き宛先（目次が使う名前だけを必要になった時点で引く）
+    let named_dests = NamedDests::new(doc, decryptor);
+
+    file_timings.analysis_ms = timings::ms(analysis_start.elapsed());
+
+    // 3. 目次スキャン
+    let scan_start = Instant::now();
+    let mut chapter_starts = Vec::new();
+    let mut skipped_bookmarks = Vec::new();
+
+    if let Ok(catalog_ref) = doc.trailer.get(b"Root").and_then(|o| o.as_reference())
+        && let Ok(catalog) = doc.get_object(catalog_ref).and_then(|o| o.as_dict())
+    {
+        let outlines_opt = if let Ok(obj) = catalog.get(b"Outlines") {
+             if let Ok(real_obj) = resolve_object(doc, obj) {
+                 real_obj.as_dict().ok()
+             } else { None }
+        } else { None };
+
+        if let Some(outlines) = outlines_opt {
+            if levels.depth > 1 {
+                progress.println(format!("Scanning Outlines (down to level {})...", levels.depth));
+            } else {
+                progress.println("Scanning Outlines (Top-level only)...");
+            }
+            if let Some(first_ref) = outlines.get(b"First").ok().and_then(|o| o.as_reference().ok()) {
+                 let ctx = OutlineContext {
+                     doc,
+                     object_id_to_page: &object_id_to_page,
+                     named_dests: &named_dests,
+                     decryptor,
+                     fallback,
+                     progress,
+                 };
+                 let mut outline = OutlineWalk { levels, parents: Vec::new(), visited: HashSet::new() };
+                 collect_bookmarks(&ctx, first_ref, &mut outline, None, &mut chapter_starts, &mut skipped_bookmarks);
+                 if named_dests.resolved() > 0 {
+                     progress.println(format!("Resolved {} named destinations.", named_dests.resolved()));
+                 }
+            }
+        } else {
+            progress.println("PDF has no Outlines dictionary.");
+        }
+    }
+
+    file_timings.outline_scan_ms = timings::ms(scan_start.elapsed());
+    file_timings.named_dests_ms = timings::ms(named_dests.lookup_time());
+
+    cache::Analysis { page_numbers, chapter_starts, page_tree_warnings, skipped_bookmarks }
+}
+
+fn chapter_page_ids(page_numbers: &BTreeMap<u32, ObjectId>, chapter: &PlannedChapter) -> Vec<ObjectId> {
+    page_numbers.range(chapter.start_page..=chapter.end_page).map(|(_, id)| *id).collect()
+}
+
+/// `--dry-run`: 各章の出力サイズを、ページから到達できるオブジェクトのサイズの合計で見積もる（戻り値は合計）
+fn print_dry_run(
+    plan: &[PlannedChapter],
+    total_chapters: usize,
+    extractor: &extract::PageExtractor,
+    page_numbers: &BTreeMap<u32, ObjectId>,
+    progress: &Progress,
+) -> u64 {
+    progress.println(format!("Found {} chapters (dry run, nothing is written).", total_chapters));
+    let mut total = 0;
+    for chapter in plan {
+        let estimate = extractor.estimate_size(&chapter_page_ids(page_numbers, chapter));
+        total += estimate;
+        progress.println(format!(
+            "Plan: [{}/{} p.{}-p.{}] \"{}\" (~{})",
+            chapter.index + 1, total_chapters, chapter.start_page, chapter.end_page, chapter.filename,
+            memory::format_size(estimate)
+        ));
+    }
+    progress.println(format!("Estimated total output size: ~{}", memory::format_size(total)));
+    total
+}
+
+fn load_document(input_path: &Path, use_mmap: bool) -> Result<Document> {
+    let file = File::open(input_path).with_context(|| format!("Failed to open file: {:?}", input_path))?;
+    let size = file.metadata().with_context(|| format!("Failed to open file: {:?}", input_path))?.len();
+    if size > xref64::LOPDF_MAX_OFFSET {
+        // 4 GiB を超えるファイルは、lopdf の u32 のオフセットでは読めないため自前の相互参照の読み込みを使う
+        // SAFETY: 読み込み中に他のプロセスが入力ファイルを書き換えないことを前提とする
+        let mmap = unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to map file: {:?}", input_path))?;
+        return xref64::load(&mmap).with_context(|| format!("Failed to load PDF: {:?}", input_path));
+    }
+    let doc = if use_mmap {
+        // SAFETY: 読み込み中に他のプロセスが入力ファイルを書き換えないことを前提とする
+        let mmap = unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to map file: {:?}", input_path))?;
+        Document::load_mem(&mmap)
+    } else {
+        // 高速化: BufReaderを使用
+        Document::load_from(BufReader::new(file))
+    };
+    doc.with_context(|| format!("Failed to load PDF: {:?}", input_path))
+}
+
+/// 文書全体を `output_path` に書き出す（完成するまでは別名で書き、失敗したら書きかけを消す）
+fn save_document(doc: &Document, output_path: &Path) -> Result<()> {
+    let partial_path = interrupt::partial_path(output_path);
+    let save = || -> Result<()> {
+        let file = File::create(&partial_path)?;
+        StreamingWriter::new(BufWriter::new(file), &doc.version)?.finish(doc)?;
+        Ok(())
+    };
+    if let Err(e) = save() {
+        let _ = std::fs::remove_file(&partial_path);
+        return Err(e.context(format!("Failed to write {:?}", output_path)));
+    }
+    std::fs::rename(&partial_path, output_path).with_context(|| format!("Failed to rename {:?} to {:?}", partial_path, output_path))
+}
+
+/// 分割の章と同じページのコピーで、`page_ids` のページ（この順に並べる）とそこから到達できるオブジェクトだけを `output_path` に書き出す
+///
+/// どのページからも使われなくなったオブジェクトは書き出さない。`input_path` は無変更のストリームをそのままコピーするための元ファイル。
+fn save_pages(doc: &Arc<Document>, input_path: &Path, page_ids: &[ObjectId], output_path: &Path, progress: &Progress) -> Result<()> {
+    let (page_numbers, _) = pagetree::collect_pages(doc);
+    let mut extractor = extract::PageExtractor::new(Arc::clone(doc), page_numbers.values().copied());
+    match raw_copy::RawSource::open(input_path) {
+        Ok(raw) => extractor = extractor.with_raw_source(raw),
+        Err(e) => progress.eprintln(format!("Warning: raw stream copy disabled: {}", e)),
+    }
+    let partial_path = interrupt::partial_path(output_path);
+    let save = || -> Result<()> {
+        let file = File::create(&partial_path)?;
+        let mut writer = StreamingWriter::new(BufWriter::new(file), &doc.version)?;
+        let (extracted, _) = extractor.extract_streaming(page_ids, &mut writer)?;
+        writer.finish(&extracted)?;
+        Ok(())
+    };
+    if let Err(e) = save() {
+        let _ = std::fs::remove_file(&partial_path);
+        return Err(e.context(format!("Failed to write {:?}", output_path)));
+    }
+    std::fs::rename(&partial_path, output_path).with_context(|| format!("Failed to rename {:?} to {:?}", partial_path, output_path))
+}
+
+fn resolve_object<'a>(doc: &'a Document, obj: &'a Object) -> Result<&'a Object, lopdf::Error> {
+    match obj {
+        Object::Reference(id) => doc.get_object(*id),
+        _ => Ok(obj),
+    }
+}
+
+/// 目次スキャン中に共有する参照情報
+struct OutlineContext<'a> {
+    doc: &'a Document,
+    object_id_to_page: &'a BTreeMap<ObjectId, u32>,
+    named_dests: &'a NamedDests<'a>,
+    decryptor: &'a StringDecryptor,
+    fallback: DestFallback,
+    progress: &'a Progress,
+}
+
+/// 章にする目次項目の階層と、章のタイトルの付け方（`--depth`・`--title-path`）
+#[derive(Clone, Copy, Debug)]
+struct OutlineLevels<'a> {
+    depth: u32,
+    /// 上の階層のタイトルをつなぐ区切り（`--title-path` でなければ `None`）
+    path_sep: Option<&'a str>,
+}
+
+impl OutlineLevels<'_> {
+    /// 解析結果のキャッシュの識別に加える設定（既定の設定では空にし、これまでのキャッシュを使えるようにする）
+    fn cache_key(&self) -> String {
+        match (self.depth, self.path_sep) {
+            (1, _) => String::new(),
+            (depth, sep) => format!("-depth{}-{:?}", depth, sep),
+        }
+    }
+}
+
+/// 目次をたどる間の状態
+struct OutlineWalk<'a> {
+    levels: OutlineLevels<'a>,
+    /// 今たどっている項目の上の階層の項目のタイトル（外側から順に）
+    parents: Vec<String>,
+    /// 循環した /Next や /First を二度たどらないよう、たどった項目
+    visited: HashSet<ObjectId>,
+}
+
+/// `start_id` から /Next でつながる項目を、`--depth` の階層まで /First の子もたどって章の開始ページにする
+///
+/// `parent_slot` は、親の項目を章にした `results` の位置。親と同じページを指す最初の子は、親の代わりに章にする
+/// （親の章が空にならず、`--title-path` では子のタイトルに親のタイトルも入る）。
+fn collect_bookmarks(
+    ctx: &OutlineContext,
+    start_id: ObjectId,
+    outline: &mut OutlineWalk,
+    mut parent_slot: Option<usize>,
+    results: &mut Vec<(u32, String)>,
+    skipped: &mut Vec<SkippedBookmark>
+) {
+    let OutlineContext { doc, decryptor, fallback, progress, .. } = *ctx;
+    let mut current_id_opt = Some(start_id);
+    while let Some(id) = current_id_opt {
+        if !outline.visited.insert(id) {
+            break;
+        }
+        if let Ok(item) = doc.get_object(id).and_then(|o| o.as_dict()) {
+            let own_title = outline_title(doc, decryptor, id, item);
+            let title = match outline.levels.path_sep {
+                Some(sep) if !outline.parents.is_empty() => {
+                    outline.parents.iter().map(String::as_str).chain([own_title.as_str()]).collect::<Vec<_>>().join(sep)
+                }
+                _ => own_title.clone(),
+            };
+
+            let mut target_page_num = resolve_outline_item(ctx, id, item, DestFallback::Skip);
+            if target_page_num.is_none() && fallback == DestFallback::Nearest {
+                target_page_num = resolve_outline_item(ctx, id, item, DestFallback::Nearest);
+                if let Some(page_num) = target_page_num {
+                    progress.eprintln(format!("Warning: '{}' points to a page outside the page tree; snapped to p.{}", title, page_num));
+                }
+            }
+            let mut slot = None;
+            if let Some(page_num) = target_page_num {
+                match parent_slot {
+                    Some(parent) if parent + 1 == results.len() && results[parent].0 == page_num => {
+                        results[parent] = (page_num, title);
+                        slot = Some(parent);
+                    }
+                    _ => {
+                        results.push((page_num, title));
+                        slot = Some(results.len() - 1);
+                    }
+                }
+            } else {
+                let reason = format!("{} (outline item {} {} R)", unresolved_reason(ctx, id, item), id.0, id.1);
+                skipped.push(SkippedBookmark { title, reason });
+            }
+            parent_slot = None;
+
+            if outline.parents.len() + 1 < outline.levels.depth as usize
+                && let Ok(child) = item.get(b"First").and_then(|o| o.as_reference())
+            {
+                outline.parents.push(own_title);
+                collect_bookmarks(ctx, child, outline, slot, results, skipped);
+                outline.parents.pop();
+            }
+
+            current_id_opt = item.get(b"Next")
+                .ok()
+                .and_then(|o| o.as_reference().ok());
+        } else {
+            break;
+        }
+    }
+}
+
+/// 目次項目の宛先を解決できなかった理由（`resolve_outline_item` と同じ順に /Dest、GoTo アクションの /D を調べる）
+fn unresolved_reason(ctx: &OutlineContext, id: ObjectId, item: &lopdf::Dictionary) -> String {
+    let OutlineContext { doc, decryptor, .. } = *ctx;
+    if let Ok(dest) = item.get(b"Dest") {
+        return dest_reason(ctx, &decryptor.decrypt_dest(doc, id, dest));
+    }
+    let Ok(action_obj) = item.get(b"A") else { return "it has neither /Dest nor /A".to_string() };
+    let Ok(action) = resolve_object(doc, action_obj).and_then(|o| o.as_dict()) else {
+        return "its /A action is not a dictionary".to_string();
+    };
+    match action.get(b"S").and_then(|o| o.as_name_str()) {
+        Ok("GoTo") => {}
+        Ok(kind) => return format!("its action is /{}, not /GoTo", kind),
+        Err(_) => return "its action has no /S type".to_string(),
+    }
+    let Ok(d) = action.get(b"D") else { return "its GoTo action has no /D".to_string() };
+    let container = action_obj.as_reference().unwrap_or(id);
+    dest_reason(ctx, &decryptor.decrypt_dest(doc, container, d))
+}
+
+fn dest_reason(ctx: &OutlineContext, dest: &Object) -> String {
+    let OutlineContext { doc, object_id_to_page, named_dests, .. } = *ctx;
+    let Ok(dest) = resolve_object(doc, dest) else { return "its destination object is missing".to_string() };
+    let target = match dest {
+        Object::Array(_) => dest.clone(),
+        Object::String(name, _) | Object::Name(name) => match named_dests.get(name) {
+            Some(target) => target,
+            None => return format!("named destination {:?} is not defined", String::from_utf8_lossy(name)),
+        },
+        _ => return "its destination is neither an array nor a name".to_string(),
+    };
+    let array = match resolve_object(doc, &target) {
+        Ok(Object::Array(array)) => Some(array),
+        Ok(Object::Dictionary(dict)) => dict.get(b"D").and_then(|d| resolve_object(doc, d)).and_then(|d| d.as_array()).ok(),
+        _ => None,
+    };
+    match array.and_then(|array| array.first()) {
+        Some(Object::Reference(page_id)) if !object_id_to_page.contains_key(page_id) => {
+            format!("its destination page {} {} R is not in the page tree", page_id.0, page_id.1)
+        }
+        Some(Object::Reference(_)) => "its destination could not be resolved".to_string(),
+        Some(Object::Integer(page)) => format!("its destination is page index {} of another document", page),
+        _ => "its destination does not name a page".to_string(),
+    }
+}
+
+/// 目次項目のタイトル（間接参照で置かれたタイトルは参照先の鍵で復号する）
+fn outline_title(doc: &Document, decryptor: &StringDecryptor, id: ObjectId, item: &lopdf::Dictionary) -> String {
+    let Ok(title_obj) = item.get(b"Title") else { return "No Title".to_string() };
+    let container = title_obj.as_reference().unwrap_or(id);
+    resolve_object(doc, title_obj)
+        .and_then(|o| o.as_str())
+        .map(|bytes| decode_pdf_string(&decryptor.decrypt(container, bytes)))
+        .unwrap_or_else(|_| "No Title".to_string())
+}
+
+/// 目次項目の /Dest または GoTo アクションの宛先をページ番号に変換する
+fn resolve_outline_item(ctx: &OutlineContext, id: ObjectId, item: &lopdf::Dictionary, fallback: DestFallback) -> Option<u32> {
+    let OutlineContext { doc, object_id_to_page, named_dests, decryptor, .. } = *ctx;
+    let mut target_page_num = None;
+    if let Ok(dest) = item.get(b"Dest") {
+        let dest = decryptor.decrypt_dest(doc, id, dest);
+        target_page_num = resolve_dest(doc, &dest, object_id_to_page, named_dests, fallback);
+    }
+    if target_page_num.is_none()
+        && let Ok(action_obj) = item.get(b"A")
+        && let Ok(action) = resolve_object(doc, action_obj).and_then(|o| o.as_dict())
+    {
+        let is_goto = action.get(b"S")
+            .ok()
+            .and_then(|o| o.as_name_str().ok())
+            .map(|s| s == "GoTo")
+            .unwrap_or(false);
+        if is_goto && let Ok(d) = action.get(b"D") {
+            let container = action_obj.as_reference().unwrap_or(id);
+            let d = decryptor.decrypt_dest(doc, container, d);
+            target_page_num = resolve_dest(doc, &d, object_id_to_page, named_dests, fallback);
+        }
+    }
+    target_page_num
+}
+
+fn resolve_dest(
+    doc: &Document,
+    dest_obj: &Object, 
+    page_map: &BTreeMap<ObjectId, u32>,
+    named_dests: &NamedDests,
+    fallback: DestFallback,
+) -> Option<u32> {
+    let real_dest = match resolve_object(doc, dest_obj) {
+        Ok(o) => o, Err(_) => return None,
+    };
+    if let Ok(arr) = real_dest.as_array() {
+        if let Some(Ok(page_ref)) = arr.first().map(|o| o.as_reference()) {
+            return lookup_page(page_map, page_ref, fallback);
+        }
+        return None;
+    }
+    let key = match real_dest {
+        Object::String(bytes, _) => Some(bytes.clone()),
+        Object::Name(bytes) => Some(bytes.clone()),
+        _ => None,
+    };
+    if let Some(k) = key
+        && let Some(target_obj) = named_dests.get(&k)
+        && let Ok(resolved_target) = resolve_object(doc, &target_obj)
+    {
+        if let Ok(arr) = resolved_target.as_array()
+            && let Some(Ok(page_ref)) = arr.first().map(|o| o.as_reference())
+        {
+            return lookup_page(page_map, page_ref, fallback);
+        }
+        if let Ok(dict) = resolved_target.as_dict()
+            && let Ok(inner_d) = dict.get(b"D")
+            && let Ok(inner_arr_obj) = resolve_object(doc, inner_d)
+            && let Ok(inner_arr) = inner_arr_obj.as_array()
+            && let Some(Ok(page_ref)) = inner_arr.first().map(|o| o.as_reference())
+        {
+            return lookup_page(page_map, page_ref, fallback);
+        }
+    }
+    None
+}
+
+/// ページ参照をページ番号に変換する。見つからない場合は `fallback` に従う
+fn lookup_page(page_map: &BTreeMap<ObjectId, u32>, page_ref: ObjectId, fallback: DestFallback) -> Option<u32> {
+    if let Some(page_num) = page_map.get(&page_ref) {
+        return Some(*page_num);
+    }
+    match fallback {
+        DestFallback::Skip => None,
+        DestFallback::Nearest => {
+            // オブジェクト番号順で前後に最も近いページを選ぶ
+            let before = page_map.range(..page_ref).next_back();
+            let after = page_map.range(page_ref..).next();
+            match (before, after) {
+                (Some((b_id, b)), Some((a_id, a))) => {
+                    if page_ref.0 - b_id.0 <= a_id.0 - page_ref.0 { Some(*b) } else { Some(*a) }
+                }
+                (Some((_, p)), None) | (None, Some((_, p))) => Some(*p),
+                (None, None) => None,
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::memory::format_size;
+use anyhow::{Result, bail};
+use std::path::Path;
+
+/// 出力先の空き容量が推定出力サイズに足りるか確認する
+///
+/// 途中でディスクが一杯になると、書きかけのファイルが残ったまま失敗するため事前に止める。
+/// `force` の場合は警告文を返して続行させる。空き容量を取得できない場合は確認しない。
+pub fn check_free_space(dir: &Path, required: u64, force: bool) -> Result<Option<String>> {
+    let Ok(available) = fs2::available_space(dir) else { return Ok(None) };
+    if available >= required {
+        return Ok(None);
+    }
+    let message = format!(
+        "estimated output size is ~{}, but only {} is free in {:?}",
+        format_size(required),
+        format_size(available),
+        dir
+    );
+    if !force {
+        bail!("{} (use --force to split anyway)", message);
+    }
+    Ok(Some(message))
+}
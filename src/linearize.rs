@@ -0,0 +1,36 @@
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::process::Command;
+
+// lopdf にはリニアライズ用のライタが無いため、保存後に qpdf で後処理する
+pub const QPDF: &str = "qpdf";
+
+/// qpdf が利用可能か事前に確認する（全章の処理後に失敗が発覚するのを避ける）。`option` はエラーに出すオプション名
+pub fn check_available(option: &str) -> Result<()> {
+    Command::new(QPDF)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("{} requires `{}` on PATH", option, QPDF))?;
+    Ok(())
+}
+
+/// 保存済みのPDFをその場でリニアライズ（Web表示用に最適化）する
+pub fn linearize_in_place(path: &Path) -> Result<()> {
+    let output = Command::new(QPDF)
+        .arg("--linearize")
+        .arg("--replace-input")
+        .arg(path)
+        .output()
+        .with_context(|| format!("Failed to run {} on {:?}", QPDF, path))?;
+
+    // 終了コード 3 は警告のみ（出力は有効）
+    match output.status.code() {
+        Some(0) | Some(3) => Ok(()),
+        _ => bail!(
+            "{} --linearize failed for {:?}: {}",
+            QPDF,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
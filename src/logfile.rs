@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// `--log-file` の形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// `2026-01-02T03:04:05.678Z INFO メッセージ` の1行ずつ
+    Text,
+    /// `{"time": ..., "level": ..., "message": ...}` の JSON Lines
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// `--log-file`: 画面の表示とは別に、デバッグ用の行も含めてすべてのログを時刻付きで書き残す
+///
+/// 夜間の一括処理が失敗したときに後から調べられるよう、1行ごとにフラッシュする。
+pub struct LogFile {
+    writer: BufWriter<File>,
+    format: LogFormat,
+}
+
+impl LogFile {
+    pub fn create(path: &Path, format: LogFormat) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create log file {:?}", path))?;
+        Ok(Self { writer: BufWriter::new(file), format })
+    }
+
+    pub fn write(&mut self, level: Level, message: &str) {
+        let time = crate::provenance::utc_timestamp(SystemTime::now());
+        let line = match self.format {
+            LogFormat::Text => format!("{} {:5} {}", time, level.as_str().to_uppercase(), message),
+            LogFormat::Json => json!({ "time": time, "level": level.as_str(), "message": message }).to_string(),
+        };
+        // ログを書けなくても処理は続ける
+        let _ = writeln!(self.writer, "{}", line).and_then(|()| self.writer.flush());
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 1つの入力の処理結果（`jobs` の結果の行や `--notify-url` の通知に使う）
+#[derive(Debug, Serialize)]
+pub struct RunManifest {
+    pub input: String,
+    pub ok: bool,
+    /// 保存した章のファイル（章の順）
+    pub outputs: Vec<String>,
+    /// `--bates-start` で各ファイルに振った Bates 番号の範囲
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bates: Vec<BatesEntry>,
+    /// `--exclude-pages` で各ファイルから除いた元の文書のページ
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub excluded_pages: Vec<ExcludedPagesEntry>,
+    /// 宛先を解決できずに章にしなかった目次項目
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_bookmarks: Vec<SkippedBookmark>,
+    /// 一時的な入出力の失敗で書き直しても書き出せなかった章（`--io-retries`）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub retries_exhausted: Vec<RetriesExhausted>,
+    /// 書き出した章・ページ・バイト数などの集計（`--dry-run` と失敗した場合は無し）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: f64,
+}
+
+/// 中断したときに書き出す、途中までの結果（`<入力名>_interrupted.json`）
+#[derive(Debug, Serialize)]
+pub struct InterruptedRun {
+    pub input: String,
+    /// 書き終えた章のファイル
+    pub completed: Vec<String>,
+    /// 書き出さなかった（途中で打ち切った）章のファイル名
+    pub not_written: Vec<String>,
+}
+
+impl InterruptedRun {
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").with_context(|| format!("Failed to write {:?}", path))
+    }
+}
+
+/// 一時的な入出力の失敗で書き直しても書き出せなかった章
+#[derive(Debug, Clone, Serialize)]
+pub struct RetriesExhausted {
+    pub output: String,
+    /// 書き出しを試した回数（最初の1回を含む）
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// 書き出せなかった章があったときのエラー（やり直しを使い切った章を結果の JSON に載せるため、型で区別する）
+#[derive(Debug)]
+pub struct ChaptersFailed {
+    pub failed: usize,
+    pub total: usize,
+    pub retries_exhausted: Vec<RetriesExhausted>,
+}
+
+impl std::fmt::Display for ChaptersFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} chapters failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for ChaptersFailed {}
+
+/// 1つの入力を分割した結果
+#[derive(Debug, Default)]
+pub struct FileOutcome {
+    /// 保存した章のファイル（章の順、`--dry-run` では空）
+    pub outputs: Vec<SavedOutput>,
+    pub skipped_bookmarks: Vec<SkippedBookmark>,
+    /// 章を書き出した場合の集計
+    pub summary: Option<Summary>,
+}
+
+/// 処理の集計（終了時に表示し、結果の JSON にも載せる）。複数の入力では `add` で足し合わせる
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Summary {
+    pub inputs: usize,
+    /// 書き出した章のファイルの数
+    pub chapters: usize,
+    /// 書き出した元の文書のページ数（表紙など挿入したページは含まない）
+    pub pages: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub skipped_bookmarks: usize,
+    /// 処理中に出した警告の数（並列に処理しているほかの入力の警告が含まれることがある）
+    pub warnings: usize,
+    pub load_ms: f64,
+    /// 復号の準備・ページツリーと目次の走査
+    pub analysis_ms: f64,
+    /// 全章の書き出し（章は並列に処理するため、章ごとの時間の合計ではない）
+    pub chapters_ms: f64,
+    pub total_ms: f64,
+}
+
+impl Summary {
+    pub fn add(&mut self, other: &Summary) {
+        self.inputs += other.inputs;
+        self.chapters += other.chapters;
+        self.pages += other.pages;
+        self.input_bytes += other.input_bytes;
+        self.output_bytes += other.output_bytes;
+        self.skipped_bookmarks += other.skipped_bookmarks;
+        self.warnings += other.warnings;
+        self.load_ms += other.load_ms;
+        self.analysis_ms += other.analysis_ms;
+        self.chapters_ms += other.chapters_ms;
+        self.total_ms += other.total_ms;
+    }
+
+    /// 終了時に表示する要約
+    pub fn render(&self) -> String {
+        let ratio = if self.input_bytes > 0 { self.output_bytes as f64 * 100.0 / self.input_bytes as f64 } else { 0.0 };
+        format!(
+            "Summary: {} chapters ({} pages) from {} input(s); wrote {} from {} ({:.0}%); {} skipped bookmarks; {} warnings\n\
+             Phases: load {:.2} ms, analysis {:.2} ms, chapters {:.2} ms, total {:.2} ms",
+            self.chapters,
+            self.pages,
+            self.inputs,
+            crate::memory::format_size(self.output_bytes),
+            crate::memory::format_size(self.input_bytes),
+            ratio,
+            self.skipped_bookmarks,
+            self.warnings,
+            self.load_ms,
+            self.analysis_ms,
+            self.chapters_ms,
+            self.total_ms
+        )
+    }
+}
+
+/// 宛先を解決できなかった目次項目と、その理由（`--report` と結果の JSON に載せる）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedBookmark {
+    pub title: String,
+    pub reason: String,
+}
+
+/// 保存した章のファイル1つ
+#[derive(Debug, Clone)]
+pub struct SavedOutput {
+    pub path: PathBuf,
+    /// 書き出したファイルのバイト数（`--output` では送った後に手元のファイルを消すため、消す前に数える）
+    pub bytes: u64,
+    pub bates: Option<BatesRange>,
+    pub excluded_pages: Vec<u32>,
+}
+
+/// ファイルの先頭ページと最後のページの Bates 番号
+#[derive(Debug, Clone)]
+pub struct BatesRange {
+    pub first: String,
+    pub last: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatesEntry {
+    pub output: String,
+    pub first: String,
+    pub last: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExcludedPagesEntry {
+    pub output: String,
+    pub pages: Vec<u32>,
+}
+
+impl RunManifest {
+    pub fn new(input_path: &Path, result: &anyhow::Result<FileOutcome>, elapsed: Duration) -> Self {
+        let (outputs, bates, excluded_pages, skipped_bookmarks, summary, error) = match result {
+            Ok(FileOutcome { outputs, skipped_bookmarks, summary }) => (
+                outputs.iter().map(|output| output.path.display().to_string()).collect(),
+                outputs
+                    .iter()
+                    .filter_map(|output| {
+                        let range = output.bates.as_ref()?;
+                        Some(BatesEntry {
+                            output: output.path.display().to_string(),
+                            first: range.first.clone(),
+                            last: range.last.clone(),
+                        })
+                    })
+                    .collect(),
+                outputs
+                    .iter()
+                    .filter(|output| !output.excluded_pages.is_empty())
+                    .map(|output| ExcludedPagesEntry {
+                        output: output.path.display().to_string(),
+                        pages: output.excluded_pages.clone(),
+                    })
+                    .collect(),
+                skipped_bookmarks.clone(),
+                summary.clone(),
+                None,
+            ),
+            Err(e) => (Vec::new(), Vec::new(), Vec::new(), Vec::new(), None, Some(format!("{:#}", e))),
+        };
+        let retries_exhausted = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.chain().find_map(|cause| cause.downcast_ref::<ChaptersFailed>()))
+            .map(|failed| failed.retries_exhausted.clone())
+            .unwrap_or_default();
+        Self {
+            input: input_path.display().to_string(),
+            ok: error.is_none(),
+            outputs,
+            bates,
+            excluded_pages,
+            skipped_bookmarks,
+            retries_exhausted,
+            summary,
+            error,
+            elapsed_ms: crate::timings::ms(elapsed),
+        }
+    }
+
+    /// 出力のパスを置き換える（`--output` で送った先の URL にするときに使う）
+    pub fn map_outputs(&mut self, map: impl Fn(&str) -> String) {
+        for output in &mut self.outputs {
+            *output = map(output);
+        }
+        for entry in &mut self.bates {
+            entry.output = map(&entry.output);
+        }
+        for entry in &mut self.excluded_pages {
+            entry.output = map(&entry.output);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Result, bail};
+use std::sync::{Condvar, Mutex};
+
+// 抽出したオブジェクトのコピーと、保存時の書き出し用バッファの分を見込む
+const WORKING_SET_FACTOR: u64 = 2;
+
+/// `8G`・`512M`・`1.5GiB` のようなサイズ指定をバイト数に変換する（1K = 1024 バイト）
+pub fn parse_size(text: &str) -> Result<u64> {
+    let trimmed = text.trim();
+    let split = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let Ok(value) = number.parse::<f64>() else {
+        bail!("invalid size: {:?}", text);
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("invalid size unit in {:?} (use K, M, G or T)", text),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// バイト数を `1.5 MiB` のような読みやすい表記にする
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+/// 章ごとの推定作業メモリを予約し、合計が上限を超えないよう同時実行数を絞る
+pub struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// 抽出対象のオブジェクトサイズから作業メモリを見積もる
+    pub fn working_set(reachable_bytes: u64) -> u64 {
+        reachable_bytes.saturating_mul(WORKING_SET_FACTOR)
+    }
+
+    /// `bytes` を予約できるまで待つ。上限より大きい章は、他の章が終わるのを待って単独で処理する
+    pub fn acquire(&self, bytes: u64) -> MemoryReservation<'_> {
+        let bytes = bytes.min(self.limit);
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used + bytes > self.limit {
+            used = self.released.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += bytes;
+        MemoryReservation { budget: self, bytes }
+    }
+}
+
+/// 予約したメモリ。スコープを抜けると解放される
+pub struct MemoryReservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for MemoryReservation<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_accepts_suffixes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("8K").unwrap(), 8 << 10);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("8G").unwrap(), 8 << 30);
+        assert_eq!(parse_size("2T").unwrap(), 2 << 40);
+    }
+
+    #[test]
+    fn parse_size_accepts_byte_suffixes_fractions_and_lowercase() {
+        assert_eq!(parse_size("1.5GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("64MB").unwrap(), 64 << 20);
+        assert_eq!(parse_size(" 4 g ").unwrap(), 4 << 30);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+    }
+
+    #[test]
+    fn parse_size_rejects_unknown_units() {
+        for text in ["", "G", "12X", "1.5.2G", "-1G"] {
+            assert!(parse_size(text).is_err(), "{:?} should be rejected", text);
+        }
+    }
+}
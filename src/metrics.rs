@@ -0,0 +1,136 @@
+use crate::timings::FileTimings;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// 処理時間のヒストグラムのバケット（秒）。数ミリ秒の章から数分かかる巨大な文書までを覆う
+const BUCKETS: [f64; 15] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// `jobs --metrics-addr` で公開する Prometheus 形式のメトリクス
+///
+/// 常駐させたワーカーが止まっていないか（処理中のジョブが減らない、処理数が増えない）を監視できるようにする。
+#[derive(Default)]
+pub struct Metrics {
+    documents_ok: AtomicU64,
+    documents_failed: AtomicU64,
+    chapters_written: AtomicU64,
+    chapters_failed: AtomicU64,
+    bytes_out: AtomicU64,
+    jobs_in_progress: AtomicI64,
+    phases: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self { counts: [0; BUCKETS.len()], count: 0, sum: 0.0 }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+impl Metrics {
+    /// `/metrics` を返す HTTP サーバーを別スレッドで起動する（プロセスの終了まで動き続ける）
+    pub fn serve(self: &Arc<Self>, addr: &str) -> Result<()> {
+        let server = tiny_http::Server::http(addr).map_err(|e| anyhow!("failed to listen on {}: {}", addr, e))?;
+        let metrics = Arc::clone(self);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = if request.url() == "/metrics" {
+                    let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+                        .expect("valid header");
+                    tiny_http::Response::from_string(metrics.render()).with_header(header)
+                } else {
+                    tiny_http::Response::from_string("not found\n").with_status_code(404)
+                };
+                let _ = request.respond(response);
+            }
+        });
+        Ok(())
+    }
+
+    pub fn job_started(&self) {
+        self.jobs_in_progress.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn job_finished(&self, ok: bool) {
+        self.jobs_in_progress.fetch_sub(1, Ordering::Relaxed);
+        let counter = if ok { &self.documents_ok } else { &self.documents_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 1つの文書の分割が終わったときの、章の数・出力の大きさ・処理時間の内訳
+    pub fn record_file(&self, timings: &FileTimings, chapters_written: u64, chapters_failed: u64, bytes_out: u64) {
+        self.chapters_written.fetch_add(chapters_written, Ordering::Relaxed);
+        self.chapters_failed.fetch_add(chapters_failed, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+
+        let mut phases = self.phases.lock().expect("metrics lock poisoned");
+        let mut observe = |phase: &'static str, ms: f64| phases.entry(phase).or_insert_with(Histogram::new).observe(ms / 1000.0);
+        observe("load", timings.load_ms);
+        observe("analysis", timings.analysis_ms);
+        observe("outline_scan", timings.outline_scan_ms);
+        observe("document", timings.total_ms);
+        for chapter in &timings.chapters {
+            observe("chapter_wait", chapter.wait_ms);
+            observe("chapter_copy", chapter.copy_ms);
+            observe("chapter_fixup", chapter.fixup_ms);
+            observe("chapter_write", chapter.write_ms);
+            if let Some(ms) = chapter.linearize_ms {
+                observe("chapter_linearize", ms);
+            }
+            if let Some(ms) = chapter.verify_ms {
+                observe("chapter_verify", ms);
+            }
+            observe("chapter", chapter.total_ms);
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP pdf_splitter_documents_total Documents processed, by result.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_documents_total counter");
+        let _ = writeln!(out, "pdf_splitter_documents_total{{result=\"ok\"}} {}", self.documents_ok.load(Ordering::Relaxed));
+        let _ = writeln!(out, "pdf_splitter_documents_total{{result=\"failed\"}} {}", self.documents_failed.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP pdf_splitter_chapters_written_total Chapter files written.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_chapters_written_total counter");
+        let _ = writeln!(out, "pdf_splitter_chapters_written_total {}", self.chapters_written.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP pdf_splitter_chapters_failed_total Chapters that failed to save, linearize or verify.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_chapters_failed_total counter");
+        let _ = writeln!(out, "pdf_splitter_chapters_failed_total {}", self.chapters_failed.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP pdf_splitter_output_bytes_total Bytes written to chapter files.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_output_bytes_total counter");
+        let _ = writeln!(out, "pdf_splitter_output_bytes_total {}", self.bytes_out.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP pdf_splitter_jobs_in_progress Jobs currently being processed.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_jobs_in_progress gauge");
+        let _ = writeln!(out, "pdf_splitter_jobs_in_progress {}", self.jobs_in_progress.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP pdf_splitter_phase_duration_seconds Time spent per processing phase.");
+        let _ = writeln!(out, "# TYPE pdf_splitter_phase_duration_seconds histogram");
+        for (phase, histogram) in self.phases.lock().expect("metrics lock poisoned").iter() {
+            for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter()) {
+                let _ = writeln!(out, "pdf_splitter_phase_duration_seconds_bucket{{phase=\"{}\",le=\"{}\"}} {}", phase, bound, count);
+            }
+            let _ = writeln!(out, "pdf_splitter_phase_duration_seconds_bucket{{phase=\"{}\",le=\"+Inf\"}} {}", phase, histogram.count);
+            let _ = writeln!(out, "pdf_splitter_phase_duration_seconds_sum{{phase=\"{}\"}} {}", phase, histogram.sum);
+            let _ = writeln!(out, "pdf_splitter_phase_duration_seconds_count{{phase=\"{}\"}} {}", phase, histogram.count);
+        }
+        out
+    }
+}
@@ -0,0 +1,113 @@
+use crate::crypt::StringDecryptor;
+use crate::resolve_object;
+use lopdf::{Document, Object, ObjectId};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// 壊れた（循環した）名前ツリーで無限に潜らないための上限
+const MAX_TREE_DEPTH: usize = 64;
+
+/// 名前付き宛先を必要になったときだけ引く
+///
+/// 数十万件の名前を持つ文書では `/Names` `/Dests` ツリー全体の展開が分割本体より遅いため、
+/// 目次が実際に使う名前だけを `/Limits` を頼りにツリーを降りて探し、結果をキャッシュする。
+pub struct NamedDests<'a> {
+    doc: &'a Document,
+    decryptor: &'a StringDecryptor,
+    cache: RefCell<HashMap<Vec<u8>, Option<Object>>>,
+    lookup_time: Cell<Duration>,
+}
+
+impl<'a> NamedDests<'a> {
+    pub fn new(doc: &'a Document, decryptor: &'a StringDecryptor) -> Self {
+        Self { doc, decryptor, cache: RefCell::new(HashMap::new()), lookup_time: Cell::new(Duration::ZERO) }
+    }
+
+    /// これまでに引いた名前のうち、宛先が見つかった数
+    pub fn resolved(&self) -> usize {
+        self.cache.borrow().values().filter(|v| v.is_some()).count()
+    }
+
+    /// 名前ツリーの探索に費やした時間の合計
+    pub fn lookup_time(&self) -> Duration {
+        self.lookup_time.get()
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<Object> {
+        if let Some(cached) = self.cache.borrow().get(name) {
+            return cached.clone();
+        }
+        // カタログ直下の /Dests（PDF 1.1 形式）は名前ツリーより優先する
+        let start = Instant::now();
+        let found = self.lookup_catalog_dests(name).or_else(|| self.lookup_name_tree(name));
+        self.lookup_time.set(self.lookup_time.get() + start.elapsed());
+        self.cache.borrow_mut().insert(name.to_vec(), found.clone());
+        found
+    }
+
+    fn lookup_catalog_dests(&self, name: &[u8]) -> Option<Object> {
+        let dests = self.doc.catalog().ok()?.get(b"Dests").ok()?;
+        let dests = resolve_object(self.doc, dests).ok()?.as_dict().ok()?;
+        dests.get(name).ok().cloned()
+    }
+
+    fn lookup_name_tree(&self, name: &[u8]) -> Option<Object> {
+        let catalog_id = self.doc.trailer.get(b"Root").and_then(|o| o.as_reference()).ok()?;
+        let names_obj = self.doc.catalog().ok()?.get(b"Names").ok()?;
+        let names = resolve_object(self.doc, names_obj).ok()?.as_dict().ok()?;
+        let root_obj = names.get(b"Dests").ok()?;
+        // 暗号化文書ではキー文字列を含む間接オブジェクトの鍵で復号する
+        let root_id = root_obj.as_reference().or_else(|_| names_obj.as_reference()).unwrap_or(catalog_id);
+        self.search_node(root_id, resolve_object(self.doc, root_obj).ok()?, name, 0)
+    }
+
+    fn search_node(&self, node_id: ObjectId, node: &Object, name: &[u8], depth: usize) -> Option<Object> {
+        if depth > MAX_TREE_DEPTH {
+            return None;
+        }
+        let node = node.as_dict().ok()?;
+
+        if let Ok(names_obj) = node.get(b"Names")
+            && let Ok(names) = resolve_object(self.doc, names_obj).and_then(|o| o.as_array())
+        {
+            let container = names_obj.as_reference().unwrap_or(node_id);
+            for chunk in names.chunks_exact(2) {
+                if self.key_bytes(container, &chunk[0]).as_deref() == Some(name) {
+                    return Some(chunk[1].clone());
+                }
+            }
+        }
+
+        let kids = node.get(b"Kids").and_then(|o| resolve_object(self.doc, o)).and_then(|o| o.as_array()).ok()?;
+        for kid in kids {
+            let Ok(kid_id) = kid.as_reference() else { continue };
+            let Ok(kid_node) = self.doc.get_object(kid_id) else { continue };
+            // /Limits の範囲外の子は降りない（/Limits が無い子は念のため探す）
+            if let Some((low, high)) = self.limits(kid_id, kid_node)
+                && (name < low.as_slice() || name > high.as_slice())
+            {
+                continue;
+            }
+            if let Some(found) = self.search_node(kid_id, kid_node, name, depth + 1) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    fn limits(&self, node_id: ObjectId, node: &Object) -> Option<(Vec<u8>, Vec<u8>)> {
+        let limits = node.as_dict().ok()?.get(b"Limits").ok()?;
+        let limits = resolve_object(self.doc, limits).ok()?.as_array().ok()?;
+        let [low, high] = limits.as_slice() else { return None };
+        Some((self.key_bytes(node_id, low)?, self.key_bytes(node_id, high)?))
+    }
+
+    fn key_bytes(&self, container: ObjectId, key: &Object) -> Option<Vec<u8>> {
+        match key {
+            Object::String(bytes, _) => Some(self.decryptor.decrypt(container, bytes).into_owned()),
+            Object::Name(bytes) => Some(bytes.clone()),
+            _ => None,
+        }
+    }
+}
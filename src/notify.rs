@@ -0,0 +1,29 @@
+use crate::manifest::RunManifest;
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+// 通知先が応答しない場合に分割全体を止めないための上限
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `--notify-url` の値（http:// か https:// の URL）
+pub fn parse_url(value: &str) -> Result<String, String> {
+    if value.starts_with("http://") || value.starts_with("https://") {
+        Ok(value.to_string())
+    } else {
+        Err(format!("{:?} is not an http:// or https:// URL", value))
+    }
+}
+
+/// 処理結果の JSON を通知先へ POST する
+///
+/// ワークフローエンジンが出力ディレクトリをポーリングせずに次の処理へ進めるようにするため。
+/// 2xx 以外の応答や接続の失敗はエラーとして返す（呼び出し側は警告だけ出して続ける）。
+pub fn post(url: &str, manifest: &RunManifest) -> Result<()> {
+    let body = serde_json::to_string(manifest)?;
+    ureq::post(url)
+        .timeout(NOTIFY_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .context("failed to send the completion notification")?;
+    Ok(())
+}
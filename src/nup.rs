@@ -0,0 +1,149 @@
+use crate::page_edit::visible_box;
+use crate::stamp::{number, visual_frame};
+use anyhow::{Context, Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::HashMap;
+
+/// `--nup` の値（1枚に並べるページ数）
+pub fn parse_nup(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n @ (2 | 4)) => Ok(n),
+        _ => Err(format!("invalid N-up {:?} (expected 2 or 4)", value)),
+    }
+}
+
+/// 出力するページ数（`pages` ページを `n` ページずつ並べた枚数）
+pub fn sheet_count(pages: usize, n: usize) -> usize {
+    pages.div_ceil(n)
+}
+
+/// 章のページを `n` ページずつ1枚に縮小して並べる（`--nup`）
+///
+/// 各ページの内容は元の文書から読み直してフォーム XObject にし（抽出では内容ストリームを書き出さない）、
+/// 新しいページに左上から右へ、上から下への順に配置する。2-up は元のページと縦横を入れ替えた用紙に2列（横長のページなら2段）、
+/// 4-up は元のページと同じ向きの用紙に2×2で並べる。用紙の大きさは各枚の先頭ページに合わせる。
+/// 元のページへの参照（目次・名前付き宛先など）は、そのページを載せた新しいページへ付け替える。
+/// 元のページの注釈は引き継がない。新しいページを返す。
+pub fn impose(source: &Document, doc: &mut Document, page_ids: &[ObjectId], n: usize) -> Result<Vec<ObjectId>> {
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let mut sheets = Vec::new();
+    let mut replaced: HashMap<ObjectId, ObjectId> = HashMap::new();
+
+    for group in page_ids.chunks(n) {
+        let Some((width, height, _)) = visual_frame(doc, group[0]) else {
+            bail!("page {:?} has no MediaBox", group[0]);
+        };
+        let (sheet, columns, rows) = match n {
+            2 if height >= width => ((height, width), 2, 1),
+            2 => ((height, width), 1, 2),
+            _ => ((width, height), 2, 2),
+        };
+        let (cell_width, cell_height) = (sheet.0 / columns as f32, sheet.1 / rows as f32);
+
+        let mut content = String::new();
+        let mut xobjects = Dictionary::new();
+        for (slot, page_id) in group.iter().enumerate() {
+            let Some((page_width, page_height, matrix)) = visual_frame(doc, *page_id) else { continue };
+            let form_id = page_form(source, doc, *page_id)?;
+            let name = format!("PdfSplitterPage{}", slot + 1);
+            xobjects.set(name.as_bytes().to_vec(), form_id);
+
+            // セルに収まるよう縮小し、セルの中央に置く
+            let scale = (cell_width / page_width).min(cell_height / page_height);
+            let (column, row) = (slot % columns, slot / columns);
+            let x = cell_width * column as f32 + (cell_width - page_width * scale) / 2.0;
+            let y = sheet.1 - cell_height * (row + 1) as f32 + (cell_height - page_height * scale) / 2.0;
+            content.push_str(&format!(
+                "q\n{} 0 0 {} {} {} cm\n{} cm\n/{} Do\nQ\n",
+                number(scale),
+                number(scale),
+                number(x),
+                number(y),
+                invert(matrix).iter().map(|v| number(*v)).collect::<Vec<_>>().join(" "),
+                name,
+            ));
+        }
+
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let mut resources = Dictionary::new();
+        resources.set("XObject", xobjects);
+        let mut page = Dictionary::new();
+        page.set("Type", "Page");
+        page.set("Parent", pages_id);
+        page.set("MediaBox", vec![0.into(), 0.into(), Object::Real(sheet.0), Object::Real(sheet.1)]);
+        page.set("Resources", resources);
+        page.set("Contents", content_id);
+        let sheet_id = doc.add_object(page);
+        replaced.extend(group.iter().map(|page_id| (*page_id, sheet_id)));
+        sheets.push(sheet_id);
+    }
+
+    for page_id in page_ids {
+        doc.objects.remove(page_id);
+    }
+    for object in doc.objects.values_mut() {
+        replace_refs(object, &replaced);
+    }
+    let pages = doc.get_object_mut(pages_id).and_then(Object::as_dict_mut)?;
+    pages.set("Kids", sheets.iter().map(|id| Object::Reference(*id)).collect::<Vec<_>>());
+    pages.set("Count", sheets.len() as i64);
+    Ok(sheets)
+}
+
+/// ページの内容を、表示範囲を BBox とするフォーム XObject にする
+fn page_form(source: &Document, doc: &mut Document, page_id: ObjectId) -> Result<ObjectId> {
+    let bbox = visible_box(doc, page_id).context("page has no MediaBox")?;
+    let resources = doc
+        .get_dictionary(page_id)
+        .with_context(|| format!("page {} {} R is not a dictionary", page_id.0, page_id.1))?
+        .get(b"Resources")
+        .cloned()
+        .unwrap_or_else(|_| Dictionary::new().into());
+    let mut content = Vec::new();
+    for stream_id in source.get_page_contents(page_id) {
+        let stream = source
+            .get_object(stream_id)
+            .and_then(Object::as_stream)
+            .with_context(|| format!("the content {} {} R of page {} {} R is not a stream", stream_id.0, stream_id.1, page_id.0, page_id.1))?;
+        let data = if stream.filters().map(|filters| filters.is_empty()).unwrap_or(true) {
+            stream.content.clone()
+        } else {
+            stream
+                .decompressed_content()
+                .with_context(|| format!("cannot decode the content stream {} {} R", stream_id.0, stream_id.1))?
+        };
+        // 内容ストリームの境界で字句がつながらないよう区切る
+        content.extend(data);
+        content.push(b'\n');
+    }
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", "XObject");
+    dict.set("Subtype", "Form");
+    dict.set("BBox", bbox.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>());
+    dict.set("Resources", resources);
+    let mut form = Stream::new(dict, content);
+    // 元の内容ストリームは圧縮されていることが多いため、展開したままにしない
+    let _ = form.compress();
+    Ok(doc.add_object(form))
+}
+
+/// アフィン変換行列の逆行列（`visual_frame` の行列は回転と平行移動だけなので常に正則）
+fn invert([a, b, c, d, e, f]: [f32; 6]) -> [f32; 6] {
+    let det = a * d - b * c;
+    [d / det, -b / det, -c / det, a / det, (c * f - d * e) / det, (b * e - a * f) / det]
+}
+
+fn replace_refs(object: &mut Object, replaced: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(new_id) = replaced.get(id) {
+                *id = *new_id;
+            }
+        }
+        Object::Array(items) => items.iter_mut().for_each(|item| replace_refs(item, replaced)),
+        Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| replace_refs(value, replaced)),
+        Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| replace_refs(value, replaced)),
+        _ => {}
+    }
+}
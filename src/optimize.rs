@@ -0,0 +1,143 @@
+use crate::compress::Compression;
+use crate::extract::PageExtractor;
+use crate::images::Downsampler;
+use crate::interrupt;
+use crate::linearize::{self, QPDF};
+use crate::memory::format_size;
+use crate::progress::{InfoLines, Progress};
+use crate::raw_copy::RawSource;
+use crate::stream_writer::StreamingWriter;
+use crate::{load_document, pagetree};
+use anyhow::{Context, Result, bail};
+use lopdf::ObjectId;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+/// `optimize` の縮小の方法
+pub struct OptimizeOptions {
+    pub compression: Compression,
+    pub downsample: Option<(u32, u8)>,
+    /// 使っていないフォントを残す
+    pub keep_unused_fonts: bool,
+    /// オブジェクトストリームにまとめない
+    pub no_object_streams: bool,
+}
+
+/// `optimize` サブコマンド: 分割せずに文書全体へ、章の書き出しと同じ縮小の処理をかけて保存する
+///
+/// ページから到達できないオブジェクトを捨て（ガベージコレクション）、使っていないフォントをリソースから外し、
+/// ストリームを圧縮し直し、同じ内容のストリームを1つにまとめる。最後に qpdf があればオブジェクトストリームにまとめる。
+pub fn run(input_path: &Path, output_path: &Path, options: &OptimizeOptions) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let doc = Arc::new(load_document(input_path, false)?);
+    // 暗号化文書のストリームは暗号文のため、圧縮し直すことも、使っているフォントを調べることもできない
+    if doc.trailer.has(b"Encrypt") {
+        bail!("{:?} is encrypted; optimize works on unencrypted documents only", input_path);
+    }
+    let object_streams = !options.no_object_streams && qpdf_available(&progress);
+
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let page_ids: Vec<ObjectId> = page_numbers.values().copied().collect();
+    let mut extractor = PageExtractor::new(Arc::clone(&doc), page_ids.iter().copied()).with_compression(options.compression);
+    if let Some((dpi, quality)) = options.downsample {
+        extractor = extractor.with_downsampler(Downsampler::new(dpi, quality)?);
+    }
+    if !options.keep_unused_fonts {
+        extractor = extractor.with_font_stripping();
+    }
+    match RawSource::open(input_path) {
+        Ok(raw) => extractor = extractor.with_raw_source(raw),
+        Err(e) => progress.eprintln(format!("Warning: raw stream copy disabled: {}", e)),
+    }
+
+    // 完成するまでは別名で書き、失敗で残ったファイルを正しい出力と取り違えないようにする
+    let partial_path = interrupt::partial_path(output_path);
+    let save = || -> Result<_> {
+        let file = File::create(&partial_path)?;
+        let mut writer = StreamingWriter::new(BufWriter::new(file), &doc.version)?;
+        let (optimized, stats) = extractor.extract_streaming(&page_ids, &mut writer)?;
+        let deduplicated = writer.deduplicated();
+        writer.finish(&optimized)?;
+        if object_streams {
+            generate_object_streams(&partial_path)?;
+        }
+        Ok((stats, deduplicated))
+    };
+    let (stats, deduplicated) = match save() {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial_path);
+            return Err(e.context(format!("Failed to write {:?}", output_path)));
+        }
+    };
+    std::fs::rename(&partial_path, output_path).with_context(|| format!("Failed to rename {:?} to {:?}", partial_path, output_path))?;
+
+    if stats.stripped_fonts > 0 {
+        progress.println(format!(
+            "Stripped {} unused fonts (saved {} of embedded font programs).",
+            stats.stripped_fonts,
+            format_size(stats.font_bytes_saved)
+        ));
+    }
+    if stats.downsampled.images > 0 {
+        progress.println(format!("Downsampled {} images (saved {}).", stats.downsampled.images, format_size(stats.downsampled.bytes_saved)));
+    }
+    if deduplicated > 0 {
+        progress.println(format!("Merged {} duplicate streams.", deduplicated));
+    }
+    let before = std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0);
+    let after = std::fs::metadata(output_path).map(|m| m.len()).unwrap_or(0);
+    // 入力がオブジェクトストリームを使っていて、こちらでまとめられなかったときは大きくなることもある
+    let change = if after > before {
+        format!("grew by {}", format_size(after - before))
+    } else {
+        format!("saved {}, {:.1}%", format_size(before - after), if before == 0 { 0.0 } else { (before - after) as f64 * 100.0 / before as f64 })
+    };
+    progress.println(format!(
+        "Wrote {:?}: {} -> {} ({} pages, {}).",
+        output_path,
+        format_size(before),
+        format_size(after),
+        page_ids.len(),
+        change
+    ));
+    Ok(())
+}
+
+/// qpdf が無ければ、オブジェクトストリームにまとめずに続ける
+fn qpdf_available(progress: &Progress) -> bool {
+    match linearize::check_available("optimize") {
+        Ok(()) => true,
+        Err(e) => {
+            progress.eprintln(format!("Warning: {:#}; writing without object streams.", e));
+            false
+        }
+    }
+}
+
+/// 保存したPDFの辞書などのオブジェクトを、その場で圧縮したオブジェクトストリームにまとめる（lopdf には書き出す機能が無い）
+fn generate_object_streams(path: &Path) -> Result<()> {
+    let output = Command::new(QPDF)
+        .arg("--object-streams=generate")
+        .arg("--replace-input")
+        .arg(path)
+        .output()
+        .with_context(|| format!("Failed to run {} on {:?}", QPDF, path))?;
+
+    // 終了コード 3 は警告のみ（出力は有効）
+    match output.status.code() {
+        Some(0) | Some(3) => Ok(()),
+        _ => bail!(
+            "{} --object-streams failed for {:?}: {}",
+            QPDF,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// 出力ファイル名の文字コード（`--output-encoding`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputEncoding {
+    /// OS の既定（Unix 系では UTF-8、Windows では Unicode）
+    Utf8,
+    /// Shift_JIS のバイト列（Unix 系のみ。Shift_JIS のファイル名を前提にした古い共有フォルダ向け）
+    Sjis,
+}
+
+impl OutputEncoding {
+    /// 起動時に、この OS で使えるかを確かめる
+    pub fn check_supported(self) -> Result<()> {
+        if self == OutputEncoding::Sjis && !cfg!(unix) {
+            anyhow::bail!("--output-encoding sjis is only supported on Unix-like systems (Windows file names are always Unicode)");
+        }
+        Ok(())
+    }
+}
+
+/// 計画のファイル名（文字列）から、実際に書き出すパスを作る
+///
+/// 計画や画面の表示では入力名を文字列に直して使うが、入力のパスが UTF-8 として正しくない場合、
+/// そのままでは出力名の入力名の部分が置換文字に化ける。ファイル名が入力名で始まる場合はその部分を
+/// 元のバイト列のまま使い、残りを `encoding` の文字コードで付け加える。
+pub struct OutputNames {
+    /// UTF-8 として正しくない入力名と、それを文字列に直したもの（正しい場合は `None`）
+    raw_stem: Option<(OsString, String)>,
+    encoding: OutputEncoding,
+}
+
+impl OutputNames {
+    /// `display_stem` は `raw_stem` を文字列に直したもの（計画のファイル名の先頭に使ったもの）
+    pub fn new(raw_stem: &OsStr, display_stem: &str, encoding: OutputEncoding) -> Self {
+        let raw_stem = raw_stem.to_str().is_none().then(|| (raw_stem.to_os_string(), display_stem.to_string()));
+        Self { raw_stem, encoding }
+    }
+
+    pub fn path(&self, dir: &Path, filename: &str) -> PathBuf {
+        let name = match &self.raw_stem {
+            Some((raw_stem, display_stem)) if filename.starts_with(display_stem.as_str()) => {
+                let mut name = raw_stem.clone();
+                name.push(self.encode(&filename[display_stem.len()..]));
+                name
+            }
+            _ => self.encode(filename),
+        };
+        dir.join(name)
+    }
+
+    fn encode(&self, text: &str) -> OsString {
+        match self.encoding {
+            OutputEncoding::Utf8 => OsString::from(text),
+            OutputEncoding::Sjis => shift_jis(text),
+        }
+    }
+}
+
+/// Shift_JIS のバイト列のファイル名にする（Shift_JIS に無い文字は `_` にする）
+#[cfg(unix)]
+fn shift_jis(text: &str) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut buf = [0; 4];
+    for c in text.chars() {
+        let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+        if unmappable {
+            bytes.push(b'_');
+        } else {
+            bytes.extend_from_slice(&encoded);
+        }
+    }
+    OsString::from_vec(bytes)
+}
+
+// Windows では `check_supported` で起動時に断るため、ここには来ない
+#[cfg(not(unix))]
+fn shift_jis(text: &str) -> OsString {
+    OsString::from(text)
+}
@@ -0,0 +1,103 @@
+use crate::page_ranges::PageRanges;
+use lopdf::{Document, Object, ObjectId};
+
+/// `--rotate` の角度（90・180・270）
+pub fn parse_rotation(value: &str) -> Result<i64, String> {
+    match value.trim() {
+        "90" => Ok(90),
+        "180" => Ok(180),
+        "270" => Ok(270),
+        _ => Err(format!("invalid rotation {:?} (use 90, 180 or 270)", value)),
+    }
+}
+
+/// 章のページを時計回りに回転する（既存の /Rotate に加える）
+///
+/// `source_pages` は `page_ids` のそれぞれの元の文書でのページ番号。
+/// `pages` の指定があれば、元の文書のページ番号がそこに含まれるページだけを回転する。
+/// 戻り値は回転したページ数。
+pub fn rotate(doc: &mut Document, page_ids: &[ObjectId], source_pages: &[u32], degrees: i64, pages: Option<&PageRanges>) -> usize {
+    let mut rotated = 0;
+    for (&page_number, page_id) in source_pages.iter().zip(page_ids) {
+        if pages.is_some_and(|pages| !pages.contains(page_number)) {
+            continue;
+        }
+        let Ok(page) = doc.get_object_mut(*page_id).and_then(Object::as_dict_mut) else { continue };
+        // 葉のページには抽出時に継承属性の /Rotate も引き継がれている
+        let current = page.get(b"Rotate").and_then(Object::as_i64).unwrap_or(0);
+        page.set("Rotate", (current + degrees).rem_euclid(360));
+        rotated += 1;
+    }
+    rotated
+}
+
+/// `--crop` / `--set-cropbox` で設定するトリミング
+#[derive(Debug, Clone, Copy)]
+pub enum Crop {
+    /// 今の表示範囲（CropBox、無ければ MediaBox）から削る余白（上・右・下・左、ポイント単位）
+    Margins([f32; 4]),
+    /// CropBox に設定する矩形（左下 x, 左下 y, 右上 x, 右上 y、ポイント単位）
+    Box([f32; 4]),
+}
+
+/// `--crop`: `10`（四辺とも同じ）または `10,20,10,20`（上・右・下・左）
+pub fn parse_margins(value: &str) -> Result<Crop, String> {
+    let numbers = parse_numbers(value)?;
+    match numbers.as_slice() {
+        [all] => Ok(Crop::Margins([*all; 4])),
+        [top, right, bottom, left] => Ok(Crop::Margins([*top, *right, *bottom, *left])),
+        _ => Err(format!("invalid margins {:?} (use one value or top,right,bottom,left)", value)),
+    }
+}
+
+/// `--set-cropbox`: `左下x,左下y,右上x,右上y`
+pub fn parse_box(value: &str) -> Result<Crop, String> {
+    match parse_numbers(value)?.as_slice() {
+        [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok(Crop::Box([*x0, *y0, *x1, *y1])),
+        _ => Err(format!("invalid box {:?} (use llx,lly,urx,ury with llx < urx and lly < ury)", value)),
+    }
+}
+
+fn parse_numbers(value: &str) -> Result<Vec<f32>, String> {
+    value
+        .split(',')
+        .map(|n| n.trim().parse::<f32>().map_err(|_| format!("invalid number {:?} in {:?}", n.trim(), value)))
+        .collect()
+}
+
+/// 章のページの CropBox を設定する。戻り値は (設定したページ数, 余白が大きすぎて飛ばしたページ数)
+///
+/// 余白は回転前のページの座標で削る（/Rotate のあるページでは見た目の上下左右と異なる）。
+pub fn crop(doc: &mut Document, page_ids: &[ObjectId], crop: Crop) -> (usize, usize) {
+    let (mut cropped, mut skipped) = (0, 0);
+    for page_id in page_ids {
+        let rect = match crop {
+            Crop::Box(rect) => Some(rect),
+            Crop::Margins([top, right, bottom, left]) => visible_box(doc, *page_id).and_then(|[x0, y0, x1, y1]| {
+                let rect = [x0 + left, y0 + bottom, x1 - right, y1 - top];
+                (rect[0] < rect[2] && rect[1] < rect[3]).then_some(rect)
+            }),
+        };
+        let Some(rect) = rect else {
+            skipped += 1;
+            continue;
+        };
+        let Ok(page) = doc.get_object_mut(*page_id).and_then(Object::as_dict_mut) else { continue };
+        page.set("CropBox", rect.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>());
+        cropped += 1;
+    }
+    (cropped, skipped)
+}
+
+/// ページの表示範囲（CropBox、無ければ MediaBox）を正規化した矩形で返す
+pub fn visible_box(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let page = doc.get_dictionary(page_id).ok()?;
+    let rect = page.get(b"CropBox").or_else(|_| page.get(b"MediaBox")).ok()?;
+    let values = doc.dereference(rect).ok()?.1.as_array().ok()?;
+    let numbers: Vec<f32> = values
+        .iter()
+        .filter_map(|v| doc.dereference(v).ok().and_then(|(_, v)| v.as_float().ok()))
+        .collect();
+    let [a, b, c, d] = numbers.as_slice() else { return None };
+    Some([a.min(*c), b.min(*d), a.max(*c), b.max(*d)])
+}
@@ -0,0 +1,289 @@
+use crate::crypt::StringDecryptor;
+use crate::{decode_pdf_string, encode_pdf_string, resolve_object};
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// 壊れた（循環した）数値ツリーで無限に潜らないための上限
+const MAX_TREE_DEPTH: usize = 64;
+
+/// ページラベル（/PageLabels）から各ページの表示上のページ番号を作る
+///
+/// 前付けをローマ数字で数える本などでは、ビューアに表示されるページ番号と通し番号がずれる。
+/// 戻り値は通し番号（1始まり）からラベルへの対応表。ラベルが定義されていない文書では `None`。
+pub fn collect(doc: &Document, decryptor: &StringDecryptor, total_pages: u32) -> Option<BTreeMap<u32, String>> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(|o| o.as_reference()).ok()?;
+    let root_obj = doc.catalog().ok()?.get(b"PageLabels").ok()?;
+    let root_id = root_obj.as_reference().unwrap_or(catalog_id);
+
+    // ページの開始位置（0始まり）ごとの範囲の定義
+    let mut ranges = BTreeMap::new();
+    let reader = Reader { doc, decryptor };
+    reader.visit(root_id, resolve_object(doc, root_obj).ok()?, &mut ranges, 0);
+    if ranges.is_empty() {
+        return None;
+    }
+
+    let mut labels = BTreeMap::new();
+    for page in 1..=total_pages {
+        let index = page - 1;
+        // 最初の範囲より前のページにはラベルが無い
+        let label = match ranges.range(..=index).next_back() {
+            Some((start, range)) => range.label(index - start),
+            None => String::new(),
+        };
+        labels.insert(page, label);
+    }
+    Some(labels)
+}
+
+/// ページ範囲の指定（`--pages`・`--exclude-pages`・計画ファイル）のページ番号の数え方（`--page-numbering`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PageNumbering {
+    /// 先頭からの通し番号
+    Physical,
+    /// 印刷されたページ番号（ページラベル）
+    Logical,
+}
+
+/// 印刷されたページ番号から通し番号を引く
+///
+/// 目次に印刷されたページ番号は、前付けの分だけ通し番号とずれる。ページラベルが数字だけのページを
+/// その番号で引けるようにする（ローマ数字や接頭辞付きのラベルは対象外）。
+pub struct LogicalPages {
+    pages: HashMap<u32, Vec<u32>>,
+}
+
+impl LogicalPages {
+    pub fn new(labels: &BTreeMap<u32, String>) -> Self {
+        let mut pages: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (page, label) in labels {
+            if let Ok(printed) = label.parse::<u32>() {
+                pages.entry(printed).or_default().push(*page);
+            }
+        }
+        Self { pages }
+    }
+
+    /// 印刷されたページ番号 `printed` のページの通し番号
+    pub fn physical(&self, printed: u32) -> Result<u32> {
+        match self.pages.get(&printed).map(Vec::as_slice) {
+            Some([page]) => Ok(*page),
+            Some([first, second, ..]) => bail!("printed page {} is ambiguous (pages {} and {} both have that label)", printed, first, second),
+            _ => bail!("no page is labelled {}", printed),
+        }
+    }
+}
+
+/// `set-pagelabels` の `--spec`: ラベル範囲の開始ページ（1始まり）と、その範囲の定義の並び
+#[derive(Clone, Debug)]
+pub struct LabelSpec {
+    pub ranges: Vec<(u32, LabelRange)>,
+}
+
+/// `--spec` を解釈する（例: "1:r,15:1,200:A-1"）
+///
+/// 各項目は `開始ページ:先頭のラベル`。先頭のラベルが数字で終われば、その前を接頭辞とする算用数字の範囲
+/// （"15:1" → 1, 2, …、"200:A-1" → A-1, A-2, …）。`r`・`R`・`a`・`A` で終わり、その前が英数字でなければ
+/// ローマ数字・アルファベットの範囲（"1:r" → i, ii, …、"300:App-A" → App-A, App-B, …）。
+/// それ以外は番号の無い、接頭辞だけのラベルにする（"1:Cover"）。最初の項目は1ページ目から始める。
+pub fn parse_spec(text: &str) -> Result<LabelSpec> {
+    let mut ranges: Vec<(u32, LabelRange)> = Vec::new();
+    for part in text.split(',').map(str::trim) {
+        let Some((page, label)) = part.split_once(':') else {
+            bail!("invalid label range {:?} in {:?} (expected PAGE:LABEL, e.g. 15:1)", part, text);
+        };
+        let page = match page.trim().parse::<u32>() {
+            Ok(page) if page >= 1 => page,
+            _ => bail!("invalid page number {:?} in {:?}", page.trim(), text),
+        };
+        if let Some((previous, _)) = ranges.last()
+            && page <= *previous
+        {
+            bail!("label ranges must be in ascending page order ({} comes after {}) in {:?}", page, previous, text);
+        }
+        ranges.push((page, LabelRange::parse(label)?));
+    }
+    if ranges.first().is_none_or(|(page, _)| *page != 1) {
+        bail!("the first label range must start at page 1 in {:?}", text);
+    }
+    Ok(LabelSpec { ranges })
+}
+
+/// 1つのラベル範囲（/S 番号の様式, /P 接頭辞, /St 開始番号）
+#[derive(Clone, Debug)]
+pub struct LabelRange {
+    style: Option<Vec<u8>>,
+    pub prefix: String,
+    start: u32,
+}
+
+impl LabelRange {
+    /// `parse_spec` の1項目の先頭のラベルから範囲を作る
+    fn parse(label: &str) -> Result<Self> {
+        let digits = label.len() - label.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            let (prefix, number) = label.split_at(label.len() - digits);
+            let start = match number.parse::<u32>() {
+                Ok(start) if start >= 1 => start,
+                _ => bail!("invalid first page number {:?} in label {:?} (must be 1 or more)", number, label),
+            };
+            return Ok(LabelRange { style: Some(b"D".to_vec()), prefix: prefix.to_string(), start });
+        }
+        let mut chars = label.chars();
+        if let Some(style @ ('r' | 'R' | 'a' | 'A')) = chars.next_back()
+            && !chars.clone().next_back().is_some_and(char::is_alphanumeric)
+        {
+            return Ok(LabelRange { style: Some(vec![style as u8]), prefix: chars.as_str().to_string(), start: 1 });
+        }
+        Ok(LabelRange { style: None, prefix: label.to_string(), start: 1 })
+    }
+
+    /// 範囲の定義を /PageLabels の数値ツリーの値の辞書にする
+    fn to_dict(&self) -> Dictionary {
+        let mut dict = Dictionary::new();
+        dict.set("Type", "PageLabel");
+        if let Some(style) = &self.style {
+            dict.set("S", Object::Name(style.clone()));
+        }
+        if !self.prefix.is_empty() {
+            dict.set("P", encode_pdf_string(&self.prefix));
+        }
+        if self.start != 1 {
+            dict.set("St", self.start as i64);
+        }
+        dict
+    }
+
+    pub fn label(&self, offset: u32) -> String {
+        let number = self.start.saturating_add(offset);
+        let numeral = match self.style.as_deref() {
+            Some(b"D") => number.to_string(),
+            Some(b"R") => roman(number),
+            Some(b"r") => roman(number).to_lowercase(),
+            Some(b"A") => letters(number),
+            Some(b"a") => letters(number).to_lowercase(),
+            // 様式が無い範囲は接頭辞だけを付ける
+            _ => String::new(),
+        };
+        format!("{}{}", self.prefix, numeral)
+    }
+}
+
+/// カタログの /PageLabels を `spec` の範囲だけの数値ツリー（/Nums だけの1つのノード）に置き換える
+///
+/// 元のツリーのオブジェクトは取り除き、出力に持ち越さない。
+pub fn set(doc: &mut Document, spec: &LabelSpec) -> Result<()> {
+    let mut nums = Vec::new();
+    for (page, range) in &spec.ranges {
+        nums.push(Object::Integer(i64::from(page - 1)));
+        nums.push(Object::Dictionary(range.to_dict()));
+    }
+    let mut tree = Dictionary::new();
+    tree.set("Nums", nums);
+
+    let old_objects = match doc.catalog()?.get(b"PageLabels") {
+        Ok(root) => {
+            let mut ids = HashSet::new();
+            tree_objects(doc, root, &mut ids, 0);
+            ids
+        }
+        Err(_) => HashSet::new(),
+    };
+    for id in old_objects {
+        doc.objects.remove(&id);
+    }
+    doc.catalog_mut()?.set("PageLabels", tree);
+    Ok(())
+}
+
+/// ページラベルの数値ツリーから参照されているオブジェクト（ノード・/Nums の配列・範囲の辞書）
+fn tree_objects(doc: &Document, object: &Object, ids: &mut HashSet<ObjectId>, depth: usize) {
+    if depth > MAX_TREE_DEPTH {
+        return;
+    }
+    match object {
+        Object::Reference(id) => {
+            if ids.insert(*id)
+                && let Ok(object) = doc.get_object(*id)
+            {
+                tree_objects(doc, object, ids, depth + 1);
+            }
+        }
+        Object::Array(array) => array.iter().for_each(|item| tree_objects(doc, item, ids, depth + 1)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| tree_objects(doc, value, ids, depth + 1)),
+        _ => {}
+    }
+}
+
+struct Reader<'a> {
+    doc: &'a Document,
+    decryptor: &'a StringDecryptor,
+}
+
+impl Reader<'_> {
+    fn visit(&self, node_id: ObjectId, node: &Object, ranges: &mut BTreeMap<u32, LabelRange>, depth: usize) {
+        if depth > MAX_TREE_DEPTH {
+            return;
+        }
+        let Ok(node) = node.as_dict() else { return };
+
+        if let Ok(nums_obj) = node.get(b"Nums")
+            && let Ok(nums) = resolve_object(self.doc, nums_obj).and_then(|o| o.as_array())
+        {
+            let container = nums_obj.as_reference().unwrap_or(node_id);
+            for pair in nums.chunks_exact(2) {
+                let Ok(start) = pair[0].as_i64().map(|n| n.max(0) as u32) else { continue };
+                let container = pair[1].as_reference().unwrap_or(container);
+                if let Ok(dict) = resolve_object(self.doc, &pair[1]).and_then(|o| o.as_dict()) {
+                    ranges.insert(start, self.range(container, dict));
+                }
+            }
+        }
+
+        if let Ok(kids) = node.get(b"Kids").and_then(|o| resolve_object(self.doc, o)).and_then(|o| o.as_array()) {
+            for kid in kids {
+                let Ok(kid_id) = kid.as_reference() else { continue };
+                if let Ok(kid_node) = self.doc.get_object(kid_id) {
+                    self.visit(kid_id, kid_node, ranges, depth + 1);
+                }
+            }
+        }
+    }
+
+    fn range(&self, container: ObjectId, dict: &Dictionary) -> LabelRange {
+        let style = dict.get(b"S").and_then(|o| o.as_name()).ok().map(|name| name.to_vec());
+        let prefix = dict
+            .get(b"P")
+            .and_then(|o| o.as_str())
+            .map(|bytes| decode_pdf_string(&self.decryptor.decrypt(container, bytes)))
+            .unwrap_or_default();
+        let start = dict.get(b"St").and_then(|o| o.as_i64()).map(|n| n.max(1) as u32).unwrap_or(1);
+        LabelRange { style, prefix, start }
+    }
+}
+
+fn roman(mut number: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+    ];
+    let mut out = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            out.push_str(numeral);
+            number -= value;
+        }
+    }
+    out
+}
+
+/// A..Z の次は AA..ZZ、その次は AAA..ZZZ（同じ文字を繰り返す）
+fn letters(number: u32) -> String {
+    if number == 0 {
+        return String::new();
+    }
+    let letter = char::from(b'A' + ((number - 1) % 26) as u8);
+    letter.to_string().repeat(((number - 1) / 26 + 1) as usize)
+}
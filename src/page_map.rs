@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// `--page-map`: 元の文書のページ番号（とページラベル）から章のファイルのページ番号への対応表
+///
+/// 元の文書のページ番号で管理している注釈や引用のデータベースを、章のファイルに付け替えるため。
+#[derive(Serialize)]
+struct PageMap<'a> {
+    source: String,
+    output: &'a str,
+    pages: Vec<PageMapping<'a>>,
+}
+
+#[derive(Serialize)]
+struct PageMapping<'a> {
+    source_page: u32,
+    /// 元の文書のページラベル（/PageLabels が無い文書では省く）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_label: Option<&'a str>,
+    page: u32,
+}
+
+/// 章のファイルと同じ名前の `.pages.json` を書き出す
+///
+/// `source_pages` は章のファイルのページを順に並べた元の文書でのページ番号（白紙を除いた場合は飛びがある）。
+/// `inserted_pages` は章のページの前に加えたページ（表紙）の数。
+pub fn write(
+    json_path: &Path,
+    source: &Path,
+    output: &str,
+    source_pages: &[u32],
+    inserted_pages: u32,
+    labels: Option<&BTreeMap<u32, String>>,
+) -> Result<()> {
+    let pages = source_pages
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(i, source_page)| PageMapping {
+            source_page,
+            source_label: labels.and_then(|labels| labels.get(&source_page)).map(String::as_str),
+            page: inserted_pages + i as u32 + 1,
+        })
+        .collect();
+    let map = PageMap { source: source.display().to_string(), output, pages };
+    let json = serde_json::to_string_pretty(&map)?;
+    std::fs::write(json_path, json + "\n").with_context(|| format!("Failed to write {:?}", json_path))
+}
+
+/// 章のファイル名の `pdf` を置き換える拡張子
+pub const EXTENSION: &str = "pages.json";
+
+/// `chapter.pdf` に対する `chapter.pages.json`
+pub fn path_for(out_path: &Path) -> std::path::PathBuf {
+    out_path.with_extension(EXTENSION)
+}
@@ -0,0 +1,123 @@
+use crate::page_labels::LogicalPages;
+use anyhow::{Context, Result, bail};
+
+/// `3,7-9,12-` のようなページ範囲の指定（元の文書のページ番号、1始まり）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRanges {
+    /// (開始, 終了)。終了が None なら最後のページまで
+    ranges: Vec<(u32, Option<u32>)>,
+}
+
+impl PageRanges {
+    pub fn contains(&self, page: u32) -> bool {
+        self.ranges.iter().any(|&(start, end)| page >= start && end.is_none_or(|end| page <= end))
+    }
+
+    /// 指定した順に並べたページ番号（終わりの無い範囲は `total` まで。`total` を超えるページは含めない）
+    pub fn expand(&self, total: u32) -> Vec<u32> {
+        self.ranges.iter().flat_map(|&(start, end)| start..=end.unwrap_or(total).min(total)).collect()
+    }
+
+    /// 印刷されたページ番号（`--page-numbering logical`）の指定を通し番号に直す。`option` はエラーに添えるオプション名
+    pub fn to_physical(&self, logical: &LogicalPages, option: &str) -> Result<PageRanges> {
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            let first = logical.physical(start).with_context(|| format!("invalid {}", option))?;
+            let last = end.map(|end| logical.physical(end)).transpose().with_context(|| format!("invalid {}", option))?;
+            if let Some(last) = last
+                && first > last
+            {
+                bail!("invalid {}: printed pages {}-{} are pages {}-{} (start is after end)", option, start, end.unwrap_or(start), first, last);
+            }
+            ranges.push((first, last));
+        }
+        Ok(PageRanges { ranges })
+    }
+}
+
+pub fn parse(text: &str) -> Result<PageRanges> {
+    let mut ranges = Vec::new();
+    for part in text.split(',').map(str::trim) {
+        let parse_page = |s: &str| -> Result<u32> {
+            match s.trim().parse::<u32>() {
+                Ok(page) if page >= 1 => Ok(page),
+                _ => bail!("invalid page number {:?} in {:?}", s.trim(), text),
+            }
+        };
+        let range = match part.split_once('-') {
+            Some((start, "")) => (parse_page(start)?, None),
+            Some((start, end)) => {
+                let (start, end) = (parse_page(start)?, parse_page(end)?);
+                if start > end {
+                    bail!("invalid page range {:?} (start is after end)", part);
+                }
+                (start, Some(end))
+            }
+            None => {
+                let page = parse_page(part)?;
+                (page, Some(page))
+            }
+        };
+        ranges.push(range);
+    }
+    Ok(PageRanges { ranges })
+}
+
+/// ページ番号の並び（昇順）を `parse` と同じ形式で表す（例: [1, 2, 3, 7] → "1-3,7"）
+pub fn format(pages: &[u32]) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < pages.len() {
+        let start = pages[i];
+        while i + 1 < pages.len() && pages[i + 1] == pages[i] + 1 {
+            i += 1;
+        }
+        parts.push(if pages[i] == start { start.to_string() } else { format!("{}-{}", start, pages[i]) });
+        i += 1;
+    }
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_keeps_the_given_order() {
+        assert_eq!(parse("12-,3,7-9").unwrap().expand(14), vec![12, 13, 14, 3, 7, 8, 9]);
+    }
+
+    #[test]
+    fn open_ended_range_runs_to_the_last_page() {
+        let ranges = parse("5-").unwrap();
+        assert_eq!(ranges.expand(7), vec![5, 6, 7]);
+        assert!(ranges.contains(1000));
+        assert!(!ranges.contains(4));
+        // 開始が最後のページより後ろなら何も含めない
+        assert_eq!(ranges.expand(3), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn expand_drops_pages_beyond_the_total() {
+        assert_eq!(parse("2-10").unwrap().expand(4), vec![2, 3, 4]);
+        assert_eq!(parse("9,1").unwrap().expand(4), vec![1]);
+    }
+
+    #[test]
+    fn parse_accepts_spaces_around_parts() {
+        assert_eq!(parse(" 1 , 3 - 4 ").unwrap().expand(10), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_ranges() {
+        for text in ["", "0", "0-3", "5-3", "a", "1,,2", "-3", "1-2-3"] {
+            assert!(parse(text).is_err(), "{:?} should be rejected", text);
+        }
+    }
+
+    #[test]
+    fn format_joins_consecutive_pages() {
+        assert_eq!(format(&[1, 2, 3, 7, 9, 10]), "1-3,7,9-10");
+        assert_eq!(format(&[]), "");
+    }
+}
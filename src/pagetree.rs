@@ -0,0 +1,107 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashSet};
+
+// lopdf のページツリー走査と同じ深さ制限
+const PAGE_TREE_DEPTH_LIMIT: usize = 256;
+
+/// ページツリーを走査してページ番号とページオブジェクトの対応表を作る
+///
+/// `/Kids` に紛れ込んだ null や注釈などのページ以外の葉はスキップし、警告を返す。
+/// `/Type` が欠けた葉でもページとして扱えるものはページとして数える
+/// （ここで数え方がずれると、以降の章の境界がすべてずれる）。
+pub fn collect_pages(doc: &Document) -> (BTreeMap<u32, ObjectId>, Vec<String>) {
+    let mut walker = Walker {
+        doc,
+        pages: BTreeMap::new(),
+        warnings: Vec::new(),
+        visited: HashSet::new(),
+    };
+    match doc.catalog().and_then(|c| c.get(b"Pages")).and_then(|o| o.as_reference()) {
+        Ok(root) => walker.visit_node(root, 0),
+        Err(_) => walker.warnings.push("catalog has no /Pages reference".to_string()),
+    }
+    (walker.pages, walker.warnings)
+}
+
+struct Walker<'a> {
+    doc: &'a Document,
+    pages: BTreeMap<u32, ObjectId>,
+    warnings: Vec<String>,
+    visited: HashSet<ObjectId>,
+}
+
+impl Walker<'_> {
+    fn visit_node(&mut self, node_id: ObjectId, depth: usize) {
+        if depth > PAGE_TREE_DEPTH_LIMIT {
+            self.warnings.push(format!("page tree deeper than {} levels at {:?}; ignoring subtree", PAGE_TREE_DEPTH_LIMIT, node_id));
+            return;
+        }
+        if !self.visited.insert(node_id) {
+            self.warnings.push(format!("page tree node {:?} is referenced more than once; skipped", node_id));
+            return;
+        }
+        let Ok(node) = self.doc.get_dictionary(node_id) else {
+            self.warnings.push(format!("page tree node {:?} is not a dictionary; skipped", node_id));
+            return;
+        };
+        let Ok(kids) = node.get(b"Kids").and_then(|o| self.doc.dereference(o)).and_then(|(_, o)| o.as_array()) else {
+            self.warnings.push(format!("page tree node {:?} has no /Kids array", node_id));
+            return;
+        };
+        for kid in kids {
+            let Ok(kid_id) = kid.as_reference() else {
+                self.warnings.push(format!("skipped non-reference entry in /Kids of {:?}: {:?}", node_id, kid));
+                continue;
+            };
+            match self.doc.get_dictionary(kid_id) {
+                Ok(kid_dict) => match classify(kid_dict) {
+                    NodeKind::Pages => self.visit_node(kid_id, depth + 1),
+                    NodeKind::Page => {
+                        let number = self.pages.len() as u32 + 1;
+                        self.pages.insert(number, kid_id);
+                    }
+                    NodeKind::UntypedPage => {
+                        self.warnings.push(format!("leaf {:?} has no /Type; treating it as page {}", kid_id, self.pages.len() + 1));
+                        let number = self.pages.len() as u32 + 1;
+                        self.pages.insert(number, kid_id);
+                    }
+                    NodeKind::Other(type_name) => {
+                        self.warnings.push(format!("skipped /{} object {:?} found in /Kids", type_name, kid_id));
+                    }
+                },
+                Err(_) => self.warnings.push(format!("skipped non-page object {:?} found in /Kids", kid_id)),
+            }
+        }
+    }
+}
+
+enum NodeKind {
+    Pages,
+    Page,
+    UntypedPage,
+    Other(String),
+}
+
+fn classify(dict: &Dictionary) -> NodeKind {
+    match dict.type_name() {
+        Ok("Pages") => NodeKind::Pages,
+        Ok("Page") => NodeKind::Page,
+        Ok(other) => NodeKind::Other(other.to_string()),
+        // /Type の無い中間ノードや葉を作るジェネレータがある
+        Err(_) if dict.has(b"Kids") => NodeKind::Pages,
+        Err(_) if dict.has(b"Contents") || dict.has(b"MediaBox") || dict.has(b"Resources") => NodeKind::UntypedPage,
+        Err(_) => NodeKind::Other(String::from("(untyped)")),
+    }
+}
+
+/// ページツリーの親からの継承も含めてページの属性を引く
+pub fn inherited(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    for _ in 0..PAGE_TREE_DEPTH_LIMIT {
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        node = doc.get_dictionary(node.get(b"Parent").and_then(Object::as_reference).ok()?).ok()?;
+    }
+    None
+}
@@ -0,0 +1,66 @@
+use crate::csv;
+use crate::plan::PlannedChapter;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// 章ごとに暗号化するパスワード（`--passwords`）
+///
+/// CSV の `chapter` 列には章の番号（1始まり）かタイトルを、`password` 列には閲覧用のパスワードを書く。
+/// 省略できる `owner_password` 列は権限の変更に使うパスワードで、空なら閲覧用と同じにする。
+/// 数字だけの `chapter` は章の番号として扱う。
+#[derive(Debug, Default)]
+pub struct ChapterPasswords {
+    by_number: HashMap<usize, Password>,
+    by_title: HashMap<String, Password>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Password {
+    pub user: String,
+    pub owner: String,
+}
+
+impl ChapterPasswords {
+    pub fn read(path: &Path) -> Result<Self> {
+        let rows = csv::read(path).with_context(|| format!("Failed to read passwords {:?}", path))?;
+        let mut rows = rows.into_iter().enumerate();
+        let header = rows.next().map(|(_, row)| row).unwrap_or_default();
+        let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let (Some(chapter_column), Some(password_column)) = (column("chapter"), column("password")) else {
+            bail!("passwords {:?} must have \"chapter\" and \"password\" columns", path);
+        };
+        let owner_column = column("owner_password");
+        let mut passwords = Self::default();
+        for (line, row) in rows {
+            if row.iter().all(|field| field.trim().is_empty()) {
+                continue;
+            }
+            let chapter = row.get(chapter_column).map(|c| c.trim()).unwrap_or_default();
+            let user = row.get(password_column).cloned().unwrap_or_default();
+            if user.is_empty() {
+                bail!("passwords {:?} row {} has an empty password", path, line + 1);
+            }
+            let owner = owner_column
+                .and_then(|column| row.get(column))
+                .filter(|owner| !owner.is_empty())
+                .cloned()
+                .unwrap_or_else(|| user.clone());
+            let password = Password { user, owner };
+            let duplicate = match chapter.parse::<usize>() {
+                Ok(number) => passwords.by_number.insert(number, password).is_some(),
+                Err(_) if chapter.is_empty() => bail!("passwords {:?} row {} has no chapter", path, line + 1),
+                Err(_) => passwords.by_title.insert(chapter.to_string(), password).is_some(),
+            };
+            if duplicate {
+                bail!("passwords {:?} lists chapter {:?} more than once", path, chapter);
+            }
+        }
+        Ok(passwords)
+    }
+
+    /// 章の番号での指定をタイトルでの指定より優先する
+    pub fn for_chapter(&self, chapter: &PlannedChapter) -> Option<&Password> {
+        self.by_number.get(&(chapter.index + 1)).or_else(|| self.by_title.get(chapter.title.trim()))
+    }
+}
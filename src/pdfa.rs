@@ -0,0 +1,86 @@
+use crate::version::PdfVersion;
+use crate::xmp::catalog_metadata;
+use lopdf::{Document, Object};
+use std::fmt;
+
+/// XMPの `pdfaid` から読み取ったPDF/A準拠レベル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfAConformance {
+    pub part: String,
+    pub conformance: String,
+}
+
+impl PdfAConformance {
+    /// この準拠レベルが許容する最大のPDFバージョン
+    pub fn max_pdf_version(&self) -> PdfVersion {
+        match self.part.as_str() {
+            "1" => PdfVersion::V1_4,
+            "4" => PdfVersion::V2_0,
+            _ => PdfVersion::V1_7,
+        }
+    }
+}
+
+impl fmt::Display for PdfAConformance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PDF/A-{}{}", self.part, self.conformance.to_lowercase())
+    }
+}
+
+/// カタログの /Metadata (XMP) から PDF/A 準拠宣言を検出する
+pub fn detect(doc: &Document) -> Option<PdfAConformance> {
+    let xmp = catalog_metadata(doc)?;
+    let part = xmp_property(&xmp, "pdfaid:part")?;
+    let conformance = xmp_property(&xmp, "pdfaid:conformance").unwrap_or_default();
+    Some(PdfAConformance { part, conformance })
+}
+
+/// カタログに /OutputIntents が設定されているか
+pub fn has_output_intent(doc: &Document) -> bool {
+    doc.catalog()
+        .and_then(|c| c.get(b"OutputIntents"))
+        .and_then(|o| doc.dereference(o))
+        .and_then(|(_, o)| o.as_array())
+        .map(|arr| !arr.is_empty())
+        .unwrap_or(false)
+}
+
+/// 分割後のドキュメントに、準拠維持に必要なカタログ要素とトレーラーの /ID を引き継ぐ
+pub fn preserve(source: &Document, split_doc: &mut Document) {
+    let Ok(source_catalog) = source.catalog() else { return };
+    let carried: Vec<(Vec<u8>, Object)> = [b"OutputIntents".as_slice(), b"Metadata".as_slice()]
+        .iter()
+        .filter_map(|key| source_catalog.get(key).ok().map(|v| (key.to_vec(), v.clone())))
+        .collect();
+
+    if let Ok(catalog) = split_doc.catalog_mut() {
+        for (key, value) in carried {
+            if !catalog.has(&key) {
+                catalog.set(key, value);
+            }
+        }
+    }
+    if !split_doc.trailer.has(b"ID")
+        && let Ok(id) = source.trailer.get(b"ID")
+    {
+        split_doc.trailer.set("ID", id.clone());
+    }
+}
+
+/// `pdfaid:part="2"` 形式と `<pdfaid:part>2</pdfaid:part>` 形式の両方に対応する
+fn xmp_property(xmp: &str, name: &str) -> Option<String> {
+    let attr = format!("{}=", name);
+    if let Some(pos) = xmp.find(&attr) {
+        let rest = &xmp[pos + attr.len()..];
+        let quote = rest.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let value = &rest[1..];
+            return value.find(quote).map(|end| value[..end].trim().to_string());
+        }
+    }
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xmp.find(&open)? + open.len();
+    let end = xmp[start..].find(&close)? + start;
+    Some(xmp[start..end].trim().to_string())
+}
@@ -0,0 +1,421 @@
+use crate::page_labels::LogicalPages;
+use crate::{interrupt, page_map, sanitize_filename, sidecar, text, thumbnails};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+// 計画ファイルの形式を変えたら上げる
+const PLAN_FORMAT: u32 = 1;
+
+/// 分割計画の1章分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedChapter {
+    /// 目次上の章の番号（0始まり、ファイル名の連番に使う）。計画ファイルでは並び順から決める
+    #[serde(skip)]
+    pub index: usize,
+    pub title: String,
+    pub start_page: u32,
+    pub end_page: u32,
+    /// 計画ファイルで省略した場合はタイトルから作る
+    #[serde(default)]
+    pub filename: String,
+}
+
+/// `--plan-out` で書き出し、`--plan` で読み込む計画ファイル
+#[derive(Serialize, Deserialize)]
+struct PlanFile {
+    format: u32,
+    /// 計画を作った入力ファイル（参考情報。読み込み時には照合しない）
+    #[serde(default)]
+    source: String,
+    total_pages: u32,
+    chapters: Vec<PlannedChapter>,
+}
+
+impl PlannedChapter {
+    pub fn page_count(&self) -> u32 {
+        self.end_page - self.start_page + 1
+    }
+}
+
+/// 最後の章の本来の終わりより後ろのページ（索引・奥付など、目次に無い後付け）の扱い（`--back-matter`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BackMatter {
+    /// 最後の章に含める（従来どおり）
+    Append,
+    /// 最後の章から切り離し、`<入力名>_back_matter.pdf` として書き出す
+    Separate,
+    /// 書き出さない
+    Drop,
+}
+
+/// 最後の章を後付けの始まり（`start`）の直前で終わらせ、`policy` に従って後付けを別の章にするか捨てる
+///
+/// 後付けが最後の章の先頭ページ以前から始まる場合は何もしない。
+pub fn split_back_matter(plan: &mut Vec<PlannedChapter>, start: u32, policy: BackMatter, file_stem: &str) {
+    let Some(last) = plan.last_mut() else { return };
+    if policy == BackMatter::Append || start <= last.start_page || start > last.end_page {
+        return;
+    }
+    let (index, end_page) = (last.index + 1, last.end_page);
+    last.end_page = start - 1;
+    if policy == BackMatter::Separate {
+        plan.push(PlannedChapter {
+            index,
+            title: "Back Matter".to_string(),
+            start_page: start,
+            end_page,
+            filename: format!("{}_back_matter.pdf", file_stem),
+        });
+    }
+}
+
+// ファイル名のタイトル部分の既定の上限（文字数）
+const MAX_TITLE_CHARS: usize = 50;
+
+// 1つのパス要素（ファイル名）の上限。ext4 などはバイト数で 255、Windows は UTF-16 で 255 文字
+const MAX_COMPONENT_BYTES: usize = 255;
+
+// パス全体の上限（終端の NUL を除く）。Windows の MAX_PATH は UTF-16 で 260 文字、Linux の PATH_MAX は 4096 バイト
+const MAX_PATH_BYTES: usize = if cfg!(windows) { 259 } else { 4095 };
+
+// 章のファイル名の `pdf` を置き換えて作る名前の拡張子（書き出し中の名前と、--page-map・--sidecar・--thumbnails・--extract-text の出力）
+const DERIVED_EXTENSIONS: [&str; 5] =
+    [interrupt::PARTIAL_EXTENSION, page_map::EXTENSION, sidecar::YAML_EXTENSION, thumbnails::EXTENSION, text::EXTENSION];
+
+// 入力名を切り詰めても、章のファイル名のタイトル部分にはこれだけのバイト数を残す
+const MIN_TITLE_BYTES: usize = 32;
+
+// `output_stem` で章の連番に見込む桁数
+const MAX_INDEX_DIGITS: usize = 6;
+
+/// `output_dir` に置くファイル名（拡張子の前まで）に使えるバイト数
+///
+/// 章のファイル名から作る名前のうち最も長い拡張子でも、ファイル名とパス全体がそれぞれ OS の上限に収まるようにする。
+fn name_budget(output_dir: &Path) -> usize {
+    let dir = std::path::absolute(output_dir).unwrap_or_else(|_| output_dir.to_path_buf());
+    // 区切り文字の分を足す
+    let dir_bytes = dir.as_os_str().len() + 1;
+    let extension_bytes = ".".len() + DERIVED_EXTENSIONS.iter().map(|extension| extension.len()).max().unwrap_or(0);
+    MAX_COMPONENT_BYTES.min(MAX_PATH_BYTES.saturating_sub(dir_bytes)).saturating_sub(extension_bytes)
+}
+
+/// 出力ファイル名の先頭に使う入力名（`<入力名>_chapter_01_…`・`<入力名>_back_matter.pdf` など）
+///
+/// 入力名が長すぎて章のファイル名が OS の上限を超える場合は、タイトル部分に `MIN_TITLE_BYTES` バイトが残るよう
+/// 文字の途中で切らずに切り詰める。出力先のディレクトリのパスが長く、入力名を1文字も残せない場合はエラーにする。
+pub fn output_stem(file_stem: &str, output_dir: &Path) -> Result<String> {
+    let fixed_bytes = "_chapter_".len() + MAX_INDEX_DIGITS + "_".len() + MIN_TITLE_BYTES;
+    let stem = truncate_bytes(file_stem, name_budget(output_dir).saturating_sub(fixed_bytes));
+    if stem.is_empty() && !file_stem.is_empty() {
+        bail!("the output directory {:?} is too long to hold the output file names", output_dir);
+    }
+    Ok(stem.to_string())
+}
+
+/// ファイル名に使うタイトル部分（無害化して切り詰めたもの）を、1回の実行の中で重複しないようにする
+///
+/// 「まとめ?」と「まとめ*」のように無害化すると同じになるタイトルや、同じタイトルの章には、
+/// 2つ目以降に `_2`・`_3` … を付けて区別する。大文字・小文字だけの違いも重複とみなす
+/// （大文字・小文字を区別しないファイルシステムで紛らわしい名前にしない）。
+///
+/// タイトルは50文字までに加え、出力先のパス全体とファイル名がそれぞれ OS の上限に収まるバイト数まで
+/// 切り詰める。長さはすべて UTF-8 のバイト数で数える（UTF-16 で数える Windows でも上限を超えない）。
+struct TitleNames {
+    used: HashMap<String, usize>,
+    max_bytes: usize,
+}
+
+impl TitleNames {
+    /// `output_dir` は出力先のディレクトリ、`prefix` はタイトルの前に付く部分（`output_stem` の入力名で作った `<入力名>_chapter_01_` など）
+    fn new(output_dir: &Path, prefix: &str) -> Self {
+        Self { used: HashMap::new(), max_bytes: name_budget(output_dir).saturating_sub(prefix.len()) }
+    }
+
+    fn unique(&mut self, title: &str) -> String {
+        let safe_title: String = sanitize_filename(title).chars().take(MAX_TITLE_CHARS).collect();
+        let safe_title = truncate_bytes(&safe_title, self.max_bytes).to_string();
+        let count = self.used.entry(safe_title.to_lowercase()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            return safe_title;
+        }
+        let mut suffix = *count;
+        loop {
+            let suffix_text = format!("_{}", suffix);
+            let candidate = format!("{}{}", truncate_bytes(&safe_title, self.max_bytes.saturating_sub(suffix_text.len())), suffix_text);
+            if let Entry::Vacant(entry) = self.used.entry(candidate.to_lowercase()) {
+                entry.insert(1);
+                return candidate;
+            }
+            suffix += 1;
+        }
+    }
+}
+
+/// 文字の途中で切らないように、`max_bytes` バイト以下に切り詰める
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let end = (0..=max_bytes).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0);
+    &text[..end]
+}
+
+/// 章の開始ページ（ソート・重複除去済み）から、各章のページ範囲と出力ファイル名を決める
+///
+/// 出力ファイル名は `output_dir` に置いたときのパスの長さが OS の上限に収まるようにする。`file_stem` は `output_stem` の入力名。
+pub fn plan_chapters(chapter_starts: &[(u32, String)], total_pages: u32, file_stem: &str, output_dir: &Path) -> Vec<PlannedChapter> {
+    let total_chapters = chapter_starts.len();
+    // 桁数（パディング幅）の計算
+    let pad_width = std::cmp::max(2, total_chapters.to_string().len());
+    let mut names = TitleNames::new(output_dir, &format!("{}_chapter_{:0width$}_", file_stem, 0, width = pad_width));
+
+    chapter_starts
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (start_page, title))| {
+            let end_page = if i + 1 < total_chapters {
+                if chapter_starts[i + 1].0 > *start_page {
+                    chapter_starts[i + 1].0 - 1
+                } else {
+                    *start_page
+                }
+            } else {
+                total_pages
+            };
+            if *start_page > end_page {
+                return None;
+            }
+
+            let safe_title_short = names.unique(title);
+
+            // ファイル名生成時にゼロ埋めパディングを適用
+            let filename = format!("{}_chapter_{:0width$}_{}.pdf", file_stem, i + 1, safe_title_short, width = pad_width);
+            Some(PlannedChapter { index: i, title: title.clone(), start_page: *start_page, end_page, filename })
+        })
+        .collect()
+}
+
+/// 目次から選んだ項目（先頭ページ・終了ページ・タイトル）を章にする（GUI の章のツリーで選んだ項目）
+///
+/// 各章は、次に選んだ項目の直前か、項目そのものの終了ページの早い方で終える（子の項目を選んだ親は子の直前まで）。
+/// 選んだ項目に含まれないページは書き出さない。章の番号と出力ファイル名は `plan_chapters` と同じく決める。
+pub fn plan_sections(sections: &[(u32, u32, String)], total_pages: u32, file_stem: &str, output_dir: &Path) -> Vec<PlannedChapter> {
+    let mut sections = sections.to_vec();
+    sections.sort_by_key(|(start_page, _, _)| *start_page);
+    sections.dedup_by_key(|(start_page, _, _)| *start_page);
+    let chapter_starts: Vec<(u32, String)> = sections.iter().map(|(start_page, _, title)| (*start_page, title.clone())).collect();
+    let mut plan = plan_chapters(&chapter_starts, total_pages, file_stem, output_dir);
+    for chapter in &mut plan {
+        chapter.end_page = chapter.end_page.min(sections[chapter.index].1);
+    }
+    plan
+}
+
+/// `--plan-out`: 解決済みの計画（タイトル・ページ範囲・出力ファイル名）を JSON で書き出す
+pub fn write_plan(path: &Path, source: &Path, total_pages: u32, plan: &[PlannedChapter]) -> Result<()> {
+    let file = PlanFile {
+        format: PLAN_FORMAT,
+        source: source.display().to_string(),
+        total_pages,
+        chapters: plan.to_vec(),
+    };
+    let json = serde_json::to_string_pretty(&file)?;
+    fs::write(path, json + "\n").with_context(|| format!("Failed to write plan {:?}", path))
+}
+
+/// `--edit-plan`: 計画を一時ファイルに書き出してエディタで開き、保存された計画を読み直す
+///
+/// エディタは `$VISUAL`、`$EDITOR` の順に探し、どちらも無ければ `vi` を使う（`code --wait` のような引数付きも可）。
+/// 読み直した計画は `--plan` と同じく検証し、誤りがあれば一時ファイルを残して、直して `--plan` で渡せるようにする。
+pub fn edit_plan(
+    source: &Path,
+    total_pages: u32,
+    plan: &[PlannedChapter],
+    file_stem: &str,
+    output_dir: &Path,
+) -> Result<Vec<PlannedChapter>> {
+    let path = std::env::temp_dir().join(format!("{}_plan_{}.json", sanitize_filename(file_stem), std::process::id()));
+    write_plan(&path, source, total_pages, plan)?;
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to start the editor {:?}", editor))?;
+    if !status.success() {
+        let _ = fs::remove_file(&path);
+        bail!("the editor {:?} exited with {}; nothing was split", editor, status);
+    }
+    // 書き出した計画のページ番号は通し番号のため、--page-numbering logical でも読み替えない
+    let edited = read_plan(&path, total_pages, file_stem, output_dir, None)
+        .with_context(|| format!("the edited plan is kept at {:?}; fix it and pass it with --plan", path))?;
+    let _ = fs::remove_file(&path);
+    Ok(edited)
+}
+
+/// `--plan`: 計画ファイルを読み込み、この文書に対して実行できるか確かめる
+///
+/// `logical` があれば、計画ファイルのページ番号を印刷されたページ番号として通し番号に直す。
+pub fn read_plan(
+    path: &Path,
+    total_pages: u32,
+    file_stem: &str,
+    output_dir: &Path,
+    logical: Option<&LogicalPages>,
+) -> Result<Vec<PlannedChapter>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read plan {:?}", path))?;
+    let file: PlanFile = serde_json::from_slice(&data).with_context(|| format!("Invalid plan {:?}", path))?;
+    if file.format != PLAN_FORMAT {
+        bail!("plan {:?} has format {}, expected {}", path, file.format, PLAN_FORMAT);
+    }
+    if file.chapters.is_empty() {
+        bail!("plan {:?} has no chapters", path);
+    }
+    let pad_width = std::cmp::max(2, file.chapters.len().to_string().len());
+    let mut filenames = HashSet::new();
+    let mut names = TitleNames::new(output_dir, &format!("{}_chapter_{:0width$}_", file_stem, 0, width = pad_width));
+    let mut chapters = file.chapters;
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        chapter.index = i;
+        if let Some(logical) = logical {
+            let to_physical = |page| logical.physical(page).with_context(|| format!("plan chapter {}", i + 1));
+            (chapter.start_page, chapter.end_page) = (to_physical(chapter.start_page)?, to_physical(chapter.end_page)?);
+        }
+        if chapter.start_page < 1 || chapter.start_page > chapter.end_page || chapter.end_page > total_pages {
+            bail!(
+                "plan chapter {} has pages {}-{}, but the document has pages 1-{}",
+                i + 1, chapter.start_page, chapter.end_page, total_pages
+            );
+        }
+        if chapter.filename.is_empty() {
+            let safe_title = names.unique(&chapter.title);
+            chapter.filename = format!("{}_chapter_{:0width$}_{}.pdf", file_stem, i + 1, safe_title, width = pad_width);
+        }
+        // 出力は入力と同じディレクトリに限る（計画ファイルからディレクトリの外へ書かせない）
+        if chapter.filename.contains(['/', '\\']) || chapter.filename.starts_with('.') {
+            bail!("plan chapter {} has an invalid filename {:?}", i + 1, chapter.filename);
+        }
+        if !filenames.insert(chapter.filename.clone()) {
+            bail!("plan has more than one chapter named {:?}", chapter.filename);
+        }
+    }
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn names(max_bytes: usize) -> TitleNames {
+        TitleNames { used: HashMap::new(), max_bytes }
+    }
+
+    #[test]
+    fn unique_numbers_repeated_titles() {
+        let mut names = names(100);
+        assert_eq!(names.unique("Intro"), "Intro");
+        assert_eq!(names.unique("Intro"), "Intro_2");
+        assert_eq!(names.unique("Intro"), "Intro_3");
+    }
+
+    #[test]
+    fn unique_skips_suffixes_taken_by_other_titles() {
+        let mut names = names(100);
+        assert_eq!(names.unique("Intro"), "Intro");
+        assert_eq!(names.unique("Intro_2"), "Intro_2");
+        assert_eq!(names.unique("Intro"), "Intro_3");
+    }
+
+    #[test]
+    fn unique_treats_case_only_differences_as_collisions() {
+        let mut names = names(100);
+        assert_eq!(names.unique("Summary"), "Summary");
+        assert_eq!(names.unique("SUMMARY"), "SUMMARY_2");
+        assert_eq!(names.unique("summary_2"), "summary_2_2");
+    }
+
+    #[test]
+    fn unique_separates_titles_that_sanitize_to_the_same_name() {
+        let mut names = names(100);
+        assert_eq!(names.unique("まとめ?"), "まとめ_");
+        assert_eq!(names.unique("まとめ*"), "まとめ__2");
+    }
+
+    #[test]
+    fn unique_truncates_at_char_boundaries() {
+        // 「あ」は UTF-8 で 3 バイト
+        let mut names = names(7);
+        assert_eq!(names.unique("あいうえ"), "あい");
+        assert_eq!(names.unique("あいうえ"), "あ_2");
+    }
+
+    /// 章のファイル名から作る名前のうち最も長いものの、ファイル名とパス全体のバイト数
+    fn longest_derived(dir: &Path, filename: &str) -> (usize, usize) {
+        let longest = DERIVED_EXTENSIONS.iter().map(|extension| extension.len()).max().unwrap();
+        let name = filename.strip_suffix("pdf").unwrap().len() + longest;
+        (name, std::path::absolute(dir).unwrap().as_os_str().len() + 1 + name)
+    }
+
+    #[test]
+    fn output_stem_keeps_short_names() {
+        assert_eq!(output_stem("report", Path::new("out")).unwrap(), "report");
+    }
+
+    #[test]
+    fn long_input_names_fit_with_every_derived_name() {
+        let dir = Path::new("out");
+        let stem = output_stem(&"長".repeat(100), dir).unwrap();
+        assert!(stem.len() < 300 && stem.chars().all(|c| c == '長'));
+        let chapters = [(1, "Introduction".repeat(10)), (3, "Introduction".repeat(10))];
+        for chapter in plan_chapters(&chapters, 5, &stem, dir) {
+            let (name, _) = longest_derived(dir, &chapter.filename);
+            assert!(name <= MAX_COMPONENT_BYTES, "{:?} is {} bytes", chapter.filename, name);
+            assert!(chapter.filename.starts_with(&format!("{}_chapter_0", stem)));
+        }
+    }
+
+    #[test]
+    fn long_output_directories_shorten_the_names() {
+        let dir = PathBuf::from(format!("/{}", "d".repeat(MAX_PATH_BYTES - 150)));
+        let stem = output_stem(&"s".repeat(200), &dir).unwrap();
+        for chapter in plan_chapters(&[(1, "t".repeat(200))], 1, &stem, &dir) {
+            let (_, path) = longest_derived(&dir, &chapter.filename);
+            assert!(path <= MAX_PATH_BYTES, "{} bytes", path);
+        }
+        let too_long = PathBuf::from(format!("/{}", "d".repeat(MAX_PATH_BYTES)));
+        assert!(output_stem("report", &too_long).is_err());
+    }
+
+    #[test]
+    fn plan_sections_ends_chapters_at_the_next_section_or_their_own_end() {
+        // Part I（1-10）とその子の 1.1（2-5）、Part II（11-20）の子の 2.1（12-15）だけを選ぶ
+        let sections = [(12, 15, "2.1".to_string()), (1, 10, "Part I".to_string()), (2, 5, "1.1".to_string())];
+        let plan = plan_sections(&sections, 20, "book", Path::new("."));
+        let ranges: Vec<(u32, u32, &str)> = plan.iter().map(|c| (c.start_page, c.end_page, c.title.as_str())).collect();
+        assert_eq!(ranges, [(1, 1, "Part I"), (2, 5, "1.1"), (12, 15, "2.1")]);
+        assert_eq!(plan[2].filename, "book_chapter_03_2_1.pdf");
+    }
+
+    #[test]
+    fn truncate_bytes_keeps_whole_chars() {
+        assert_eq!(truncate_bytes("abc", 5), "abc");
+        assert_eq!(truncate_bytes("abc", 2), "ab");
+        assert_eq!(truncate_bytes("あいう", 6), "あい");
+        assert_eq!(truncate_bytes("あいう", 5), "あ");
+        assert_eq!(truncate_bytes("あいう", 2), "");
+        assert_eq!(truncate_bytes("aé", 2), "a");
+    }
+}
@@ -0,0 +1,89 @@
+use crate::cert::KeyFile;
+use crate::crypt::StringDecryptor;
+use crate::progress::{InfoLines, Progress};
+use crate::{Args, embedded_files, load_document, sanitize_filename};
+use anyhow::{Context, Result};
+use clap::Parser;
+use lopdf::Document;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// 文書が PDF ポートフォリオ（カタログに /Collection を持つ文書）か
+///
+/// ポートフォリオの本体は表紙の1ページだけで、中身は添付された PDF にある。
+pub fn is_portfolio(doc: &Document) -> bool {
+    doc.catalog().is_ok_and(|catalog| catalog.has(b"Collection"))
+}
+
+/// `unpack` サブコマンド: ポートフォリオに添付された PDF をそれぞれ別のファイルに書き出す
+///
+/// 出力は入力と同じディレクトリの `<入力名>_<番号>_<添付ファイル名>.pdf`。PDF 以外の添付ファイルは書き出さない。
+/// `split` では、書き出した PDF を `split_options`（分割のオプション）でそれぞれ章に分割する。
+pub fn run(
+    input_path: &Path,
+    password: Option<&str>,
+    key_file: Option<&KeyFile>,
+    split: bool,
+    split_options: &[OsString],
+) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    let doc = load_document(input_path, false)?;
+    let decryptor = StringDecryptor::for_document(&doc, password, key_file)?;
+    if !is_portfolio(&doc) {
+        progress.eprintln(format!("Warning: {:?} is not a PDF portfolio (no /Collection); extracting its attached PDFs anyway.", input_path));
+    }
+    let file_stem = sanitize_filename(&input_path.file_stem().context("Invalid file name")?.to_string_lossy());
+    let parent_dir = input_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let attachments = embedded_files::list(&doc, &decryptor);
+    let pdf_count = attachments.iter().filter(|attachment| is_pdf(&attachment.data)).count();
+    let mut written: Vec<PathBuf> = Vec::new();
+    for attachment in attachments {
+        if !is_pdf(&attachment.data) {
+            progress.println(format!("Skipped {:?}: not a PDF.", attachment.name));
+            continue;
+        }
+        let name = display_name(&attachment.name);
+        let out_path = parent_dir.join(format!("{}_{:02}_{}.pdf", file_stem, written.len() + 1, sanitize_filename(name)));
+        std::fs::write(&out_path, &attachment.data).with_context(|| format!("Failed to write {:?}", out_path))?;
+        progress.println(format!("Saved: {} ({} of {})", out_path.display(), written.len() + 1, pdf_count));
+        written.push(out_path);
+    }
+    if written.is_empty() {
+        anyhow::bail!("{:?} has no attached PDFs", input_path);
+    }
+    progress.println(format!("Extracted {} PDFs from {:?}.", written.len(), input_path));
+
+    if split {
+        // 書き出した PDF を一括処理と同じ経路で分割する（オプションの検証もコマンドラインと同じ定義に任せる）
+        let argv = std::iter::once(OsString::from("pdf_splitter"))
+            .chain(written.iter().map(|path| path.as_os_str().to_owned()))
+            .chain(split_options.iter().cloned());
+        let args = Args::try_parse_from(argv).map_err(|e| {
+            let message = e.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            anyhow::anyhow!("invalid split options: {}", first_line.trim_start_matches("error: "))
+        })?;
+        crate::run(&args)?;
+    }
+    Ok(())
+}
+
+/// ポートフォリオのフォルダーに置かれた添付ファイルのキーの `<フォルダー番号>` の接頭辞と、拡張子を除いた名前
+fn display_name(name: &str) -> &str {
+    let name = match name.strip_prefix('<').and_then(|rest| rest.split_once('>')) {
+        Some((id, rest)) if id.bytes().all(|b| b.is_ascii_digit()) => rest,
+        _ => name,
+    };
+    // フォルダー内の添付ファイルは、名前にパスを含むことがある
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    match name.len().checked_sub(4) {
+        Some(end) if name.is_char_boundary(end) && name[end..].eq_ignore_ascii_case(".pdf") => &name[..end],
+        _ => name,
+    }
+}
+
+/// 中身が PDF か（先頭 1024 バイトに `%PDF-` があるか）
+fn is_pdf(data: &[u8]) -> bool {
+    data[..data.len().min(1024)].windows(5).any(|window| window == b"%PDF-")
+}
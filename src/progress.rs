@@ -0,0 +1,363 @@
+use crate::logfile::{Level, LogFile};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 読み込み・解析中のスピナーと、章の書き出し中のプログレスバー
+///
+/// 大きな文書では何分も出力が止まり、固まったと思われて中断されることがあるため進捗を表示する。
+/// 端末でない出力先（リダイレクトやパイプ）では indicatif が描画を省くため、ログだけが残る。
+///
+/// ログの行はすべてチャネル経由で1つの出力スレッドが書き出すため、並列に動くワーカーの行が
+/// 途中で混ざることはない。章の処理中に出す行は `ChapterLog` にためて章の終わりにまとめて送り、
+/// `ordered` の場合は出力スレッドが章の順番に並べ替えてから書き出す。
+/// 通常のログの行の出力先は `info` で決める（`Stderr` では標準出力を `emit` する機械可読な行だけにし、
+/// `Hidden` では表示せずプログレスバーも出さない）。警告とエラーはいずれの場合も標準エラーへ出す。
+/// `--log-file` があれば、画面に出す行に加えて `debug` の行もそのファイルへ書き出す。
+/// `with_observer` で作った場合は、ログの行と書き出しの進み具合を `ProgressEvent` としても送る（GUI の進捗表示）。
+pub struct Progress {
+    multi: MultiProgress,
+    sender: Mutex<Option<Sender<Message>>>,
+    printer: Option<JoinHandle<()>>,
+    next_file: AtomicUsize,
+    /// これまでに出した警告（`Warning` で始まる行）の数
+    warnings: AtomicUsize,
+    observer: Option<Sender<ProgressEvent>>,
+}
+
+/// `Progress::with_observer` の受け取り側へ送る、ログの行と書き出しの進み具合
+#[derive(Clone, Debug)]
+pub enum ProgressEvent {
+    /// 画面に出すログの行（警告・エラーも含む。`debug` の行は送らない）
+    Line(String),
+    /// 書き出すページの総数（ファイルの章を書き始める前）
+    Pages(u64),
+    /// 章の書き出しを始めた（プログレスバーと同じ `[1/12] <ファイル名>` の表示）
+    ChapterStarted(String),
+    /// 章の書き出しを終えた（成否に関わらず送る）。表示と章のページ数
+    ChapterFinished(String, u64),
+}
+
+/// 通常のログの行（警告・エラー以外）の出力先
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InfoLines {
+    Stdout,
+    /// 標準出力を機械可読な結果に空けておく（`jobs`）
+    Stderr,
+    /// 表示しない（`--quiet`。`--log-file` には書く）
+    Hidden,
+}
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+    /// `emit` の行（`info` の指定に関わらず標準出力へ出す）
+    Data,
+    /// ログファイルにだけ書く行
+    Log(Level),
+}
+
+enum Message {
+    Line(Stream, String),
+    /// ファイルの章の数（章順に並べる場合、これを受け取るまで終わりが分からない）
+    FileChapters { file: usize, chapters: usize },
+    Chapter { file: usize, index: usize, lines: Vec<(Stream, String)> },
+}
+
+impl Progress {
+    pub fn new(ordered: bool, info: InfoLines, log_file: Option<LogFile>) -> Self {
+        Self::build(ordered, info, log_file, None)
+    }
+
+    /// ログの行と進み具合を `observer` へも送る（端末には `info` の指定どおりに出す）
+    pub fn with_observer(info: InfoLines, observer: Sender<ProgressEvent>) -> Self {
+        Self::build(false, info, None, Some(observer))
+    }
+
+    fn build(ordered: bool, info: InfoLines, log_file: Option<LogFile>, observer: Option<Sender<ProgressEvent>>) -> Self {
+        let multi = if info == InfoLines::Hidden {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let printer_multi = multi.clone();
+        let printer_observer = observer.clone();
+        let printer = thread::spawn(move || {
+            let mut printer =
+                Printer { multi: printer_multi, ordered, info, log_file, observer: printer_observer, files: HashMap::new() };
+            for message in receiver {
+                printer.handle(message);
+            }
+            printer.flush_all();
+        });
+        Self {
+            multi,
+            sender: Mutex::new(Some(sender)),
+            printer: Some(printer),
+            next_file: AtomicUsize::new(0),
+            warnings: AtomicUsize::new(0),
+            observer,
+        }
+    }
+
+    /// 所要時間の読めない処理（読み込み・解析）のスピナー（戻り値を捨てると表示から消える）
+    pub fn spinner(&self, message: &str) -> ActiveBar {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(ProgressStyle::with_template("{spinner} {msg} [{elapsed}]").expect("valid template"));
+        bar.set_message(message.to_string());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        ActiveBar { bar, multi: self.multi.clone(), pages: None, observer: None }
+    }
+
+    /// 全章の書き出し状況（処理済みページ数と残り時間）
+    pub fn pages(&self, total_pages: u64) -> ProgressBar {
+        let bar = self.multi.add(ProgressBar::new(total_pages));
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} pages [{elapsed} < {eta}]")
+                .expect("valid template"),
+        );
+        self.notify(ProgressEvent::Pages(total_pages));
+        bar
+    }
+
+    /// 書き出し中の1章（戻り値を捨てると表示から消え、`pages` に章のページ数が加算される）
+    pub fn chapter(&self, pages: &ProgressBar, message: String, page_count: u64) -> ActiveBar {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(ProgressStyle::with_template("  {spinner} {msg} [{elapsed}]").expect("valid template"));
+        bar.set_message(message.clone());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        self.notify(ProgressEvent::ChapterStarted(message));
+        ActiveBar { bar, multi: self.multi.clone(), pages: Some((pages.clone(), page_count)), observer: self.observer.clone() }
+    }
+
+    /// スピナーやバーを消して表示から取り除く
+    pub fn finish(&self, bar: &ProgressBar) {
+        bar.finish_and_clear();
+        self.multi.remove(bar);
+    }
+
+    /// 章ごとのログを束ねる単位として、処理するファイルとその章の数を登録する
+    pub fn begin_file(&self, chapters: usize) -> usize {
+        let file = self.next_file.fetch_add(1, Ordering::Relaxed);
+        self.send(Message::FileChapters { file, chapters });
+        file
+    }
+
+    /// 1章分のログ（破棄した時点でまとめて出力スレッドへ送る）
+    pub fn chapter_log(&self, file: usize, index: usize) -> ChapterLog<'_> {
+        ChapterLog { progress: self, file, index, lines: RefCell::new(Vec::new()) }
+    }
+
+    pub fn println(&self, line: impl Into<String>) {
+        self.send(Message::Line(Stream::Stdout, line.into()));
+    }
+
+    pub fn eprintln(&self, line: impl Into<String>) {
+        let line = line.into();
+        self.count_warning(&line);
+        self.send(Message::Line(Stream::Stderr, line));
+    }
+
+    /// これまでに出した警告の数（終了時の要約に載せる）
+    pub fn warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    fn count_warning(&self, line: &str) {
+        if line.starts_with("Warning") {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// ログファイルにだけ書く調査用の行（`--log-file` が無ければ捨てる）
+    pub fn debug(&self, line: impl Into<String>) {
+        self.send(Message::Line(Stream::Log(Level::Debug), line.into()));
+    }
+
+    /// 呼び出し元が画面に表示するエラーを、ログファイルにも残す
+    pub fn log_error(&self, line: impl Into<String>) {
+        self.send(Message::Line(Stream::Log(Level::Error), line.into()));
+    }
+
+    /// 機械可読な結果の行（ログの出力先に関わらず標準出力へ出す）
+    pub fn emit(&self, line: impl Into<String>) {
+        self.send(Message::Line(Stream::Data, line.into()));
+    }
+
+    fn notify(&self, event: ProgressEvent) {
+        if let Some(observer) = &self.observer {
+            // 受け取り側が既に閉じている場合は捨てる
+            let _ = observer.send(event);
+        }
+    }
+
+    fn send(&self, message: Message) {
+        if let Some(sender) = self.sender.lock().expect("output lock poisoned").as_ref() {
+            // 出力スレッドが既に終わっている場合は捨てる
+            let _ = sender.send(message);
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// 送られた行をすべて書き出し終えるまで待つ
+    fn drop(&mut self) {
+        self.sender.lock().expect("output lock poisoned").take();
+        if let Some(printer) = self.printer.take() {
+            let _ = printer.join();
+        }
+    }
+}
+
+/// 処理中のスピナー。途中で失敗して抜けた場合も含め、破棄された時点で表示から取り除く
+///
+/// 章のスピナーは、破棄時に全体のバーへ章のページ数を処理済みとして加算する。
+pub struct ActiveBar {
+    bar: ProgressBar,
+    multi: MultiProgress,
+    pages: Option<(ProgressBar, u64)>,
+    observer: Option<Sender<ProgressEvent>>,
+}
+
+impl Drop for ActiveBar {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        self.multi.remove(&self.bar);
+        if let Some((pages, count)) = &self.pages {
+            pages.inc(*count);
+            if let Some(observer) = &self.observer {
+                let _ = observer.send(ProgressEvent::ChapterFinished(self.bar.message(), *count));
+            }
+        }
+    }
+}
+
+/// 1章の処理中に出す行。途中で抜けた章も、破棄時に（空でも）送って章順の並べ替えを進める
+pub struct ChapterLog<'a> {
+    progress: &'a Progress,
+    file: usize,
+    index: usize,
+    lines: RefCell<Vec<(Stream, String)>>,
+}
+
+impl ChapterLog<'_> {
+    pub fn println(&self, line: impl Into<String>) {
+        self.lines.borrow_mut().push((Stream::Stdout, line.into()));
+    }
+
+    pub fn eprintln(&self, line: impl Into<String>) {
+        let line = line.into();
+        self.progress.count_warning(&line);
+        self.lines.borrow_mut().push((Stream::Stderr, line));
+    }
+
+    pub fn debug(&self, line: impl Into<String>) {
+        self.lines.borrow_mut().push((Stream::Log(Level::Debug), line.into()));
+    }
+}
+
+impl Drop for ChapterLog<'_> {
+    fn drop(&mut self) {
+        let lines = self.lines.take();
+        self.progress.send(Message::Chapter { file: self.file, index: self.index, lines });
+    }
+}
+
+/// 出力スレッド側の状態
+struct Printer {
+    multi: MultiProgress,
+    ordered: bool,
+    info: InfoLines,
+    log_file: Option<LogFile>,
+    observer: Option<Sender<ProgressEvent>>,
+    files: HashMap<usize, PendingChapters>,
+}
+
+/// 章順に並べるため、先に終わった章のログを保留しておく
+#[derive(Default)]
+struct PendingChapters {
+    next: usize,
+    total: Option<usize>,
+    waiting: BTreeMap<usize, Vec<(Stream, String)>>,
+}
+
+impl Printer {
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Line(stream, line) => self.print(&[(stream, line)]),
+            Message::FileChapters { file, chapters } => {
+                if self.ordered {
+                    self.files.entry(file).or_default().total = Some(chapters);
+                }
+            }
+            Message::Chapter { lines, .. } if !self.ordered => self.print(&lines),
+            Message::Chapter { file, index, lines } => {
+                let pending = self.files.entry(file).or_default();
+                pending.waiting.insert(index, lines);
+                let mut ready = Vec::new();
+                while let Some(lines) = pending.waiting.remove(&pending.next) {
+                    ready.extend(lines);
+                    pending.next += 1;
+                }
+                if pending.total == Some(pending.next) {
+                    self.files.remove(&file);
+                }
+                self.print(&ready);
+            }
+        }
+    }
+
+    /// 届かなかった章があっても、保留中のログは捨てずに書き出す
+    fn flush_all(&mut self) {
+        let files: Vec<PendingChapters> = self.files.drain().map(|(_, pending)| pending).collect();
+        for pending in files {
+            let lines: Vec<(Stream, String)> = pending.waiting.into_values().flatten().collect();
+            self.print(&lines);
+        }
+    }
+
+    fn print(&mut self, lines: &[(Stream, String)]) {
+        if lines.is_empty() {
+            return;
+        }
+        if let Some(log_file) = &mut self.log_file {
+            for (stream, line) in lines {
+                let level = match stream {
+                    Stream::Stdout | Stream::Data => Level::Info,
+                    Stream::Stderr if line.starts_with("Error") => Level::Error,
+                    Stream::Stderr => Level::Warn,
+                    Stream::Log(level) => *level,
+                };
+                log_file.write(level, line);
+            }
+        }
+        if let Some(observer) = &self.observer {
+            for (stream, line) in lines {
+                if !matches!(stream, Stream::Log(_)) {
+                    let _ = observer.send(ProgressEvent::Line(line.clone()));
+                }
+            }
+        }
+        self.multi.suspend(|| {
+            for (stream, line) in lines {
+                match stream {
+                    Stream::Stdout => match self.info {
+                        InfoLines::Stdout => println!("{}", line),
+                        InfoLines::Stderr => eprintln!("{}", line),
+                        InfoLines::Hidden => {}
+                    },
+                    Stream::Stderr => eprintln!("{}", line),
+                    Stream::Data => println!("{}", line),
+                    Stream::Log(_) => {}
+                }
+            }
+        });
+    }
+}
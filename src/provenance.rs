@@ -0,0 +1,172 @@
+use crate::embedded_files;
+use crate::plan::PlannedChapter;
+use crate::xmp::{self, Description};
+use anyhow::{Context, Result};
+use lopdf::Document;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 各章に添付する由来情報のファイル名
+pub const ATTACHMENT_NAME: &str = "pdf_splitter-provenance.json";
+
+// `--provenance-xmp` で書き込む XMP プロパティの名前空間
+const XMP_NS: &str = "https://github.com/ricordanza/pdf_splitter/xmp/1.0/";
+
+/// 章のPDFがどの文書のどのページから作られたか（`--embed-provenance` / `--provenance-xmp`）
+///
+/// 元の文書のハッシュと作成日時は入力ファイルごとに一度だけ求め、全章で共有する。
+pub struct Provenance {
+    file_name: String,
+    sha256: String,
+    created_at: SystemTime,
+}
+
+impl Provenance {
+    pub fn for_source(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        let mut reader = BufReader::with_capacity(1 << 20, file);
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let n = reader.read(&mut buf).with_context(|| format!("Failed to read {:?}", path))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(Self {
+            file_name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            sha256: hex(&hasher.finalize()),
+            created_at: SystemTime::now(),
+        })
+    }
+
+    /// 由来情報の JSON を添付ファイルとして章の文書に加える
+    pub fn embed(&self, doc: &mut Document, chapter: &PlannedChapter) -> Result<()> {
+        let record = json!({
+            "tool": env!("CARGO_PKG_NAME"),
+            "tool_version": env!("CARGO_PKG_VERSION"),
+            "source": { "file_name": self.file_name, "sha256": self.sha256 },
+            "chapter": chapter.index + 1,
+            "title": chapter.title,
+            "source_pages": { "start": chapter.start_page, "end": chapter.end_page },
+            "created_at": self.rfc3339(),
+        });
+        let data = serde_json::to_vec_pretty(&record)?;
+        embedded_files::add(
+            doc,
+            ATTACHMENT_NAME,
+            data,
+            "application/json",
+            "Provenance of this chapter (source file, SHA-256 and page range)",
+            &pdf_date(self.created_at),
+        )
+    }
+
+    /// 添付ファイルを取り除くツールを通しても残るよう、同じ由来情報を XMP のプロパティとしても書き込む
+    pub fn xmp_description(&self, chapter: &PlannedChapter) -> Description {
+        Description {
+            namespaces: &[("pdfsplitter", XMP_NS)],
+            properties: format!(
+                "<pdfsplitter:sourceFile>{}</pdfsplitter:sourceFile>\n\
+                 <pdfsplitter:sourceHash>sha256:{}</pdfsplitter:sourceHash>\n\
+                 <pdfsplitter:sourcePages>{}-{}</pdfsplitter:sourcePages>\n\
+                 <pdfsplitter:chapter>{}</pdfsplitter:chapter>\n\
+                 <pdfsplitter:toolVersion>{}</pdfsplitter:toolVersion>\n\
+                 <pdfsplitter:createdAt>{}</pdfsplitter:createdAt>\n",
+                xmp::escape_text(&self.file_name),
+                self.sha256,
+                chapter.start_page,
+                chapter.end_page,
+                chapter.index + 1,
+                env!("CARGO_PKG_VERSION"),
+                self.rfc3339()
+            ),
+        }
+    }
+
+    fn rfc3339(&self) -> String {
+        let (year, month, day, hour, minute, second) = utc_fields(self.created_at);
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+    }
+}
+
+/// PDF の日付文字列（D:YYYYMMDDHHmmSSZ、UTC）
+pub fn pdf_date(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc_fields(time);
+    format!("D:{:04}{:02}{:02}{:02}{:02}{:02}Z", year, month, day, hour, minute, second)
+}
+
+/// `YYYY-MM-DD`（UTC）
+pub fn utc_date(time: SystemTime) -> String {
+    let (year, month, day, ..) = utc_fields(time);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.mmmZ`（UTC、ミリ秒まで）
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc_fields(time);
+    let millis = time.duration_since(UNIX_EPOCH).map(|d| d.subsec_millis()).unwrap_or(0);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, hour, minute, second, millis)
+}
+
+/// PDF の日付文字列（`D:YYYYMMDDHHmmSSOHH'mm'`。月以降と時差は省略できる）を時刻にする
+pub fn parse_pdf_date(text: &str) -> Option<SystemTime> {
+    let text = text.strip_prefix("D:").unwrap_or(text);
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 {
+        return None;
+    }
+    let field = |start: usize, len: usize, default: i64| -> i64 {
+        text.get(start..start + len).filter(|_| start + len <= digits).and_then(|f| f.parse().ok()).unwrap_or(default)
+    };
+    let (year, month, day) = (field(0, 4, 0), field(4, 2, 1), field(6, 2, 1));
+    let (hour, minute, second) = (field(8, 2, 0), field(10, 2, 0), field(12, 2, 0));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    // 時差（Z、+HH'mm'、-HH'mm'）。UTC にするため、書かれた時差を引く
+    let zone = &text[digits..];
+    let offset = match zone.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            let numbers: Vec<i64> = zone[1..].split('\'').filter(|part| !part.is_empty()).filter_map(|part| part.parse().ok()).collect();
+            let minutes = numbers.first().copied().unwrap_or(0) * 60 + numbers.get(1).copied().unwrap_or(0);
+            if *sign == b'-' { -minutes } else { minutes }
+        }
+        _ => 0,
+    };
+    // H. Hinnant の days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second - offset * 60;
+    u64::try_from(secs).ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// バイト列の16進表記（小文字。SHA-256 のダイジェストなど）
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// UTC の年・月・日・時・分・秒（日付の計算は H. Hinnant の civil_from_days による）
+pub fn utc_fields(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400) as u32);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
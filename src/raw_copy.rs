@@ -0,0 +1,89 @@
+use crate::xref64;
+use lopdf::xref::XrefEntry;
+use lopdf::{Document, ObjectId, Stream};
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// 入力ファイルのバイト列から、無変更のストリームオブジェクトをそのまま切り出す
+///
+/// 辞書を書き換えないストリーム（画像XObjectやフォントプログラム等）は、
+/// lopdf のオブジェクトを直列化し直すより元ファイルの該当範囲をコピーする方が速い。
+/// 元ファイル上の位置と長さが読み込んだ内容と食い違う場合は `None` を返し、呼び出し側で直列化させる。
+pub struct RawSource {
+    map: Mmap,
+    /// 4 GiB を超えるファイルのオブジェクトの位置（`reference_table` の u32 には収まらないため自前で読んだもの）
+    offsets: Option<BTreeMap<u32, (u64, u16)>>,
+}
+
+impl RawSource {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: 処理中に他のプロセスが入力ファイルを書き換えないことを前提とする
+        let map = unsafe { Mmap::map(&file) }?;
+        let offsets = if map.len() as u64 > xref64::LOPDF_MAX_OFFSET {
+            Some(xref64::object_offsets(&map).map_err(io::Error::other)?)
+        } else {
+            None
+        };
+        Ok(Self { map, offsets })
+    }
+
+    /// `N G obj` からストリームデータの末尾までのバイト列（`endstream` 以降は含まない）
+    pub fn stream_bytes(&self, doc: &Document, id: ObjectId, stream: &Stream) -> Option<&[u8]> {
+        let (offset, generation) = match &self.offsets {
+            Some(offsets) => *offsets.get(&id.0)?,
+            None => match doc.reference_table.get(id.0) {
+                Some(XrefEntry::Normal { offset, generation }) => (*offset as u64, *generation),
+                _ => return None,
+            },
+        };
+        if generation != id.1 {
+            return None;
+        }
+        let start = usize::try_from(offset).ok()?;
+        let bytes = self.map.get(start..)?;
+
+        let header = format!("{} {} obj", id.0, id.1);
+        if !bytes.starts_with(header.as_bytes()) {
+            return None;
+        }
+
+        // 辞書の直後の stream キーワードを探し、続く改行（CRLF か LF）の後からがデータ
+        let keyword = find_stream_keyword(bytes)?;
+        let mut data_start = keyword + b"stream".len();
+        if bytes.get(data_start) == Some(&b'\r') {
+            data_start += 1;
+        }
+        if bytes.get(data_start) != Some(&b'\n') {
+            return None;
+        }
+        data_start += 1;
+
+        let data_end = data_start + stream.content.len();
+        let rest = bytes.get(data_end..)?;
+        let trimmed = rest.iter().position(|b| !b.is_ascii_whitespace()).map(|i| &rest[i..])?;
+        if !trimmed.starts_with(b"endstream") {
+            return None;
+        }
+        Some(&bytes[..data_end])
+    }
+}
+
+// ストリーム辞書がこれより長いことは想定しない（見つからなければ直列化に任せる）
+const MAX_DICT_LEN: usize = 1 << 20;
+
+fn find_stream_keyword(bytes: &[u8]) -> Option<usize> {
+    let window = &bytes[..bytes.len().min(MAX_DICT_LEN)];
+    let mut from = 0;
+    loop {
+        let pos = from + window[from..].windows(6).position(|w| w == b"stream")?;
+        // 辞書の閉じ括弧 >> の直後にあるものだけがキーワード
+        if window[..pos].iter().rev().find(|b| !b.is_ascii_whitespace()) == Some(&b'>') {
+            return Some(pos);
+        }
+        from = pos + 1;
+    }
+}
@@ -0,0 +1,73 @@
+use crate::page_ranges::PageRanges;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, save_pages};
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use lopdf::ObjectId;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// よく使う並べ替え（`--preset`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// 最後のページから逆順に並べる
+    Reverse,
+    /// 前半（表面）と後半（裏面）を1ページずつ交互に並べる（裏面を表面と同じ順に読み取ったとき）
+    Interleave,
+    /// 前半（表面）と、逆順にした後半（裏面）を交互に並べる（原稿の束を裏返して裏面を読み取ったとき）
+    InterleaveReverse,
+}
+
+impl Preset {
+    /// `total` ページの文書の、並べ替えた後のページ番号
+    fn order(self, total: u32) -> Vec<u32> {
+        match self {
+            Preset::Reverse => (1..=total).rev().collect(),
+            Preset::Interleave | Preset::InterleaveReverse => {
+                // 表面が1ページ多い（最後の裏面が白紙で読み取られていない）こともある
+                let fronts = total.div_ceil(2);
+                let backs: Vec<u32> = match self {
+                    Preset::InterleaveReverse => (fronts + 1..=total).rev().collect(),
+                    _ => (fronts + 1..=total).collect(),
+                };
+                (1..=fronts).flat_map(|front| std::iter::once(front).chain(backs.get(front as usize - 1).copied())).collect()
+            }
+        }
+    }
+}
+
+/// `reorder` サブコマンド: 分割と同じページのコピー（`save_pages`）で、ページを指定した順に並べ替えて保存する
+///
+/// `order` は `1,3,2,4-` のようにページ番号を出力の順に並べた指定で、載っていないページは出力に含めない。
+/// 同じページを2回並べることはできない。
+pub fn run(input_path: &Path, output_path: &Path, order: Option<&PageRanges>, preset: Option<Preset>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let doc = Arc::new(load_document(input_path, false)?);
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let total = page_numbers.len() as u32;
+    let pages = match (order, preset) {
+        (Some(order), _) => order.expand(total),
+        (None, Some(preset)) => preset.order(total),
+        (None, None) => bail!("Specify --order or --preset"),
+    };
+    if pages.is_empty() {
+        bail!("No pages of {:?} are in --order (the document has {} pages)", input_path, total);
+    }
+    let mut seen = HashSet::new();
+    if let Some(page) = pages.iter().find(|page| !seen.insert(**page)) {
+        bail!("Page {} is listed more than once in --order", page);
+    }
+    let left_out = total as usize - pages.len();
+    if left_out > 0 {
+        progress.eprintln(format!("Warning: {} pages are not in --order and are left out.", left_out));
+    }
+    let page_ids: Vec<ObjectId> = pages.iter().filter_map(|page| page_numbers.get(page).copied()).collect();
+
+    save_pages(&doc, input_path, &page_ids, output_path, &progress)?;
+    progress.println(format!("Wrote {:?} with {} pages in the new order.", output_path, page_ids.len()));
+    Ok(())
+}
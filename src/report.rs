@@ -0,0 +1,13 @@
+use crate::manifest::SkippedBookmark;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// `--report`: 宛先を解決できなかった目次項目を1行ずつ書き出す（無ければ見出しだけの空の一覧になる）
+pub fn write_skipped(path: &Path, source: &Path, skipped: &[SkippedBookmark]) -> Result<()> {
+    let mut text = format!("# Bookmarks skipped in {:?}: {}\n", source, skipped.len());
+    for bookmark in skipped {
+        let _ = writeln!(text, "'{}': {}", bookmark.title, bookmark.reason);
+    }
+    std::fs::write(path, text).with_context(|| format!("Failed to write {:?}", path))
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use std::io;
+use std::time::Duration;
+
+/// 章のファイルの書き出しを、一時的な入出力の失敗でやり直す回数と最初の待ち時間（`--io-retries`・`--io-retry-delay`）
+///
+/// NFS・SMB などのネットワーク上の共有に書き出すと、`EIO` やロックの競合が一時的に起きることがある。
+/// そうした失敗に限り、待ち時間を倍にしながら書き直す。それ以外の失敗（容量不足・権限など）はすぐに失敗にする。
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// `operation` を実行し、一時的な失敗ならやり直す。結果と試した回数を返す
+    ///
+    /// やり直す前に `on_retry` に失敗と次の試行までの待ち時間を渡す。
+    pub fn run<T>(
+        &self,
+        mut operation: impl FnMut() -> Result<T>,
+        mut on_retry: impl FnMut(&anyhow::Error, Duration),
+    ) -> (Result<T>, u32) {
+        let mut attempt = 1;
+        let mut delay = self.delay;
+        loop {
+            match operation() {
+                Err(e) if attempt <= self.retries && is_transient(&e) && !crate::interrupt::is_interrupted() => {
+                    on_retry(&e, delay);
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                    delay = delay.saturating_mul(2);
+                }
+                result => return (result, attempt),
+            }
+        }
+    }
+}
+
+/// 書き直せば済む可能性のある入出力の失敗か
+pub fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().filter_map(|cause| cause.downcast_ref::<io::Error>()).any(|e| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ResourceBusy
+                | io::ErrorKind::StaleNetworkFileHandle
+        ) || e.raw_os_error().is_some_and(is_transient_os_error)
+    })
+}
+
+#[cfg(unix)]
+fn is_transient_os_error(code: i32) -> bool {
+    [libc::EIO, libc::ENOLCK, libc::EDEADLK].contains(&code)
+}
+
+#[cfg(windows)]
+fn is_transient_os_error(code: i32) -> bool {
+    // ERROR_SHARING_VIOLATION・ERROR_LOCK_VIOLATION・ERROR_NETNAME_DELETED・ERROR_UNEXP_NET_ERR
+    [32, 33, 64, 59].contains(&code)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_transient_os_error(_code: i32) -> bool {
+    false
+}
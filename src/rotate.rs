@@ -0,0 +1,40 @@
+use crate::extract::inherited_attribute;
+use crate::page_edit;
+use crate::page_ranges::PageRanges;
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, save_document};
+use anyhow::{Result, bail};
+use lopdf::{Object, ObjectId};
+use std::path::Path;
+
+/// `rotate` サブコマンド: 分割せずに、指定したページの /Rotate を時計回りに `degrees` だけ変えて保存する
+///
+/// ページの内容には触れないため、暗号化された文書もそのまま（元の暗号化のまま）扱える。
+/// `pages` を省略するとすべてのページを回転する。
+pub fn run(input_path: &Path, output_path: &Path, pages: Option<&PageRanges>, degrees: i64) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut doc = load_document(input_path, false)?;
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let (source_pages, page_ids): (Vec<u32>, Vec<ObjectId>) = page_numbers.into_iter().unzip();
+
+    // page_edit::rotate は葉のページの /Rotate だけを見るため、親ノードから継承した回転を先にページへ移す
+    for page_id in &page_ids {
+        let Ok(page) = doc.get_dictionary(*page_id) else { continue };
+        if !page.has(b"Rotate")
+            && let Some(rotation) = inherited_attribute(&doc, page, b"Rotate")
+            && let Ok(page) = doc.get_object_mut(*page_id).and_then(Object::as_dict_mut)
+        {
+            page.set("Rotate", rotation);
+        }
+    }
+    let rotated = page_edit::rotate(&mut doc, &page_ids, &source_pages, degrees, pages);
+    if rotated == 0 {
+        bail!("No pages of {:?} are in --pages (the document has {} pages)", input_path, page_ids.len());
+    }
+    save_document(&doc, output_path)?;
+    progress.println(format!("Rotated {} of {} pages by {} degrees; wrote {:?}.", rotated, page_ids.len(), degrees, output_path));
+    Ok(())
+}
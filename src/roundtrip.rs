@@ -0,0 +1,114 @@
+use crate::{load_document, pagetree};
+use lopdf::{Document, Object, ObjectId};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// 1つの出力と、そこに書き出したはずの元のページ
+pub struct ExpectedOutput<'a> {
+    pub path: &'a Path,
+    /// 出力の先頭に挿入したページ（表紙）の数
+    pub inserted_pages: u32,
+    /// 出力の末尾に加えたページ（`--keep-full-outline=placeholder` の説明のページ）の数
+    pub appended_pages: u32,
+    /// 出力のページ順に並べた元のページ番号
+    pub source_pages: Vec<u32>,
+}
+
+/// 元の文書との突き合わせの結果
+#[derive(Debug, Default)]
+pub struct Roundtrip {
+    /// 元のページの欠落・重複・内容の不一致（検証失敗として扱う）
+    pub problems: Vec<String>,
+    /// どの章の範囲にも入っていない元のページ（計画どおりなら情報のみ）
+    pub uncovered: Vec<u32>,
+    /// 内容が一致した出力のページ数
+    pub matched_pages: usize,
+}
+
+impl Roundtrip {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// 全出力を読み直し、計画した元のページがちょうど1回ずつ、同じ内容で書き出されているかを確かめる（`--verify-roundtrip`）
+///
+/// ページの内容は内容ストリームごとの（展開した）SHA-256 で比べる。フッターや透かしなどで前後に
+/// ストリームが加わっていても、元のページのストリームが同じ順に連続して含まれていれば一致とみなす。
+/// `chapter_ranges` は計画の各章のページ範囲で、どれにも入らないページを `uncovered` に集める。
+pub fn verify(
+    source: &Document,
+    page_numbers: &BTreeMap<u32, ObjectId>,
+    chapter_ranges: &[(u32, u32)],
+    outputs: &[ExpectedOutput],
+) -> Roundtrip {
+    let mut result = Roundtrip::default();
+
+    let mut written: BTreeMap<u32, Vec<&Path>> = BTreeMap::new();
+    for output in outputs {
+        for page in &output.source_pages {
+            written.entry(*page).or_default().push(output.path);
+        }
+    }
+    for (page, paths) in &written {
+        if paths.len() > 1 {
+            let names: Vec<String> = paths.iter().map(|path| format!("{:?}", file_name(path))).collect();
+            result.problems.push(format!("page {} is written {} times ({})", page, paths.len(), names.join(", ")));
+        }
+    }
+    result.uncovered = page_numbers
+        .keys()
+        .filter(|page| !chapter_ranges.iter().any(|(start, end)| (start..=end).contains(page)))
+        .copied()
+        .collect();
+
+    for output in outputs {
+        let name = file_name(output.path);
+        let doc = match load_document(output.path, false) {
+            Ok(doc) => doc,
+            Err(e) => {
+                result.problems.push(format!("{:?}: failed to parse: {}", name, e.root_cause()));
+                continue;
+            }
+        };
+        let (pages, _) = pagetree::collect_pages(&doc);
+        let expected = (output.inserted_pages + output.appended_pages) as usize + output.source_pages.len();
+        if pages.len() != expected {
+            result.problems.push(format!("{:?}: expected {} pages, found {}", name, expected, pages.len()));
+            continue;
+        }
+        for (page, output_page_id) in output.source_pages.iter().zip(pages.values().skip(output.inserted_pages as usize)) {
+            let Some(source_page_id) = page_numbers.get(page) else {
+                result.problems.push(format!("{:?}: page {} is not in the source", name, page));
+                continue;
+            };
+            let source_hashes = content_hashes(source, *source_page_id);
+            let output_hashes = content_hashes(&doc, *output_page_id);
+            let matches = source_hashes.is_empty()
+                || output_hashes.windows(source_hashes.len()).any(|window| window == source_hashes.as_slice());
+            if matches {
+                result.matched_pages += 1;
+            } else {
+                result.problems.push(format!("{:?}: the content of page {} differs from the source", name, page));
+            }
+        }
+    }
+    result
+}
+
+/// ページの内容ストリームごとのハッシュ（展開できないストリームは、暗号化されたものも含めてそのままのバイト列で計算する）
+fn content_hashes(doc: &Document, page_id: ObjectId) -> Vec<[u8; 32]> {
+    doc.get_page_contents(page_id)
+        .into_iter()
+        .filter_map(|id| doc.get_object(id).and_then(Object::as_stream).ok())
+        .map(|stream| {
+            let data = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+            Sha256::digest(&data).into()
+        })
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
@@ -0,0 +1,107 @@
+use crate::progress::{InfoLines, Progress};
+use crate::provenance::{pdf_date, utc_timestamp};
+use crate::xmp::{self, DC_NS, Description, PDF_NS, XMP_BASIC_NS};
+use crate::{encode_pdf_string, info_dict_mut, load_document, save_document};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// `set-metadata` で書き換える項目（`None` の項目は元のまま）
+pub struct MetadataOptions<'a> {
+    pub title: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub keywords: Option<&'a str>,
+    /// 元の XMP パケットの代わりに使う XMP ファイル
+    pub xmp: Option<&'a Path>,
+}
+
+/// `set-metadata` サブコマンド: 文書情報辞書と XMP メタデータの両方を、同じ値で書き換えて保存する
+///
+/// 章ごとのメタデータ（`--title-path`・`--series-metadata`）と同じく、文書情報辞書の文字列を書き換え、
+/// XMP は `xmp::with_descriptions` で書き足したパケットを新しいメタデータストリームにする。
+/// 書き換える XMP のプロパティは先にパケットから取り除き、古い値と食い違わないようにする。更新日時も両方で今の時刻にする。
+pub fn run(input_path: &Path, output_path: &Path, options: &MetadataOptions) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let fields = [("Title", options.title), ("Author", options.author), ("Subject", options.subject), ("Keywords", options.keywords)];
+    if options.xmp.is_none() && fields.iter().all(|(_, value)| value.is_none()) {
+        bail!("Nothing to set; give at least one of --title, --author, --subject, --keywords or --xmp");
+    }
+    let mut doc = load_document(input_path, false)?;
+    // 文書情報辞書の文字列とメタデータストリームは暗号化されるため、平文で書き込めない
+    if doc.is_encrypted() {
+        bail!("{:?} is encrypted; decrypt it first with `pdf_splitter decrypt`", input_path);
+    }
+    let packet = match options.xmp {
+        Some(path) => {
+            let packet = std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+            if !packet.contains("</rdf:RDF>") {
+                bail!("{:?} is not an XMP packet (no </rdf:RDF>)", path);
+            }
+            Some(packet)
+        }
+        None => xmp::catalog_metadata(&doc),
+    };
+
+    let now = SystemTime::now();
+    let info = info_dict_mut(&mut doc);
+    for (key, value) in fields {
+        if let Some(value) = value {
+            info.set(key, encode_pdf_string(value));
+        }
+    }
+    info.set("ModDate", lopdf::Object::string_literal(pdf_date(now)));
+
+    let mut replaced = vec!["xmp:ModifyDate", "xmp:MetadataDate"];
+    let mut dc = String::new();
+    if let Some(title) = options.title {
+        replaced.push("dc:title");
+        dc.push_str(&format!("<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n", xmp::escape_text(title)));
+    }
+    if let Some(author) = options.author {
+        replaced.push("dc:creator");
+        dc.push_str(&format!("<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n", xmp::escape_text(author)));
+    }
+    if let Some(subject) = options.subject {
+        replaced.push("dc:description");
+        dc.push_str(&format!(
+            "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            xmp::escape_text(subject)
+        ));
+    }
+    let mut pdf = String::new();
+    if let Some(keywords) = options.keywords {
+        replaced.push("pdf:Keywords");
+        pdf.push_str(&format!("<pdf:Keywords>{}</pdf:Keywords>\n", xmp::escape_text(keywords)));
+    }
+    let timestamp = utc_timestamp(now);
+    let mut descriptions = vec![Description {
+        namespaces: &[("xmp", XMP_BASIC_NS)],
+        properties: format!("<xmp:ModifyDate>{}</xmp:ModifyDate>\n<xmp:MetadataDate>{}</xmp:MetadataDate>\n", timestamp, timestamp),
+    }];
+    if !dc.is_empty() {
+        descriptions.push(Description { namespaces: &[("dc", DC_NS)], properties: dc });
+    }
+    if !pdf.is_empty() {
+        descriptions.push(Description { namespaces: &[("pdf", PDF_NS)], properties: pdf });
+    }
+    let packet = packet.map(|packet| xmp::without_properties(&packet, &replaced));
+    // 古い値が残ったストリームを出力に持ち越さない
+    if let Ok(old_id) = doc.catalog().and_then(|catalog| catalog.get(b"Metadata")).and_then(lopdf::Object::as_reference) {
+        doc.objects.remove(&old_id);
+    }
+    xmp::set_catalog_metadata(&mut doc, xmp::with_descriptions(packet.as_deref(), &descriptions));
+
+    save_document(&doc, output_path)?;
+    let changed: Vec<&str> = fields.iter().filter(|(_, value)| value.is_some()).map(|(key, _)| *key).collect();
+    progress.println(format!(
+        "Updated {}{} in the info dictionary and XMP; wrote {:?}.",
+        if changed.is_empty() { "the modification date".to_string() } else { changed.join(", ") },
+        if options.xmp.is_some() { " (XMP from the given packet)" } else { "" },
+        output_path
+    ));
+    Ok(())
+}
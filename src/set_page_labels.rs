@@ -0,0 +1,33 @@
+use crate::page_labels::{self, LabelSpec};
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, save_document};
+use anyhow::{Result, bail};
+use std::path::Path;
+
+/// `set-pagelabels` サブコマンド: /PageLabels を `spec` の範囲で作り直して保存する
+///
+/// 元のページラベルはすべて捨てる。接頭辞の無い指定なら、暗号化された文書もそのまま（元の暗号化のまま）扱える。
+pub fn run(input_path: &Path, output_path: &Path, spec: &LabelSpec) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    if output_path.canonicalize().ok().is_some_and(|output| input_path.canonicalize().ok() == Some(output)) {
+        bail!("The output {:?} must differ from the input", output_path);
+    }
+    let mut doc = load_document(input_path, false)?;
+    // 接頭辞の文字列は暗号化されるため、平文で書き込めない
+    if doc.is_encrypted() && spec.ranges.iter().any(|(_, range)| !range.prefix.is_empty()) {
+        bail!("{:?} is encrypted; label prefixes need it decrypted first with `pdf_splitter decrypt`", input_path);
+    }
+    let total = pagetree::collect_pages(&doc).0.len() as u32;
+    if let Some((page, _)) = spec.ranges.iter().find(|(page, _)| *page > total) {
+        bail!("--spec has a label range starting at page {}, but {:?} has only {} pages", page, input_path, total);
+    }
+
+    page_labels::set(&mut doc, spec)?;
+    save_document(&doc, output_path)?;
+    for (i, (first, range)) in spec.ranges.iter().enumerate() {
+        let last = spec.ranges.get(i + 1).map_or(total, |(next, _)| next - 1);
+        progress.println(format!("  pp.{}-{}: {} .. {}", first, last, range.label(0), range.label(last - first)));
+    }
+    progress.println(format!("Set {} page label ranges; wrote {:?}.", spec.ranges.len(), output_path));
+    Ok(())
+}
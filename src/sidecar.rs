@@ -0,0 +1,79 @@
+use crate::page_ranges;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// `--sidecar` の形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SidecarFormat {
+    /// 静的サイトジェネレーターがフロントマターとして読める YAML
+    Yaml,
+}
+
+/// `--sidecar yaml` の拡張子（章のファイル名の `pdf` を置き換える）
+pub const YAML_EXTENSION: &str = "yaml";
+
+/// 章のファイルの横に書き出す、章の情報
+pub struct ChapterInfo<'a> {
+    /// 章の番号（1始まり）
+    pub chapter: usize,
+    pub title: &'a str,
+    pub source: &'a Path,
+    pub output: &'a str,
+    /// 章のファイルのページを順に並べた元の文書でのページ番号
+    pub source_pages: &'a [u32],
+    /// 元の文書のページラベル（/PageLabels が無い文書では `None`）
+    pub labels: Option<&'a BTreeMap<u32, String>>,
+    /// 本文の語数（`--extract-text` のときだけ）
+    pub word_count: Option<usize>,
+}
+
+/// 章のファイルと同じ名前の `.yaml` を書き出す
+pub fn write(path: &Path, format: SidecarFormat, info: &ChapterInfo) -> Result<()> {
+    let text = match format {
+        SidecarFormat::Yaml => render_yaml(info),
+    };
+    std::fs::write(path, text).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// `chapter.pdf` に対する `chapter.yaml`
+pub fn path_for(out_path: &Path, format: SidecarFormat) -> PathBuf {
+    match format {
+        SidecarFormat::Yaml => out_path.with_extension(YAML_EXTENSION),
+    }
+}
+
+/// 1つの YAML 文書（キーは固定の順）。文字列は JSON と同じ書き方の二重引用符の文字列にする（YAML としても正しい）
+fn render_yaml(info: &ChapterInfo) -> String {
+    let mut out = String::from("---\n");
+    let mut field = |key: &str, value: String| {
+        let _ = writeln!(out, "{}: {}", key, value);
+    };
+    field("title", quote(info.title));
+    field("chapter", info.chapter.to_string());
+    field("source", quote(&info.source.display().to_string()));
+    field("output", quote(info.output));
+    field("pages", quote(&page_ranges::format(info.source_pages)));
+    if let (Some(first), Some(last)) = (info.source_pages.first(), info.source_pages.last()) {
+        field("start_page", first.to_string());
+        field("end_page", last.to_string());
+    }
+    field("page_count", info.source_pages.len().to_string());
+    if let Some(labels) = info.labels {
+        let label = |page: Option<&u32>| page.and_then(|page| labels.get(page));
+        if let (Some(first), Some(last)) = (label(info.source_pages.first()), label(info.source_pages.last())) {
+            field("start_label", quote(first));
+            field("end_label", quote(last));
+        }
+    }
+    if let Some(word_count) = info.word_count {
+        field("word_count", word_count.to_string());
+    }
+    out
+}
+
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).expect("strings are serializable")
+}
@@ -0,0 +1,578 @@
+use crate::csv;
+use crate::page_edit::visible_box;
+use anyhow::{Context, Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Helvetica の字幅（AFM、1000 分率）。WinAnsiEncoding の 0x20〜0x7E
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // 空白〜/
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // 0〜?
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // @〜O
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // P〜_
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // `〜o
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // p〜~
+];
+
+// WinAnsiEncoding の 0x80〜0x9F に割り当てられた文字（Latin-1 と異なる部分）
+const WIN_ANSI_HIGH: [(char, u8); 27] = [
+    ('€', 0x80), ('‚', 0x82), ('ƒ', 0x83), ('„', 0x84), ('…', 0x85), ('†', 0x86), ('‡', 0x87),
+    ('ˆ', 0x88), ('‰', 0x89), ('Š', 0x8A), ('‹', 0x8B), ('Œ', 0x8C), ('Ž', 0x8E), ('‘', 0x91),
+    ('’', 0x92), ('“', 0x93), ('”', 0x94), ('•', 0x95), ('–', 0x96), ('—', 0x97), ('˜', 0x98),
+    ('™', 0x99), ('š', 0x9A), ('›', 0x9B), ('œ', 0x9C), ('ž', 0x9E), ('Ÿ', 0x9F),
+];
+
+/// ページに重ねて書く文字列のフォント（埋め込まない標準のフォントだけを使う）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayFont {
+    /// 欧文。Helvetica（WinAnsiEncoding）
+    Helvetica,
+    /// 和文を含む場合。Adobe-Japan1 のゴシック体（UniJIS-UCS2-HW-H、半角の欧文は字幅 500 固定）
+    Gothic,
+}
+
+impl OverlayFont {
+    /// 文字列をすべて表せるフォントを選ぶ
+    pub fn for_text(text: &str) -> Self {
+        if text.chars().all(|c| win_ansi_code(c).is_some()) { Self::Helvetica } else { Self::Gothic }
+    }
+
+    /// 文字列をこのフォントの符号化で PDF の文字列にする（表せない文字は `?`）
+    pub fn encode(self, text: &str) -> Object {
+        let bytes = match self {
+            Self::Helvetica => text.chars().map(|c| win_ansi_code(c).unwrap_or(b'?')).collect(),
+            Self::Gothic => text
+                .chars()
+                .flat_map(|c| u16::try_from(u32::from(c)).unwrap_or(u16::from(b'?')).to_be_bytes())
+                .collect(),
+        };
+        Object::String(bytes, StringFormat::Hexadecimal)
+    }
+
+    /// 文字列の幅（ポイント）。中央揃えに使うおおよその値
+    pub fn width(self, text: &str, size: f32) -> f32 {
+        let units: u32 = text
+            .chars()
+            .map(|c| match self {
+                Self::Helvetica => match c {
+                    ' '..='~' => u32::from(HELVETICA_WIDTHS[c as usize - 0x20]),
+                    '—' | '…' | '‰' | '™' => 1000,
+                    '‘' | '’' | '‚' => 222,
+                    '“' | '”' | '„' => 333,
+                    _ => 556,
+                },
+                Self::Gothic if c.is_ascii() || ('\u{FF61}'..='\u{FF9F}').contains(&c) => 500,
+                Self::Gothic => 1000,
+            })
+            .sum();
+        units as f32 * size / 1000.0
+    }
+
+    /// フォント辞書を文書に加えてIDを返す
+    pub fn add_to(self, doc: &mut Document) -> ObjectId {
+        match self {
+            Self::Helvetica => {
+                let mut font = Dictionary::new();
+                font.set("Type", "Font");
+                font.set("Subtype", "Type1");
+                font.set("BaseFont", "Helvetica");
+                font.set("Encoding", "WinAnsiEncoding");
+                doc.add_object(font)
+            }
+            Self::Gothic => {
+                let mut system_info = Dictionary::new();
+                system_info.set("Registry", Object::string_literal("Adobe"));
+                system_info.set("Ordering", Object::string_literal("Japan1"));
+                system_info.set("Supplement", 2);
+                let mut descriptor = Dictionary::new();
+                descriptor.set("Type", "FontDescriptor");
+                descriptor.set("FontName", "HeiseiKakuGo-W5");
+                descriptor.set("Flags", 4);
+                descriptor.set("FontBBox", vec![(-92).into(), (-250).into(), 1010.into(), 922.into()]);
+                descriptor.set("ItalicAngle", 0);
+                descriptor.set("Ascent", 880);
+                descriptor.set("Descent", -120);
+                descriptor.set("CapHeight", 737);
+                descriptor.set("StemV", 114);
+                let descriptor_id = doc.add_object(descriptor);
+                let mut cid_font = Dictionary::new();
+                cid_font.set("Type", "Font");
+                cid_font.set("Subtype", "CIDFontType0");
+                cid_font.set("BaseFont", "HeiseiKakuGo-W5");
+                cid_font.set("CIDSystemInfo", system_info);
+                cid_font.set("FontDescriptor", descriptor_id);
+                cid_font.set("DW", 1000);
+                // 半角の欧文と半角カナ
+                cid_font.set("W", vec![231.into(), 389.into(), 500.into()]);
+                let cid_font_id = doc.add_object(cid_font);
+                let mut font = Dictionary::new();
+                font.set("Type", "Font");
+                font.set("Subtype", "Type0");
+                font.set("BaseFont", "HeiseiKakuGo-W5-UniJIS-UCS2-HW-H");
+                font.set("Encoding", "UniJIS-UCS2-HW-H");
+                font.set("DescendantFonts", vec![cid_font_id.into()]);
+                doc.add_object(font)
+            }
+        }
+    }
+}
+
+fn win_ansi_code(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '\u{A0}'..='\u{FF}' => Some(c as u32 as u8),
+        _ => WIN_ANSI_HIGH.iter().find(|(ch, _)| *ch == c).map(|(_, code)| *code),
+    }
+}
+
+/// 見た目の向き（/Rotate を適用した後）でのページの大きさと、その座標からユーザー空間への変換行列
+///
+/// 行列を `cm` で設定すると、原点が見た目の左下、x 軸が右向き、y 軸が上向きになる。
+pub fn visual_frame(doc: &Document, page_id: ObjectId) -> Option<(f32, f32, [f32; 6])> {
+    let [x0, y0, x1, y1] = visible_box(doc, page_id)?;
+    let rotate = doc
+        .get_dictionary(page_id)
+        .ok()?
+        .get(b"Rotate")
+        .and_then(Object::as_i64)
+        .unwrap_or(0)
+        .rem_euclid(360);
+    let (width, height) = (x1 - x0, y1 - y0);
+    Some(match rotate {
+        90 => (height, width, [0.0, 1.0, -1.0, 0.0, x1, y0]),
+        180 => (width, height, [-1.0, 0.0, 0.0, -1.0, x1, y1]),
+        270 => (height, width, [0.0, -1.0, 1.0, 0.0, x0, y1]),
+        _ => (width, height, [1.0, 0.0, 0.0, 1.0, x0, y0]),
+    })
+}
+
+/// ページの既存の内容の後ろに描画命令を重ねる
+///
+/// 既存の内容がグラフィックス状態を戻さずに終わっていても影響を受けないよう、
+/// 既存の内容の前後を q/Q で囲む。`resources` は (リソースの種類, 名前, オブジェクト)。
+pub fn overlay(doc: &mut Document, page_id: ObjectId, content: Vec<u8>, resources: &[(&str, &str, ObjectId)]) -> Result<()> {
+    let contents = {
+        let page = doc.get_dictionary(page_id).with_context(|| format!("page {} {} R is not a dictionary", page_id.0, page_id.1))?;
+        match page.get(b"Contents") {
+            Ok(Object::Reference(id)) => match doc.get_object(*id) {
+                // /Contents が配列への間接参照の場合
+                Ok(Object::Array(items)) => items.clone(),
+                _ => vec![Object::Reference(*id)],
+            },
+            Ok(Object::Array(items)) => items.clone(),
+            _ => Vec::new(),
+        }
+    };
+    let save_id = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let mut overlay = b"Q\n".to_vec();
+    overlay.extend(content);
+    let overlay_id = doc.add_object(Stream::new(Dictionary::new(), overlay));
+    let mut new_contents = vec![Object::Reference(save_id)];
+    new_contents.extend(contents);
+    new_contents.push(Object::Reference(overlay_id));
+
+    for (category, name, id) in resources {
+        add_resource(doc, page_id, category, name, *id)?;
+    }
+    let page = doc
+        .get_object_mut(page_id)
+        .and_then(Object::as_dict_mut)
+        .with_context(|| format!("page {} {} R is not a dictionary", page_id.0, page_id.1))?;
+    page.set("Contents", new_contents);
+    Ok(())
+}
+
+/// ページの /Resources の `category`（/Font や /ExtGState）に `name` を加える
+///
+/// リソース辞書が間接オブジェクトの場合は、それを共有するほかのページにも同じ名前が加わる（害はない）。
+fn add_resource(doc: &mut Document, page_id: ObjectId, category: &str, name: &str, id: ObjectId) -> Result<()> {
+    let resources_ref = doc
+        .get_dictionary(page_id)
+        .with_context(|| format!("page {} {} R is not a dictionary", page_id.0, page_id.1))?
+        .get(b"Resources")
+        .ok()
+        .cloned();
+    let resources_id = match resources_ref {
+        Some(Object::Reference(id)) if doc.get_dictionary(id).is_ok() => id,
+        other => {
+            // 直接の辞書か、リソースが無いページ。間接オブジェクトにしてから書き換える
+            let dict = match other {
+                Some(Object::Dictionary(dict)) => dict,
+                _ => Dictionary::new(),
+            };
+            let id = doc.add_object(dict);
+            doc.get_object_mut(page_id).and_then(Object::as_dict_mut)?.set("Resources", id);
+            id
+        }
+    };
+    let category_ref = doc.get_dictionary(resources_id)?.get(category.as_bytes()).ok().cloned();
+    match category_ref {
+        Some(Object::Reference(category_id)) => {
+            doc.get_object_mut(category_id)
+                .and_then(Object::as_dict_mut)
+                .with_context(|| {
+                    format!("page resource /{} ({} {} R) of page {} {} R is not a dictionary", category, category_id.0, category_id.1, page_id.0, page_id.1)
+                })?
+                .set(name, id);
+        }
+        other => {
+            let mut dict = match other {
+                Some(Object::Dictionary(dict)) => dict,
+                _ => Dictionary::new(),
+            };
+            dict.set(name, id);
+            doc.get_object_mut(resources_id).and_then(Object::as_dict_mut)?.set(category, dict);
+        }
+    }
+    Ok(())
+}
+
+/// 数値を PDF の内容ストリーム向けに短く書く
+pub fn number(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" { "0".to_string() } else { text.to_string() }
+}
+
+/// PDF の文字列オブジェクトを内容ストリームの字句にする
+pub fn string_operand(object: &Object) -> String {
+    match object {
+        Object::String(bytes, _) => format!("<{}>", bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>()),
+        _ => String::new(),
+    }
+}
+
+/// `--stamp-footer`: 各ページの下端中央に書き込むフッター
+///
+/// テンプレートの `{title}`（章のタイトル）、`{chapter}`（章の番号）、`{n}`（章の中のページ番号）、
+/// `{total}`（章のページ数）、`{source_page}`（元の文書のページ番号）を置き換える。
+pub struct Footer {
+    template: String,
+}
+
+// フッターの文字の大きさと、見た目の下端からベースラインまでの距離（ポイント）
+const FOOTER_SIZE: f32 = 9.0;
+const FOOTER_BASELINE: f32 = 20.0;
+const FOOTER_FONT_NAME: &str = "PdfSplitterFooter";
+
+impl Footer {
+    pub fn new(template: String) -> Self {
+        Self { template }
+    }
+
+    /// 章のすべてのページにフッターを書き込む（`source_pages` は各ページの元の文書でのページ番号）
+    pub fn apply(&self, doc: &mut Document, page_ids: &[ObjectId], source_pages: &[u32], chapter: usize, title: &str) -> Result<()> {
+        let total = page_ids.len();
+        let template = self.template.replace("{title}", title).replace("{chapter}", &chapter.to_string());
+        // ページ番号は数字だけなので、タイトルを埋めた時点でフォントを決められる
+        let font = OverlayFont::for_text(&template);
+        let font_id = font.add_to(doc);
+        for (n, (page_id, source_page)) in page_ids.iter().zip(source_pages).enumerate() {
+            let text = template
+                .replace("{n}", &(n + 1).to_string())
+                .replace("{total}", &total.to_string())
+                .replace("{source_page}", &source_page.to_string());
+            let Some((width, _, matrix)) = visual_frame(doc, *page_id) else { continue };
+            let x = (width - font.width(&text, FOOTER_SIZE)) / 2.0;
+            let content = format!(
+                "q\n{} cm\nBT\n/{} {} Tf\n{} {} Td\n{} Tj\nET\nQ\n",
+                matrix.iter().map(|v| number(*v)).collect::<Vec<_>>().join(" "),
+                FOOTER_FONT_NAME,
+                number(FOOTER_SIZE),
+                number(x.max(0.0)),
+                number(FOOTER_BASELINE),
+                string_operand(&font.encode(&text)),
+            );
+            overlay(doc, *page_id, content.into_bytes(), &[("Font", FOOTER_FONT_NAME, font_id)])?;
+        }
+        Ok(())
+    }
+}
+
+/// `--watermark`: 各ページの中央に対角線の向きで半透明の文字を書き込む
+///
+/// テンプレートの `{title}`・`{chapter}` と、`--watermark-recipients` で章ごとに指定した `{recipient}` を置き換える。
+pub struct Watermark {
+    template: String,
+    /// 章の番号（1始まり）ごとの配布先
+    recipients: BTreeMap<usize, String>,
+}
+
+// 透明度と、文字の大きさの上限（ポイント）。文字列がページの対角線の 8 割に収まるよう縮める
+const WATERMARK_OPACITY: f32 = 0.25;
+const WATERMARK_MAX_SIZE: f32 = 72.0;
+const WATERMARK_FONT_NAME: &str = "PdfSplitterWatermark";
+const WATERMARK_STATE_NAME: &str = "PdfSplitterWatermarkGS";
+
+impl Watermark {
+    pub fn new(template: String, recipients: BTreeMap<usize, String>) -> Self {
+        Self { template, recipients }
+    }
+
+    /// `{recipient}` を使うのに配布先の指定が無い章の番号（書き出しを始める前に確かめる）
+    pub fn missing_recipients(&self, chapters: impl IntoIterator<Item = usize>) -> Vec<usize> {
+        if !self.template.contains("{recipient}") {
+            return Vec::new();
+        }
+        chapters.into_iter().filter(|chapter| !self.recipients.contains_key(chapter)).collect()
+    }
+
+    /// 章のすべてのページに透かしを書き込む
+    pub fn apply(&self, doc: &mut Document, page_ids: &[ObjectId], chapter: usize, title: &str) -> Result<()> {
+        let text = self
+            .template
+            .replace("{title}", title)
+            .replace("{chapter}", &chapter.to_string())
+            .replace("{recipient}", self.recipients.get(&chapter).map(String::as_str).unwrap_or_default());
+        let font = OverlayFont::for_text(&text);
+        let font_id = font.add_to(doc);
+        let mut state = Dictionary::new();
+        state.set("Type", "ExtGState");
+        state.set("ca", WATERMARK_OPACITY);
+        state.set("CA", WATERMARK_OPACITY);
+        let state_id = doc.add_object(state);
+        let encoded = string_operand(&font.encode(&text));
+        let unit_width = font.width(&text, 1.0);
+
+        for page_id in page_ids {
+            let Some((width, height, matrix)) = visual_frame(doc, *page_id) else { continue };
+            let diagonal = width.hypot(height);
+            let size = if unit_width > 0.0 { (diagonal * 0.8 / unit_width).min(WATERMARK_MAX_SIZE) } else { WATERMARK_MAX_SIZE };
+            let (sin, cos) = height.atan2(width).sin_cos();
+            let content = format!(
+                "q\n{} cm\n/{} gs\n0.5 g\n1 0 0 1 {} {} cm\n{} {} {} {} 0 0 cm\nBT\n/{} {} Tf\n{} {} Td\n{} Tj\nET\nQ\n",
+                matrix.iter().map(|v| number(*v)).collect::<Vec<_>>().join(" "),
+                WATERMARK_STATE_NAME,
+                number(width / 2.0),
+                number(height / 2.0),
+                number(cos),
+                number(sin),
+                number(-sin),
+                number(cos),
+                WATERMARK_FONT_NAME,
+                number(size),
+                number(-unit_width * size / 2.0),
+                // 大文字の高さのおよそ半分だけ下げて、文字列の中心をページの中心に合わせる
+                number(-size * 0.35),
+                encoded,
+            );
+            overlay(
+                doc,
+                *page_id,
+                content.into_bytes(),
+                &[("Font", WATERMARK_FONT_NAME, font_id), ("ExtGState", WATERMARK_STATE_NAME, state_id)],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `--watermark-recipients`: `chapter,recipient` の見出し行と、章の番号と配布先の行が並んだ CSV を読む
+pub fn read_recipients(path: &Path) -> Result<BTreeMap<usize, String>> {
+    let rows = csv::read(path).with_context(|| format!("Failed to read recipients {:?}", path))?;
+    let mut rows = rows.into_iter().enumerate();
+    let header = rows.next().map(|(_, row)| row).unwrap_or_default();
+    let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+    let (Some(chapter_column), Some(recipient_column)) = (column("chapter"), column("recipient")) else {
+        bail!("recipients {:?} must have \"chapter\" and \"recipient\" columns", path);
+    };
+    let mut recipients = BTreeMap::new();
+    for (line, row) in rows {
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let chapter = row.get(chapter_column).map(|c| c.trim()).unwrap_or_default();
+        let Ok(chapter) = chapter.parse::<usize>() else {
+            bail!("recipients {:?} row {} has an invalid chapter number {:?}", path, line + 1, chapter);
+        };
+        let recipient = row.get(recipient_column).cloned().unwrap_or_default();
+        if recipients.insert(chapter, recipient).is_some() {
+            bail!("recipients {:?} lists chapter {} more than once", path, chapter);
+        }
+    }
+    Ok(recipients)
+}
+
+/// `--title-page`: 章の番号・タイトル・元の文書のタイトル・日付を並べた表紙を、章の先頭に加える
+pub struct TitlePage {
+    source_title: String,
+    date: String,
+}
+
+const TITLE_PAGE_FONT_NAME: &str = "PdfSplitterTitle";
+
+impl TitlePage {
+    pub fn new(source_title: String, date: String) -> Self {
+        Self { source_title, date }
+    }
+
+    /// 表紙の大きさは章の先頭ページ（`first_page_id`）の表示範囲に合わせる
+    /// 加えた表紙のページを返す
+    pub fn insert(&self, doc: &mut Document, first_page_id: ObjectId, chapter: usize, title: &str) -> Result<ObjectId> {
+        let [x0, y0, x1, y1] = visible_box(doc, first_page_id).unwrap_or([0.0, 0.0, 595.0, 842.0]);
+        let (width, height) = (x1 - x0, y1 - y0);
+        let chapter_label = format!("Chapter {}", chapter);
+        // (文字列, 大きさ, 上端からの位置の割合)
+        let lines = [
+            (chapter_label.as_str(), 16.0, 0.38),
+            (title, 24.0, 0.45),
+            (self.source_title.as_str(), 12.0, 0.55),
+            (self.date.as_str(), 10.0, 0.60),
+        ];
+        let font = OverlayFont::for_text(&lines.iter().map(|(text, ..)| *text).collect::<String>());
+        let font_id = font.add_to(doc);
+
+        let mut content = String::from("BT\n");
+        for (text, size, position) in lines {
+            // ページの幅の 8 割に収まらない行は縮める
+            let text_width = font.width(text, 1.0);
+            let size = if text_width * size > width * 0.8 { (width * 0.8 / text_width).max(6.0) } else { size };
+            content.push_str(&format!(
+                "/{} {} Tf\n1 0 0 1 {} {} Tm\n{} Tj\n",
+                TITLE_PAGE_FONT_NAME,
+                number(size),
+                number(x0 + ((width - text_width * size) / 2.0).max(0.0)),
+                number(y1 - height * position),
+                string_operand(&font.encode(text)),
+            ));
+        }
+        content.push_str("ET\n");
+        add_text_page(doc, [x0, y0, x1, y1], content, font_id, true)
+    }
+}
+
+/// `--keep-full-outline placeholder`: 他の章を指す目次項目のリンク先として、章の末尾に加える説明のページ
+///
+/// この部分が別のファイルにあることと、元の文書を分けた各章のファイル名とページ範囲を載せる。
+pub struct PlaceholderPage {
+    lines: Vec<String>,
+}
+
+// 説明のページの文字の大きさと行の間隔
+const PLACEHOLDER_FONT_SIZE: f32 = 11.0;
+const PLACEHOLDER_LEADING: f32 = 16.0;
+
+impl PlaceholderPage {
+    /// `chapters` は各章の（ページ範囲, ファイル名）
+    pub fn new(chapters: &[(String, String)]) -> Self {
+        let mut lines = vec![
+            "This part of the document is not included in this file.".to_string(),
+            String::new(),
+            "The original document was split into the following files:".to_string(),
+        ];
+        lines.extend(chapters.iter().map(|(pages, file_name)| format!("{}    {}", pages, file_name)));
+        Self { lines }
+    }
+
+    /// 大きさは章の最後のページ（`last_page_id`）の表示範囲に合わせる。加えたページを返す
+    pub fn append(&self, doc: &mut Document, last_page_id: ObjectId) -> Result<ObjectId> {
+        let [x0, y0, x1, y1] = visible_box(doc, last_page_id).unwrap_or([0.0, 0.0, 595.0, 842.0]);
+        let margin = 72.0_f32.min((x1 - x0) / 8.0).min((y1 - y0) / 8.0);
+        let fitting = (((y1 - y0) - margin * 2.0) / PLACEHOLDER_LEADING).max(1.0) as usize;
+        // 入りきらない章は数だけを書く
+        let mut lines: Vec<String> = self.lines.iter().take(fitting).cloned().collect();
+        if self.lines.len() > fitting {
+            lines.pop();
+            lines.push(format!("... and {} more", self.lines.len() - fitting + 1));
+        }
+        let font = OverlayFont::for_text(&lines.concat());
+        let font_id = font.add_to(doc);
+
+        let mut content = format!(
+            "BT\n/{} {} Tf\n{} TL\n1 0 0 1 {} {} Tm\n",
+            TITLE_PAGE_FONT_NAME,
+            number(PLACEHOLDER_FONT_SIZE),
+            number(PLACEHOLDER_LEADING),
+            number(x0 + margin),
+            number(y1 - margin - PLACEHOLDER_FONT_SIZE),
+        );
+        for line in &lines {
+            content.push_str(&format!("{} Tj\nT*\n", string_operand(&font.encode(line))));
+        }
+        content.push_str("ET\n");
+        add_text_page(doc, [x0, y0, x1, y1], content, font_id, false)
+    }
+}
+
+/// 文字だけのページを加える（`first` なら先頭に、そうでなければ末尾に）
+fn add_text_page(doc: &mut Document, media_box: [f32; 4], content: String, font_id: ObjectId, first: bool) -> Result<ObjectId> {
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let mut fonts = Dictionary::new();
+    fonts.set(TITLE_PAGE_FONT_NAME, font_id);
+    let mut resources = Dictionary::new();
+    resources.set("Font", fonts);
+    let mut page = Dictionary::new();
+    page.set("Type", "Page");
+    page.set("Parent", pages_id);
+    page.set("MediaBox", media_box.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>());
+    page.set("Resources", resources);
+    page.set("Contents", content_id);
+    let page_id = doc.add_object(page);
+
+    let pages = doc.get_object_mut(pages_id).and_then(Object::as_dict_mut)?;
+    let mut kids = pages.get(b"Kids").and_then(Object::as_array).cloned().unwrap_or_default();
+    if first {
+        kids.insert(0, page_id.into());
+    } else {
+        kids.push(page_id.into());
+    }
+    pages.set("Count", kids.len() as i64);
+    pages.set("Kids", kids);
+    Ok(page_id)
+}
+
+/// `--bates-start` の値（"ABC000001" のような、接頭辞と桁数を固定した連番の最初の番号）
+#[derive(Debug, Clone)]
+pub struct BatesStart {
+    prefix: String,
+    digits: usize,
+    number: u64,
+}
+
+pub fn parse_bates_start(value: &str) -> Result<BatesStart, String> {
+    let prefix = value.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &value[prefix.len()..];
+    match digits.parse::<u64>() {
+        Ok(number) => Ok(BatesStart { prefix: prefix.to_string(), digits: digits.len(), number }),
+        Err(_) => Err(format!("invalid Bates number {:?} (expected e.g. ABC000001)", value)),
+    }
+}
+
+impl BatesStart {
+    /// 最初の番号から `offset` 番目の Bates 番号（桁が溢れたら桁数を増やす）
+    pub fn label(&self, offset: u64) -> String {
+        format!("{}{:0width$}", self.prefix, self.number + offset, width = self.digits)
+    }
+}
+
+const BATES_SIZE: f32 = 9.0;
+const BATES_MARGIN: f32 = 36.0;
+const BATES_FONT_NAME: &str = "PdfSplitterBates";
+
+/// `--bates-start`: 各ページの右下に Bates 番号を書き込む
+///
+/// 番号は全出力を通した連番で、章のファイルの先頭ページの番号を `offset`（最初の番号からの差）で受け取る。
+pub fn stamp_bates(doc: &mut Document, page_ids: &[ObjectId], start: &BatesStart, offset: u64) -> Result<()> {
+    // 番号の桁は数字だけなので、接頭辞でフォントを決められる
+    let font = OverlayFont::for_text(&start.prefix);
+    let font_id = font.add_to(doc);
+    for (n, page_id) in page_ids.iter().enumerate() {
+        let text = start.label(offset + n as u64);
+        let Some((width, _, matrix)) = visual_frame(doc, *page_id) else { continue };
+        let x = width - BATES_MARGIN - font.width(&text, BATES_SIZE);
+        let content = format!(
+            "q\n{} cm\nBT\n/{} {} Tf\n{} {} Td\n{} Tj\nET\nQ\n",
+            matrix.iter().map(|v| number(*v)).collect::<Vec<_>>().join(" "),
+            BATES_FONT_NAME,
+            number(BATES_SIZE),
+            number(x.max(0.0)),
+            number(FOOTER_BASELINE),
+            string_operand(&font.encode(&text)),
+        );
+        overlay(doc, *page_id, content.into_bytes(), &[("Font", BATES_FONT_NAME, font_id)])?;
+    }
+    Ok(())
+}
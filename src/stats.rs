@@ -0,0 +1,308 @@
+use crate::extract::object_size;
+use crate::memory::format_size;
+use crate::{load_document, pagetree};
+use anyhow::Result;
+use clap::ValueEnum;
+use lopdf::{Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::Path;
+
+// テキストの出力で内訳を示す、大きいページの数
+const LARGEST_PAGES: usize = 10;
+
+/// `stats` の出力形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    /// 種類ごとの合計と、大きいページの一覧
+    Text,
+    /// すべてのページを含む JSON
+    Json,
+}
+
+/// オブジェクトの種類（大きさをどこに数えるか）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Category {
+    /// ページの内容ストリーム（/Contents）
+    PageContent,
+    /// 画像 XObject（ソフトマスクを含む）
+    Images,
+    /// フォーム XObject
+    Forms,
+    /// フォントの辞書・フォントディスクリプター・埋め込みフォント・ToUnicode など
+    Fonts,
+    /// 添付ファイル（/EmbeddedFile のストリームとファイル指定）
+    Attachments,
+    /// XMP メタデータのストリームと文書情報辞書
+    Metadata,
+    /// ページツリー・目次・注釈・リソース辞書など、その他のオブジェクト
+    Other,
+    /// トレーラーから到達できない（どこからも使われていない）オブジェクト
+    Unused,
+}
+
+impl Category {
+    fn label(self) -> &'static str {
+        match self {
+            Category::PageContent => "Page content streams",
+            Category::Images => "Image XObjects",
+            Category::Forms => "Form XObjects",
+            Category::Fonts => "Fonts",
+            Category::Attachments => "Attachments",
+            Category::Metadata => "Metadata",
+            Category::Other => "Other objects",
+            Category::Unused => "Unused objects",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CategoryTotal {
+    objects: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PageSize {
+    page: u32,
+    /// 内容ストリームの大きさ
+    content_bytes: u64,
+    /// ページのリソースから直接描く画像の大きさ（ほかのページと共有する画像も数える）
+    image_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    input: String,
+    file_bytes: u64,
+    pages: usize,
+    objects: usize,
+    categories: BTreeMap<Category, CategoryTotal>,
+    page_sizes: Vec<PageSize>,
+}
+
+/// `stats` サブコマンド: 文書のバイト数が、ページの内容・画像・フォント・添付ファイル・メタデータ・使われていないオブジェクトの
+/// どこにあるかを集計する
+///
+/// 大きさは各オブジェクトを書き出したときのおおよそのバイト数（ストリームは保存されている圧縮後の長さ）で、
+/// オブジェクトストリームと相互参照ストリームは中身のオブジェクトで数える。1つのオブジェクトは1つの種類にだけ数える。
+pub fn run(input_path: &Path, format: StatsFormat) -> Result<()> {
+    let doc = load_document(input_path, false)?;
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+    let categories = categorize(&doc, &page_numbers);
+
+    let mut totals: BTreeMap<Category, CategoryTotal> = BTreeMap::new();
+    for (id, category) in &categories {
+        let Ok(object) = doc.get_object(*id) else { continue };
+        let total = totals.entry(*category).or_default();
+        total.objects += 1;
+        total.bytes += object_size(object);
+    }
+    let page_sizes = page_numbers
+        .iter()
+        .map(|(page, page_id)| PageSize {
+            page: *page,
+            content_bytes: page_contents(&doc, *page_id).iter().filter_map(|id| doc.get_object(*id).ok()).map(object_size).sum(),
+            image_bytes: page_images(&doc, *page_id).iter().filter_map(|id| doc.get_object(*id).ok()).map(object_size).sum(),
+        })
+        .collect();
+    let stats = Stats {
+        input: input_path.display().to_string(),
+        file_bytes: std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0),
+        pages: page_numbers.len(),
+        objects: categories.len(),
+        categories: totals,
+        page_sizes,
+    };
+
+    let rendered = match format {
+        StatsFormat::Text => render_text(&stats),
+        StatsFormat::Json => serde_json::to_string_pretty(&stats)? + "\n",
+    };
+    std::io::stdout().lock().write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+/// すべてのオブジェクトを種類に分ける（コンテナのストリームは除く）
+fn categorize(doc: &Document, page_numbers: &BTreeMap<u32, ObjectId>) -> HashMap<ObjectId, Category> {
+    let reachable = reachable_objects(doc);
+    let mut categories = HashMap::new();
+    // ページの内容ストリームは、フォーム XObject などの判定より先に決める
+    for page_id in page_numbers.values() {
+        for id in page_contents(doc, *page_id) {
+            categories.insert(id, Category::PageContent);
+        }
+    }
+    for (id, object) in &doc.objects {
+        if is_container(object) || categories.contains_key(id) {
+            continue;
+        }
+        let category = if !reachable.contains(id) {
+            Category::Unused
+        } else {
+            classify(object)
+        };
+        categories.insert(*id, category);
+    }
+    // フォントの辞書から参照される埋め込みフォント・ToUnicode・幅の配列などもフォントに数える（Type3 のリソースはたどらない）
+    let font_roots: Vec<ObjectId> = categories.iter().filter(|(_, category)| **category == Category::Fonts).map(|(id, _)| *id).collect();
+    for id in referenced_from(doc, &font_roots, &[b"Resources", b"Parent"]) {
+        if let Some(category @ (Category::Other | Category::Metadata)) = categories.get_mut(&id) {
+            *category = Category::Fonts;
+        }
+    }
+    let attachment_roots: Vec<ObjectId> =
+        categories.iter().filter(|(_, category)| **category == Category::Attachments).map(|(id, _)| *id).collect();
+    for id in referenced_from(doc, &attachment_roots, &[]) {
+        if let Some(category @ Category::Other) = categories.get_mut(&id) {
+            *category = Category::Attachments;
+        }
+    }
+    if let Ok(info_id) = doc.trailer.get(b"Info").and_then(Object::as_reference)
+        && let Some(category) = categories.get_mut(&info_id)
+    {
+        *category = Category::Metadata;
+    }
+    categories
+}
+
+/// オブジェクトの辞書の種類から分ける
+fn classify(object: &Object) -> Category {
+    let dict = match object {
+        Object::Stream(stream) => &stream.dict,
+        Object::Dictionary(dict) => dict,
+        _ => return Category::Other,
+    };
+    let name = |key: &[u8]| dict.get(key).and_then(Object::as_name).ok();
+    match (name(b"Type"), name(b"Subtype")) {
+        (_, Some(b"Image")) if matches!(object, Object::Stream(_)) => Category::Images,
+        (_, Some(b"Form")) if matches!(object, Object::Stream(_)) => Category::Forms,
+        (Some(b"Font" | b"FontDescriptor"), _) => Category::Fonts,
+        (Some(b"EmbeddedFile" | b"Filespec"), _) => Category::Attachments,
+        (Some(b"Metadata"), _) => Category::Metadata,
+        _ => Category::Other,
+    }
+}
+
+/// オブジェクトストリームと相互参照ストリーム（中身は個々のオブジェクトとして数える）
+fn is_container(object: &Object) -> bool {
+    matches!(object, Object::Stream(stream) if stream.dict.type_is(b"ObjStm") || stream.dict.type_is(b"XRef"))
+}
+
+/// トレーラーからたどれるオブジェクト
+fn reachable_objects(doc: &Document) -> HashSet<ObjectId> {
+    let roots: Vec<ObjectId> = doc.trailer.iter().filter_map(|(_, value)| value.as_reference().ok()).collect();
+    let mut reachable = referenced_from(doc, &roots, &[]);
+    reachable.extend(roots);
+    reachable
+}
+
+/// `roots` から参照をたどって到達できるオブジェクト（`roots` 自身は含めない）。`skip_keys` のキーの値はたどらない
+fn referenced_from(doc: &Document, roots: &[ObjectId], skip_keys: &[&[u8]]) -> HashSet<ObjectId> {
+    let mut seen: HashSet<ObjectId> = roots.iter().copied().collect();
+    let mut found = HashSet::new();
+    let mut queue: VecDeque<ObjectId> = roots.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        let Ok(object) = doc.get_object(id) else { continue };
+        let mut refs = Vec::new();
+        collect_refs(object, skip_keys, &mut refs);
+        for reference in refs {
+            if seen.insert(reference) {
+                found.insert(reference);
+                queue.push_back(reference);
+            }
+        }
+    }
+    found
+}
+
+fn collect_refs(object: &Object, skip_keys: &[&[u8]], refs: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => refs.push(*id),
+        Object::Array(array) => array.iter().for_each(|o| collect_refs(o, skip_keys, refs)),
+        Object::Dictionary(dict) => dict
+            .iter()
+            .filter(|(key, _)| !skip_keys.contains(&key.as_slice()))
+            .for_each(|(_, o)| collect_refs(o, skip_keys, refs)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter()
+            .filter(|(key, _)| !skip_keys.contains(&key.as_slice()))
+            .for_each(|(_, o)| collect_refs(o, skip_keys, refs)),
+        _ => {}
+    }
+}
+
+/// ページの /Contents のストリーム
+fn page_contents(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let Ok(page) = doc.get_dictionary(page_id) else { return Vec::new() };
+    match page.get(b"Contents") {
+        Ok(Object::Reference(id)) => match doc.get_object(*id) {
+            Ok(Object::Array(items)) => items.iter().filter_map(|item| item.as_reference().ok()).collect(),
+            _ => vec![*id],
+        },
+        Ok(Object::Array(items)) => items.iter().filter_map(|item| item.as_reference().ok()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// ページのリソース（継承したものを含む）の /XObject にある画像
+fn page_images(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let (resources, parents) = doc.get_page_resources(page_id);
+    let mut dicts: Vec<&lopdf::Dictionary> = resources.into_iter().collect();
+    dicts.extend(parents.into_iter().filter_map(|id| doc.get_dictionary(id).ok()));
+    let mut images = Vec::new();
+    for resources in dicts {
+        let Ok((_, Object::Dictionary(xobjects))) = resources.get(b"XObject").and_then(|o| doc.dereference(o)) else { continue };
+        for (_, xobject) in xobjects.iter() {
+            let Ok(id) = xobject.as_reference() else { continue };
+            if let Ok(Object::Stream(stream)) = doc.get_object(id)
+                && stream.dict.get(b"Subtype").and_then(Object::as_name).is_ok_and(|subtype| subtype == b"Image")
+                && !images.contains(&id)
+            {
+                images.push(id);
+            }
+        }
+    }
+    images
+}
+
+fn render_text(stats: &Stats) -> String {
+    let total: u64 = stats.categories.values().map(|total| total.bytes).sum();
+    let percent = |bytes: u64| if total == 0 { 0.0 } else { bytes as f64 * 100.0 / total as f64 };
+    let mut out = format!(
+        "{}: {} on disk, {} pages, {} objects (~{} counted)\n",
+        stats.input,
+        format_size(stats.file_bytes),
+        stats.pages,
+        stats.objects,
+        format_size(total)
+    );
+    for (category, category_total) in &stats.categories {
+        out.push_str(&format!(
+            "  {:<22} {:>10} {:>6.1}%  ({} objects)\n",
+            category.label(),
+            format_size(category_total.bytes),
+            percent(category_total.bytes),
+            category_total.objects
+        ));
+    }
+    let mut largest: Vec<&PageSize> = stats.page_sizes.iter().collect();
+    largest.sort_by_key(|page| std::cmp::Reverse(page.content_bytes + page.image_bytes));
+    largest.truncate(LARGEST_PAGES);
+    if !largest.is_empty() {
+        out.push_str("Largest pages (content streams + images drawn on the page):\n");
+        for page in largest {
+            out.push_str(&format!(
+                "  p.{:<6} {:>10}  (content {}, images {})\n",
+                page.page,
+                format_size(page.content_bytes + page.image_bytes),
+                format_size(page.content_bytes),
+                format_size(page.image_bytes)
+            ));
+        }
+    }
+    out
+}
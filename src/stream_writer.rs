@@ -0,0 +1,376 @@
+use clap::ValueEnum;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// 章のファイルの保存方法（`--save-mode`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SaveMode {
+    /// ファイル全体を書き直す
+    Full,
+    /// 元のファイルをそのまま残し、変更したオブジェクトだけを増分更新として後ろに追記する
+    Incremental,
+}
+
+// 相互参照表の1行（オブジェクト番号と、使用中ならオフセットと世代番号）
+type XrefRow = (u32, Option<(u64, u16)>);
+
+/// 相互参照表に書けるオフセットの上限（10桁）
+const MAX_TABLE_OFFSET: u64 = 9_999_999_999;
+
+// 重複排除の候補として覚えておく、最初に書き出したストリーム
+struct FirstStream {
+    id: ObjectId,
+    // /Length を除いた辞書の直列化
+    dict_bytes: Vec<u8>,
+    // 出力の中での内容の位置
+    content_at: u64,
+}
+
+/// オブジェクトを組み立てた順に書き出していくPDFライタ
+///
+/// `Document::save` は全オブジェクトをメモリ上の `Document` に揃えてから書き出すが、
+/// こちらは大きなストリームを抽出したそばから書き出して手放せるため、章ごとのピークメモリを抑えられる。
+/// 相互参照は従来の xref 表（10桁オフセット）で書き、10桁に収まらないオフセットはエラーにする。
+///
+/// 辞書と内容が同一のストリームは最初の1つだけを書き出し、以降の参照をそちらへ付け替える。
+/// 内容はハッシュで候補を絞った後、書き出したものを読み返してバイト単位で比べる（`with_readback`）。
+pub struct StreamingWriter<W: Write> {
+    out: W,
+    position: u64,
+    offsets: BTreeMap<u32, (u64, u16)>,
+    // ストリームの重複排除（内容のハッシュ → 最初に書き出したストリーム）
+    hasher: RandomState,
+    stream_hashes: HashMap<(usize, u64), FirstStream>,
+    // 書き出した内容を読み返すための、出力ファイルの読み取り用のハンドル
+    readback: Option<File>,
+    aliases: Aliases,
+    // 書き出し済みのストリームから参照されているオブジェクト（これらは付け替えできない）
+    emitted_refs: HashSet<ObjectId>,
+    // 増分更新では、元の文書と元の相互参照の位置
+    base: Option<(Arc<Document>, usize)>,
+}
+
+impl<W: Write> StreamingWriter<W> {
+    /// ヘッダーを書き出す。バージョンは後から変更できないため、この時点で確定させる
+    pub fn new(out: W, version: &str) -> io::Result<Self> {
+        let mut writer = Self::empty(out);
+        writer.write_raw(format!("%PDF-{}\n", version).as_bytes())?;
+        // バイナリを含むファイルであることを示すコメント行
+        writer.write_raw(b"%\xE2\xE3\xCF\xD3\n")?;
+        Ok(writer)
+    }
+
+    /// 元のファイル（`original`）をそのまま書き写し、その後ろに増分更新を書くライタを作る
+    ///
+    /// `source` は元のファイルを読み込んだ文書。これと同じ内容のオブジェクトは書き出さず、元の版のものをそのまま使う。
+    /// 元の版のバイト列は変わらないため、元の版に掛けられた電子署名は有効なまま残る。
+    /// 参照を付け替えると変更していないオブジェクトまで書き直すことになるため、ストリームの重複排除はしない。
+    pub fn append_to(out: W, mut original: impl Read, source: Arc<Document>) -> io::Result<Self> {
+        let mut writer = Self::empty(out);
+        writer.position = io::copy(&mut original, &mut writer.out)?;
+        // 元のファイルが %%EOF の後に改行を置いていなくても、追記した部分が同じ行に続かないようにする
+        writer.write_raw(b"\n")?;
+        let xref_start = source.xref_start;
+        writer.base = Some((source, xref_start));
+        Ok(writer)
+    }
+
+    fn empty(out: W) -> Self {
+        Self {
+            out,
+            position: 0,
+            offsets: BTreeMap::new(),
+            hasher: RandomState::new(),
+            stream_hashes: HashMap::new(),
+            readback: None,
+            aliases: HashMap::new(),
+            emitted_refs: HashSet::new(),
+            base: None,
+        }
+    }
+
+    /// 出力先のファイルを読み取り用に開いたものを渡すと、ストリームの重複排除を有効にする
+    ///
+    /// ハッシュの一致だけでは別の内容を取り違えうるため、最初に書き出した内容を読み返して比べる。
+    pub fn with_readback(mut self, file: File) -> Self {
+        self.readback = Some(file);
+        self
+    }
+
+    /// 増分更新で、元の版と同じ内容のオブジェクトか
+    fn is_unchanged(&self, id: ObjectId, object: impl FnOnce(&Object) -> bool) -> bool {
+        self.base.as_ref().is_some_and(|(source, _)| source.objects.get(&id).is_some_and(object))
+    }
+
+    pub fn has_written(&self, id: ObjectId) -> bool {
+        self.offsets.contains_key(&id.0) || self.aliases.contains_key(&id)
+    }
+
+    /// 重複として書き出しを省いたストリームの数
+    pub fn deduplicated(&self) -> usize {
+        self.aliases.len()
+    }
+
+    /// ストリームオブジェクトを書き出す
+    ///
+    /// `raw` には元ファイルから切り出したバイト列（`N G obj` からデータ末尾まで）を渡すと、直列化せずにそのまま書く。
+    /// 既に同じ辞書・内容のストリームを書き出していれば、書き出さずに参照の付け替えだけを記録する。
+    pub fn write_stream(&mut self, id: ObjectId, stream: &Stream, raw: Option<&[u8]>) -> io::Result<()> {
+        if self.base.is_some() {
+            if !self.is_unchanged(id, |original| matches!(original, Object::Stream(original) if original == stream)) {
+                self.offsets.insert(id.0, (self.position, id.1));
+                let buf = indirect_object(id, |buf| serialize_stream(buf, stream, &self.aliases))?;
+                self.write_raw(&buf)?;
+            }
+            return Ok(());
+        }
+        // /Length は間接参照のこともあるため比較から外す（内容の長さはキーに含める）
+        let mut dict = stream.dict.clone();
+        dict.remove(b"Length");
+        let mut dict_bytes = Vec::new();
+        serialize_dictionary(&mut dict_bytes, &dict, &self.aliases)?;
+        let key = (stream.content.len(), self.hasher.hash_one((&dict_bytes, &stream.content)));
+        let candidate = match self.stream_hashes.get(&key) {
+            Some(first) if !self.emitted_refs.contains(&id) && first.dict_bytes == dict_bytes => {
+                Some((first.id, first.content_at))
+            }
+            _ => None,
+        };
+        if let Some((original, content_at)) = candidate
+            && self.written_content_equals(content_at, &stream.content)?
+        {
+            self.aliases.insert(id, original);
+            return Ok(());
+        }
+
+        let mut refs = HashSet::new();
+        collect_refs(&stream.dict, &mut refs);
+        // 元のバイト列では付け替えた参照を反映できないため、その場合は直列化し直す
+        let raw = raw.filter(|_| refs.iter().all(|r| !self.aliases.contains_key(r)));
+        self.emitted_refs.extend(refs);
+        self.offsets.insert(id.0, (self.position, id.1));
+        // 元のバイト列は内容の末尾で終わり、直列化したものは内容の後に endstream・endobj が続く
+        let content_end = match raw {
+            Some(raw) => {
+                self.write_raw(raw)?;
+                let content_end = self.position;
+                self.write_raw(b"\nendstream\nendobj\n")?;
+                content_end
+            }
+            None => {
+                let buf = indirect_object(id, |buf| serialize_stream(buf, stream, &self.aliases))?;
+                self.write_raw(&buf)?;
+                self.position - b"\nendstream\nendobj\n".len() as u64
+            }
+        };
+        if self.readback.is_some() {
+            let content_at = content_end - stream.content.len() as u64;
+            self.stream_hashes.entry(key).or_insert(FirstStream { id, dict_bytes, content_at });
+        }
+        Ok(())
+    }
+
+    /// 出力の `content_at` から書き出した内容が `content` と同じか、読み返して比べる
+    fn written_content_equals(&mut self, content_at: u64, content: &[u8]) -> io::Result<bool> {
+        let Some(file) = &mut self.readback else { return Ok(false) };
+        self.out.flush()?;
+        file.seek(SeekFrom::Start(content_at))?;
+        let mut chunk = vec![0; content.len().min(64 * 1024)];
+        for expected in content.chunks(chunk.len().max(1)) {
+            let written = &mut chunk[..expected.len()];
+            file.read_exact(written)?;
+            if written != expected {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 間接オブジェクトを1つ書き出す
+    fn write_object(&mut self, id: ObjectId, object: &Object) -> io::Result<()> {
+        self.offsets.insert(id.0, (self.position, id.1));
+        let buf = indirect_object(id, |buf| serialize(buf, object, &self.aliases))?;
+        self.write_raw(&buf)
+    }
+
+    /// 残りのオブジェクト、相互参照表、トレーラーを書き出して完了する
+    pub fn finish(mut self, doc: &Document) -> io::Result<W> {
+        for (id, object) in &doc.objects {
+            if !self.has_written(*id)
+                && !matches!(object.type_name(), Ok("ObjStm") | Ok("XRef") | Ok("Linearized"))
+                && !self.is_unchanged(*id, |original| original == object)
+            {
+                self.write_object(*id, object)?;
+            }
+        }
+
+        let xref_start = self.position;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"xref\n");
+        // 連続した番号ごとにサブセクションを分ける（0番は常に空きエントリ）
+        let mut sections: Vec<Vec<XrefRow>> = vec![vec![(0, None)]];
+        for (&num, &entry) in &self.offsets {
+            let last = sections.last_mut().expect("at least one section");
+            if last.last().map(|(n, _)| n + 1) != Some(num) {
+                sections.push(Vec::new());
+            }
+            sections.last_mut().expect("at least one section").push((num, Some(entry)));
+        }
+        for section in sections.iter().filter(|s| !s.is_empty()) {
+            writeln!(buf, "{} {}", section[0].0, section.len())?;
+            for (_, entry) in section {
+                match entry {
+                    Some((offset, _)) if *offset > MAX_TABLE_OFFSET => {
+                        return Err(io::Error::other(format!(
+                            "the output is too large for an xref table: object offset {} needs more than 10 digits",
+                            offset
+                        )));
+                    }
+                    Some((offset, generation)) => write!(buf, "{:010} {:05} n\r\n", offset, generation)?,
+                    None => buf.extend_from_slice(b"0000000000 65535 f\r\n"),
+                }
+            }
+        }
+
+        let mut trailer = doc.trailer.clone();
+        let size = self.offsets.keys().next_back().map(|n| n + 1).unwrap_or(1).max(doc.max_id + 1);
+        trailer.set("Size", i64::from(size));
+        for key in [b"Prev".as_slice(), b"XRefStm", b"Type", b"W", b"Index", b"Length", b"Filter", b"DecodeParms"] {
+            trailer.remove(key);
+        }
+        // 増分更新は元の版の相互参照へつなぎ、番号の範囲も元の版を含める
+        if let Some((source, xref_start)) = &self.base {
+            trailer.set("Prev", *xref_start as i64);
+            trailer.set("Size", i64::from(size.max(source.max_id + 1)));
+        }
+        buf.extend_from_slice(b"trailer\n");
+        serialize_dictionary(&mut buf, &trailer, &self.aliases)?;
+        write!(buf, "\nstartxref\n{}\n%%EOF\n", xref_start)?;
+        self.write_raw(&buf)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn indirect_object(id: ObjectId, body: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    writeln!(buf, "{} {} obj", id.0, id.1)?;
+    body(&mut buf)?;
+    buf.extend_from_slice(b"\nendobj\n");
+    Ok(buf)
+}
+
+fn collect_refs(dict: &Dictionary, refs: &mut HashSet<ObjectId>) {
+    fn walk(object: &Object, refs: &mut HashSet<ObjectId>) {
+        match object {
+            Object::Reference(id) => {
+                refs.insert(*id);
+            }
+            Object::Array(array) => array.iter().for_each(|o| walk(o, refs)),
+            Object::Dictionary(dict) => dict.iter().for_each(|(_, o)| walk(o, refs)),
+            _ => {}
+        }
+    }
+    dict.iter().for_each(|(_, o)| walk(o, refs));
+}
+
+// 参照は重複排除で付け替えた先に読み替えて書く
+type Aliases = HashMap<ObjectId, ObjectId>;
+
+fn serialize(out: &mut Vec<u8>, object: &Object, aliases: &Aliases) -> io::Result<()> {
+    match object {
+        Object::Null => out.extend_from_slice(b"null"),
+        Object::Boolean(value) => out.extend_from_slice(if *value { b"true" } else { b"false" }),
+        Object::Integer(value) => write!(out, "{}", value)?,
+        Object::Real(value) => write!(out, "{}", value)?,
+        Object::Name(name) => serialize_name(out, name),
+        Object::String(text, format) => serialize_string(out, text, *format),
+        Object::Array(array) => {
+            out.push(b'[');
+            for (i, item) in array.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                serialize(out, item, aliases)?;
+            }
+            out.push(b']');
+        }
+        Object::Dictionary(dict) => serialize_dictionary(out, dict, aliases)?,
+        Object::Stream(stream) => serialize_stream(out, stream, aliases)?,
+        Object::Reference(id) => {
+            let id = aliases.get(id).unwrap_or(id);
+            write!(out, "{} {} R", id.0, id.1)?
+        }
+    }
+    Ok(())
+}
+
+fn serialize_dictionary(out: &mut Vec<u8>, dict: &Dictionary, aliases: &Aliases) -> io::Result<()> {
+    out.extend_from_slice(b"<<");
+    for (key, value) in dict.iter() {
+        serialize_name(out, key);
+        out.push(b' ');
+        serialize(out, value, aliases)?;
+    }
+    out.extend_from_slice(b">>");
+    Ok(())
+}
+
+fn serialize_stream(out: &mut Vec<u8>, stream: &Stream, aliases: &Aliases) -> io::Result<()> {
+    let mut dict = stream.dict.clone();
+    dict.set("Length", stream.content.len() as i64);
+    serialize_dictionary(out, &dict, aliases)?;
+    out.extend_from_slice(b"stream\n");
+    out.extend_from_slice(&stream.content);
+    out.extend_from_slice(b"\nendstream");
+    Ok(())
+}
+
+fn serialize_name(out: &mut Vec<u8>, name: &[u8]) {
+    out.push(b'/');
+    for &byte in name {
+        // 空白・区切り文字と範囲外のバイトは #xx で表す
+        if b" \t\n\r\x0C()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            out.extend_from_slice(format!("#{:02X}", byte).as_bytes());
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+fn serialize_string(out: &mut Vec<u8>, text: &[u8], format: StringFormat) {
+    match format {
+        StringFormat::Literal => {
+            out.push(b'(');
+            for &byte in text {
+                if matches!(byte, b'(' | b')' | b'\\') {
+                    out.push(b'\\');
+                    out.push(byte);
+                } else if byte == b'\r' {
+                    out.extend_from_slice(b"\\r");
+                } else {
+                    out.push(byte);
+                }
+            }
+            out.push(b')');
+        }
+        StringFormat::Hexadecimal => {
+            out.push(b'<');
+            for byte in text {
+                out.extend_from_slice(format!("{:02X}", byte).as_bytes());
+            }
+            out.push(b'>');
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashSet};
+
+// 壊れた（循環した）数値ツリーで無限に潜らないための上限
+const MAX_TREE_DEPTH: usize = 64;
+
+/// /ParentTree を作り直した結果
+#[derive(Debug, Default)]
+pub struct RebuiltParentTree {
+    /// 章のページと注釈に振り直した番号の数
+    pub entries: usize,
+    /// 他の章のページのものだったため取り除いた項目の数
+    pub dropped: usize,
+}
+
+/// 章の文書のページの /StructParents と注釈の /StructParent を 0 から振り直し、構造ツリーの /ParentTree を作り直す
+///
+/// 元の /ParentTree には文書全体のページの項目が残っており、章のページの番号は飛び飛びになる。
+/// 章のページ（と注釈）の順に番号を詰め、その番号だけを持つ数値ツリーにして /ParentTreeNextKey も合わせる。
+/// 構造ツリーの無い文書では何もせず `None` を返す。
+pub fn rebuild_parent_tree(doc: &mut Document) -> Option<RebuiltParentTree> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference).ok()?;
+    let root_obj = doc.get_dictionary(catalog_id).ok()?.get(b"StructTreeRoot").ok()?.clone();
+    // /StructTreeRoot が直接の辞書の場合はカタログの中を書き換える
+    let root_id = root_obj.as_reference().ok();
+    let root = match root_id {
+        Some(id) => doc.get_dictionary(id).ok()?,
+        None => root_obj.as_dict().ok()?,
+    };
+
+    let mut old_entries = BTreeMap::new();
+    let mut old_nodes = Vec::new();
+    if let Ok(tree) = root.get(b"ParentTree") {
+        if let Ok(id) = tree.as_reference() {
+            old_nodes.push(id);
+        }
+        if let Ok((_, Object::Dictionary(node))) = doc.dereference(tree) {
+            collect_entries(doc, node, &mut old_entries, &mut old_nodes, 0);
+        }
+    }
+
+    // ページと、その上の注釈（ページの順、注釈は /Annots の順）の古い番号を新しい番号に置き換える
+    let mut nums = Vec::new();
+    let mut used = HashSet::new();
+    let mut renumbered = HashSet::new();
+    for page_id in doc.get_pages().into_values() {
+        let targets: Vec<(ObjectId, &[u8])> = std::iter::once((page_id, &b"StructParents"[..]))
+            .chain(annotation_ids(doc, page_id).into_iter().map(|id| (id, &b"StructParent"[..])))
+            .collect();
+        for (id, key) in targets {
+            let Ok(dict) = doc.get_object_mut(id).and_then(Object::as_dict_mut) else { continue };
+            let Ok(old) = dict.get(key).and_then(Object::as_i64) else { continue };
+            match old_entries.get(&old) {
+                Some(value) => {
+                    dict.set(key, (nums.len() / 2) as i64);
+                    nums.push(Object::Integer((nums.len() / 2) as i64));
+                    nums.push(value.clone());
+                    used.insert(old);
+                    renumbered.insert(id);
+                }
+                // 対応する項目の無い番号は、矛盾を残さないよう外す
+                None => {
+                    dict.remove(key);
+                }
+            }
+        }
+    }
+    let entries = nums.len() / 2;
+
+    // 構造要素の /OBJR から参照されて残った、他の章のページの注釈の古い番号も外す
+    for (id, object) in doc.objects.iter_mut() {
+        if renumbered.contains(id) {
+            continue;
+        }
+        if let Object::Dictionary(dict) = object {
+            dict.remove(b"StructParent");
+        }
+    }
+
+    // 使わなくなった項目のうち、この項目のためだけに置かれた配列オブジェクトも消す
+    let mut dropped = 0;
+    for (number, value) in &old_entries {
+        if used.contains(number) {
+            continue;
+        }
+        dropped += 1;
+        if let Object::Reference(id) = value
+            && matches!(doc.objects.get(id), Some(Object::Array(_)))
+        {
+            doc.objects.remove(id);
+        }
+    }
+    for id in old_nodes {
+        doc.objects.remove(&id);
+    }
+
+    let mut tree = Dictionary::new();
+    tree.set("Nums", nums);
+    let root = match root_id {
+        Some(id) => doc.get_object_mut(id).and_then(Object::as_dict_mut).ok()?,
+        None => doc
+            .get_object_mut(catalog_id)
+            .and_then(Object::as_dict_mut)
+            .and_then(|catalog| catalog.get_mut(b"StructTreeRoot"))
+            .and_then(Object::as_dict_mut)
+            .ok()?,
+    };
+    root.set("ParentTree", tree);
+    root.set("ParentTreeNextKey", entries as i64);
+    Some(RebuiltParentTree { entries, dropped })
+}
+
+/// 数値ツリーの葉の /Nums をすべて集める。中間ノードのオブジェクトは `nodes` に加える
+fn collect_entries(doc: &Document, node: &Dictionary, entries: &mut BTreeMap<i64, Object>, nodes: &mut Vec<ObjectId>, depth: usize) {
+    if depth > MAX_TREE_DEPTH {
+        return;
+    }
+    if let Ok((_, Object::Array(nums))) = node.get(b"Nums").and_then(|o| doc.dereference(o)) {
+        for pair in nums.chunks_exact(2) {
+            if let Ok(number) = pair[0].as_i64() {
+                entries.insert(number, pair[1].clone());
+            }
+        }
+    }
+    if let Ok((_, Object::Array(kids))) = node.get(b"Kids").and_then(|o| doc.dereference(o)) {
+        for kid in kids {
+            let Ok(kid_id) = kid.as_reference() else { continue };
+            let Ok(kid_node) = doc.get_dictionary(kid_id) else { continue };
+            nodes.push(kid_id);
+            collect_entries(doc, kid_node, entries, nodes, depth + 1);
+        }
+    }
+}
+
+/// ページの /Annots にある、間接オブジェクトとして置かれた注釈
+fn annotation_ids(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let Ok(page) = doc.get_dictionary(page_id) else { return Vec::new() };
+    let Ok((_, Object::Array(annots))) = page.get(b"Annots").and_then(|o| doc.dereference(o)) else { return Vec::new() };
+    annots.iter().filter_map(|annot| annot.as_reference().ok()).collect()
+}
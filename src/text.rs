@@ -0,0 +1,121 @@
+use crate::decode_pdf_string;
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+use std::collections::BTreeMap;
+
+/// `--extract-text` の出力の拡張子（章のファイル名の `pdf` を置き換える）
+pub const EXTENSION: &str = "txt";
+
+/// 章のページから本文のテキストを取り出す（`--extract-text`）
+///
+/// lopdf の `Document::extract_text` と同じ方法（Tj/TJ 演算子の文字列をフォントの符号化で復号）だが、
+/// ページ番号ではなくページツリーの走査結果のページIDで引き、読めないページは飛ばして続ける。
+/// ページの区切りには改ページ文字（U+000C）を入れる。戻り値は本文と、読めなかったページ数。
+pub fn extract_pages(doc: &Document, page_ids: &[ObjectId]) -> (String, usize) {
+    let mut text = String::new();
+    let mut failed = 0;
+    for (i, page_id) in page_ids.iter().enumerate() {
+        if i > 0 {
+            text.push('\x0c');
+        }
+        if extract_page(doc, *page_id, &mut text).is_err() {
+            failed += 1;
+        }
+    }
+    (text, failed)
+}
+
+fn extract_page(doc: &Document, page_id: ObjectId, text: &mut String) -> lopdf::Result<()> {
+    let encodings: BTreeMap<Vec<u8>, &str> = doc
+        .get_page_fonts(page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font.get_font_encoding()))
+        .collect();
+    let content = Content::decode(&doc.get_page_content(page_id)?)?;
+    let mut current_encoding = None;
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "Tf" => {
+                current_encoding = operation
+                    .operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| encodings.get(name).copied());
+            }
+            "Tj" | "TJ" | "'" | "\"" => collect_text(text, current_encoding, &operation.operands),
+            "ET" if !text.ends_with('\n') => text.push('\n'),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// ページの内容で、`tag` のタグ（`/InvoiceNumber BMC … EMC` など）で囲まれた最初の空でないテキスト（`--split-by-field`）
+///
+/// BDC のプロパティに /ActualText があればそれを使う。読めないページや、タグの無いページでは `None`。
+pub fn tagged_text(doc: &Document, page_id: ObjectId, tag: &str) -> Option<String> {
+    let encodings: BTreeMap<Vec<u8>, &str> = doc
+        .get_page_fonts(page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font.get_font_encoding()))
+        .collect();
+    let content = Content::decode(&doc.get_page_content(page_id).ok()?).ok()?;
+    let mut current_encoding = None;
+    // 入れ子になったマーク付き内容のうち、`tag` のものの深さ（開いていなければ None）
+    let (mut depth, mut tagged_depth) = (0usize, None);
+    let mut text = String::new();
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "BMC" | "BDC" => {
+                depth += 1;
+                if tagged_depth.is_none() && operation.operands.first().and_then(|o| o.as_name().ok()) == Some(tag.as_bytes()) {
+                    let actual_text = operation.operands.get(1).and_then(|o| o.as_dict().ok()).and_then(|d| d.get(b"ActualText").ok());
+                    if let Some(Object::String(bytes, _)) = actual_text {
+                        let value = decode_pdf_string(bytes);
+                        if !value.trim().is_empty() {
+                            return Some(value.trim().to_string());
+                        }
+                    }
+                    tagged_depth = Some(depth);
+                    text.clear();
+                }
+            }
+            "EMC" => {
+                if tagged_depth == Some(depth) {
+                    let value = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !value.is_empty() {
+                        return Some(value);
+                    }
+                    tagged_depth = None;
+                }
+                depth = depth.saturating_sub(1);
+            }
+            "Tf" => {
+                current_encoding = operation
+                    .operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| encodings.get(name).copied());
+            }
+            "Tj" | "TJ" | "'" | "\"" if tagged_depth.is_some() => collect_text(&mut text, current_encoding, &operation.operands),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Tj/TJ などの文字列の被演算子を、フォントの符号化で復号して `text` に加える
+pub fn collect_text(text: &mut String, encoding: Option<&str>, operands: &[Object]) {
+    for operand in operands {
+        match operand {
+            Object::String(bytes, _) => text.push_str(&Document::decode_text(encoding, bytes)),
+            Object::Array(items) => {
+                collect_text(text, encoding, items);
+                text.push(' ');
+            }
+            // TJ の大きな字送りは単語の区切りとみなす
+            Object::Integer(i) if *i < -100 => text.push(' '),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use lopdf::{Document, Object, ObjectId};
+use std::collections::HashSet;
+
+/// 記事スレッド（カタログの /Threads）を書き直した結果
+#[derive(Debug, Default)]
+pub struct RebuiltThreads {
+    /// 章のページにビーズが残ったスレッドの数
+    pub threads: usize,
+    /// 他の章のページにあったため取り除いたビーズの数
+    pub dropped_beads: usize,
+}
+
+/// 章の文書にコピーされた記事スレッドを、章のページにあるビーズだけでつなぎ直す
+///
+/// スレッドのビーズは /N・/V で環状につながり、それぞれのビーズは /P で載っているページを指す。
+/// 章に無いページへの /P は抽出で取り除かれるため、そのままではビーズの輪が章の外のビーズを経由してしまう。
+/// 章のページにあるビーズだけで輪を作り直し、ビーズが1つも残らないスレッドはカタログから外す。
+pub fn rebuild_threads(doc: &mut Document) -> RebuiltThreads {
+    let mut result = RebuiltThreads::default();
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else { return result };
+    let Some(thread_ids) = thread_ids(doc, catalog_id) else { return result };
+    let pages: HashSet<ObjectId> = doc.get_pages().into_values().collect();
+
+    let mut kept_threads = Vec::new();
+    for thread_id in thread_ids {
+        let beads = beads(doc, thread_id);
+        let (kept, dropped): (Vec<ObjectId>, Vec<ObjectId>) = beads.into_iter().partition(|bead_id| {
+            doc.get_dictionary(*bead_id)
+                .and_then(|bead| bead.get(b"P"))
+                .and_then(Object::as_reference)
+                .is_ok_and(|page_id| pages.contains(&page_id))
+        });
+        result.dropped_beads += dropped.len();
+        for bead_id in dropped {
+            doc.objects.remove(&bead_id);
+        }
+        let Some(&first) = kept.first() else {
+            // 情報辞書（/I）も他からは参照されない
+            if let Ok(info_id) = doc.get_dictionary(thread_id).and_then(|thread| thread.get(b"I")).and_then(Object::as_reference) {
+                doc.objects.remove(&info_id);
+            }
+            doc.objects.remove(&thread_id);
+            continue;
+        };
+        for (index, bead_id) in kept.iter().enumerate() {
+            let next = kept[(index + 1) % kept.len()];
+            let previous = kept[(index + kept.len() - 1) % kept.len()];
+            if let Ok(bead) = doc.get_object_mut(*bead_id).and_then(Object::as_dict_mut) {
+                bead.set("N", next);
+                bead.set("V", previous);
+                if *bead_id == first {
+                    bead.set("T", thread_id);
+                }
+            }
+        }
+        if let Ok(thread) = doc.get_object_mut(thread_id).and_then(Object::as_dict_mut) {
+            thread.set("F", first);
+        }
+        kept_threads.push(Object::Reference(thread_id));
+    }
+    result.threads = kept_threads.len();
+
+    // 配列オブジェクトへの参照だった場合は、直接の配列に置き換えて元の配列を消す
+    if let Ok(array_id) = doc.get_dictionary(catalog_id).and_then(|catalog| catalog.get(b"Threads")).and_then(Object::as_reference) {
+        doc.objects.remove(&array_id);
+    }
+    if let Ok(catalog) = doc.get_object_mut(catalog_id).and_then(Object::as_dict_mut) {
+        if kept_threads.is_empty() {
+            catalog.remove(b"Threads");
+        } else {
+            catalog.set("Threads", kept_threads);
+        }
+    }
+    result
+}
+
+/// カタログの /Threads（直接の配列または配列オブジェクトへの参照）。無ければ `None`
+fn thread_ids(doc: &Document, catalog_id: ObjectId) -> Option<Vec<ObjectId>> {
+    let threads = doc.get_dictionary(catalog_id).ok()?.get(b"Threads").ok()?;
+    let (_, threads) = doc.dereference(threads).ok()?;
+    Some(threads.as_array().ok()?.iter().filter_map(|thread| thread.as_reference().ok()).collect())
+}
+
+/// スレッドの最初のビーズ（/F）から /N をたどり、輪を一周するまでのビーズ
+fn beads(doc: &Document, thread_id: ObjectId) -> Vec<ObjectId> {
+    let mut beads = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = doc.get_dictionary(thread_id).and_then(|thread| thread.get(b"F")).and_then(Object::as_reference).ok();
+    while let Some(bead_id) = current {
+        if !visited.insert(bead_id) {
+            break;
+        }
+        let Ok(bead) = doc.get_dictionary(bead_id) else { break };
+        beads.push(bead_id);
+        current = bead.get(b"N").and_then(Object::as_reference).ok();
+    }
+    beads
+}
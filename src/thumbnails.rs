@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// サムネイルの拡張子（章のファイル名の `pdf` を置き換える）
+pub const EXTENSION: &str = "png";
+
+/// `--thumbnails` の幅（"300px" または "300"）
+pub fn parse_width(value: &str) -> Result<u32, String> {
+    let digits = value.strip_suffix("px").unwrap_or(value);
+    match digits.parse::<u32>() {
+        Ok(width) if (1..=10_000).contains(&width) => Ok(width),
+        _ => Err(format!("invalid thumbnail width {:?} (expected e.g. 300px)", value)),
+    }
+}
+
+/// 章の先頭ページのサムネイル（PNG）を描画する
+///
+/// 描画には PDFium を使うため、`render` フィーチャー付きでビルドし、実行時に PDFium の共有ライブラリ
+/// （実行ファイルと同じディレクトリか、システムのライブラリパス上）が必要になる。
+#[cfg(feature = "render")]
+pub struct Renderer {
+    pdfium: pdfium_render::prelude::Pdfium,
+    width: u32,
+}
+
+#[cfg(feature = "render")]
+impl Renderer {
+    pub fn new(width: u32) -> Result<Self> {
+        Ok(Self { pdfium: bind_pdfium("--thumbnails")?, width })
+    }
+
+    /// 保存済みの章のPDFを開き直し、1ページ目を `png_path` に書き出す
+    pub fn render_first_page(&self, pdf_path: &Path, png_path: &Path, password: Option<&str>) -> Result<()> {
+        use anyhow::Context;
+        use pdfium_render::prelude::PdfRenderConfig;
+
+        let document = self.pdfium.load_pdf_from_file(pdf_path, password)?;
+        let page = document.pages().get(0)?;
+        let config = PdfRenderConfig::new().set_target_width(self.width as i32);
+        let image = page.render_with_config(&config)?.as_image();
+        image
+            .save_with_format(png_path, image::ImageFormat::Png)
+            .with_context(|| format!("Failed to write {:?}", png_path))
+    }
+}
+
+/// PDFium の共有ライブラリを、実行ファイルと同じディレクトリ、システムのライブラリパスの順に探して読み込む
+#[cfg(feature = "render")]
+pub fn bind_pdfium(option: &str) -> Result<pdfium_render::prelude::Pdfium> {
+    use anyhow::Context;
+    use pdfium_render::prelude::Pdfium;
+
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Pdfium::pdfium_platform_library_name_at_path));
+    let bindings = match beside_exe.map(Pdfium::bind_to_library) {
+        Some(Ok(bindings)) => bindings,
+        _ => Pdfium::bind_to_system_library().with_context(|| format!("{} requires the PDFium shared library", option))?,
+    };
+    Ok(Pdfium::new(bindings))
+}
+
+/// `render` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "render"))]
+pub struct Renderer;
+
+#[cfg(not(feature = "render"))]
+impl Renderer {
+    pub fn new(_width: u32) -> Result<Self> {
+        anyhow::bail!("--thumbnails is not available in this build; rebuild with `cargo build --release --features render`")
+    }
+
+    pub fn render_first_page(&self, _pdf_path: &Path, _png_path: &Path, _password: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// `--timings` の出力形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimingsFormat {
+    /// 人が読むための表
+    Text,
+    /// 1ファイルにつき1行のJSON（バージョン間の比較やスクリプトでの集計用）
+    Json,
+}
+
+/// 1ファイル分の処理時間の内訳（ミリ秒）
+#[derive(Debug, Default, Serialize)]
+pub struct FileTimings {
+    pub input: String,
+    pub load_ms: f64,
+    /// 復号の準備・PDF/A の検出・ページツリーの走査
+    pub analysis_ms: f64,
+    /// 目次の走査（名前付き宛先の解決を含む）
+    pub outline_scan_ms: f64,
+    /// うち名前付き宛先の解決にかかった時間
+    pub named_dests_ms: f64,
+    pub chapters: Vec<ChapterTimings>,
+    pub total_ms: f64,
+}
+
+/// 1章分の処理時間の内訳（ミリ秒）
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChapterTimings {
+    pub chapter: usize,
+    /// `--max-memory` の予約待ち
+    pub wait_ms: f64,
+    /// ページとそこから参照されるオブジェクトのコピー（ストリームの書き出しを含む）
+    pub copy_ms: f64,
+    /// 注釈リンクの修復・PDF/A 要素の引き継ぎ・バージョン変更
+    pub fixup_ms: f64,
+    /// 残りのオブジェクトと相互参照表の書き出し
+    pub write_ms: f64,
+    pub linearize_ms: Option<f64>,
+    pub verify_ms: Option<f64>,
+    /// `--thumbnails` の先頭ページの描画
+    pub thumbnail_ms: Option<f64>,
+    /// `--extract-text` の本文の取り出しと書き出し
+    pub extract_text_ms: Option<f64>,
+    /// `--passwords` の暗号化
+    pub encrypt_ms: Option<f64>,
+    pub total_ms: f64,
+}
+
+pub fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl FileTimings {
+    pub fn render(&self, format: TimingsFormat) -> String {
+        match format {
+            TimingsFormat::Json => serde_json::to_string(self).expect("timings are serializable"),
+            TimingsFormat::Text => self.render_text(),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Timings for {}:", self.input);
+        let _ = writeln!(out, "  load          {:>10.2} ms", self.load_ms);
+        let _ = writeln!(out, "  analysis      {:>10.2} ms", self.analysis_ms);
+        let _ = writeln!(
+            out,
+            "  outline scan  {:>10.2} ms (named destinations {:.2} ms)",
+            self.outline_scan_ms, self.named_dests_ms
+        );
+        for chapter in &self.chapters {
+            let _ = write!(
+                out,
+                "  chapter {:<5} {:>10.2} ms (wait {:.2}, copy {:.2}, fixup {:.2}, write {:.2}",
+                chapter.chapter, chapter.total_ms, chapter.wait_ms, chapter.copy_ms, chapter.fixup_ms, chapter.write_ms
+            );
+            if let Some(linearize) = chapter.linearize_ms {
+                let _ = write!(out, ", linearize {:.2}", linearize);
+            }
+            if let Some(verify) = chapter.verify_ms {
+                let _ = write!(out, ", verify {:.2}", verify);
+            }
+            if let Some(thumbnail) = chapter.thumbnail_ms {
+                let _ = write!(out, ", thumbnail {:.2}", thumbnail);
+            }
+            if let Some(extract_text) = chapter.extract_text_ms {
+                let _ = write!(out, ", text {:.2}", extract_text);
+            }
+            if let Some(encrypt) = chapter.encrypt_ms {
+                let _ = write!(out, ", encrypt {:.2}", encrypt);
+            }
+            out.push_str(")\n");
+        }
+        let _ = write!(out, "  total         {:>10.2} ms", self.total_ms);
+        out
+    }
+}
@@ -0,0 +1,198 @@
+use crate::cert::KeyFile;
+use crate::crypt::StringDecryptor;
+use crate::named_dests::NamedDests;
+use crate::progress::{InfoLines, Progress};
+use crate::{DestFallback, OutlineContext, load_document, outline_title, page_labels, pagetree, resolve_object, resolve_outline_item};
+use anyhow::Result;
+use clap::ValueEnum;
+use lopdf::{Document, ObjectId};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+// 壊れた（循環した）目次で無限に潜らないための上限
+const MAX_OUTLINE_DEPTH: usize = 64;
+
+/// `toc` の出力形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TocFormat {
+    /// Excel で開ける CSV（BOM 付き UTF-8）
+    Csv,
+    /// Wiki やリリースノートに貼り付けられる、入れ子の箇条書き
+    Markdown,
+}
+
+/// 目次の1項目（全階層）
+#[derive(Clone, Debug)]
+pub struct TocEntry {
+    /// 階層（トップレベルが 1）
+    pub level: usize,
+    pub title: String,
+    /// 宛先を解決できなかった項目は `None`
+    pub start_page: Option<u32>,
+    /// 同じかより上の階層の次の項目の直前のページ（最後の項目は文書の最終ページ）
+    pub end_page: Option<u32>,
+}
+
+/// `toc` サブコマンド: 目次を全階層たどり、各項目のページ範囲を書き出す
+pub fn run(input_path: &Path, format: TocFormat, password: Option<&str>, key_file: Option<&KeyFile>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    let doc = load_document(input_path, false)?;
+    let decryptor = StringDecryptor::for_document(&doc, password, key_file)?;
+    let (total_pages, entries) = document_entries(&doc, &decryptor, &progress);
+    if entries.is_empty() {
+        progress.eprintln("Warning: the document has no outline entries.");
+    }
+    let labels = page_labels::collect(&doc, &decryptor, total_pages);
+
+    let rendered = match format {
+        TocFormat::Csv => render_csv(&entries, labels.as_ref()),
+        TocFormat::Markdown => render_markdown(&entries),
+    };
+    std::io::stdout().lock().write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+/// `toc` と同じく、入力の目次を全階層たどった項目と文書のページ数（GUI の章のツリー）
+pub fn read_entries(input_path: &Path, password: Option<&str>, progress: &Progress) -> Result<(u32, Vec<TocEntry>)> {
+    let doc = load_document(input_path, false)?;
+    let decryptor = StringDecryptor::for_document(&doc, password, None)?;
+    Ok(document_entries(&doc, &decryptor, progress))
+}
+
+fn document_entries(doc: &Document, decryptor: &StringDecryptor, progress: &Progress) -> (u32, Vec<TocEntry>) {
+    let (page_numbers, page_tree_warnings) = pagetree::collect_pages(doc);
+    for warning in &page_tree_warnings {
+        progress.eprintln(format!("Warning: {}", warning));
+    }
+    let total_pages = page_numbers.len() as u32;
+    let object_id_to_page: BTreeMap<_, _> = page_numbers.iter().map(|(num, id)| (*id, *num)).collect();
+    let named_dests = NamedDests::new(doc, decryptor);
+    let ctx = OutlineContext {
+        doc,
+        object_id_to_page: &object_id_to_page,
+        named_dests: &named_dests,
+        decryptor,
+        fallback: DestFallback::Skip,
+        progress,
+    };
+    (total_pages, collect_entries(&ctx, total_pages))
+}
+
+/// 目次を全階層、表示順にたどる
+pub(crate) fn collect_entries(ctx: &OutlineContext, total_pages: u32) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    if let Some(first) = first_outline_item(ctx.doc) {
+        let mut visited = HashSet::new();
+        walk(ctx, first, 1, &mut visited, &mut entries);
+    }
+
+    // 終了ページ: 後ろにある、同じかより上の階層で宛先の分かる最初の項目の直前まで
+    for i in 0..entries.len() {
+        let Some(start) = entries[i].start_page else { continue };
+        let next_start = entries[i + 1..]
+            .iter()
+            .filter(|next| next.level <= entries[i].level)
+            .find_map(|next| next.start_page);
+        entries[i].end_page = Some(match next_start {
+            Some(next) if next > start => next - 1,
+            Some(_) => start,
+            None => total_pages.max(start),
+        });
+    }
+    entries
+}
+
+fn walk(ctx: &OutlineContext, first: ObjectId, level: usize, visited: &mut HashSet<ObjectId>, entries: &mut Vec<TocEntry>) {
+    if level > MAX_OUTLINE_DEPTH {
+        return;
+    }
+    let mut current = Some(first);
+    while let Some(id) = current {
+        // 循環した /Next や /First を二度たどらない
+        if !visited.insert(id) {
+            break;
+        }
+        let Ok(item) = ctx.doc.get_object(id).and_then(|o| o.as_dict()) else { break };
+        entries.push(TocEntry {
+            level,
+            title: outline_title(ctx.doc, ctx.decryptor, id, item),
+            start_page: resolve_outline_item(ctx, id, item, DestFallback::Skip),
+            end_page: None,
+        });
+        if let Ok(child) = item.get(b"First").and_then(|o| o.as_reference()) {
+            walk(ctx, child, level + 1, visited, entries);
+        }
+        current = item.get(b"Next").and_then(|o| o.as_reference()).ok();
+    }
+}
+
+/// 目次（/Outlines）の最初の項目
+pub(crate) fn first_outline_item(doc: &Document) -> Option<ObjectId> {
+    let outlines = doc.catalog().ok()?.get(b"Outlines").ok()?;
+    let outlines = resolve_object(doc, outlines).ok()?.as_dict().ok()?;
+    outlines.get(b"First").and_then(|o| o.as_reference()).ok()
+}
+
+/// 列は level, title, start_page, page_label, end_page
+pub fn render_csv(entries: &[TocEntry], labels: Option<&BTreeMap<u32, String>>) -> String {
+    // Excel が UTF-8 と認識して日本語のタイトルが化けないよう BOM を付ける
+    let mut out = String::from("\u{feff}level,title,start_page,page_label,end_page\r\n");
+    for entry in entries {
+        let label = entry
+            .start_page
+            .and_then(|page| labels.and_then(|labels| labels.get(&page)))
+            .map(String::as_str)
+            .unwrap_or("");
+        let fields = [
+            entry.level.to_string(),
+            csv_field(&entry.title),
+            entry.start_page.map(|p| p.to_string()).unwrap_or_default(),
+            csv_field(label),
+            entry.end_page.map(|p| p.to_string()).unwrap_or_default(),
+        ];
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// 階層ごとに2文字ずつ字下げした箇条書き。ページ範囲は「p. 4」「pp. 5–7」の形で添える
+pub fn render_markdown(entries: &[TocEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let indent = "  ".repeat(entry.level - 1);
+        let title = markdown_text(&entry.title);
+        match (entry.start_page, entry.end_page) {
+            (Some(start), Some(end)) if end > start => out.push_str(&format!("{}- {} (pp. {}–{})\n", indent, title, start, end)),
+            (Some(start), _) => out.push_str(&format!("{}- {} (p. {})\n", indent, title, start)),
+            (None, _) => out.push_str(&format!("{}- {}\n", indent, title)),
+        }
+    }
+    out
+}
+
+/// タイトル中の記号が強調やリンクとして解釈されないようにエスケープし、改行は空白にする
+fn markdown_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' | '\n' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// カンマ・引用符・改行を含むフィールドだけを引用符で囲む（RFC 4180）
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cert::KeyFile;
+use crate::crypt::StringDecryptor;
+use crate::embedded_files::{self, Attachment};
+use crate::progress::{InfoLines, Progress};
+use crate::{load_document, pagetree, sanitize_filename};
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// `unpack-attachments` サブコマンド: 文書に埋め込まれたすべてのファイルを、元の名前で `out_dir` に書き出す
+///
+/// 名前ツリー（/Names /EmbeddedFiles）の添付ファイルと、ページのファイル添付注釈のファイルを対象にする。
+/// 埋め込まれた更新日時があれば、書き出したファイルの更新日時にする。
+/// 同じ名前が重なったら `名前 (2).拡張子` のように番号を付け、既存のファイルは上書きしない。
+pub fn run(input_path: &Path, out_dir: &Path, password: Option<&str>, key_file: Option<&KeyFile>) -> Result<()> {
+    let progress = Progress::new(false, InfoLines::Stdout, None);
+    let doc = load_document(input_path, false)?;
+    let decryptor = StringDecryptor::for_document(&doc, password, key_file)?;
+    let (page_numbers, _) = pagetree::collect_pages(&doc);
+
+    let mut attachments: Vec<(Option<u32>, Attachment)> = embedded_files::list(&doc, &decryptor).into_iter().map(|a| (None, a)).collect();
+    attachments.extend(embedded_files::list_annotations(&doc, &decryptor, &page_numbers).into_iter().map(|(page, a)| (Some(page), a)));
+    if attachments.is_empty() {
+        bail!("{:?} has no embedded files", input_path);
+    }
+    std::fs::create_dir_all(out_dir).with_context(|| format!("Failed to create {:?}", out_dir))?;
+
+    let mut used = HashSet::new();
+    for (page, attachment) in &attachments {
+        let out_path = (1..)
+            .map(|n| out_dir.join(numbered(&safe_name(&attachment.name), n)))
+            .find(|path| used.insert(path.clone()) && !path.exists())
+            .expect("some numbered name is free");
+        let mut file = File::create_new(&out_path).with_context(|| format!("Failed to create {:?}", out_path))?;
+        file.write_all(&attachment.data).with_context(|| format!("Failed to write {:?}", out_path))?;
+        if let Some(modified) = attachment.modified
+            && let Err(e) = file.set_modified(modified)
+        {
+            progress.eprintln(format!("Warning: could not set the modification time of {}: {}", out_path.display(), e));
+        }
+        let origin = match page {
+            Some(page) => format!("file attachment annotation on p.{}", page),
+            None => "attachment".to_string(),
+        };
+        progress.println(format!("Saved: {} ({} bytes, {})", out_path.display(), attachment.data.len(), origin));
+    }
+    progress.println(format!("Extracted {} embedded files from {:?} to {:?}.", attachments.len(), input_path, out_dir));
+    Ok(())
+}
+
+/// 添付ファイルの名前を、拡張子を残したまま1つのファイル名として安全な形にする（フォルダーのパスは捨てる）
+fn safe_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let name = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            format!("{}.{}", sanitize_filename(stem), sanitize_filename(extension))
+        }
+        _ => sanitize_filename(name),
+    };
+    if name.is_empty() { "attachment".to_string() } else { name }
+}
+
+/// 2つ目以降は `名前 (n).拡張子`
+fn numbered(name: &str, n: usize) -> String {
+    if n == 1 {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{} ({}).{}", stem, n, extension),
+        None => format!("{} ({})", name, n),
+    }
+}
@@ -0,0 +1,391 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `--output` の書き出し先（`s3://bucket/prefix/` または `gs://bucket/prefix/`）
+#[derive(Debug, Clone)]
+pub struct Destination {
+    pub scheme: Scheme,
+    pub bucket: String,
+    /// オブジェクト名の前に付ける部分（空か `/` で終わる）
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    S3,
+    Gcs,
+}
+
+pub fn parse_destination(value: &str) -> Result<Destination, String> {
+    let (scheme, rest) = if let Some(rest) = value.strip_prefix("s3://") {
+        (Scheme::S3, rest)
+    } else if let Some(rest) = value.strip_prefix("gs://") {
+        (Scheme::Gcs, rest)
+    } else {
+        return Err(format!("{:?} is not an s3:// or gs:// URL", value));
+    };
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(format!("{:?} has no bucket name", value));
+    }
+    let mut prefix = prefix.to_string();
+    if !prefix.is_empty() && !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    Ok(Destination { scheme, bucket: bucket.to_string(), prefix })
+}
+
+impl Destination {
+    /// 書き出したオブジェクトの URL（`s3://bucket/prefix/name`）
+    pub fn object_url(&self, name: &str) -> String {
+        let scheme = match self.scheme {
+            Scheme::S3 => "s3",
+            Scheme::Gcs => "gs",
+        };
+        format!("{}://{}/{}{}", scheme, self.bucket, self.prefix, name)
+    }
+}
+
+/// `--output` で、送るまでの間だけ章のファイルを置く一時ディレクトリを作る（入力ごとに別のディレクトリ）
+pub fn staging_dir() -> Result<PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("pdf_splitter-upload-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    Ok(dir)
+}
+
+/// 章のファイルと結果の JSON をオブジェクトストレージへ送る（`--output`）
+///
+/// S3 の REST API（署名は AWS Signature Version 4）で PUT する。GCS は S3 互換の XML API と HMAC キーで送る。
+/// 認証情報は環境変数 `AWS_ACCESS_KEY_ID`・`AWS_SECRET_ACCESS_KEY`（一時的な認証情報なら `AWS_SESSION_TOKEN` も）、
+/// リージョンは `AWS_REGION`（無ければ `AWS_DEFAULT_REGION`、既定は us-east-1）から読む。
+/// `AWS_ENDPOINT_URL` があれば、MinIO などの S3 互換のサーバーへパス形式の URL で送る。
+#[cfg(feature = "upload")]
+pub struct Sink {
+    destination: Destination,
+    credentials: Credentials,
+    region: String,
+    /// バケットを含まないエンドポイント（パス形式）か、バケットを含むホスト（仮想ホスト形式）
+    endpoint: Endpoint,
+}
+
+#[cfg(feature = "upload")]
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+#[cfg(feature = "upload")]
+enum Endpoint {
+    PathStyle(String),
+    VirtualHost(String),
+}
+
+// 一時的な失敗（接続の失敗と 5xx・429 の応答）で送り直す回数
+#[cfg(feature = "upload")]
+const MAX_ATTEMPTS: u32 = 4;
+
+#[cfg(feature = "upload")]
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+// これより大きいファイルはマルチパートアップロードで送る（1回の PUT で送れるのは 5 GB まで）
+#[cfg(feature = "upload")]
+const MULTIPART_THRESHOLD: u64 = 64 << 20;
+
+// マルチパートアップロードの1パートの大きさ（パートは 10,000 個までなので、大きなファイルではそれに合わせて広げる）
+#[cfg(feature = "upload")]
+const PART_SIZE: u64 = 16 << 20;
+
+#[cfg(feature = "upload")]
+const MAX_PARTS: u64 = 10_000;
+
+/// 要求の本文（メモリ上のバイト列か、ファイルの一部）
+///
+/// ファイルは送るたびに開き直して読みながら送るため、章のファイルをメモリに載せない。
+#[cfg(feature = "upload")]
+enum Body<'a> {
+    Bytes(&'a [u8]),
+    File { path: &'a Path, offset: u64, len: u64 },
+}
+
+#[cfg(feature = "upload")]
+impl Body<'_> {
+    /// 署名に含める本文の SHA-256（ファイルは読みながら求める）
+    fn sha256(&self) -> Result<String> {
+        use crate::provenance::hex;
+        use sha2::{Digest, Sha256};
+        use std::io::Read;
+
+        match self {
+            Body::Bytes(data) => Ok(hex(&Sha256::digest(data))),
+            Body::File { path, offset, len } => {
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut open_at(path, *offset)?.take(*len), &mut hasher)
+                    .with_context(|| format!("Failed to read {:?}", path))?;
+                Ok(hex(&hasher.finalize()))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "upload")]
+impl Sink {
+    pub fn new(destination: &Destination) -> Result<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let credentials = Credentials {
+            access_key: env("AWS_ACCESS_KEY_ID").context("--output requires AWS_ACCESS_KEY_ID")?,
+            secret_key: env("AWS_SECRET_ACCESS_KEY").context("--output requires AWS_SECRET_ACCESS_KEY")?,
+            session_token: env("AWS_SESSION_TOKEN"),
+        };
+        let (region, endpoint) = match (destination.scheme, env("AWS_ENDPOINT_URL")) {
+            (_, Some(url)) => (
+                env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string()),
+                Endpoint::PathStyle(url.trim_end_matches('/').to_string()),
+            ),
+            (Scheme::Gcs, None) => ("auto".to_string(), Endpoint::PathStyle("https://storage.googleapis.com".to_string())),
+            (Scheme::S3, None) => {
+                let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+                let host = format!("https://{}.s3.{}.amazonaws.com", destination.bucket, region);
+                (region, Endpoint::VirtualHost(host))
+            }
+        };
+        Ok(Self { destination: destination.clone(), credentials, region, endpoint })
+    }
+
+    pub fn destination(&self) -> &Destination {
+        &self.destination
+    }
+
+    /// ファイルを `prefix` + ファイル名のオブジェクトとして送り、その URL を返す
+    ///
+    /// 内容はファイルから読みながら送る。`MULTIPART_THRESHOLD` を超えるファイルはマルチパートアップロードにする。
+    pub fn upload_file(&self, path: &Path, content_type: &str) -> Result<String> {
+        let name = path.file_name().context("output has no file name")?.to_string_lossy().into_owned();
+        let len = std::fs::metadata(path).with_context(|| format!("Failed to read {:?}", path))?.len();
+        if len > MULTIPART_THRESHOLD {
+            self.upload_multipart(&name, path, len, content_type)?;
+        } else {
+            self.send("PUT", &name, "", Body::File { path, offset: 0, len }, Some(content_type))?;
+        }
+        Ok(self.destination.object_url(&name))
+    }
+
+    /// `name` のオブジェクトとして `data` を送り、その URL を返す
+    pub fn put(&self, name: &str, data: &[u8], content_type: &str) -> Result<String> {
+        self.send("PUT", name, "", Body::Bytes(data), Some(content_type))?;
+        Ok(self.destination.object_url(name))
+    }
+
+    /// マルチパートアップロードで送る。失敗したら、途中まで送ったパートが残らないようアップロードを取り消す
+    fn upload_multipart(&self, name: &str, path: &Path, len: u64, content_type: &str) -> Result<()> {
+        let response = self.send("POST", name, "uploads=", Body::Bytes(&[]), Some(content_type))?;
+        let body = response.into_string().context("Failed to read the response to CreateMultipartUpload")?;
+        let upload_id = xml_element(&body, "UploadId").context("CreateMultipartUpload returned no UploadId")?;
+        let query = format!("uploadId={}", uri_encode(upload_id));
+        let result = self.upload_parts(name, &query, path, len);
+        if result.is_err() {
+            let _ = self.send("DELETE", name, &query, Body::Bytes(&[]), None);
+        }
+        result
+    }
+
+    /// ファイルを `PART_SIZE` ごとのパートとして送り、最後にパートをまとめる（`query` は `uploadId=…`）
+    fn upload_parts(&self, name: &str, query: &str, path: &Path, len: u64) -> Result<()> {
+        let part_size = PART_SIZE.max(len.div_ceil(MAX_PARTS));
+        let mut parts = String::new();
+        for (index, offset) in (0..len).step_by(part_size as usize).enumerate() {
+            let number = index + 1;
+            let body = Body::File { path, offset, len: part_size.min(len - offset) };
+            let response = self.send("PUT", name, &format!("partNumber={}&{}", number, query), body, None)?;
+            let etag = response.header("ETag").context("UploadPart returned no ETag")?;
+            parts.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag));
+        }
+        let completion = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+        let response = self.send("POST", name, query, Body::Bytes(completion.as_bytes()), Some("application/xml"))?;
+        // 完了の要求は、200 を返した後の本文でエラーを知らせることがある
+        let body = response.into_string().unwrap_or_default();
+        if body.contains("<Error>") {
+            anyhow::bail!("uploading {} failed: {}", self.destination.object_url(name), error_message(&body));
+        }
+        Ok(())
+    }
+
+    /// 署名した要求を `name` のオブジェクトへ送り、一時的な失敗なら送り直す
+    ///
+    /// `query` は署名に使う形（キーの順に並べ、値を URI エンコードしたもの）で渡す。
+    fn send(&self, method: &str, name: &str, query: &str, body: Body, content_type: Option<&str>) -> Result<ureq::Response> {
+        use anyhow::bail;
+        use std::io::Read;
+        use std::time::{Duration, SystemTime};
+
+        let key = format!("{}{}", self.destination.prefix, name);
+        let (url, host, canonical_uri) = self.locate(&key);
+        let url = if query.is_empty() { url } else { format!("{}?{}", url, query) };
+        let payload_hash = body.sha256()?;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let headers = self.sign(method, &host, &canonical_uri, query, &payload_hash, SystemTime::now());
+            let mut request = ureq::request(method, &url).timeout(REQUEST_TIMEOUT);
+            if let Some(content_type) = content_type {
+                request = request.set("Content-Type", content_type);
+            }
+            for (name, value) in &headers {
+                request = request.set(name, value);
+            }
+            let result = match &body {
+                Body::Bytes(data) => request.send_bytes(data),
+                // 長さを明示して、チャンク形式（S3 は受け付けない）で送らないようにする
+                Body::File { path, offset, len } => {
+                    request.set("Content-Length", &len.to_string()).send(open_at(path, *offset)?.take(*len))
+                }
+            };
+            let error = match result {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(code, response)) if code >= 500 || code == 429 => {
+                    format!("HTTP {} {}", code, response.status_text())
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    bail!("uploading {} failed with HTTP {}: {}", self.destination.object_url(name), code, error_message(&body));
+                }
+                Err(ureq::Error::Transport(e)) => e.to_string(),
+            };
+            if attempt >= MAX_ATTEMPTS {
+                bail!("uploading {} failed after {} attempts: {}", self.destination.object_url(name), attempt, error);
+            }
+            std::thread::sleep(Duration::from_secs(1 << attempt));
+        }
+    }
+
+    /// オブジェクトの URL・Host ヘッダー・署名に使うパス
+    fn locate(&self, key: &str) -> (String, String, String) {
+        let encoded_key: Vec<String> = key.split('/').map(uri_encode).collect();
+        let encoded_key = encoded_key.join("/");
+        let (base, path) = match &self.endpoint {
+            Endpoint::PathStyle(base) => (base, format!("/{}/{}", uri_encode(&self.destination.bucket), encoded_key)),
+            Endpoint::VirtualHost(base) => (base, format!("/{}", encoded_key)),
+        };
+        let host = base.split_once("://").map_or(base.as_str(), |(_, rest)| rest).to_string();
+        (format!("{}{}", base, path), host, path)
+    }
+
+    /// AWS Signature Version 4 の署名を付けたヘッダー
+    fn sign(
+        &self,
+        method: &str,
+        host: &str,
+        canonical_uri: &str,
+        query: &str,
+        payload_hash: &str,
+        now: std::time::SystemTime,
+    ) -> Vec<(String, String)> {
+        use crate::provenance::hex;
+        use sha2::{Digest, Sha256};
+
+        let (year, month, day, hour, minute, second) = crate::provenance::utc_fields(now);
+        let date = format!("{:04}{:02}{:02}", year, month, day);
+        let amz_date = format!("{}T{:02}{:02}{:02}Z", date, hour, minute, second);
+
+        let mut headers = vec![
+            ("host".to_string(), host.to_string()),
+            ("x-amz-content-sha256".to_string(), payload_hash.to_string()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        headers.sort();
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        let signed_headers = signed_headers.join(";");
+        let canonical_request =
+            format!("{}\n{}\n{}\n{}\n{}\n{}", method, canonical_uri, query, canonical_headers, signed_headers, payload_hash);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+        let mut key = hmac_sha256(format!("AWS4{}", self.credentials.secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key, scope, signed_headers, signature
+        );
+
+        // Host は ureq が付けるため、送るヘッダーからは除く
+        headers.retain(|(name, _)| name != "host");
+        headers.push(("Authorization".to_string(), authorization));
+        headers
+    }
+}
+
+/// `upload` フィーチャー無しでビルドした場合は、起動時に分かりやすいエラーにする
+#[cfg(not(feature = "upload"))]
+pub struct Sink;
+
+#[cfg(not(feature = "upload"))]
+impl Sink {
+    pub fn new(_destination: &Destination) -> Result<Self> {
+        anyhow::bail!("--output is not available in this build; rebuild with `cargo build --release --features upload`")
+    }
+
+    pub fn destination(&self) -> &Destination {
+        unreachable!("Sink cannot be constructed without the upload feature")
+    }
+
+    pub fn upload_file(&self, _path: &Path, _content_type: &str) -> Result<String> {
+        unreachable!("Sink cannot be constructed without the upload feature")
+    }
+
+    pub fn put(&self, _name: &str, _data: &[u8], _content_type: &str) -> Result<String> {
+        unreachable!("Sink cannot be constructed without the upload feature")
+    }
+}
+
+/// S3 の署名で使う URI エンコード（非予約文字以外を %XX にする）
+#[cfg(feature = "upload")]
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(feature = "upload")]
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// `offset` の位置から読むように開いたファイル
+#[cfg(feature = "upload")]
+fn open_at(path: &Path, offset: u64) -> Result<std::fs::File> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    file.seek(SeekFrom::Start(offset)).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(file)
+}
+
+/// 応答の XML から、最初の `<tag>` 要素の中身を取り出す
+#[cfg(feature = "upload")]
+fn xml_element<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let (_, rest) = body.split_once(&format!("<{}>", tag))?;
+    rest.split_once(&format!("</{}>", tag)).map(|(content, _)| content)
+}
+
+/// エラー応答の XML から <Message> を取り出す（無ければ本文の先頭）
+#[cfg(feature = "upload")]
+fn error_message(body: &str) -> &str {
+    xml_element(body, "Message").unwrap_or_else(|| body.get(..200).unwrap_or(body))
+}
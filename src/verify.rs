@@ -0,0 +1,149 @@
+use crate::crypt::StringDecryptor;
+use crate::pagetree;
+use crate::named_dests::NamedDests;
+use crate::{DestFallback, decode_pdf_string, load_document, resolve_dest, resolve_object};
+use lopdf::{Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// 保存済み出力の検証結果
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// 出力として不正な問題（検証失敗として扱う）
+    pub problems: Vec<String>,
+    /// この章の範囲外を指していて解決できない目次・リンクの数（情報のみ）
+    pub unresolved_destinations: usize,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// 出力ファイルを読み直し、解析可否・ページ数・目次とリンクの宛先を確認する
+pub fn verify_output(path: &Path, expected_pages: u32) -> Verification {
+    let mut result = Verification::default();
+
+    let doc = match load_document(path, false) {
+        Ok(doc) => doc,
+        Err(e) => {
+            result.problems.push(format!("failed to parse: {}", e.root_cause()));
+            return result;
+        }
+    };
+
+    let (pages, _) = pagetree::collect_pages(&doc);
+    if pages.len() as u32 != expected_pages {
+        result.problems.push(format!("expected {} pages, found {}", expected_pages, pages.len()));
+    }
+
+    // ページツリー /Count の不整合はビューアによっては開けない原因になる
+    if let Ok(pages_obj) = doc.catalog().and_then(|c| c.get(b"Pages")).and_then(|o| resolve_object(&doc, o))
+        && let Ok(count) = pages_obj.as_dict().and_then(|d| d.get(b"Count")).and_then(|o| o.as_i64())
+        && count != pages.len() as i64
+    {
+        result.problems.push(format!("page tree /Count is {}, but {} pages are reachable", count, pages.len()));
+    }
+
+    let page_map: BTreeMap<ObjectId, u32> = pages.iter().map(|(num, id)| (*id, *num)).collect();
+    let decryptor = StringDecryptor::default();
+    let named_dests = NamedDests::new(&doc, &decryptor);
+
+    // 目次
+    if let Ok(outlines) = doc.catalog().and_then(|c| c.get(b"Outlines")).and_then(|o| resolve_object(&doc, o))
+        && let Ok(first) = outlines.as_dict().and_then(|d| d.get(b"First")).and_then(|o| o.as_reference())
+    {
+        let mut visited = HashSet::new();
+        check_outline_level(&doc, first, &page_map, &named_dests, &mut visited, &mut result);
+    }
+
+    // ページ上のリンク注釈
+    for (page_num, page_id) in &pages {
+        for annot in doc.get_page_annotations(*page_id) {
+            let is_link = annot.get(b"Subtype").and_then(|o| o.as_name()).map(|n| n == b"Link").unwrap_or(false);
+            if is_link {
+                check_destination(&doc, annot, &format!("link on page {}", page_num), &page_map, &named_dests, &mut result);
+            }
+        }
+    }
+
+    result
+}
+
+fn check_outline_level(
+    doc: &Document,
+    start_id: ObjectId,
+    page_map: &BTreeMap<ObjectId, u32>,
+    named_dests: &NamedDests,
+    visited: &mut HashSet<ObjectId>,
+    result: &mut Verification,
+) {
+    let mut current = Some(start_id);
+    while let Some(id) = current {
+        // 循環した目次で無限ループしないように
+        if !visited.insert(id) {
+            result.problems.push(format!("outline item {:?} is part of a cycle", id));
+            return;
+        }
+        let Ok(item) = doc.get_object(id).and_then(|o| o.as_dict()) else {
+            result.problems.push(format!("outline item {:?} is missing", id));
+            return;
+        };
+        let title = item.get(b"Title")
+            .ok()
+            .and_then(|o| o.as_str().ok())
+            .map(decode_pdf_string)
+            .unwrap_or_else(|| "No Title".to_string());
+        check_destination(doc, item, &format!("outline '{}'", title), page_map, named_dests, result);
+
+        if let Ok(child) = item.get(b"First").and_then(|o| o.as_reference()) {
+            check_outline_level(doc, child, page_map, named_dests, visited, result);
+        }
+        current = item.get(b"Next").ok().and_then(|o| o.as_reference().ok());
+    }
+}
+
+/// /Dest または GoTo アクションの宛先を確認する
+fn check_destination(
+    doc: &Document,
+    item: &lopdf::Dictionary,
+    label: &str,
+    page_map: &BTreeMap<ObjectId, u32>,
+    named_dests: &NamedDests,
+    result: &mut Verification,
+) {
+    let dest = item.get(b"Dest").ok().or_else(|| {
+        item.get(b"A")
+            .ok()
+            .and_then(|a| resolve_object(doc, a).ok())
+            .and_then(|a| a.as_dict().ok())
+            .filter(|a| a.get(b"S").and_then(|s| s.as_name()).map(|s| s == b"GoTo").unwrap_or(false))
+            .and_then(|a| a.get(b"D").ok())
+    });
+    let Some(dest) = dest else { return };
+
+    if resolve_dest(doc, dest, page_map, named_dests, DestFallback::Skip).is_some() {
+        return;
+    }
+    // 存在しないオブジェクトへの参照は書き出し側の不具合
+    if let Some(missing) = dangling_reference(doc, dest) {
+        result.problems.push(format!("{} references missing object {:?}", label, missing));
+    } else {
+        result.unresolved_destinations += 1;
+    }
+}
+
+fn dangling_reference(doc: &Document, dest: &Object) -> Option<ObjectId> {
+    let target = match dest {
+        Object::Reference(id) => match doc.get_object(*id) {
+            Ok(obj) => obj,
+            Err(_) => return Some(*id),
+        },
+        _ => dest,
+    };
+    match target.as_array().ok()?.first()? {
+        Object::Reference(id) if !doc.has_object(*id) => Some(*id),
+        _ => None,
+    }
+}
@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+use lopdf::xref::XrefType;
+use lopdf::{Document, Object};
+
+/// 出力PDFのバージョン
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum PdfVersion {
+    #[value(name = "1.4")]
+    V1_4,
+    #[value(name = "1.7")]
+    V1_7,
+    #[value(name = "2.0")]
+    V2_0,
+}
+
+impl PdfVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PdfVersion::V1_4 => "1.4",
+            PdfVersion::V1_7 => "1.7",
+            PdfVersion::V2_0 => "2.0",
+        }
+    }
+
+    /// ヘッダーとカタログの /Version を設定し、対象バージョンで使えない機能を落とす
+    pub fn apply(self, doc: &mut Document) {
+        doc.version = self.as_str().to_string();
+
+        if self == PdfVersion::V1_4 {
+            // 相互参照ストリームとオブジェクトストリームは 1.5 以降の機能。
+            // lopdf は読み込み時にオブジェクトストリームを展開するので、従来の xref 表で書けば十分
+            doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+            doc.objects.retain(|_, obj| {
+                !matches!(obj.type_name(), Ok("ObjStm") | Ok("XRef"))
+            });
+        }
+
+        // カタログの /Version はヘッダーより優先されるため、ヘッダーと揃える
+        if let Ok(catalog) = doc.catalog_mut() {
+            if self == PdfVersion::V1_4 {
+                catalog.remove(b"Version");
+            } else {
+                catalog.set("Version", Object::Name(self.as_str().as_bytes().to_vec()));
+            }
+        }
+    }
+}
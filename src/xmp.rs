@@ -0,0 +1,140 @@
+use lopdf::{Dictionary, Document, Object, Stream};
+
+const CALIBRE_NS: &str = "http://calibre-ebook.com/xmp-namespace";
+const CALIBRE_SI_NS: &str = "http://calibre-ebook.com/xmp-namespace-series-index";
+pub const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+pub const PDF_NS: &str = "http://ns.adobe.com/pdf/1.3/";
+pub const XMP_BASIC_NS: &str = "http://ns.adobe.com/xap/1.0/";
+
+/// `--series-metadata`: 章を Calibre に取り込んだときに1つのシリーズにまとまるよう書き込むシリーズ情報
+pub struct Series {
+    title: String,
+}
+
+impl Series {
+    pub fn new(title: String) -> Self {
+        Self { title }
+    }
+
+    /// Calibre が書き出すのと同じ形（rdf:value と calibreSI:series_index）で、章の番号をシリーズ内の番号にする
+    pub fn description(&self, index: usize) -> Description {
+        Description {
+            namespaces: &[("calibre", CALIBRE_NS), ("calibreSI", CALIBRE_SI_NS)],
+            properties: format!(
+                "<calibre:series rdf:parseType=\"Resource\">\n\
+                 <rdf:value>{}</rdf:value>\n\
+                 <calibreSI:series_index>{}.00</calibreSI:series_index>\n\
+                 </calibre:series>\n",
+                escape_text(&self.title),
+                index
+            ),
+        }
+    }
+}
+
+/// XMP パケットに足す `rdf:Description` 1つ分
+pub struct Description {
+    /// (接頭辞, URI)
+    pub namespaces: &'static [(&'static str, &'static str)],
+    /// Description の中に置く要素の XML
+    pub properties: String,
+}
+
+/// カタログの /Metadata (XMP) を文字列で返す
+pub fn catalog_metadata(doc: &Document) -> Option<String> {
+    let metadata = doc.catalog().ok()?.get(b"Metadata").ok()?;
+    let stream = doc.dereference(metadata).ok()?.1.as_stream().ok()?;
+    let content = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// 既存の XMP パケットに `rdf:Description` を足す（パケットが無いか読めなければ新しく作る）
+pub fn with_descriptions(packet: Option<&str>, descriptions: &[Description]) -> String {
+    let mut added = String::new();
+    for description in descriptions {
+        added.push_str("<rdf:Description rdf:about=\"\"");
+        for (prefix, uri) in description.namespaces {
+            added.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+        }
+        added.push_str(">\n");
+        added.push_str(&description.properties);
+        added.push_str("</rdf:Description>\n");
+    }
+
+    if let Some(packet) = packet
+        && let Some(end) = packet.rfind("</rdf:RDF>")
+    {
+        return format!("{}{}{}", &packet[..end], added, &packet[end..]);
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         {}</rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        added
+    )
+}
+
+/// パケットから `names`（`dc:title` など）のプロパティを取り除く（要素の形も、`rdf:Description` の属性の形も）
+///
+/// 書き換えるプロパティを `with_descriptions` で足す前に、古い値が別の `rdf:Description` に残って食い違わないようにする。
+/// 接頭辞は `names` と同じものを使っているパケットを前提にする。
+pub fn without_properties(packet: &str, names: &[&str]) -> String {
+    let mut packet = packet.to_string();
+    for name in names {
+        // 要素: <name ...>...</name> または <name .../>
+        let open = format!("<{}", name);
+        let close = format!("</{}>", name);
+        let mut search_from = 0;
+        while let Some(offset) = packet[search_from..].find(&open) {
+            let start = search_from + offset;
+            let after_name = packet[start + open.len()..].chars().next();
+            if !matches!(after_name, Some(' ' | '\t' | '\r' | '\n' | '>' | '/')) {
+                search_from = start + open.len();
+                continue;
+            }
+            let Some(tag_end) = packet[start..].find('>').map(|i| start + i) else { break };
+            let end = if packet[..tag_end].ends_with('/') {
+                tag_end + 1
+            } else {
+                match packet[tag_end..].find(&close) {
+                    Some(i) => tag_end + i + close.len(),
+                    None => break,
+                }
+            };
+            packet.replace_range(start..end, "");
+            search_from = start;
+        }
+        // 属性: name="..." または name='...'
+        for quote in ['"', '\''] {
+            let attribute = format!(" {}={}", name, quote);
+            while let Some(start) = packet.find(&attribute) {
+                let value_start = start + attribute.len();
+                let Some(end) = packet[value_start..].find(quote).map(|i| value_start + i + 1) else { break };
+                packet.replace_range(start..end, "");
+            }
+        }
+    }
+    packet
+}
+
+/// XMP パケットを新しいメタデータストリームとして加え、カタログの /Metadata を付け替える
+///
+/// 元のメタデータストリームは書き出し済みのことがあるため書き換えず、参照されないまま残る。
+pub fn set_catalog_metadata(doc: &mut Document, packet: String) {
+    let mut dict = Dictionary::new();
+    dict.set("Type", "Metadata");
+    dict.set("Subtype", "XML");
+    // PDF/A などでは XMP を圧縮せずに置く
+    let stream = Stream::new(dict, packet.into_bytes()).with_compression(false);
+    let metadata_id = doc.add_object(Object::Stream(stream));
+    if let Ok(catalog) = doc.catalog_mut() {
+        catalog.set("Metadata", metadata_id);
+    }
+}
+
+pub fn escape_text(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
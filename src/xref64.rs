@@ -0,0 +1,354 @@
+use anyhow::{Context, Result, bail};
+use lopdf::xref::{Xref, XrefEntry, XrefType};
+use lopdf::{Dictionary, Document, Object, ObjectId, ObjectStream, Stream};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+
+/// lopdf の読み込みは相互参照のオフセットを u32 で扱うため、これより大きいファイルは `load` で読む
+pub const LOPDF_MAX_OFFSET: u64 = u32::MAX as u64;
+
+// startxref を探すファイル末尾の範囲
+const TAIL_LEN: usize = 1024;
+
+// ストリーム以外のオブジェクト（とストリームの辞書）がこれより長いことは想定しない
+const MAX_OBJECT_LEN: usize = 16 << 20;
+
+// 壊れた（循環した）/Prev で無限にたどらないための上限
+const MAX_SECTIONS: usize = 4096;
+
+/// 相互参照の項目（オフセットは 64 ビット）
+#[derive(Clone, Copy, Debug)]
+pub enum Entry {
+    Normal { offset: u64, generation: u16 },
+    Compressed { container: u32, index: u16 },
+}
+
+/// 相互参照表・相互参照ストリームを 64 ビットのオフセットで読んだ結果
+pub struct CrossReference {
+    pub entries: BTreeMap<u32, Entry>,
+    pub trailer: Dictionary,
+    pub xref_start: u64,
+    pub stream_type: bool,
+}
+
+/// `buffer`（ファイル全体）の相互参照を、/Prev と /XRefStm をたどってすべて読む
+///
+/// 同じオブジェクト番号の項目は、新しい（先に読んだ）版のものを使う。
+pub fn cross_reference(buffer: &[u8]) -> Result<CrossReference> {
+    let xref_start = xref_start(buffer).context("No startxref at the end of the file")?;
+    let mut entries = BTreeMap::new();
+    let mut trailer = None;
+    let mut stream_type = false;
+    let mut pending = vec![xref_start];
+    let mut visited = HashSet::new();
+    while let Some(offset) = pending.pop() {
+        if !visited.insert(offset) || visited.len() > MAX_SECTIONS {
+            continue;
+        }
+        let (section, section_trailer, is_stream) = read_section(buffer, offset, &entries)
+            .with_context(|| format!("Invalid cross-reference section at offset {}", offset))?;
+        for (id, entry) in section {
+            entries.entry(id).or_insert(entry);
+        }
+        // 版をさかのぼる /Prev は、同じ版の /XRefStm（混在形式のファイル）より後に読む
+        if let Some(prev) = offset_value(&section_trailer, b"Prev") {
+            pending.push(prev);
+        }
+        if let Some(stream) = offset_value(&section_trailer, b"XRefStm") {
+            pending.push(stream);
+        }
+        if trailer.is_none() {
+            stream_type = is_stream;
+            trailer = Some(section_trailer);
+        }
+    }
+    let mut trailer = trailer.context("No cross-reference section")?;
+    for key in [b"Prev".as_slice(), b"XRefStm", b"Type", b"W", b"Index", b"Length", b"Filter", b"DecodeParms"] {
+        trailer.remove(key);
+    }
+    Ok(CrossReference { entries, trailer, xref_start, stream_type })
+}
+
+/// 4 GiB を超える入力を読み込む
+///
+/// 相互参照と各オブジェクトの位置は自前で 64 ビットのオフセットとして読み、オブジェクトの構文の解析には
+/// lopdf の `ObjectStream` を使う（1つのオブジェクトだけを入れたオブジェクトストリームとして解析させる）。
+/// 読み込んだ結果は `Document::load_mem` と同じ形（オブジェクトストリームの中身も展開済み）にする。
+/// u32 に収まらないオフセットのオブジェクトは `reference_table` に載せない（元の位置は `cross_reference` で引ける）。
+pub fn load(buffer: &[u8]) -> Result<Document> {
+    let version = header_version(buffer).context("Not a PDF file (no %PDF- header)")?;
+    let xref = cross_reference(buffer)?;
+
+    let loaded: Vec<(ObjectId, Object)> = xref
+        .entries
+        .par_iter()
+        .filter_map(|(id, entry)| match *entry {
+            Entry::Normal { offset, generation } => {
+                read_object(buffer, offset, (*id, generation), &xref.entries).map(|object| ((*id, generation), object))
+            }
+            Entry::Compressed { .. } => None,
+        })
+        .collect();
+    let mut objects: BTreeMap<ObjectId, Object> = loaded.into_iter().collect();
+
+    let containers: Vec<ObjectStream> = objects
+        .par_iter()
+        .filter_map(|(_, object)| {
+            let stream = object.as_stream().ok().filter(|stream| stream.dict.type_is(b"ObjStm"))?;
+            ObjectStream::new(&mut stream.clone()).ok()
+        })
+        .collect();
+    for container in containers {
+        for (id, object) in container.objects {
+            objects.entry(id).or_insert(object);
+        }
+    }
+
+    let mut reference_table = Xref::new(0, if xref.stream_type { XrefType::CrossReferenceStream } else { XrefType::CrossReferenceTable });
+    for (id, entry) in &xref.entries {
+        match *entry {
+            Entry::Normal { offset, generation } if offset <= LOPDF_MAX_OFFSET => {
+                reference_table.insert(*id, XrefEntry::Normal { offset: offset as u32, generation })
+            }
+            Entry::Normal { .. } => {}
+            Entry::Compressed { container, index } => reference_table.insert(*id, XrefEntry::Compressed { container, index }),
+        }
+    }
+    reference_table.size = xref.entries.keys().next_back().map_or(1, |max| max + 1);
+
+    let mut doc = Document::new();
+    doc.version = version;
+    doc.max_id = reference_table.size - 1;
+    doc.trailer = xref.trailer;
+    doc.reference_table = reference_table;
+    doc.objects = objects;
+    doc.xref_start = xref.xref_start as usize;
+    Ok(doc)
+}
+
+fn header_version(buffer: &[u8]) -> Option<String> {
+    let rest = buffer.get(..buffer.len().min(1024))?;
+    let start = find(rest, b"%PDF-")? + b"%PDF-".len();
+    let end = rest[start..].iter().position(|b| b.is_ascii_whitespace()).map_or(rest.len(), |i| start + i);
+    Some(String::from_utf8_lossy(&rest[start..end]).into_owned())
+}
+
+fn xref_start(buffer: &[u8]) -> Option<u64> {
+    let tail_start = buffer.len().saturating_sub(TAIL_LEN);
+    let keyword = tail_start + rfind(&buffer[tail_start..], b"startxref")?;
+    let (value, _) = unsigned(buffer, skip_whitespace(buffer, keyword + b"startxref".len()))?;
+    Some(value)
+}
+
+type Section = (Vec<(u32, Entry)>, Dictionary, bool);
+
+/// `offset` の相互参照の1区画（`xref` で始まる表か、相互参照ストリーム）
+fn read_section(buffer: &[u8], offset: u64, known: &BTreeMap<u32, Entry>) -> Result<Section> {
+    let start = usize::try_from(offset).ok().filter(|start| *start < buffer.len()).context("offset beyond the end of the file")?;
+    let start = skip_whitespace(buffer, start);
+    if buffer[start..].starts_with(b"xref") {
+        let (entries, trailer) = read_table(buffer, start + b"xref".len())?;
+        return Ok((entries, trailer, false));
+    }
+    let (_, object) = read_indirect(buffer, start, known).context("neither an xref table nor an xref stream")?;
+    let stream = object.as_stream().ok().filter(|stream| stream.dict.type_is(b"XRef")).context("not an xref stream")?;
+    let entries = stream_entries(stream)?;
+    Ok((entries, stream.dict.clone(), true))
+}
+
+/// `xref` キーワードの後の相互参照表と、続く trailer 辞書
+fn read_table(buffer: &[u8], mut pos: usize) -> Result<(Vec<(u32, Entry)>, Dictionary)> {
+    let mut entries = Vec::new();
+    loop {
+        pos = skip_whitespace(buffer, pos);
+        if buffer[pos..].starts_with(b"trailer") {
+            pos += b"trailer".len();
+            break;
+        }
+        let (first, next) = unsigned(buffer, pos).context("bad subsection header")?;
+        let (count, next) = unsigned(buffer, skip_whitespace(buffer, next)).context("bad subsection header")?;
+        pos = next;
+        let end = first.checked_add(count).context("bad subsection header")?;
+        for number in first..end {
+            let (offset, next) = unsigned(buffer, skip_whitespace(buffer, pos)).context("bad xref entry")?;
+            let (generation, next) = unsigned(buffer, skip_whitespace(buffer, next)).context("bad xref entry")?;
+            let kind_pos = skip_whitespace(buffer, next);
+            let kind = *buffer.get(kind_pos).context("truncated xref table")?;
+            pos = kind_pos + 1;
+            if kind == b'n' {
+                entries.push((number as u32, Entry::Normal { offset, generation: generation as u16 }));
+            }
+        }
+    }
+    let window = &buffer[pos..buffer.len().min(pos + MAX_OBJECT_LEN)];
+    let end = find(window, b"startxref").unwrap_or(window.len());
+    let trailer = parse_direct(&window[..end]).and_then(|o| o.as_dict().ok().cloned()).context("bad trailer dictionary")?;
+    Ok((entries, trailer))
+}
+
+/// 相互参照ストリームの項目（/W の各欄は最大 8 バイト）
+fn stream_entries(stream: &Stream) -> Result<Vec<(u32, Entry)>> {
+    let widths: Vec<usize> = stream.dict.get(b"W").and_then(Object::as_array)?.iter().filter_map(|w| w.as_i64().ok()).map(|w| w as usize).collect();
+    let [w0, w1, w2] = <[usize; 3]>::try_from(widths).ok().filter(|w| w.iter().all(|w| *w <= 8)).context("bad /W")?;
+    if w0 + w1 + w2 == 0 {
+        bail!("bad /W: the rows have no width");
+    }
+    let size = stream.dict.get(b"Size").and_then(Object::as_i64)?;
+    let index: Vec<i64> = match stream.dict.get(b"Index").and_then(Object::as_array) {
+        Ok(index) => index.iter().filter_map(|i| i.as_i64().ok()).collect(),
+        Err(_) => vec![0, size],
+    };
+    let content = if stream.dict.has(b"Filter") { stream.decompressed_content()? } else { stream.content.clone() };
+
+    let field = |bytes: &[u8]| bytes.iter().fold(0u64, |value, b| value << 8 | *b as u64);
+    let mut rows = content.chunks_exact(w0 + w1 + w2);
+    let mut entries = Vec::new();
+    for range in index.chunks_exact(2) {
+        let end = range[0].checked_add(range[1]).context("bad /Index")?;
+        for number in range[0]..end {
+            let Some(row) = rows.next() else { return Ok(entries) };
+            // 種類の欄が無い（幅 0 の）ときは通常のオブジェクト
+            let kind = if w0 == 0 { 1 } else { field(&row[..w0]) };
+            let second = field(&row[w0..w0 + w1]);
+            let third = field(&row[w0 + w1..]);
+            match kind {
+                1 => entries.push((number as u32, Entry::Normal { offset: second, generation: third as u16 })),
+                2 => entries.push((number as u32, Entry::Compressed { container: second as u32, index: third as u16 })),
+                _ => {}
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// `offset` の `N G obj` が `id` のオブジェクトなら、その中身
+fn read_object(buffer: &[u8], offset: u64, id: ObjectId, known: &BTreeMap<u32, Entry>) -> Option<Object> {
+    let (found, object) = read_indirect(buffer, usize::try_from(offset).ok()?, known)?;
+    (found == id).then_some(object)
+}
+
+/// `start` の `N G obj … endobj` を読む
+///
+/// ストリームの長さが間接参照なら `known` の位置から読む。長さが不正なら endstream までをデータとする。
+fn read_indirect(buffer: &[u8], start: usize, known: &BTreeMap<u32, Entry>) -> Option<(ObjectId, Object)> {
+    let (number, pos) = unsigned(buffer, skip_whitespace(buffer, start))?;
+    let (generation, pos) = unsigned(buffer, skip_whitespace(buffer, pos))?;
+    let pos = skip_whitespace(buffer, pos);
+    if !buffer.get(pos..)?.starts_with(b"obj") {
+        return None;
+    }
+    let id = (number as u32, generation as u16);
+    let body_start = pos + b"obj".len();
+    // ストリームのデータ（数 GiB になりうる）は、辞書と長さが分かるまで読まない
+    let window = &buffer[body_start..buffer.len().min(body_start + MAX_OBJECT_LEN)];
+    let Some(keyword) = stream_keyword(window).filter(|keyword| find(&window[..*keyword], b"endobj").is_none()) else {
+        let end = find(window, b"endobj").unwrap_or(window.len());
+        return Some((id, parse_direct(&window[..end])?));
+    };
+    let dict = parse_direct(&window[..keyword])?.as_dict().ok()?.clone();
+    let mut data_start = body_start + keyword + b"stream".len();
+    if buffer.get(data_start) == Some(&b'\r') {
+        data_start += 1;
+    }
+    if buffer.get(data_start) == Some(&b'\n') {
+        data_start += 1;
+    }
+    let length = match dict.get(b"Length").ok()? {
+        Object::Reference(length_id) => match known.get(&length_id.0) {
+            Some(Entry::Normal { offset, .. }) => {
+                read_object(buffer, *offset, *length_id, known).and_then(|o| o.as_i64().ok())
+            }
+            _ => None,
+        },
+        length => length.as_i64().ok(),
+    };
+    // 長さが途方もなく大きい（足すと桁あふれする）オブジェクトは読めないものとする
+    let declared_end = match length.and_then(|length| usize::try_from(length).ok()) {
+        Some(length) => Some(data_start.checked_add(length)?),
+        None => None,
+    };
+    let data_end = match declared_end {
+        Some(data_end) if buffer.get(data_end..).is_some_and(|rest| trim_start(rest).starts_with(b"endstream")) => data_end,
+        _ => data_start + find(buffer.get(data_start..)?, b"endstream")?,
+    };
+    Some((id, Object::Stream(Stream::new(dict, buffer[data_start..data_end].to_vec()))))
+}
+
+/// 1つのオブジェクトのバイト列を lopdf で解析する（1つだけを入れたオブジェクトストリームとして解析させる）
+fn parse_direct(bytes: &[u8]) -> Option<Object> {
+    let mut content = b"0 0\n".to_vec();
+    let first = content.len() as i64;
+    // lopdf の解析は先頭の空白を読み飛ばさない
+    content.extend_from_slice(&bytes[skip_whitespace(bytes, 0)..]);
+    let mut dict = Dictionary::new();
+    dict.set("N", 1);
+    dict.set("First", first);
+    let mut stream = Stream::new(dict, content);
+    ObjectStream::new(&mut stream).ok()?.objects.remove(&(0, 0))
+}
+
+/// オブジェクトの本文中の、辞書の直後の stream キーワードの位置
+fn stream_keyword(body: &[u8]) -> Option<usize> {
+    let mut from = 0;
+    loop {
+        let pos = from + find(&body[from..], b"stream")?;
+        if body[..pos].iter().rev().find(|b| !b.is_ascii_whitespace()) == Some(&b'>') {
+            return Some(pos);
+        }
+        from = pos + 1;
+    }
+}
+
+fn offset_value(dict: &Dictionary, key: &[u8]) -> Option<u64> {
+    dict.get(key).and_then(Object::as_i64).ok().and_then(|value| u64::try_from(value).ok())
+}
+
+fn unsigned(buffer: &[u8], pos: usize) -> Option<(u64, usize)> {
+    let digits = buffer.get(pos..)?.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let value = std::str::from_utf8(&buffer[pos..pos + digits]).ok()?.parse().ok()?;
+    Some((value, pos + digits))
+}
+
+fn skip_whitespace(buffer: &[u8], mut pos: usize) -> usize {
+    while pos < buffer.len() {
+        match buffer[pos] {
+            b if b.is_ascii_whitespace() || b == 0 => pos += 1,
+            // コメントは行末まで読み飛ばす
+            b'%' => pos += buffer[pos..].iter().position(|b| *b == b'\n' || *b == b'\r').unwrap_or(buffer.len() - pos),
+            _ => break,
+        }
+    }
+    pos
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
+
+/// オブジェクト番号ごとの 64 ビットのオフセットと世代番号（`RawSource` が元の位置を引くのに使う）
+pub fn object_offsets(buffer: &[u8]) -> Result<BTreeMap<u32, (u64, u16)>> {
+    let xref = cross_reference(buffer)?;
+    if xref.entries.is_empty() {
+        bail!("empty cross-reference");
+    }
+    Ok(xref
+        .entries
+        .into_iter()
+        .filter_map(|(id, entry)| match entry {
+            Entry::Normal { offset, generation } => Some((id, (offset, generation))),
+            Entry::Compressed { .. } => None,
+        })
+        .collect())
+}